@@ -0,0 +1,124 @@
+//! Heap use per stored policy, the bottleneck for caches holding millions of entries.
+//!
+//! Run with `cargo bench --bench policy_memory`. Counts the bytes each policy keeps alive and
+//! the allocations made to build it, with `policy_for`, with `policy_for_shared_options`, and
+//! when decoding a segment written by `PolicyEncoder`.
+
+use http::{Request, Response};
+use http_cache_semantics::{
+    policy_for, policy_for_shared_options, CacheOptions, CachePolicy, PathAction, PathRule,
+    PolicyDecoder, PolicyEncoder,
+};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static FREED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        FREED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const POLICIES: usize = 100_000;
+
+fn counters() -> (usize, usize) {
+    let allocated = ALLOCATED.load(Ordering::Relaxed);
+    let live = allocated - FREED.load(Ordering::Relaxed);
+    (ALLOCATIONS.load(Ordering::Relaxed), live)
+}
+
+/// Builds `POLICIES` policies with `build`, keeping them all alive, and reports the memory
+/// each takes, counting both the `CachePolicy` itself and what it keeps on the heap.
+fn measure(name: &str, mut build: impl FnMut(usize) -> CachePolicy) {
+    let (allocations, live) = counters();
+    let mut policies = Vec::with_capacity(POLICIES);
+    let start = Instant::now();
+    for i in 0..POLICIES {
+        policies.push(build(i));
+    }
+    let elapsed = start.elapsed();
+    let (allocations_after, live_after) = counters();
+    let heap = (live_after - live - POLICIES * mem::size_of::<CachePolicy>()) / POLICIES;
+    println!(
+        "{:<12} {:>6} bytes/policy ({} on the heap), {:>5.1} allocations/policy, {:>6} ns/policy",
+        name,
+        mem::size_of::<CachePolicy>() + heap,
+        heap,
+        (allocations_after - allocations) as f64 / POLICIES as f64,
+        elapsed.as_nanos() / POLICIES as u128,
+    );
+    drop(policies);
+}
+
+fn main() {
+    let options = CacheOptions {
+        path_rules: vec![PathRule {
+            pattern: "/static/**".to_string(),
+            action: PathAction::Ttl(Duration::from_secs(86_400)),
+        }],
+        ..CacheOptions::default()
+    };
+    let now = SystemTime::now();
+    let request = Request::get("https://example.com/products/42?color=blue")
+        .header("host", "example.com")
+        .header("accept-encoding", "gzip, br")
+        .header("user-agent", "bench/1.0")
+        .body(())
+        .unwrap();
+    let response = Response::builder()
+        .header(
+            "cache-control",
+            "public, max-age=300, stale-while-revalidate=60",
+        )
+        .header("content-type", "text/html; charset=utf-8")
+        .header("content-encoding", "br")
+        .header("content-length", "14230")
+        .header("date", httpdate::fmt_http_date(now))
+        .header("etag", "\"5f1c-abc123\"")
+        .header("last-modified", "Mon, 01 Jan 2024 00:00:00 GMT")
+        .header("vary", "Accept-Encoding")
+        .header("x-served-by", "cache-fra-1")
+        .header("x-request-id", "0f8e6a4c-1b2d-4c3e-8f9a-0b1c2d3e4f5a")
+        .body(())
+        .unwrap();
+
+    println!(
+        "size_of::<CachePolicy>() = {}",
+        mem::size_of::<CachePolicy>()
+    );
+    measure("policy_for", |_| {
+        policy_for(&request, &response, &options, now)
+    });
+    let shared_options = Arc::new(options.clone());
+    measure("shared", |_| {
+        policy_for_shared_options(&request, &response, &shared_options, now)
+    });
+
+    let mut encoder = PolicyEncoder::new(Vec::new());
+    let policy = policy_for(&request, &response, &options, now);
+    for _ in 0..POLICIES {
+        encoder.write(&policy).unwrap();
+    }
+    let segment = encoder.into_inner();
+    println!("segment: {} bytes/policy", segment.len() / POLICIES);
+    let mut decoder = PolicyDecoder::new(&segment[..], options.clone());
+    measure("decode", |_| decoder.read().unwrap().unwrap());
+}
@@ -0,0 +1,328 @@
+//! `proptest` strategies for realistic requests, responses and policies, and checks for
+//! invariants every policy should uphold.
+//!
+//! Enabled by the `proptest` feature.
+
+use crate::http_cache_semantics::format_http_date;
+use crate::{policy_for, CacheOptions, CachePolicy, HeuristicFraction};
+use proptest::prelude::*;
+use proptest::sample::subsequence;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The time generated exchanges are stored at. Header dates are generated relative to it.
+pub fn base_time() -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(1_577_836_800)
+}
+
+fn http_date_near_base() -> impl Strategy<Value = String> {
+    prop_oneof![
+        (0..20 * 86400u64).prop_map(|offset| format_http_date(
+            base_time() - Duration::from_secs(10 * 86400) + Duration::from_secs(offset)
+        )),
+        Just("0".to_string()),
+        Just("not a date".to_string()),
+    ]
+}
+
+/// IMF-fixdates in any year from 0 to 99999, with arbitrary weekdays, including the many that
+/// are invalid or out of range.
+pub fn http_date_any_year() -> impl Strategy<Value = String> {
+    let weekdays = vec!["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+    let months = vec![
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    (
+        proptest::sample::select(weekdays),
+        1u32..=31,
+        proptest::sample::select(months),
+        0u32..100_000,
+        0u32..86400,
+    )
+        .prop_map(|(weekday, day, month, year, time)| {
+            format!(
+                "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+                weekday,
+                day,
+                month,
+                year,
+                time / 3600,
+                time / 60 % 60,
+                time % 60
+            )
+        })
+}
+
+fn delta_seconds() -> impl Strategy<Value = String> {
+    prop_oneof![
+        (0u64..100_000).prop_map(|n| n.to_string()),
+        Just("0".to_string()),
+        Just("-1".to_string()),
+        Just("99999999999999999999".to_string()),
+    ]
+}
+
+/// `Cache-Control` values a response might carry.
+pub fn response_cache_control() -> impl Strategy<Value = String> {
+    let flags = vec![
+        "public",
+        "private",
+        "no-cache",
+        "no-store",
+        "must-revalidate",
+        "proxy-revalidate",
+        "immutable",
+        "no-transform",
+        "pre-check=0",
+        "post-check=0",
+    ];
+    (
+        subsequence(flags, 0..4),
+        proptest::option::of(delta_seconds()),
+        proptest::option::of(delta_seconds()),
+    )
+        .prop_map(|(flags, max_age, s_maxage)| {
+            let mut directives: Vec<String> = flags.into_iter().map(str::to_string).collect();
+            if let Some(max_age) = max_age {
+                directives.push(format!("max-age={}", max_age));
+            }
+            if let Some(s_maxage) = s_maxage {
+                directives.push(format!("s-maxage={}", s_maxage));
+            }
+            directives.join(", ")
+        })
+}
+
+/// `Cache-Control` values a request might carry.
+pub fn request_cache_control() -> impl Strategy<Value = String> {
+    let flags = vec!["no-cache", "no-store", "only-if-cached", "max-stale"];
+    (
+        subsequence(flags, 0..3),
+        proptest::option::of(delta_seconds()),
+        proptest::option::of(delta_seconds()),
+    )
+        .prop_map(|(flags, max_age, min_fresh)| {
+            let mut directives: Vec<String> = flags.into_iter().map(str::to_string).collect();
+            if let Some(max_age) = max_age {
+                directives.push(format!("max-age={}", max_age));
+            }
+            if let Some(min_fresh) = min_fresh {
+                directives.push(format!("min-fresh={}", min_fresh));
+            }
+            directives.join(", ")
+        })
+}
+
+/// Response headers that affect caching, each present or absent independently.
+pub fn response_headers() -> impl Strategy<Value = Vec<(String, String)>> {
+    (
+        proptest::option::of(response_cache_control()),
+        proptest::option::of(http_date_near_base()),
+        proptest::option::of(http_date_near_base()),
+        proptest::option::of(http_date_near_base()),
+        proptest::option::of(delta_seconds()),
+        proptest::option::of(prop_oneof![
+            Just("\"v1\""),
+            Just("W/\"v1\""),
+            Just("\"v2\"")
+        ]),
+        proptest::option::of(prop_oneof![
+            Just("accept-encoding"),
+            Just("accept-language, cookie"),
+            Just("*")
+        ]),
+        any::<bool>(),
+        any::<bool>(),
+    )
+        .prop_map(
+            |(cc, date, expires, last_modified, age, etag, vary, set_cookie, pragma)| {
+                let mut headers = Vec::new();
+                let mut push = |name: &str, value: Option<String>| {
+                    if let Some(value) = value {
+                        headers.push((name.to_string(), value));
+                    }
+                };
+                push("cache-control", cc);
+                push("date", date);
+                push("expires", expires);
+                push("last-modified", last_modified);
+                push("age", age);
+                push("etag", etag.map(str::to_string));
+                push("vary", vary.map(str::to_string));
+                push("set-cookie", Some("a=b".to_string()).filter(|_| set_cookie));
+                push("pragma", Some("no-cache".to_string()).filter(|_| pragma));
+                headers
+            },
+        )
+}
+
+/// Request headers that affect caching, each present or absent independently.
+pub fn request_headers() -> impl Strategy<Value = Vec<(String, String)>> {
+    (
+        proptest::option::of(request_cache_control()),
+        proptest::option::of(prop_oneof![Just("en"), Just("fr")]),
+        proptest::option::of(prop_oneof![Just("gzip"), Just("br")]),
+        any::<bool>(),
+    )
+        .prop_map(|(cc, language, encoding, authorization)| {
+            let mut headers = Vec::new();
+            if let Some(cc) = cc {
+                headers.push(("cache-control".to_string(), cc));
+            }
+            if let Some(language) = language {
+                headers.push(("accept-language".to_string(), language.to_string()));
+            }
+            if let Some(encoding) = encoding {
+                headers.push(("accept-encoding".to_string(), encoding.to_string()));
+            }
+            if authorization {
+                headers.push(("authorization".to_string(), "Bearer x".to_string()));
+            }
+            headers
+        })
+}
+
+/// Status codes with interesting caching behavior.
+pub fn status() -> impl Strategy<Value = u16> {
+    proptest::sample::select(vec![
+        200, 203, 204, 206, 301, 302, 304, 307, 308, 404, 410, 429, 500, 501, 503,
+    ])
+}
+
+/// Request methods with interesting caching behavior.
+pub fn method() -> impl Strategy<Value = http::Method> {
+    proptest::sample::select(vec![
+        http::Method::GET,
+        http::Method::HEAD,
+        http::Method::POST,
+        http::Method::PUT,
+    ])
+}
+
+/// Builds a request from generated headers.
+pub fn build_request(method: http::Method, headers: &[(String, String)]) -> http::Request<()> {
+    let mut builder = http::Request::builder()
+        .method(method)
+        .uri("http://example.com/resource");
+    for (name, value) in headers {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    builder.body(()).expect("generated request is valid")
+}
+
+/// Builds a response from a generated status and headers.
+pub fn build_response(status: u16, headers: &[(String, String)]) -> http::Response<()> {
+    let mut builder = http::Response::builder().status(status);
+    for (name, value) in headers {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    builder.body(()).expect("generated response is valid")
+}
+
+impl Arbitrary for CacheOptions {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (
+            any::<bool>(),
+            (0.0f32..1.0).prop_map(HeuristicFraction::clamped),
+            any::<bool>(),
+            proptest::option::of((0u64..1_000_000).prop_map(Duration::from_secs)),
+            any::<bool>(),
+            any::<bool>(),
+        )
+            .prop_map(
+                |(
+                    shared,
+                    cache_heuristic,
+                    ignore_cargo_cult,
+                    permanent_redirect_ttl,
+                    cache_retry_after,
+                    generate_warnings,
+                )| CacheOptions {
+                    shared,
+                    cache_heuristic,
+                    ignore_cargo_cult,
+                    permanent_redirect_ttl,
+                    cache_retry_after,
+                    generate_warnings,
+                    ..CacheOptions::default()
+                },
+            )
+            .boxed()
+    }
+}
+
+impl Arbitrary for CachePolicy {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    /// Policies for generated exchanges stored at `base_time()`.
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (
+            method(),
+            request_headers(),
+            status(),
+            response_headers(),
+            any::<CacheOptions>(),
+        )
+            .prop_map(|(method, req, status, res, options)| {
+                policy_for(
+                    &build_request(method, &req),
+                    &build_response(status, &res),
+                    &options,
+                    base_time(),
+                )
+            })
+            .boxed()
+    }
+}
+
+/// A response that can be served fresh must be storable in the first place.
+pub fn check_fresh_implies_storable(policy: &CachePolicy, now: SystemTime) -> Result<(), String> {
+    if !policy.stale(now) && !policy.storable() {
+        return Err(format!(
+            "fresh at {} but not storable: {}",
+            format_http_date(now),
+            policy
+        ));
+    }
+    Ok(())
+}
+
+/// Time to live never grows and age never shrinks as time passes.
+pub fn check_ttl_monotonic(
+    policy: &CachePolicy,
+    earlier: SystemTime,
+    later: SystemTime,
+) -> Result<(), String> {
+    let (earlier, later) = if earlier <= later {
+        (earlier, later)
+    } else {
+        (later, earlier)
+    };
+    if policy.time_to_live(later) > policy.time_to_live(earlier) {
+        return Err(format!(
+            "ttl grew from {} to {} between {} and {}",
+            policy.time_to_live(earlier),
+            policy.time_to_live(later),
+            format_http_date(earlier),
+            format_http_date(later)
+        ));
+    }
+    if policy.age(later) < policy.age(earlier) {
+        return Err(format!(
+            "age shrank between {} and {}",
+            format_http_date(earlier),
+            format_http_date(later)
+        ));
+    }
+    if policy.stale(earlier) && !policy.stale(later) {
+        return Err(format!(
+            "became fresh again between {} and {}",
+            format_http_date(earlier),
+            format_http_date(later)
+        ));
+    }
+    Ok(())
+}
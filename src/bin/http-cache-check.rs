@@ -0,0 +1,92 @@
+//! Fetches a URL and explains how a cache would treat the response.
+//!
+//! Usage: `http-cache-check [--private] URL`
+//!
+//! Enabled by the `cli` feature.
+
+use http::header::VARY;
+use http::Request;
+use http_cache_semantics::{cache_key_with, policy_for, CacheOptions};
+use std::env;
+use std::process;
+use std::time::SystemTime;
+
+fn usage() -> ! {
+    eprintln!("usage: http-cache-check [--private] URL");
+    process::exit(2);
+}
+
+fn main() {
+    let mut options = CacheOptions::default();
+    let mut url = None;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--private" => options.shared = false,
+            "-h" | "--help" => usage(),
+            _ if url.is_none() && !arg.starts_with('-') => url = Some(arg),
+            _ => usage(),
+        }
+    }
+    let url = url.unwrap_or_else(|| usage());
+
+    let request = match Request::get(url.as_str()).body(()) {
+        Ok(request) => request,
+        Err(err) => {
+            eprintln!("invalid URL {}: {}", url, err);
+            process::exit(2);
+        }
+    };
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .http_status_as_error(false)
+        .build()
+        .into();
+    let response = match agent.run(request.clone()) {
+        Ok(response) => response.map(|_| ()),
+        Err(err) => {
+            eprintln!("request failed: {}", err);
+            process::exit(1);
+        }
+    };
+    let now = SystemTime::now();
+    let policy = policy_for(&request, &response, &options, now);
+
+    println!("{} {}", request.method(), request.uri());
+    println!("status: {}", response.status());
+    println!("summary: {}", policy.summary(now));
+    match policy.unstorable_reason() {
+        None => println!("storable: yes"),
+        Some(reason) => println!("storable: no ({:?})", reason),
+    }
+    println!("ttl: {}s", policy.time_to_live(now));
+    for citation in policy.summary(now).citations() {
+        println!("see: {} <{}>", citation, citation.url());
+    }
+    println!("cache key: {}", cache_key_with(&request, &options));
+    let vary: Vec<&str> = response
+        .headers()
+        .get_all(VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|field| !field.is_empty())
+        .collect();
+    if vary.is_empty() {
+        println!("vary: none");
+    } else {
+        println!("vary: {}", vary.join(", "));
+    }
+    let revalidation_headers = policy.revalidation_headers(&request);
+    let validators: Vec<_> = ["if-none-match", "if-modified-since"]
+        .iter()
+        .filter_map(|name| Some((*name, revalidation_headers.get(*name)?)))
+        .collect();
+    if validators.is_empty() {
+        println!("revalidation: no validators, a full refetch is needed");
+    } else {
+        println!("revalidation headers:");
+        for (name, value) in validators {
+            println!("  {}: {}", name, String::from_utf8_lossy(value.as_bytes()));
+        }
+    }
+}
@@ -0,0 +1,439 @@
+//! Parsing and formatting of `Cache-Control` directives (RFC 9111 section 5.2).
+//!
+//! `CacheControlTokenizer` splits a header value without allocating, `CacheDirectives` is a
+//! typed view of the directives this crate understands, which `CachePolicy` stores, and
+//! `CacheControl` is the untyped map that `CachePolicyObject` stores. These are the building
+//! blocks the rest of the crate uses, and can be used on their own to inspect or rewrite
+//! `Cache-Control` headers.
+//! `RequestCacheControl` builds the directives a client sends.
+
+use http::header::{HeaderValue, CACHE_CONTROL};
+use http::Request;
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+/// Parsed `Cache-Control` directives. Directives without a value map to `None`.
+pub type CacheControl = HashMap<String, Option<String>>;
+
+/// Parses every value of a `Cache-Control` header into a map from lowercased directive names to
+/// their unquoted values. Values that aren't visible ASCII are skipped, and when a directive
+/// appears more than once, the last occurrence wins.
+pub fn parse_cache_control<'a>(values: impl IntoIterator<Item = &'a HeaderValue>) -> CacheControl {
+    let mut cc = CacheControl::new();
+    for value in values {
+        // TODO: When there is more than one value present for a given directive
+        // (e.g., two Expires header fields, multiple Cache-Control: max-age directives),
+        // the directive's value is considered invalid.
+        if value.to_str().is_err() {
+            continue;
+        }
+        for (name, value) in CacheControlTokenizer::new(value.as_bytes()) {
+            // Both slices are ASCII, since the whole header value is.
+            let name = String::from_utf8_lossy(name).to_ascii_lowercase();
+            let value = value.map(|v| String::from_utf8_lossy(v).into_owned());
+            cc.insert(name, value);
+        }
+    }
+    cc
+}
+
+/// Splits a `Cache-Control` header value into directive names and values, borrowing both
+/// from the input.
+///
+/// Empty list elements and surrounding whitespace are skipped, and quoted values may contain
+/// commas. Values are returned without their quotes but with any backslash escapes intact.
+/// Names keep their original case.
+#[derive(Debug, Clone)]
+pub struct CacheControlTokenizer<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> CacheControlTokenizer<'a> {
+    pub fn new(value: &'a [u8]) -> Self {
+        CacheControlTokenizer { rest: value }
+    }
+
+    /// Consumes input up to and including the next comma, returning what preceded it.
+    fn take_element(&mut self) -> &'a [u8] {
+        let end = self
+            .rest
+            .iter()
+            .position(|&b| b == b',')
+            .unwrap_or(self.rest.len());
+        let element = &self.rest[..end];
+        self.rest = self.rest.get(end + 1..).unwrap_or(&[]);
+        element
+    }
+}
+
+impl<'a> Iterator for CacheControlTokenizer<'a> {
+    type Item = (&'a [u8], Option<&'a [u8]>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.rest = self.rest.trim_ascii_start();
+            if self.rest.is_empty() {
+                return None;
+            }
+            let name_end = self
+                .rest
+                .iter()
+                .position(|&b| b == b'=' || b == b',')
+                .unwrap_or(self.rest.len());
+            let name = self.rest[..name_end].trim_ascii();
+            if self.rest.get(name_end) != Some(&b'=') {
+                self.take_element();
+                if name.is_empty() {
+                    continue;
+                }
+                return Some((name, None));
+            }
+
+            self.rest = self.rest[name_end + 1..].trim_ascii_start();
+            let value = if let Some(quoted) = self.rest.strip_prefix(b"\"") {
+                let mut end = 0;
+                while end < quoted.len() && quoted[end] != b'"' {
+                    end += if quoted[end] == b'\\' { 2 } else { 1 };
+                }
+                let end = cmp::min(end, quoted.len());
+                self.rest = quoted.get(end + 1..).unwrap_or(&[]);
+                // Anything between the closing quote and the next comma is ignored.
+                self.take_element();
+                &quoted[..end]
+            } else {
+                self.take_element().trim_ascii()
+            };
+            if name.is_empty() {
+                continue;
+            }
+            return Some((name, Some(value)));
+        }
+    }
+}
+
+pub(crate) fn seconds_from_bytes(value: &[u8]) -> u64 {
+    value
+        .iter()
+        .skip_while(|b| b.is_ascii_whitespace())
+        .take_while(|b| b.is_ascii_digit())
+        .fold(0u64, |n, &b| {
+            n.saturating_mul(10).saturating_add(u64::from(b - b'0'))
+        })
+}
+
+/// Typed view of the `Cache-Control` directives this crate understands.
+///
+/// Built directly on `CacheControlTokenizer`, so parsing doesn't allocate. When a directive
+/// appears more than once, the last occurrence wins. Invalid or missing delta-seconds count
+/// as 0.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct CacheDirectives {
+    pub max_age: Option<u64>,
+    pub s_maxage: Option<u64>,
+    pub min_fresh: Option<u64>,
+    /// `Some(None)` for a bare `max-stale`, which accepts a response of any staleness.
+    pub max_stale: Option<Option<u64>>,
+    pub stale_while_revalidate: Option<u64>,
+    pub stale_if_error: Option<u64>,
+    pub no_cache: bool,
+    pub no_store: bool,
+    pub no_transform: bool,
+    pub only_if_cached: bool,
+    pub must_revalidate: bool,
+    pub proxy_revalidate: bool,
+    pub must_understand: bool,
+    pub public: bool,
+    pub private: bool,
+    pub immutable: bool,
+}
+
+impl CacheDirectives {
+    /// Parses every value of a `Cache-Control` header.
+    pub fn parse<'a>(values: impl IntoIterator<Item = &'a HeaderValue>) -> Self {
+        let mut directives = CacheDirectives::default();
+        for value in values {
+            directives.add(value.as_bytes());
+        }
+        directives
+    }
+
+    /// Parses a single `Cache-Control` value.
+    pub fn from_bytes(value: &[u8]) -> Self {
+        let mut directives = CacheDirectives::default();
+        directives.add(value);
+        directives
+    }
+
+    /// Typed view of directives that have already been parsed into a map.
+    pub fn from_cache_control(cc: &CacheControl) -> Self {
+        let mut directives = CacheDirectives::default();
+        for (name, value) in cc {
+            directives.set(name.as_bytes(), value.as_ref().map(String::as_bytes));
+        }
+        directives
+    }
+
+    fn add(&mut self, value: &[u8]) {
+        for (name, value) in CacheControlTokenizer::new(value) {
+            self.set(name, value);
+        }
+    }
+
+    fn set(&mut self, name: &[u8], value: Option<&[u8]>) {
+        let seconds = || value.map_or(0, seconds_from_bytes);
+        let is = |directive: &str| name.eq_ignore_ascii_case(directive.as_bytes());
+        if is("max-age") {
+            self.max_age = Some(seconds());
+        } else if is("s-maxage") {
+            self.s_maxage = Some(seconds());
+        } else if is("min-fresh") {
+            self.min_fresh = Some(seconds());
+        } else if is("max-stale") {
+            self.max_stale = Some(value.map(seconds_from_bytes));
+        } else if is("stale-while-revalidate") {
+            self.stale_while_revalidate = Some(seconds());
+        } else if is("stale-if-error") {
+            self.stale_if_error = Some(seconds());
+        } else if is("no-cache") {
+            self.no_cache = true;
+        } else if is("no-store") {
+            self.no_store = true;
+        } else if is("no-transform") {
+            self.no_transform = true;
+        } else if is("only-if-cached") {
+            self.only_if_cached = true;
+        } else if is("must-revalidate") {
+            self.must_revalidate = true;
+        } else if is("proxy-revalidate") {
+            self.proxy_revalidate = true;
+        } else if is("must-understand") {
+            self.must_understand = true;
+        } else if is("public") {
+            self.public = true;
+        } else if is("private") {
+            self.private = true;
+        } else if is("immutable") {
+            self.immutable = true;
+        }
+    }
+}
+
+/// Builder for the `Cache-Control` directives a client sends with a request (RFC 9111 section
+/// 5.2.1). Its `Display` form is the header value, with directives in canonical order.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct RequestCacheControl {
+    max_age: Option<u64>,
+    min_fresh: Option<u64>,
+    max_stale: Option<Option<u64>>,
+    no_cache: bool,
+    only_if_cached: bool,
+}
+
+impl RequestCacheControl {
+    /// No directives.
+    pub fn new() -> Self {
+        RequestCacheControl::default()
+    }
+
+    /// `max-age`: don't accept a response older than `age`.
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age.as_secs());
+        self
+    }
+
+    /// `min-fresh`: only accept a response that stays fresh for at least `fresh` longer.
+    pub fn min_fresh(mut self, fresh: Duration) -> Self {
+        self.min_fresh = Some(fresh.as_secs());
+        self
+    }
+
+    /// `max-stale`: accept a response that has been stale for up to `stale`.
+    pub fn max_stale(mut self, stale: Duration) -> Self {
+        self.max_stale = Some(Some(stale.as_secs()));
+        self
+    }
+
+    /// A bare `max-stale`: accept a stale response however old it is.
+    pub fn any_stale(mut self) -> Self {
+        self.max_stale = Some(None);
+        self
+    }
+
+    /// `no-cache`: don't use a stored response without revalidating it.
+    pub fn no_cache(mut self) -> Self {
+        self.no_cache = true;
+        self
+    }
+
+    /// `only-if-cached`: answer from the cache or with a `504`, without contacting the
+    /// origin.
+    pub fn only_if_cached(mut self) -> Self {
+        self.only_if_cached = true;
+        self
+    }
+
+    /// The directives as the evaluation APIs will parse them.
+    pub fn directives(&self) -> CacheDirectives {
+        CacheDirectives {
+            max_age: self.max_age,
+            min_fresh: self.min_fresh,
+            max_stale: self.max_stale,
+            no_cache: self.no_cache,
+            only_if_cached: self.only_if_cached,
+            ..CacheDirectives::default()
+        }
+    }
+
+    /// The `Cache-Control` header value, or `None` if there are no directives.
+    pub fn to_header_value(&self) -> Option<HeaderValue> {
+        if *self == RequestCacheControl::default() {
+            return None;
+        }
+        // Only digits, tokens and separators are ever written.
+        HeaderValue::from_str(&self.to_string()).ok()
+    }
+
+    /// Replaces the `Cache-Control` header of `request` with these directives, or removes it
+    /// if there are none, ready to be passed to `CachePolicy::evaluate` and friends.
+    pub fn apply<B>(&self, request: &mut Request<B>) {
+        match self.to_header_value() {
+            Some(value) => {
+                request.headers_mut().insert(CACHE_CONTROL, value);
+            }
+            None => {
+                request.headers_mut().remove(CACHE_CONTROL);
+            }
+        }
+    }
+}
+
+impl fmt::Display for RequestCacheControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(max_age) = self.max_age {
+            parts.push(format!("max-age={}", max_age));
+        }
+        match self.max_stale {
+            Some(Some(max_stale)) => parts.push(format!("max-stale={}", max_stale)),
+            Some(None) => parts.push("max-stale".to_string()),
+            None => {}
+        }
+        if let Some(min_fresh) = self.min_fresh {
+            parts.push(format!("min-fresh={}", min_fresh));
+        }
+        if self.no_cache {
+            parts.push("no-cache".to_string());
+        }
+        if self.only_if_cached {
+            parts.push("only-if-cached".to_string());
+        }
+        f.write_str(&parts.join(", "))
+    }
+}
+
+/// Directives defined by RFC 9111 and its extensions, which `KnownOnly` rewriting keeps.
+const KNOWN_DIRECTIVES: [&str; 13] = [
+    "immutable",
+    "max-age",
+    "must-revalidate",
+    "must-understand",
+    "no-cache",
+    "no-store",
+    "no-transform",
+    "private",
+    "proxy-revalidate",
+    "public",
+    "s-maxage",
+    "stale-if-error",
+    "stale-while-revalidate",
+];
+
+fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Serializes directives in a canonical form: sorted by name, one entry per directive, and
+/// values quoted only where the syntax requires it. If `known_only` is set, directives this
+/// crate doesn't know about are left out.
+pub fn format_canonical_cache_control(cc: &CacheControl, known_only: bool) -> String {
+    let mut names: Vec<&String> = cc
+        .keys()
+        .filter(|name| !known_only || KNOWN_DIRECTIVES.contains(&name.as_str()))
+        .collect();
+    names.sort();
+    let parts: Vec<String> = names
+        .into_iter()
+        .map(|name| match &cc[name] {
+            Some(value) if is_token(value) => format!("{}={}", name, value),
+            Some(value) => format!(
+                "{}=\"{}\"",
+                name,
+                value.replace('\\', "\\\\").replace('"', "\\\"")
+            ),
+            None => name.clone(),
+        })
+        .collect();
+    parts.join(", ")
+}
+
+pub(crate) fn format_cache_control(cc: &CacheControl) -> String {
+    format_canonical_cache_control(cc, false)
+}
+
+/// Parses the leading digits of `value`. Values too large to represent saturate, as
+/// RFC 9111 section 1.2.2 requires of delta-seconds.
+pub(crate) fn to_number_or_zero(value: &str) -> u64 {
+    seconds_from_bytes(value.trim().as_bytes())
+}
+
+/// The directives `directives` understands, in map form.
+pub(crate) fn directives_to_cache_control(directives: &CacheDirectives) -> CacheControl {
+    let mut cc = CacheControl::new();
+    let seconds = [
+        ("max-age", directives.max_age),
+        ("s-maxage", directives.s_maxage),
+        ("min-fresh", directives.min_fresh),
+        ("stale-while-revalidate", directives.stale_while_revalidate),
+        ("stale-if-error", directives.stale_if_error),
+    ];
+    for (name, value) in &seconds {
+        if let Some(value) = value {
+            cc.insert(name.to_string(), Some(value.to_string()));
+        }
+    }
+    if let Some(max_stale) = directives.max_stale {
+        cc.insert("max-stale".to_string(), max_stale.map(|s| s.to_string()));
+    }
+    let flags = [
+        ("no-cache", directives.no_cache),
+        ("no-store", directives.no_store),
+        ("no-transform", directives.no_transform),
+        ("only-if-cached", directives.only_if_cached),
+        ("must-revalidate", directives.must_revalidate),
+        ("proxy-revalidate", directives.proxy_revalidate),
+        ("must-understand", directives.must_understand),
+        ("public", directives.public),
+        ("private", directives.private),
+        ("immutable", directives.immutable),
+    ];
+    for (name, set) in &flags {
+        if *set {
+            cc.insert(name.to_string(), None);
+        }
+    }
+    cc
+}
+
+#[cfg(feature = "headers")]
+impl From<&headers::CacheControl> for CacheDirectives {
+    fn from(cc: &headers::CacheControl) -> Self {
+        let mut values = Vec::new();
+        headers::Header::encode(cc, &mut values);
+        CacheDirectives::parse(&values)
+    }
+}
@@ -0,0 +1,41 @@
+//! The caching policy, split by concern into the submodules below. Their public items are
+//! re-exported from the crate root.
+
+use http::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCEPT, ACCEPT_LANGUAGE, ACCEPT_RANGES, AGE, ALT_SVC,
+    CACHE_CONTROL, CONNECTION, CONTENT_LANGUAGE, CONTENT_LENGTH, CONTENT_LOCATION, CONTENT_RANGE,
+    CONTENT_TYPE, DATE, ETAG, EXPIRES, HOST, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE,
+    IF_UNMODIFIED_SINCE, LAST_MODIFIED, LOCATION, PRAGMA, RANGE, RETRY_AFTER, SET_COOKIE,
+    TRANSFER_ENCODING, VARY, VIA, WARNING,
+};
+use http::uri::Authority;
+use http::{Method, Request, Response, StatusCode, Uri, Version};
+use std::borrow::Cow;
+use std::cmp;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+mod codec;
+mod freshness;
+mod policy;
+mod quirks;
+mod revalidation;
+mod serve;
+mod vary;
+
+pub use self::codec::*;
+pub use self::freshness::*;
+pub use self::policy::*;
+pub use self::quirks::*;
+pub use self::revalidation::*;
+pub use self::serve::*;
+pub use self::vary::*;
+use crate::directives::{
+    directives_to_cache_control, format_cache_control, format_canonical_cache_control,
+    parse_cache_control, seconds_from_bytes, to_number_or_zero, CacheControl,
+    CacheControlTokenizer, CacheDirectives,
+};
@@ -0,0 +1,550 @@
+//! Compact binary encoding of policies, for disk caches that persist many of them. See
+//! `PolicyEncoder`.
+
+use super::*;
+
+/// Writes policies to a stream in a compact binary form, for disk caches that persist
+/// millions of them. Header names, directive names, the values of headers such as
+/// `Content-Type` and `Vary` that are drawn from a small set, and other strings that recur
+/// are written once per stream and referred to after that, so a segment file of many
+/// policies written by one encoder shares a single dictionary. Options aren't written:
+/// they are usually the same for every entry, and storage hooks can't be saved anyway.
+///
+/// Read the stream back with a `PolicyDecoder`. The encoding is not `CachePolicyObject`'s,
+/// and is only guaranteed to be readable by the same version of this crate.
+#[derive(Debug)]
+pub struct PolicyEncoder<W> {
+    writer: W,
+    /// Index of each string written so far, in order of first appearance.
+    strings: HashMap<Vec<u8>, u64>,
+    started: bool,
+}
+
+/// Reads policies written by a `PolicyEncoder`.
+///
+/// Policies read from one decoder share what the stream only wrote once: custom header
+/// names and shared header values are reference-counted rather than copied into each
+/// policy, and all of them share the decoder's options. Loading a large cache through a
+/// single decoder therefore uses much less memory than decoding each entry separately.
+#[derive(Debug)]
+pub struct PolicyDecoder<R> {
+    reader: R,
+    /// Strings read so far, which later ones may refer to.
+    strings: Vec<Vec<u8>>,
+    /// Header names and shared values parsed from `strings`, by index, so that every
+    /// policy in the stream refers to one copy of each instead of allocating its own.
+    header_names: HashMap<usize, HeaderName>,
+    header_values: HashMap<usize, HeaderValue>,
+    started: bool,
+    /// Given to every policy read, without copying.
+    options: Arc<CacheOptions>,
+}
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+/// First byte of every stream, bumped whenever the encoding changes.
+const FORMAT_VERSION: u8 = 2;
+/// Precedes each policy, so that a reader can tell the end of the stream from a truncated one.
+const POLICY_TAG: u8 = 1;
+/// Most header fields a map is sized for before they are read, so that a corrupt count can't
+/// make the decoder allocate an enormous map up front.
+const MAX_PREALLOCATED_HEADERS: u64 = 256;
+
+/// Headers whose values are drawn from a small set, which are written once per stream like
+/// header names, instead of once per policy.
+const SHARED_VALUES: &[HeaderName] = &[
+    ACCEPT,
+    http::header::ACCEPT_ENCODING,
+    ACCEPT_LANGUAGE,
+    ACCEPT_RANGES,
+    CACHE_CONTROL,
+    http::header::CONTENT_ENCODING,
+    CONTENT_LANGUAGE,
+    CONTENT_TYPE,
+    http::header::SERVER,
+    TRANSFER_ENCODING,
+    VARY,
+];
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid cache policy: {}", what),
+    )
+}
+
+fn storage_override_code(storage_override: StorageOverride) -> u8 {
+    match storage_override {
+        StorageOverride::Default => 0,
+        StorageOverride::NeverStore => 1,
+        StorageOverride::AlwaysStore => 2,
+    }
+}
+
+fn storage_override_from_code(code: u8) -> Option<StorageOverride> {
+    match code {
+        0 => Some(StorageOverride::Default),
+        1 => Some(StorageOverride::NeverStore),
+        2 => Some(StorageOverride::AlwaysStore),
+        _ => None,
+    }
+}
+
+fn quirk_code(quirk: Quirk) -> u8 {
+    match quirk {
+        Quirk::ExpiresZero => 0,
+        Quirk::PrivateAndPublic => 1,
+        Quirk::MaxAgeTrailingGarbage => 2,
+        Quirk::VaryStarNoStore => 3,
+        Quirk::LowercaseDates => 4,
+    }
+}
+
+fn quirk_from_code(code: u8) -> Option<Quirk> {
+    match code {
+        0 => Some(Quirk::ExpiresZero),
+        1 => Some(Quirk::PrivateAndPublic),
+        2 => Some(Quirk::MaxAgeTrailingGarbage),
+        3 => Some(Quirk::VaryStarNoStore),
+        4 => Some(Quirk::LowercaseDates),
+        _ => None,
+    }
+}
+
+impl<W: Write> PolicyEncoder<W> {
+    /// Writes policies to `writer`, which should be buffered: fields are written one at a time.
+    pub fn new(writer: W) -> Self {
+        PolicyEncoder {
+            writer,
+            strings: HashMap::new(),
+            started: false,
+        }
+    }
+
+    /// Appends `policy` to the stream. Everything but its options and
+    /// `CachePolicy::response_instant` is kept.
+    pub fn write(&mut self, policy: &CachePolicy) -> io::Result<()> {
+        if !self.started {
+            self.writer.write_all(&[FORMAT_VERSION])?;
+            self.started = true;
+        }
+        self.writer.write_all(&[POLICY_TAG])?;
+        self.write_string(policy.method.as_str().as_bytes())?;
+        self.write_bytes(policy.uri.to_string().as_bytes())?;
+        self.write_option(policy.host.as_ref(), |this, host| {
+            this.write_bytes(host.as_bytes())
+        })?;
+        self.write_u64(u64::from(policy.status.as_u16()))?;
+        self.write_string(version_to_str(policy.version).as_bytes())?;
+        self.write_bool(policy.no_authorization)?;
+        self.write_headers(&policy.req)?;
+        self.write_headers(&policy.res)?;
+        self.write_directives(policy.reqcc.as_deref().unwrap_or(&NO_DIRECTIVES))?;
+        self.write_directives(&policy.rescc)?;
+        let since_epoch = policy
+            .response_time
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| invalid("response time before 1970"))?;
+        self.write_u64(since_epoch.as_secs())?;
+        self.write_u64(u64::from(since_epoch.subsec_nanos()))?;
+        self.write_option(policy.partition.as_ref(), |this, partition| {
+            this.write_bytes(partition.0.as_bytes())
+        })?;
+        self.writer
+            .write_all(&[storage_override_code(policy.storage_override)])?;
+        self.write_option(policy.path_ttl.as_ref(), |this, ttl| this.write_u64(*ttl))?;
+        self.write_bool(policy.pinned)?;
+        self.write_u64(policy.conflicting_headers.len() as u64)?;
+        for name in &policy.conflicting_headers {
+            self.write_string(name.as_str().as_bytes())?;
+        }
+        self.write_u64(policy.quirks.len() as u64)?;
+        for quirk in &policy.quirks {
+            self.writer.write_all(&[quirk_code(*quirk)])?;
+        }
+        self.write_option(policy.incomplete.as_ref(), |this, stored| {
+            this.write_u64(*stored)
+        })?;
+        self.write_option(policy.body_integrity.as_ref(), |this, integrity| {
+            this.write_string(integrity.algorithm.as_bytes())?;
+            this.write_bytes(&integrity.digest)?;
+            this.write_u64(integrity.length)
+        })
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_u64(&mut self, mut n: u64) -> io::Result<()> {
+        // LEB128: seven bits at a time, least significant first.
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                return self.writer.write_all(&[byte]);
+            }
+            self.writer.write_all(&[byte | 0x80])?;
+        }
+    }
+
+    fn write_bool(&mut self, value: bool) -> io::Result<()> {
+        self.writer.write_all(&[u8::from(value)])
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.write_u64(bytes.len() as u64)?;
+        self.writer.write_all(bytes)
+    }
+
+    /// Writes a string that is likely to recur, such as a header name, as a reference to its
+    /// first occurrence in the stream.
+    fn write_string(&mut self, string: &[u8]) -> io::Result<()> {
+        if let Some(&index) = self.strings.get(string) {
+            return self.write_u64(index + 1);
+        }
+        self.strings
+            .insert(string.to_vec(), self.strings.len() as u64);
+        self.write_u64(0)?;
+        self.write_bytes(string)
+    }
+
+    fn write_option<T>(
+        &mut self,
+        value: Option<&T>,
+        write: impl FnOnce(&mut Self, &T) -> io::Result<()>,
+    ) -> io::Result<()> {
+        self.write_bool(value.is_some())?;
+        match value {
+            Some(value) => write(self, value),
+            None => Ok(()),
+        }
+    }
+
+    fn write_headers(&mut self, headers: &HeaderMap) -> io::Result<()> {
+        self.write_u64(headers.len() as u64)?;
+        for (name, value) in headers {
+            self.write_string(name.as_str().as_bytes())?;
+            if SHARED_VALUES.contains(name) {
+                self.write_string(value.as_bytes())?;
+            } else {
+                self.write_bytes(value.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_directives(&mut self, directives: &CacheDirectives) -> io::Result<()> {
+        let flags = [
+            directives.no_cache,
+            directives.no_store,
+            directives.no_transform,
+            directives.only_if_cached,
+            directives.must_revalidate,
+            directives.proxy_revalidate,
+            directives.must_understand,
+            directives.public,
+            directives.private,
+            directives.immutable,
+        ];
+        let bits = flags
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (i, &set)| bits | u64::from(set) << i);
+        self.write_u64(bits)?;
+        for seconds in &[
+            directives.max_age,
+            directives.s_maxage,
+            directives.min_fresh,
+            directives.stale_while_revalidate,
+            directives.stale_if_error,
+        ] {
+            self.write_option(seconds.as_ref(), |this, seconds| this.write_u64(*seconds))?;
+        }
+        self.write_option(directives.max_stale.as_ref(), |this, max_stale| {
+            this.write_option(max_stale.as_ref(), |this, seconds| this.write_u64(*seconds))
+        })
+    }
+}
+
+impl<R: Read> PolicyDecoder<R> {
+    /// Reads policies written by a `PolicyEncoder` from `reader`, giving them `options`, which
+    /// should be those they were created with. Use `CachePolicy::with_options` to reclassify
+    /// them under others.
+    pub fn new(reader: R, options: CacheOptions) -> Self {
+        PolicyDecoder {
+            reader,
+            strings: Vec::new(),
+            header_names: HashMap::new(),
+            header_values: HashMap::new(),
+            started: false,
+            options: Arc::new(options),
+        }
+    }
+
+    /// Reads the next policy, or returns `None` at the end of the stream.
+    pub fn read(&mut self) -> io::Result<Option<CachePolicy>> {
+        if !self.started {
+            match self.read_byte_or_eof()? {
+                Some(FORMAT_VERSION) => self.started = true,
+                Some(_) => return Err(invalid("unsupported format version")),
+                None => return Ok(None),
+            }
+        }
+        match self.read_byte_or_eof()? {
+            Some(POLICY_TAG) => {}
+            Some(_) => return Err(invalid("bad record tag")),
+            None => return Ok(None),
+        }
+        let method = Method::from_bytes(&self.read_string()?).map_err(|_| invalid("method"))?;
+        let uri = Uri::from_maybe_shared(self.read_bytes()?).map_err(|_| invalid("URI"))?;
+        let host = self.read_option(|this| {
+            HeaderValue::from_bytes(&this.read_bytes()?).map_err(|_| invalid("Host"))
+        })?;
+        let status = u16::try_from(self.read_u64()?)
+            .ok()
+            .and_then(|status| StatusCode::from_u16(status).ok())
+            .ok_or_else(|| invalid("status"))?;
+        let version = std::str::from_utf8(&self.read_string()?)
+            .ok()
+            .and_then(version_from_str)
+            .ok_or_else(|| invalid("HTTP version"))?;
+        let no_authorization = self.read_bool()?;
+        let req = self.read_headers()?;
+        let res = self.read_headers()?;
+        let reqcc = boxed_directives(self.read_directives()?);
+        let rescc = self.read_directives()?;
+        let seconds = self.read_u64()?;
+        let nanos = u32::try_from(self.read_u64()?).map_err(|_| invalid("response time"))?;
+        let response_time = UNIX_EPOCH
+            .checked_add(Duration::new(seconds, nanos))
+            .ok_or_else(|| invalid("response time"))?;
+        let partition = self.read_option(|this| {
+            let partition = String::from_utf8(this.read_bytes()?);
+            partition
+                .map(CachePartition)
+                .map_err(|_| invalid("partition"))
+        })?;
+        let storage_override =
+            storage_override_from_code(self.read_byte()?).ok_or_else(|| invalid("override"))?;
+        let path_ttl = self.read_option(Self::read_u64)?;
+        let pinned = self.read_bool()?;
+        let mut conflicting_headers = Vec::new();
+        for _ in 0..self.read_u64()? {
+            conflicting_headers.push(self.read_header_name()?);
+        }
+        let mut quirks = Vec::new();
+        for _ in 0..self.read_u64()? {
+            quirks.push(quirk_from_code(self.read_byte()?).ok_or_else(|| invalid("quirk"))?);
+        }
+        let incomplete = self.read_option(Self::read_u64)?;
+        let body_integrity = self.read_option(|this| {
+            let algorithm =
+                String::from_utf8(this.read_string()?).map_err(|_| invalid("digest algorithm"))?;
+            let digest = this.read_bytes()?;
+            let length = this.read_u64()?;
+            Ok(BodyIntegrity {
+                algorithm,
+                digest,
+                length,
+            })
+        })?;
+        Ok(Some(CachePolicy {
+            req,
+            res,
+            uri,
+            host,
+            method,
+            status,
+            version,
+            no_authorization,
+            reqcc,
+            rescc,
+            opts: Arc::clone(&self.options),
+            response_time,
+            partition,
+            storage_override,
+            path_ttl,
+            pinned,
+            conflicting_headers,
+            quirks,
+            incomplete,
+            body_integrity,
+            response_instant: None,
+        }))
+    }
+
+    /// Returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    fn read_byte_or_eof(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        loop {
+            match self.reader.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0];
+        self.reader.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn read_u64(&mut self) -> io::Result<u64> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_byte()?;
+            n |= u64::from(byte & 0x7f)
+                .checked_shl(shift)
+                .filter(|bits| bits >> shift == u64::from(byte & 0x7f))
+                .ok_or_else(|| invalid("number too large"))?;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(invalid("number too large"))
+    }
+
+    fn read_bool(&mut self) -> io::Result<bool> {
+        match self.read_byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid("flag")),
+        }
+    }
+
+    fn read_bytes(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.read_u64()?;
+        // Read through `take` rather than allocating `len` up front, which a corrupt length
+        // could make enormous.
+        let mut bytes = Vec::new();
+        (&mut self.reader).take(len).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(bytes)
+    }
+
+    /// Reads a string written by `PolicyEncoder::write_string`, returning its index in
+    /// `strings`.
+    fn read_string_index(&mut self) -> io::Result<usize> {
+        match self.read_u64()? {
+            0 => {
+                let string = self.read_bytes()?;
+                self.strings.push(string);
+                Ok(self.strings.len() - 1)
+            }
+            index => usize::try_from(index - 1)
+                .ok()
+                .filter(|&index| index < self.strings.len())
+                .ok_or_else(|| invalid("string reference")),
+        }
+    }
+
+    fn read_string(&mut self) -> io::Result<Vec<u8>> {
+        let index = self.read_string_index()?;
+        Ok(self.strings[index].clone())
+    }
+
+    fn read_header_name(&mut self) -> io::Result<HeaderName> {
+        let index = self.read_string_index()?;
+        if let Some(name) = self.header_names.get(&index) {
+            return Ok(name.clone());
+        }
+        let name =
+            HeaderName::from_bytes(&self.strings[index]).map_err(|_| invalid("header name"))?;
+        self.header_names.insert(index, name.clone());
+        Ok(name)
+    }
+
+    fn read_header_value(&mut self, name: &HeaderName) -> io::Result<HeaderValue> {
+        if !SHARED_VALUES.contains(name) {
+            return HeaderValue::from_bytes(&self.read_bytes()?)
+                .map_err(|_| invalid("header value"));
+        }
+        let index = self.read_string_index()?;
+        if let Some(value) = self.header_values.get(&index) {
+            return Ok(value.clone());
+        }
+        let value =
+            HeaderValue::from_bytes(&self.strings[index]).map_err(|_| invalid("header value"))?;
+        self.header_values.insert(index, value.clone());
+        Ok(value)
+    }
+
+    fn read_option<T>(
+        &mut self,
+        read: impl FnOnce(&mut Self) -> io::Result<T>,
+    ) -> io::Result<Option<T>> {
+        if self.read_bool()? {
+            read(self).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn read_headers(&mut self) -> io::Result<HeaderMap> {
+        let count = self.read_u64()?;
+        // Sized up front: a map grown by inserting keeps room for fields it never gets.
+        let mut headers = HeaderMap::with_capacity(count.min(MAX_PREALLOCATED_HEADERS) as usize);
+        for _ in 0..count {
+            let name = self.read_header_name()?;
+            let value = self.read_header_value(&name)?;
+            headers.append(name, value);
+        }
+        Ok(headers)
+    }
+
+    fn read_directives(&mut self) -> io::Result<CacheDirectives> {
+        let bits = self.read_u64()?;
+        if bits >> 10 != 0 {
+            return Err(invalid("directive flags"));
+        }
+        let flag = |i: u32| bits & 1 << i != 0;
+        Ok(CacheDirectives {
+            no_cache: flag(0),
+            no_store: flag(1),
+            no_transform: flag(2),
+            only_if_cached: flag(3),
+            must_revalidate: flag(4),
+            proxy_revalidate: flag(5),
+            must_understand: flag(6),
+            public: flag(7),
+            private: flag(8),
+            immutable: flag(9),
+            max_age: self.read_option(Self::read_u64)?,
+            s_maxage: self.read_option(Self::read_u64)?,
+            min_fresh: self.read_option(Self::read_u64)?,
+            stale_while_revalidate: self.read_option(Self::read_u64)?,
+            stale_if_error: self.read_option(Self::read_u64)?,
+            max_stale: self.read_option(|this| this.read_option(Self::read_u64))?,
+        })
+    }
+}
+
+impl CachePolicy {
+    /// Writes the policy to `writer` in the compact binary form of `PolicyEncoder`, without
+    /// its options. To write many policies, use one `PolicyEncoder` for all of them instead,
+    /// so that header names are only written once.
+    pub fn serialize_into(&self, writer: &mut impl Write) -> io::Result<()> {
+        PolicyEncoder::new(writer).write(self)
+    }
+
+    /// Reads a policy written by `serialize_into`, giving it `options`.
+    pub fn deserialize_from(
+        reader: &mut impl Read,
+        options: &CacheOptions,
+    ) -> io::Result<CachePolicy> {
+        PolicyDecoder::new(reader, options.clone())
+            .read()?
+            .ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+    }
+}
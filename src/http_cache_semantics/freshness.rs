@@ -0,0 +1,944 @@
+//! HTTP dates, age and freshness lifetime calculations (RFC 9111 section 4.2), and the
+//! eviction estimates built on them.
+
+use super::*;
+
+/// Servers whose `Date` is further than this from our own clock are ignored.
+const MAX_CLOCK_DRIFT: u64 = 8 * 3600;
+
+pub(crate) fn parse_http_date(value: &HeaderValue) -> Option<SystemTime> {
+    parse_http_date_with_quality(value).0
+}
+
+/// How faithfully a date header could be interpreted. See `CachePolicy::date_quality`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DateQuality {
+    /// A valid HTTP date from 1970 on.
+    Exact,
+    /// A valid date before 1970, which no real clock produces.
+    PreEpoch,
+    /// A date after the year 9999, taken as the last second of 9999.
+    Clamped,
+    /// Not a date at all. An invalid `Expires` means the response has already expired.
+    Invalid,
+}
+
+pub(super) fn parse_http_date_with_quality(
+    value: &HeaderValue,
+) -> (Option<SystemTime>, DateQuality) {
+    let value = match value.to_str() {
+        Ok(value) => value.trim(),
+        Err(_) => return (None, DateQuality::Invalid),
+    };
+    if let Ok(date) = httpdate::parse_http_date(value) {
+        (Some(date), DateQuality::Exact)
+    } else if let Some(date) = parse_pre_epoch_http_date(value) {
+        (Some(date), DateQuality::PreEpoch)
+    } else if is_far_future_http_date(value) {
+        (Some(UNIX_EPOCH + LATEST_HTTP_DATE), DateQuality::Clamped)
+    } else {
+        (None, DateQuality::Invalid)
+    }
+}
+
+/// Recognizes IMF-fixdates whose year has more than four digits, which `httpdate` rejects.
+fn is_far_future_http_date(value: &str) -> bool {
+    let mut parts = value.split_whitespace();
+    let year = parts.nth(3).unwrap_or("");
+    year.len() > 4
+        && !year.starts_with('0')
+        && year.bytes().all(|b| b.is_ascii_digit())
+        && parts.next().is_some_and(|time| time.len() == 8)
+        && parts.next() == Some("GMT")
+        && parts.next().is_none()
+}
+
+/// Length of the 400-year cycle after which the Gregorian calendar, weekdays included,
+/// repeats.
+const GREGORIAN_CYCLE: Duration = Duration::from_secs(146_097 * 86_400);
+
+/// `httpdate` only handles years from 1970 on. Earlier IMF-fixdates are shifted forward by
+/// whole Gregorian cycles, parsed, and shifted back, so that they're still recognized as
+/// dates before the epoch rather than ignored as garbage.
+fn parse_pre_epoch_http_date(value: &str) -> Option<SystemTime> {
+    // The year of "Wed, 31 Dec 1969 23:59:00 GMT" is always at bytes 12 to 16.
+    let year: u32 = value.get(12..16)?.parse().ok()?;
+    if year >= 1970 {
+        return None;
+    }
+    let cycles = (1970 - year).div_ceil(400);
+    let shifted = format!("{}{}{}", &value[..12], year + 400 * cycles, &value[16..]);
+    httpdate::parse_http_date(&shifted)
+        .ok()?
+        .checked_sub(GREGORIAN_CYCLE * cycles)
+}
+
+/// Last moment an HTTP date can represent, at the end of the year 9999.
+const LATEST_HTTP_DATE: Duration = Duration::from_secs(253_402_300_799);
+
+/// Formats `date` as an HTTP date, clamped to the range HTTP dates can represent.
+pub(crate) fn format_http_date(date: SystemTime) -> String {
+    let date = cmp::min(cmp::max(date, UNIX_EPOCH), UNIX_EPOCH + LATEST_HTTP_DATE);
+    httpdate::fmt_http_date(date)
+}
+
+/// Picks one of the values of all `Age` headers, including values joined with commas.
+pub(super) fn select_age(headers: &HeaderMap, selection: AgeSelection) -> Option<u64> {
+    let mut ages = headers
+        .get_all(AGE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter(|age| !age.trim().is_empty())
+        .map(to_number_or_zero);
+    match selection {
+        AgeSelection::Max => ages.max(),
+        AgeSelection::First => ages.next(),
+    }
+}
+
+pub(super) fn seconds_between(earlier: SystemTime, later: SystemTime) -> u64 {
+    later
+        .duration_since(earlier)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// A fraction between 0 and 1, such as `CacheOptions::cache_heuristic`.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct HeuristicFraction(pub(super) f32);
+
+impl HeuristicFraction {
+    /// Returns `None` unless `fraction` is between 0 and 1 inclusive.
+    pub fn new(fraction: f32) -> Option<Self> {
+        if (0.0..=1.0).contains(&fraction) {
+            // Adding zero turns -0.0 into 0.0, so that equal fractions hash alike.
+            Some(HeuristicFraction(fraction + 0.0))
+        } else {
+            None
+        }
+    }
+
+    /// Like `new`, but clamps `fraction` between 0 and 1, treating NaN as 0.
+    pub fn clamped(fraction: f32) -> Self {
+        HeuristicFraction::new(fraction.clamp(0.0, 1.0)).unwrap_or(HeuristicFraction(0.0))
+    }
+
+    /// The fraction as a plain number.
+    pub fn get(self) -> f32 {
+        self.0
+    }
+}
+
+// NaN is never stored, so equality is reflexive and agrees with comparing bits.
+impl Eq for HeuristicFraction {}
+
+impl Hash for HeuristicFraction {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for HeuristicFraction {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f32(self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for HeuristicFraction {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let fraction = f32::deserialize(deserializer)?;
+        HeuristicFraction::new(fraction)
+            .ok_or_else(|| serde::de::Error::custom(format!("{} is not between 0 and 1", fraction)))
+    }
+}
+
+/// Freshness settings for responses whose `Content-Type` matches `pattern`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ContentTypeRule {
+    /// A media type such as `text/html`, a whole top-level type such as `image/*`, or
+    /// `*/*`. Matched case-insensitively, ignoring parameters.
+    pub pattern: String,
+    /// Replaces `CacheOptions::cache_heuristic` when set.
+    pub cache_heuristic: Option<HeuristicFraction>,
+    /// Replaces `CacheOptions::immutable_min_time_to_live` when set.
+    pub immutable_min_time_to_live: Option<Duration>,
+}
+
+impl ContentTypeRule {
+    fn matches(&self, media_type: &str) -> bool {
+        let pattern = self.pattern.trim();
+        if pattern == "*/*" {
+            return true;
+        }
+        match pattern.strip_suffix("/*") {
+            Some(top_level) => media_type
+                .split('/')
+                .next()
+                .is_some_and(|t| t.eq_ignore_ascii_case(top_level)),
+            None => media_type.eq_ignore_ascii_case(pattern),
+        }
+    }
+}
+
+/// How a response whose `Expires` predates its `Date` is treated. See
+/// `CacheOptions::past_expires`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PastExpires {
+    /// Store it, already stale: its freshness lifetime is zero.
+    Stale,
+    /// Store it, fresh for this long, to absorb clock skew between the servers that set
+    /// `Date` and `Expires`.
+    Clamp(Duration),
+    /// Don't store it at all.
+    Unstorable,
+}
+
+/// Parsed value of a `Retry-After` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryAfter {
+    /// Number of seconds to wait before retrying.
+    Delay(u64),
+    /// Time after which to retry.
+    Date(SystemTime),
+}
+
+/// A change in how a stored response can be used. See `CachePolicy::next_event`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CacheEventKind {
+    /// The response becomes stale, and needs revalidating unless served stale.
+    Stale,
+    /// `CacheOptions::grace` runs out.
+    GraceEnds,
+    /// The `stale-while-revalidate` window, extended by any grace, runs out.
+    StaleWhileRevalidateEnds,
+    /// The response can be evicted, as for `CachePolicy::time_to_evict`.
+    Evict,
+}
+
+/// What a policy's freshness lifetime was derived from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FreshnessSource {
+    /// The response isn't storable, so it's never fresh.
+    NotStorable,
+    /// The response's `no-cache` directive.
+    NoCache,
+    /// A `Set-Cookie` header without `public` or `immutable`, in a shared cache or under
+    /// `CacheOptions::private_set_cookie_ttl`.
+    SetCookie,
+    /// `Vary: *`.
+    VaryStar,
+    /// The response's `proxy-revalidate` directive, in a shared cache.
+    ProxyRevalidate,
+    /// The response's `s-maxage` directive, in a shared cache.
+    SMaxAge,
+    /// The response's `max-age` directive.
+    MaxAge,
+    /// The `Expires` header.
+    Expires,
+    /// `CacheOptions::permanent_redirect_ttl`.
+    PermanentRedirect,
+    /// The `Retry-After` header, with `CacheOptions::cache_retry_after`.
+    RetryAfter,
+    /// A fraction of the time since `Last-Modified`.
+    Heuristic,
+    /// `CacheOptions::immutable_min_time_to_live`, for an `immutable` response.
+    Immutable,
+    /// A `PathRule` with a time to live.
+    PathRule,
+    /// The policy is pinned, and never goes stale.
+    Pinned,
+    /// `CacheOptions::always_revalidate`.
+    AlwaysRevalidate,
+    /// Nothing: the response has no freshness information.
+    None,
+}
+
+impl FreshnessSource {
+    /// Short name for logs, such as `max-age` or `heuristic`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FreshnessSource::NotStorable => "not-storable",
+            FreshnessSource::NoCache => "no-cache",
+            FreshnessSource::SetCookie => "set-cookie",
+            FreshnessSource::VaryStar => "vary-star",
+            FreshnessSource::ProxyRevalidate => "proxy-revalidate",
+            FreshnessSource::SMaxAge => "s-maxage",
+            FreshnessSource::MaxAge => "max-age",
+            FreshnessSource::Expires => "expires",
+            FreshnessSource::PermanentRedirect => "permanent-redirect",
+            FreshnessSource::RetryAfter => "retry-after",
+            FreshnessSource::Heuristic => "heuristic",
+            FreshnessSource::Immutable => "immutable",
+            FreshnessSource::PathRule => "path-rule",
+            FreshnessSource::Pinned => "pinned",
+            FreshnessSource::AlwaysRevalidate => "always-revalidate",
+            FreshnessSource::None => "none",
+        }
+    }
+
+    /// The RFC section behind this source, or `None` for this crate's own policies.
+    pub(super) fn citation(self) -> Option<RfcCitation> {
+        match self {
+            FreshnessSource::NoCache => Some(NO_CACHE),
+            FreshnessSource::VaryStar => Some(VARY_KEYS),
+            FreshnessSource::ProxyRevalidate => Some(PROXY_REVALIDATE),
+            FreshnessSource::SMaxAge => Some(S_MAXAGE),
+            FreshnessSource::MaxAge => Some(MAX_AGE),
+            FreshnessSource::Expires => Some(EXPIRES_FIELD),
+            FreshnessSource::PermanentRedirect | FreshnessSource::Heuristic => {
+                Some(HEURISTIC_FRESHNESS)
+            }
+            FreshnessSource::RetryAfter => Some(RETRY_AFTER_FIELD),
+            FreshnessSource::Immutable => Some(IMMUTABLE),
+            FreshnessSource::NotStorable
+            | FreshnessSource::SetCookie
+            | FreshnessSource::PathRule
+            | FreshnessSource::Pinned
+            | FreshnessSource::AlwaysRevalidate
+            | FreshnessSource::None => None,
+        }
+    }
+}
+
+/// Everything `compute_freshness` needs to know about a stored response, already parsed and
+/// with the applicable options resolved. See `CachePolicy::freshness_inputs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FreshnessInputs {
+    /// Whether the policy is for a shared cache, in which `s-maxage` applies.
+    pub shared: bool,
+    /// `CacheOptions::chain_hop`, which only matters if `shared` is set.
+    pub chain_hop: bool,
+    /// `CacheOptions::always_revalidate`.
+    pub always_revalidate: bool,
+    /// The lifetime set by a matching `CacheOptions::path_rules` entry.
+    pub path_ttl: Option<u64>,
+    /// The response's `s-maxage` directive, even if a private cache ignores it.
+    pub s_maxage: Option<u64>,
+    /// The response's `max-age` directive.
+    pub max_age: Option<u64>,
+    /// The response's `no-cache` directive.
+    pub no_cache: bool,
+    /// The response's `must-revalidate` directive, which exempts it from `min_ttl`.
+    pub must_revalidate: bool,
+    /// The response's `proxy-revalidate` directive.
+    pub proxy_revalidate: bool,
+    /// The response's `public` directive.
+    pub public: bool,
+    /// The response's `immutable` directive.
+    pub immutable: bool,
+    /// The response sets a cookie.
+    pub set_cookie: bool,
+    /// `CacheOptions::private_set_cookie_ttl` in seconds.
+    pub private_set_cookie_ttl: Option<u64>,
+    /// The response has `Vary: *`.
+    pub vary_star: bool,
+    /// The response's `Expires` header, or `Some(None)` if it's present but invalid.
+    pub expires: Option<Option<SystemTime>>,
+    /// The response's `Last-Modified` header, or `None` if it's missing or invalid.
+    pub last_modified: Option<SystemTime>,
+    /// The `Date` that `Expires` and heuristics are measured from.
+    pub date: SystemTime,
+    /// Current age of the response in seconds.
+    pub age: u64,
+    /// `CacheOptions::permanent_redirect_ttl` in seconds, if the response is a permanent
+    /// redirect.
+    pub permanent_redirect_ttl: Option<u64>,
+    /// Seconds until the `Retry-After` time, if `CacheOptions::cache_retry_after` applies.
+    pub retry_after: Option<u64>,
+    /// `CacheOptions::past_expires`.
+    pub past_expires: PastExpires,
+    /// `CacheOptions::min_ttl` in seconds.
+    pub min_ttl: Option<u64>,
+    /// Whether heuristic freshness is ruled out, by `CacheOptions::require_explicit_freshness`
+    /// or `CacheOptions::heuristic_statuses`.
+    pub require_explicit_freshness: bool,
+    /// The heuristic fraction for the response's content type.
+    pub cache_heuristic: HeuristicFraction,
+    /// The minimum lifetime of `immutable` responses, for the response's content type.
+    pub immutable_min_time_to_live: Duration,
+}
+
+/// The outcome of `compute_freshness`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FreshnessResult {
+    /// The freshness lifetime in seconds.
+    pub lifetime: u64,
+    /// What the lifetime was taken from.
+    pub source: FreshnessSource,
+    /// Seconds until the response becomes stale, given `FreshnessInputs::age`.
+    pub time_to_live: u64,
+    /// The response's age has reached its freshness lifetime.
+    pub stale: bool,
+}
+
+/// What `CachePolicy::satisfies_without_revalidation` needs to know about the stored
+/// response at a given time.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct FreshnessAt {
+    pub(super) age: u64,
+    pub(super) max_age: u64,
+    pub(super) must_revalidate: bool,
+    pub(super) is_prefetch: bool,
+}
+
+/// Circumstances of a single lookup that change how strictly the stored response is judged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct EvaluationFlags {
+    /// The request is a prefetch or other speculative load. Prefetches may populate the
+    /// cache, but are only answered with fresh responses: they never get a stale response,
+    /// even when the request's `max-stale` would allow it.
+    pub is_prefetch: bool,
+    /// The origin can't be reached, so revalidation would fail. Stale responses may then be
+    /// served according to `CacheOptions::disconnected`.
+    pub origin_unreachable: bool,
+}
+
+/// Computes a freshness lifetime from already-parsed inputs, following RFC 9111 section 4.2.1
+/// and this crate's options. `CachePolicy` uses this for every storable response that isn't
+/// pinned, so `compute_freshness(policy.freshness_inputs(now))` agrees with the policy.
+pub fn compute_freshness(inputs: FreshnessInputs) -> FreshnessResult {
+    let (mut lifetime, mut source) = local_freshness_lifetime(&inputs);
+    if let Some(floor) = inputs.min_ttl {
+        let explicit = matches!(
+            source,
+            FreshnessSource::SMaxAge | FreshnessSource::MaxAge | FreshnessSource::Expires
+        );
+        if explicit && !inputs.must_revalidate {
+            lifetime = cmp::max(lifetime, floor);
+        }
+    }
+    if let Some(cap) = inputs.private_set_cookie_ttl {
+        if !inputs.shared
+            && inputs.set_cookie
+            && !inputs.public
+            && !inputs.immutable
+            && cap < lifetime
+        {
+            lifetime = cap;
+            source = FreshnessSource::SetCookie;
+        }
+    }
+    if inputs.shared && inputs.chain_hop {
+        if let Some(grant) = upstream_grant(&inputs) {
+            lifetime = cmp::min(lifetime, grant);
+        }
+    }
+    FreshnessResult {
+        lifetime,
+        source,
+        time_to_live: lifetime.saturating_sub(inputs.age),
+        stale: lifetime <= inputs.age,
+    }
+}
+
+/// The freshness lifetime the upstream explicitly granted, if any, before any local rules.
+fn upstream_grant(inputs: &FreshnessInputs) -> Option<u64> {
+    inputs.s_maxage.or(inputs.max_age).or_else(|| {
+        Some(
+            inputs
+                .expires?
+                .map_or(0, |expires| seconds_between(inputs.date, expires)),
+        )
+    })
+}
+
+/// Freshness lifetime along with what determined it, before `CacheOptions::chain_hop` caps it.
+fn local_freshness_lifetime(inputs: &FreshnessInputs) -> (u64, FreshnessSource) {
+    if inputs.always_revalidate {
+        return (0, FreshnessSource::AlwaysRevalidate);
+    }
+    if let Some(ttl) = inputs.path_ttl {
+        return (ttl, FreshnessSource::PathRule);
+    }
+    if inputs.no_cache {
+        return (0, FreshnessSource::NoCache);
+    }
+
+    // Shared responses with cookies are cacheable according to the RFC, but IMHO it'd
+    // be unwise to do so by default, so this implementation requires explicit opt-in
+    // via public header
+    if inputs.shared && inputs.set_cookie && !inputs.public && !inputs.immutable {
+        return (0, FreshnessSource::SetCookie);
+    }
+
+    if inputs.vary_star {
+        return (0, FreshnessSource::VaryStar);
+    }
+
+    if inputs.shared {
+        if inputs.proxy_revalidate && !inputs.chain_hop {
+            return (0, FreshnessSource::ProxyRevalidate);
+        }
+        // if a response includes the s-maxage directive, a shared cache recipient MUST
+        // ignore the Expires field.
+        if let Some(s_maxage) = inputs.s_maxage {
+            return (s_maxage, FreshnessSource::SMaxAge);
+        }
+    }
+
+    // If a response includes a Cache-Control field with the max-age directive, a
+    // recipient MUST ignore the Expires field.
+    if let Some(max_age) = inputs.max_age {
+        return (max_age, FreshnessSource::MaxAge);
+    }
+
+    let default_min_ttl = if inputs.immutable {
+        inputs.immutable_min_time_to_live.as_secs()
+    } else {
+        0
+    };
+
+    let server_date = inputs.date;
+    if let Some(expires) = inputs.expires {
+        // A cache recipient MUST interpret invalid date formats, especially the value
+        // "0", as representing a time in the past (i.e., "already expired").
+        let ttl = match expires {
+            Some(expires) if expires >= server_date => {
+                cmp::max(default_min_ttl, seconds_between(server_date, expires))
+            }
+            Some(_) => match inputs.past_expires {
+                PastExpires::Clamp(ttl) => ttl.as_secs(),
+                PastExpires::Stale | PastExpires::Unstorable => 0,
+            },
+            None => 0,
+        };
+        return (ttl, FreshnessSource::Expires);
+    }
+
+    if let Some(ttl) = inputs.permanent_redirect_ttl {
+        return (
+            cmp::max(default_min_ttl, ttl),
+            FreshnessSource::PermanentRedirect,
+        );
+    }
+
+    if let Some(ttl) = inputs.retry_after {
+        return (cmp::max(default_min_ttl, ttl), FreshnessSource::RetryAfter);
+    }
+
+    if let Some(last_modified) = inputs
+        .last_modified
+        .filter(|_| !inputs.require_explicit_freshness)
+    {
+        if server_date > last_modified {
+            let heuristic = seconds_between(last_modified, server_date) as f64
+                * f64::from(inputs.cache_heuristic.get());
+            return (
+                cmp::max(default_min_ttl, heuristic as u64),
+                FreshnessSource::Heuristic,
+            );
+        }
+    }
+
+    if default_min_ttl > 0 {
+        (default_min_ttl, FreshnessSource::Immutable)
+    } else {
+        (0, FreshnessSource::None)
+    }
+}
+
+impl CachePolicy {
+    /// The RFC section behind the response's freshness lifetime, or `None` if it comes from
+    /// this crate's options rather than the RFCs.
+    pub fn freshness_citation(&self) -> Option<RfcCitation> {
+        match self.freshness_lifetime().1 {
+            FreshnessSource::NotStorable => self.unstorable_citation(),
+            source => source.citation(),
+        }
+    }
+
+    pub(super) fn freshness_at(&self, now: SystemTime, flags: EvaluationFlags) -> FreshnessAt {
+        FreshnessAt {
+            age: self.age(now),
+            max_age: self.max_age(),
+            must_revalidate: self.rescc.must_revalidate
+                || self.opts.always_revalidate
+                || (self.in_chain() && self.rescc.proxy_revalidate)
+                || self.has_shared_max_age(),
+            is_prefetch: flags.is_prefetch,
+        }
+    }
+
+    /// Value of the `Date` header, or the time the response was received if the header is
+    /// missing, invalid, or too far from our own clock to be trusted.
+    pub fn date(&self) -> SystemTime {
+        self.view().date()
+    }
+
+    pub(super) fn server_date(&self) -> Option<SystemTime> {
+        self.view().server_date()
+    }
+
+    /// Current age of the response in seconds, including the time it has spent in the cache.
+    pub fn age(&self, now: SystemTime) -> u64 {
+        self.view().age(now)
+    }
+
+    /// Records the monotonic time at which the response was received, the same moment as the
+    /// `response_time` it was created with, so that `now_at` can measure how long it has been
+    /// stored without trusting the wall clock. It isn't saved by `to_object`, since instants
+    /// mean nothing in another process, and policies updated by revalidation start without one.
+    pub fn with_response_instant(mut self, instant: Instant) -> CachePolicy {
+        self.response_instant = Some(instant);
+        self
+    }
+
+    /// The instant recorded with `with_response_instant`, if any.
+    pub fn response_instant(&self) -> Option<Instant> {
+        self.response_instant
+    }
+
+    /// The time to pass as `now` at the monotonic time `instant`: `response_time` plus the
+    /// time elapsed since the instant recorded with `with_response_instant`, so that stepping
+    /// the system clock doesn't change the response's resident time. Header-derived values
+    /// such as `Date` and `Expires` are still compared on the wall clock. Falls back to
+    /// `SystemTime::now()` if no instant was recorded.
+    pub fn now_at(&self, instant: Instant) -> SystemTime {
+        match self.response_instant {
+            Some(received) => self.response_time + instant.saturating_duration_since(received),
+            None => SystemTime::now(),
+        }
+    }
+
+    /// The `Age` value the policy uses, chosen by `CacheOptions::age_selection`, or `None`
+    /// if the response has no `Age` header.
+    pub fn age_header_value(&self) -> Option<u64> {
+        self.view().age_header_value()
+    }
+
+    /// Returns true if the response's freshness lifetime was estimated from `Last-Modified`
+    /// (RFC 9111 section 4.2.2) rather than given by the origin or the options.
+    pub fn is_heuristic(&self) -> bool {
+        self.freshness_lifetime().1 == FreshnessSource::Heuristic
+    }
+
+    /// Number of seconds the response is fresh for, counted from when it was generated.
+    pub fn max_age(&self) -> u64 {
+        self.freshness_lifetime().0
+    }
+
+    /// Reports the values the freshness lifetime is computed from at `now`, e.g. to find out
+    /// why a shared cache keeps a response for a different time than a browser. Pass them to
+    /// `compute_freshness` to see which one won.
+    pub fn freshness_inputs(&self, now: SystemTime) -> FreshnessInputs {
+        self.freshness_inputs_with_age(self.age(now))
+    }
+
+    fn freshness_inputs_with_age(&self, age: u64) -> FreshnessInputs {
+        self.view().freshness_inputs_with_age(age)
+    }
+
+    pub(super) fn in_chain(&self) -> bool {
+        self.opts.shared && self.opts.chain_hop
+    }
+
+    /// Freshness lifetime along with what determined it.
+    pub(super) fn freshness_lifetime(&self) -> (u64, FreshnessSource) {
+        self.view().freshness_lifetime()
+    }
+
+    /// Number of seconds from `now` until the response becomes stale.
+    pub fn time_to_live(&self, now: SystemTime) -> u64 {
+        self.max_age().saturating_sub(self.age(now))
+    }
+
+    /// Returns true if the response is stale at `now` and must be revalidated before use.
+    pub fn stale(&self, now: SystemTime) -> bool {
+        self.max_age() <= self.age(now)
+    }
+
+    /// Number of seconds from `now` until the response can no longer be served stale under
+    /// `CacheOptions::grace`. Equal to `time_to_live` for responses that get no grace.
+    pub fn time_to_grace_end(&self, now: SystemTime) -> u64 {
+        self.max_age()
+            .saturating_add(self.grace_seconds())
+            .saturating_sub(self.age(now))
+    }
+
+    pub(super) fn grace_seconds(&self) -> u64 {
+        if self.storable() && !self.must_revalidate_when_stale() {
+            self.opts.grace.as_secs()
+        } else {
+            0
+        }
+    }
+
+    /// Seconds of the origin's `stale-while-revalidate` window, or zero if stale use is
+    /// forbidden anyway.
+    fn stale_while_revalidate_seconds(&self) -> u64 {
+        if self.must_revalidate_before_reuse() {
+            0
+        } else {
+            self.rescc.stale_while_revalidate.unwrap_or(0)
+        }
+    }
+
+    /// Number of seconds from `now` until the stored response is worthless, even as a source
+    /// of validators, and can be evicted. The `CacheOptions::keep` window starts when the
+    /// grace period and any `stale-while-revalidate` window end, and only counts for responses
+    /// with an `ETag` or `Last-Modified`. Zero if the response isn't storable at all.
+    pub fn time_to_evict(&self, now: SystemTime) -> u64 {
+        if !self.storable() {
+            return 0;
+        }
+        let keep = if self.has_validators() {
+            self.opts.keep.as_secs()
+        } else {
+            0
+        };
+        self.max_age()
+            .saturating_add(self.grace_seconds())
+            .saturating_add(self.stale_while_revalidate_seconds())
+            .saturating_add(keep)
+            .saturating_sub(self.age(now))
+    }
+
+    /// The next change after `now` in how the stored response can be used, and when it
+    /// happens, so that a cache can set a timer rather than poll `time_to_live`. When several
+    /// happen at once, the last of them in `CacheEventKind` order is reported. `None` once the
+    /// response can be evicted, or if nothing will ever change, as for pinned policies.
+    pub fn next_event(&self, now: SystemTime) -> Option<(CacheEventKind, SystemTime)> {
+        if !self.storable() {
+            return None;
+        }
+        let age = self.age(now);
+        let stale = self.max_age();
+        let grace_end = stale.saturating_add(self.grace_seconds());
+        let window = self.stale_while_revalidate_seconds();
+        let events = [
+            (CacheEventKind::Stale, stale),
+            (CacheEventKind::GraceEnds, grace_end),
+            (
+                CacheEventKind::StaleWhileRevalidateEnds,
+                grace_end.saturating_add(window),
+            ),
+            (
+                CacheEventKind::Evict,
+                age.saturating_add(self.time_to_evict(now)),
+            ),
+        ];
+        let (kind, at) = events
+            .iter()
+            .filter(|&&(_, at)| at > age && at != u64::MAX)
+            .fold(
+                None,
+                |next: Option<(CacheEventKind, u64)>, &(kind, at)| match next {
+                    Some((_, earliest)) if earliest < at => next,
+                    _ => Some((kind, at)),
+                },
+            )?;
+        let time = now.checked_add(Duration::from_secs(at - age))?;
+        Some((kind, time))
+    }
+
+    /// Priority for keeping the stored response when the cache is full: higher is more worth
+    /// keeping, and zero means it can go. Pinned policies score infinity.
+    ///
+    /// The score grows with the logarithm of `time_to_evict`, is halved for heuristic
+    /// freshness, doubled for `immutable` responses, and multiplied by one more than
+    /// `hit_count` if the caller tracks popularity. For Greedy-Dual-Size-Frequency, divide
+    /// it by the stored size and add the cache's inflation value.
+    pub fn eviction_score(&self, now: SystemTime, hit_count: Option<u64>) -> f64 {
+        if self.pinned {
+            return f64::INFINITY;
+        }
+        let remaining = self.time_to_evict(now);
+        if remaining == 0 {
+            return 0.0;
+        }
+        let mut score = (remaining as f64).ln_1p();
+        if matches!(
+            self.freshness_lifetime().1,
+            FreshnessSource::Heuristic | FreshnessSource::PermanentRedirect
+        ) {
+            score /= 2.0;
+        }
+        if self.rescc.immutable {
+            score *= 2.0;
+        }
+        score * (hit_count.unwrap_or(0) as f64 + 1.0)
+    }
+
+    /// Returns true if the stored response is within its freshness lifetime, its
+    /// `stale-while-revalidate` window and `CacheOptions::grace` at `now`, so that it may be
+    /// served to `req` while it's revalidated in the background (RFC 5861 section 3).
+    ///
+    /// Responses that must be revalidated before reuse, through `must-revalidate`, `no-cache`,
+    /// `proxy-revalidate` in a shared cache or `CacheOptions::always_revalidate`, never get
+    /// the window, and neither do requests with `no-cache` or prefetches (see
+    /// `EvaluationFlags::is_prefetch`), which are only answered with fresh responses.
+    pub fn within_stale_while_revalidate<B>(
+        &self,
+        req: &Request<B>,
+        now: SystemTime,
+        flags: EvaluationFlags,
+    ) -> bool {
+        if flags.is_prefetch
+            || !self.storable()
+            || self.must_revalidate_before_reuse()
+            || self.incomplete.is_some()
+            || self.request_directives(req.headers()).no_cache
+            || !self.request_matches(req, false)
+        {
+            return false;
+        }
+        let window = self.stale_while_revalidate_seconds();
+        self.age(now)
+            < self
+                .max_age()
+                .saturating_add(window)
+                .saturating_add(self.grace_seconds())
+    }
+
+    /// Returns true if a shared cache is using the response's `s-maxage`.
+    pub(super) fn has_shared_max_age(&self) -> bool {
+        self.opts.shared && self.rescc.s_maxage.is_some()
+    }
+
+    /// Returns true if the stored response may not be served stale without the origin's
+    /// explicit permission: `must-revalidate`, `no-cache`, `always_revalidate`, or
+    /// `proxy-revalidate` or `s-maxage` in a shared cache. This rules out client `max-stale`,
+    /// `CacheOptions::grace` and serving while disconnected, but a `stale-while-revalidate`
+    /// sent alongside `s-maxage` still applies, since the origin granted it in the same breath.
+    ///
+    /// `s-maxage` incorporates the semantics of `proxy-revalidate` (RFC 9111 section
+    /// 5.2.2.10), so `public, s-maxage=0` lets a shared cache store a response to an
+    /// authenticated request while revalidating it on every reuse.
+    pub fn must_revalidate_when_stale(&self) -> bool {
+        self.must_revalidate_before_reuse() || self.has_shared_max_age()
+    }
+
+    /// Returns true if the response may never be reused without a successful revalidation
+    /// once stale, so that no grace applies to it.
+    pub(super) fn must_revalidate_before_reuse(&self) -> bool {
+        self.opts.always_revalidate
+            || self.rescc.must_revalidate
+            || self.rescc.no_cache
+            || (self.opts.shared && self.rescc.proxy_revalidate)
+    }
+}
+
+impl PolicyView<'_> {
+    pub(super) fn date(&self) -> SystemTime {
+        match self.server_date() {
+            Some(date)
+                if cmp::max(
+                    seconds_between(date, self.response_time),
+                    seconds_between(self.response_time, date),
+                ) < MAX_CLOCK_DRIFT =>
+            {
+                date
+            }
+            _ => self.response_time,
+        }
+    }
+
+    pub(super) fn server_date(&self) -> Option<SystemTime> {
+        parse_http_date(self.res.get(DATE)?)
+    }
+
+    pub(super) fn age(&self, now: SystemTime) -> u64 {
+        let age = cmp::max(
+            seconds_between(self.date(), self.response_time),
+            self.age_value(),
+        );
+        let resident_time = seconds_between(self.response_time, now);
+        age.saturating_add(resident_time)
+    }
+
+    pub(super) fn age_value(&self) -> u64 {
+        self.age_header_value().unwrap_or(0)
+    }
+
+    pub(super) fn age_header_value(&self) -> Option<u64> {
+        select_age(self.res, self.opts.age_selection)
+    }
+
+    pub(super) fn freshness_inputs_with_age(&self, age: u64) -> FreshnessInputs {
+        let date = self.date();
+        FreshnessInputs {
+            shared: self.opts.shared,
+            chain_hop: self.opts.chain_hop,
+            always_revalidate: self.opts.always_revalidate,
+            path_ttl: self.path_ttl,
+            s_maxage: self.rescc.s_maxage,
+            max_age: self.rescc.max_age,
+            no_cache: self.rescc.no_cache,
+            must_revalidate: self.rescc.must_revalidate,
+            proxy_revalidate: self.rescc.proxy_revalidate,
+            public: self.rescc.public,
+            immutable: self.rescc.immutable,
+            set_cookie: self.res.contains_key(SET_COOKIE),
+            private_set_cookie_ttl: self.opts.private_set_cookie_ttl.map(|ttl| ttl.as_secs()),
+            vary_star: vary_fields(self.res).any(|name| name == b"*"),
+            expires: self.res.get(EXPIRES).map(parse_http_date),
+            last_modified: self.last_modified(),
+            date,
+            age,
+            permanent_redirect_ttl: self
+                .opts
+                .permanent_redirect_ttl
+                .filter(|_| self.is_permanent_redirect_status())
+                .map(|ttl| ttl.as_secs()),
+            retry_after: self
+                .retry_after()
+                .filter(|_| self.is_cacheable_retry_after())
+                .map(|retry_after| match retry_after {
+                    RetryAfter::Delay(seconds) => seconds,
+                    RetryAfter::Date(retry_date) => seconds_between(date, retry_date),
+                }),
+            past_expires: self.opts.past_expires,
+            min_ttl: self.opts.min_ttl.map(|ttl| ttl.as_secs()),
+            require_explicit_freshness: self.requires_explicit_freshness()
+                || !self.allows_heuristic_for_status(),
+            cache_heuristic: self.cache_heuristic(),
+            immutable_min_time_to_live: self.immutable_min_time_to_live(),
+        }
+    }
+
+    pub(super) fn freshness_lifetime(&self) -> (u64, FreshnessSource) {
+        if !self.storable() {
+            return (0, FreshnessSource::NotStorable);
+        }
+        if self.pinned {
+            return (u64::MAX, FreshnessSource::Pinned);
+        }
+        let result = compute_freshness(self.freshness_inputs_with_age(0));
+        (result.lifetime, result.source)
+    }
+
+    pub(super) fn allows_heuristic_for_status(&self) -> bool {
+        self.opts
+            .heuristic_statuses
+            .as_ref()
+            .is_none_or(|statuses| statuses.contains(&self.status.as_u16()))
+    }
+
+    pub(super) fn content_type_rule(&self) -> Option<&ContentTypeRule> {
+        if self.opts.per_content_type.is_empty() {
+            return None;
+        }
+        let content_type = header_str(self.res, CONTENT_TYPE)?;
+        let media_type = content_type.split(';').next().unwrap_or("").trim();
+        self.opts
+            .per_content_type
+            .iter()
+            .find(|rule| rule.matches(media_type))
+    }
+
+    pub(super) fn cache_heuristic(&self) -> HeuristicFraction {
+        self.content_type_rule()
+            .and_then(|rule| rule.cache_heuristic)
+            .unwrap_or(self.opts.cache_heuristic)
+    }
+
+    pub(super) fn immutable_min_time_to_live(&self) -> Duration {
+        self.content_type_rule()
+            .and_then(|rule| rule.immutable_min_time_to_live)
+            .unwrap_or(self.opts.immutable_min_time_to_live)
+    }
+}
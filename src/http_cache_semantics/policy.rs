@@ -0,0 +1,2455 @@
+//! `CachePolicy` and the `CacheOptions` that configure it: building a policy from a request
+//! and response, deciding whether it may be stored, and converting it to and from
+//! `CachePolicyObject`.
+
+use super::*;
+
+/// Statuses whose responses may be stored without explicit freshness information.
+const fn is_cacheable_by_default(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::OK
+            | StatusCode::NON_AUTHORITATIVE_INFORMATION
+            | StatusCode::NO_CONTENT
+            | StatusCode::PARTIAL_CONTENT
+            | StatusCode::MULTIPLE_CHOICES
+            | StatusCode::MOVED_PERMANENTLY
+            | StatusCode::PERMANENT_REDIRECT
+            | StatusCode::NOT_FOUND
+            | StatusCode::METHOD_NOT_ALLOWED
+            | StatusCode::GONE
+            | StatusCode::URI_TOO_LONG
+            | StatusCode::NOT_IMPLEMENTED
+    )
+}
+
+/// Statuses whose semantics this cache understands well enough to store.
+const fn is_understood_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::OK
+            | StatusCode::NON_AUTHORITATIVE_INFORMATION
+            | StatusCode::NO_CONTENT
+            | StatusCode::MULTIPLE_CHOICES
+            | StatusCode::MOVED_PERMANENTLY
+            | StatusCode::FOUND
+            | StatusCode::SEE_OTHER
+            | StatusCode::TEMPORARY_REDIRECT
+            | StatusCode::PERMANENT_REDIRECT
+            | StatusCode::NOT_FOUND
+            | StatusCode::METHOD_NOT_ALLOWED
+            | StatusCode::GONE
+            | StatusCode::URI_TOO_LONG
+            | StatusCode::NOT_IMPLEMENTED
+    )
+}
+
+/// Headers whose values `CachePolicy` interprets, and which therefore must be valid text.
+const INTERPRETED_HEADERS: [HeaderName; 10] = [
+    AGE,
+    CACHE_CONTROL,
+    DATE,
+    ETAG,
+    EXPIRES,
+    LAST_MODIFIED,
+    LOCATION,
+    PRAGMA,
+    RETRY_AFTER,
+    VARY,
+];
+
+/// Headers that should appear only once, and whose duplicates must be reconciled.
+const SINGLETON_HEADERS: [HeaderName; 4] = [AGE, DATE, EXPIRES, LAST_MODIFIED];
+
+/// Delta-seconds beyond this are absurd (RFC 9111 section 1.2.2), and are rejected under
+/// `CacheOptions::reject_absurd_values`.
+const MAX_DELTA_SECONDS: u64 = 1 << 31;
+
+/// `CacheDirectives::default()`, for policies whose request had no directives.
+pub(super) const NO_DIRECTIVES: CacheDirectives = CacheDirectives {
+    max_age: None,
+    s_maxage: None,
+    min_fresh: None,
+    max_stale: None,
+    stale_while_revalidate: None,
+    stale_if_error: None,
+    no_cache: false,
+    no_store: false,
+    no_transform: false,
+    only_if_cached: false,
+    must_revalidate: false,
+    proxy_revalidate: false,
+    must_understand: false,
+    public: false,
+    private: false,
+    immutable: false,
+};
+
+/// Request directives as a policy keeps them: boxed, and only if there are any.
+pub(super) fn boxed_directives(directives: CacheDirectives) -> Option<Box<CacheDirectives>> {
+    if directives == NO_DIRECTIVES {
+        None
+    } else {
+        Some(Box::new(directives))
+    }
+}
+
+pub(super) fn header_str(headers: &HeaderMap, name: HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Returns the first singleton header that is repeated with differing values.
+fn conflicting_header(headers: &HeaderMap) -> Option<HeaderName> {
+    conflicting_headers(headers).next().cloned()
+}
+
+fn conflicting_headers(headers: &HeaderMap) -> impl Iterator<Item = &'static HeaderName> + '_ {
+    SINGLETON_HEADERS.iter().filter(move |name| {
+        let mut values = headers.get_all(*name).iter().map(|value| value.as_bytes());
+        match values.next() {
+            Some(first) => values.any(|value| value.trim_ascii() != first.trim_ascii()),
+            None => false,
+        }
+    })
+}
+
+/// `known` along with the headers that conflict in `headers`, without repeats.
+pub(super) fn add_conflicts(known: &[HeaderName], headers: &HeaderMap) -> Vec<HeaderName> {
+    let mut conflicts = known.to_vec();
+    for name in conflicting_headers(headers) {
+        if !conflicts.contains(name) {
+            conflicts.push(name.clone());
+        }
+    }
+    conflicts
+}
+
+/// Replaces conflicting singleton headers with the single value described by
+/// `DuplicateHeaderMode::Lenient`.
+fn resolve_conflicting_headers(headers: &mut HeaderMap, age_selection: AgeSelection) {
+    while let Some(name) = conflicting_header(headers) {
+        let values = headers.get_all(&name).iter();
+        let resolved = if name == AGE {
+            select_age(headers, age_selection).map(|age| age.to_string())
+        } else if name == DATE {
+            values
+                .filter_map(parse_http_date)
+                .min()
+                .map(format_http_date)
+        } else if name == LAST_MODIFIED {
+            values
+                .filter_map(parse_http_date)
+                .max()
+                .map(format_http_date)
+        } else {
+            // An invalid date represents a time in the past (RFC 7234 section 5.3).
+            Some("0".to_string())
+        };
+        headers.remove(&name);
+        if let Some(value) = resolved.and_then(|value| HeaderValue::from_str(&value).ok()) {
+            headers.insert(name, value);
+        }
+    }
+}
+
+/// Hashes headers consistently with `HeaderMap`'s equality, which ignores the order of
+/// distinct header names but not the order of values for the same name.
+fn hash_headers<H: Hasher>(headers: &HeaderMap, state: &mut H) {
+    let mut names: Vec<&HeaderName> = headers.keys().collect();
+    names.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    names.len().hash(state);
+    for name in names {
+        name.hash(state);
+        for value in headers.get_all(name) {
+            value.hash(state);
+        }
+    }
+}
+
+fn headers_to_pairs(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.as_str().to_string(),
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            )
+        })
+        .collect()
+}
+
+fn headers_from_pairs(pairs: &[(String, String)]) -> Option<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in pairs {
+        let name = HeaderName::from_bytes(name.as_bytes()).ok()?;
+        let value = HeaderValue::from_str(value).ok()?;
+        headers.append(name, value);
+    }
+    Some(headers)
+}
+
+pub(super) fn version_to_str(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "HTTP/0.9",
+        Version::HTTP_10 => "HTTP/1.0",
+        Version::HTTP_2 => "HTTP/2.0",
+        Version::HTTP_3 => "HTTP/3.0",
+        _ => "HTTP/1.1",
+    }
+}
+
+pub(super) fn version_from_str(version: &str) -> Option<Version> {
+    match version {
+        "HTTP/0.9" => Some(Version::HTTP_09),
+        "HTTP/1.0" => Some(Version::HTTP_10),
+        "HTTP/1.1" => Some(Version::HTTP_11),
+        "HTTP/2.0" => Some(Version::HTTP_2),
+        "HTTP/3.0" => Some(Version::HTTP_3),
+        _ => None,
+    }
+}
+
+/// Configuration for how a `CachePolicy` interprets requests and responses.
+///
+/// With the `serde` feature, fields missing from serialized options take their default
+/// values. `storage_hooks` are code, so they're never serialized and must be supplied again.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct CacheOptions {
+    /// If true (the default), the cache is shared between users, e.g. a proxy or CDN, and
+    /// `private` responses or responses to authorized requests aren't stored.
+    /// Set to false for a single-user cache, e.g. a browser.
+    pub shared: bool,
+    /// Fraction of the time since `Last-Modified` that a response without explicit
+    /// freshness is considered fresh for. Defaults to 0.1, which matches IE.
+    pub cache_heuristic: HeuristicFraction,
+    /// Minimum freshness lifetime of responses marked `immutable`. Defaults to 24 hours.
+    pub immutable_min_time_to_live: Duration,
+    /// Ignore the cargo-cult `pre-check=0, post-check=0` combination and the `no-cache`,
+    /// `no-store`, `must-revalidate`, `Expires` and `Pragma` that usually accompany it.
+    pub ignore_cargo_cult: bool,
+    /// Freshness lifetime of permanent redirects (301 and 308) that don't
+    /// carry explicit freshness information. Browsers effectively cache these forever.
+    /// When `None` (the default), they use the `Last-Modified` heuristic like any other
+    /// response.
+    pub permanent_redirect_ttl: Option<Duration>,
+    /// Store 429 and 503 responses that carry a `Retry-After` header, and keep them fresh
+    /// until the time it gives, so that clients back off from rate-limited or overloaded
+    /// origins. Explicit freshness information still takes precedence.
+    pub cache_retry_after: bool,
+    /// Pseudonym this cache identifies itself with in `Via` headers. When set,
+    /// `CachePolicy::response_headers` adds a `Via` entry to responses served from the cache.
+    pub via_pseudonym: Option<String>,
+    /// Add `110 Response is Stale` and `113 Heuristic Expiration` warnings to responses
+    /// served from the cache. Defaults to true. RFC 9111 obsoletes the `Warning` header, so
+    /// set this to false to stop generating it; cached 1xx warnings are still removed.
+    pub generate_warnings: bool,
+    /// How a shared cache writes `Cache-Control` on responses it serves. Defaults to
+    /// `CacheControlRewrite::Preserve`, which leaves the header as the origin sent it.
+    pub rewrite_cache_control: CacheControlRewrite,
+    /// Which `Cache-Control` directives from clients the cache obeys. Defaults to
+    /// `RequestDirectiveMode::Honor`. Server-side caches often ignore them so that clients
+    /// can't bust the cache.
+    pub honor_request_directives: RequestDirectiveMode,
+    /// Overrides of `cache_heuristic` and `immutable_min_time_to_live` for particular media
+    /// types, e.g. a longer heuristic for `image/*` than for `text/html`. The first rule whose
+    /// pattern matches the response's `Content-Type` applies. Empty by default.
+    pub per_content_type: Vec<ContentTypeRule>,
+    /// Callbacks consulted when a policy is created, after the RFC 7234 rules, that can veto
+    /// or force storing the response. The first hook that doesn't return
+    /// `StorageOverride::Default` decides. Empty by default.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub storage_hooks: Vec<StorageHook>,
+    /// Rules applied by request path when a policy is created, e.g. to cache `/static/**` for
+    /// 30 days and never cache `/api/**`. The first matching rule applies, after any
+    /// `storage_hooks`. Empty by default.
+    pub path_rules: Vec<PathRule>,
+    /// Largest response body, in bytes, the cache is willing to store. Responses whose
+    /// `Content-Length` exceeds it aren't storable. `None` (the default) means no limit.
+    pub max_object_size: Option<u64>,
+    /// How to handle repeated `Age`, `Date`, `Expires` and `Last-Modified` headers that
+    /// disagree with each other.
+    pub duplicate_headers: DuplicateHeaderMode,
+    /// Treat responses carrying a `Vary` header as unstorable, for caches that keep a single
+    /// variant per URL and would otherwise store variants they can never match.
+    pub reject_vary: bool,
+    /// Most fields a response's `Vary` header may nominate before the response is treated
+    /// as unstorable. `None` (the default) means no limit.
+    pub max_vary_fields: Option<usize>,
+    /// Request headers whose values are too diverse to vary on, such as `Cookie`. Responses
+    /// whose `Vary` header nominates any of them are treated as unstorable.
+    #[cfg_attr(feature = "serde", serde(with = "header_names"))]
+    pub vary_deny_list: Vec<HeaderName>,
+    /// How request headers nominated by `Vary` are compared with those of the request that
+    /// produced the response.
+    pub vary_matching: VaryMatching,
+    /// How request URIs are compared with the one a response was stored for, and turned into
+    /// cache keys. `UrlMatching::Exact` by default.
+    pub url_matching: UrlMatching,
+    /// Sort query parameters before matching and key generation, so that their order doesn't
+    /// matter.
+    pub sort_query: bool,
+    /// Query parameters to drop before matching and key generation, such as `utm_*` or
+    /// `fbclid`. A trailing `*` matches any suffix.
+    pub ignored_query_params: Vec<String>,
+    /// Treat every stored response as if it said `max-age=0, must-revalidate`: responses are
+    /// still stored, but never served without a successful conditional request.
+    pub always_revalidate: bool,
+    /// Which value to use when a response carries several `Age` values, in repeated headers or
+    /// joined with commas. `AgeSelection::Max` by default.
+    pub age_selection: AgeSelection,
+    /// How long after it goes stale a response may still be served while the caller
+    /// revalidates it in the background, whether or not the origin sent
+    /// `stale-while-revalidate`. Responses that must be revalidated before reuse, through
+    /// `must-revalidate`, `no-cache`, `proxy-revalidate` or `s-maxage` in a shared cache or
+    /// `always_revalidate`, never get grace. Zero by default.
+    pub grace: Duration,
+    /// How long after its `grace` runs out a response with an `ETag` or `Last-Modified` is
+    /// worth keeping as a source of validators for conditional requests, though it can no
+    /// longer be served without revalidation. See `CachePolicy::time_to_evict`. Zero by
+    /// default.
+    pub keep: Duration,
+    /// How the `Date` and `Age` headers of served responses are written. Downstream caches
+    /// compute freshness from them, so the choice matters. `ServeDate::ServingTime` by default.
+    pub serve_date: ServeDate,
+    /// Revalidate stale `GET` responses with an unconditional `HEAD` request, for origins that
+    /// mishandle conditional requests. A `200` answer whose validators match the stored
+    /// response freshens it (RFC 9111 section 4.3.5).
+    pub revalidate_with_head: bool,
+    /// If true and the cache is shared, only store responses with explicit freshness
+    /// information (`s-maxage`, `max-age` or `Expires`) and never assign heuristic freshness.
+    /// Heuristics are only a MAY in RFC 9111, and CDNs often prefer not to guess. Defaults to
+    /// false.
+    pub require_explicit_freshness: bool,
+    /// Whether to serve stale responses when the origin can't be reached, as signalled by
+    /// `EvaluationFlags::origin_unreachable` (RFC 9111 section 4.2.4).
+    /// `DisconnectedPolicy::Never` by default.
+    pub disconnected: DisconnectedPolicy,
+    /// How to treat an `Expires` earlier than the response's `Date` when it determines the
+    /// freshness lifetime. `PastExpires::Stale` by default, as RFC 9111 requires.
+    pub past_expires: PastExpires,
+    /// The cache sits behind another shared cache, e.g. a regional cache fed by a CDN. In a
+    /// shared cache this makes `proxy-revalidate` forbid serving stale rather than zeroing
+    /// freshness, always increments `Age` on served responses even with
+    /// `ServeDate::Origin`, and never lets local rules extend freshness beyond the lifetime
+    /// granted upstream. Defaults to false.
+    pub chain_hop: bool,
+    /// Refuse to store responses whose `Age`, `max-age` or `s-maxage` exceeds 2^31 seconds,
+    /// about 68 years, which no legitimate server sends. By default such values saturate
+    /// instead, and all arithmetic on them saturates too.
+    pub reject_absurd_values: bool,
+    /// When a request's `max-age`, such as a browser reload's `max-age=0`, is all that stops
+    /// the stored response from being used, serve it with `CacheAction::ServeStale` and
+    /// revalidate in the background, provided it is within its freshness lifetime plus its
+    /// `stale-while-revalidate` window and `grace`. A request's `no-cache` never gets this.
+    /// Defaults to false.
+    pub request_max_age_allows_stale_while_revalidate: bool,
+    /// Status codes whose responses may be given heuristic freshness from `Last-Modified`, e.g.
+    /// `Some(vec![200, 203, 301, 410])` to avoid keeping a `404` for days. Other statuses need
+    /// explicit freshness to be served without revalidation. `None` (the default) allows every
+    /// status that is cacheable by default.
+    pub heuristic_statuses: Option<Vec<u16>>,
+    /// In a private cache, the longest freshness lifetime of a response that sets a cookie
+    /// without `public` or `immutable`, the way browsers handle them. Zero forces
+    /// revalidation. `None` (the default) leaves such responses alone; shared caches never
+    /// reuse them anyway.
+    pub private_set_cookie_ttl: Option<Duration>,
+    /// Raises explicit freshness lifetimes (`s-maxage`, `max-age` or `Expires`) below this
+    /// floor to it, for origins that send `max-age=1` on everything. This goes against RFC
+    /// 9111, which requires honoring the origin's lifetime, so only use it for crawler
+    /// politeness or origin protection. Responses with `must-revalidate` are exempt. `None`
+    /// by default.
+    pub min_ttl: Option<Duration>,
+    /// Include the request method in `cache_key_with`, `CachePolicy::cache_key` and variant
+    /// keys, for gateways in front of APIs that serve different representations for, say,
+    /// `GET` and `QUERY` on the same URI, so they are stored side by side rather than
+    /// replacing each other. `HEAD` keys as `GET`, which can answer it. Also stops a `POST`
+    /// response being reused for a `GET` through its `Content-Location`. Defaults to false.
+    pub vary_on_method: bool,
+    /// Workarounds for widespread origin misconfigurations, such as `Expires: 0` or lowercase
+    /// dates, each enabled separately. The ones a response needed are listed by
+    /// `CachePolicy::parse_report`. All off by default.
+    pub quirks: Quirks,
+}
+
+impl PartialEq for CacheOptions {
+    fn eq(&self, other: &Self) -> bool {
+        self.shared == other.shared
+            && self.cache_heuristic == other.cache_heuristic
+            && self.immutable_min_time_to_live == other.immutable_min_time_to_live
+            && self.ignore_cargo_cult == other.ignore_cargo_cult
+            && self.permanent_redirect_ttl == other.permanent_redirect_ttl
+            && self.cache_retry_after == other.cache_retry_after
+            && self.via_pseudonym == other.via_pseudonym
+            && self.generate_warnings == other.generate_warnings
+            && self.rewrite_cache_control == other.rewrite_cache_control
+            && self.honor_request_directives == other.honor_request_directives
+            && self.per_content_type == other.per_content_type
+            && self.storage_hooks == other.storage_hooks
+            && self.path_rules == other.path_rules
+            && self.max_object_size == other.max_object_size
+            && self.duplicate_headers == other.duplicate_headers
+            && self.reject_vary == other.reject_vary
+            && self.max_vary_fields == other.max_vary_fields
+            && self.vary_deny_list == other.vary_deny_list
+            && self.vary_matching == other.vary_matching
+            && self.url_matching == other.url_matching
+            && self.sort_query == other.sort_query
+            && self.ignored_query_params == other.ignored_query_params
+            && self.always_revalidate == other.always_revalidate
+            && self.age_selection == other.age_selection
+            && self.grace == other.grace
+            && self.keep == other.keep
+            && self.serve_date == other.serve_date
+            && self.revalidate_with_head == other.revalidate_with_head
+            && self.require_explicit_freshness == other.require_explicit_freshness
+            && self.disconnected == other.disconnected
+            && self.past_expires == other.past_expires
+            && self.chain_hop == other.chain_hop
+            && self.reject_absurd_values == other.reject_absurd_values
+            && self.request_max_age_allows_stale_while_revalidate
+                == other.request_max_age_allows_stale_while_revalidate
+            && self.heuristic_statuses == other.heuristic_statuses
+            && self.private_set_cookie_ttl == other.private_set_cookie_ttl
+            && self.min_ttl == other.min_ttl
+            && self.vary_on_method == other.vary_on_method
+            && self.quirks == other.quirks
+    }
+}
+
+impl Eq for CacheOptions {}
+
+impl Hash for CacheOptions {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.shared.hash(state);
+        self.cache_heuristic.hash(state);
+        self.immutable_min_time_to_live.hash(state);
+        self.ignore_cargo_cult.hash(state);
+        self.permanent_redirect_ttl.hash(state);
+        self.cache_retry_after.hash(state);
+        self.via_pseudonym.hash(state);
+        self.generate_warnings.hash(state);
+        self.rewrite_cache_control.hash(state);
+        self.honor_request_directives.hash(state);
+        self.per_content_type.hash(state);
+        self.storage_hooks.hash(state);
+        self.path_rules.hash(state);
+        self.max_object_size.hash(state);
+        self.duplicate_headers.hash(state);
+        self.reject_vary.hash(state);
+        self.max_vary_fields.hash(state);
+        self.vary_deny_list.hash(state);
+        self.vary_matching.hash(state);
+        self.url_matching.hash(state);
+        self.sort_query.hash(state);
+        self.ignored_query_params.hash(state);
+        self.always_revalidate.hash(state);
+        self.age_selection.hash(state);
+        self.grace.hash(state);
+        self.keep.hash(state);
+        self.serve_date.hash(state);
+        self.revalidate_with_head.hash(state);
+        self.require_explicit_freshness.hash(state);
+        self.disconnected.hash(state);
+        self.past_expires.hash(state);
+        self.chain_hop.hash(state);
+        self.reject_absurd_values.hash(state);
+        self.request_max_age_allows_stale_while_revalidate
+            .hash(state);
+        self.heuristic_statuses.hash(state);
+        self.private_set_cookie_ttl.hash(state);
+        self.min_ttl.hash(state);
+        self.vary_on_method.hash(state);
+        self.quirks.hash(state);
+    }
+}
+
+impl Default for CacheOptions {
+    fn default() -> Self {
+        CacheOptions {
+            shared: true,
+            cache_heuristic: HeuristicFraction(0.1),
+            immutable_min_time_to_live: Duration::from_secs(24 * 3600),
+            ignore_cargo_cult: false,
+            permanent_redirect_ttl: None,
+            cache_retry_after: false,
+            via_pseudonym: None,
+            generate_warnings: true,
+            rewrite_cache_control: CacheControlRewrite::Preserve,
+            honor_request_directives: RequestDirectiveMode::Honor,
+            per_content_type: Vec::new(),
+            storage_hooks: Vec::new(),
+            path_rules: Vec::new(),
+            max_object_size: None,
+            duplicate_headers: DuplicateHeaderMode::Lenient,
+            reject_vary: false,
+            max_vary_fields: None,
+            vary_deny_list: Vec::new(),
+            vary_matching: VaryMatching::Exact,
+            url_matching: UrlMatching::Exact,
+            sort_query: false,
+            ignored_query_params: Vec::new(),
+            always_revalidate: false,
+            age_selection: AgeSelection::Max,
+            grace: Duration::ZERO,
+            keep: Duration::ZERO,
+            serve_date: ServeDate::ServingTime,
+            revalidate_with_head: false,
+            require_explicit_freshness: false,
+            disconnected: DisconnectedPolicy::Never,
+            past_expires: PastExpires::Stale,
+            chain_hop: false,
+            reject_absurd_values: false,
+            request_max_age_allows_stale_while_revalidate: false,
+            heuristic_statuses: None,
+            private_set_cookie_ttl: None,
+            min_ttl: None,
+            vary_on_method: false,
+            quirks: Quirks::default(),
+        }
+    }
+}
+
+impl CacheOptions {
+    /// Sets `cache_heuristic` from a plain number, clamping it between 0 and 1.
+    #[deprecated(note = "assign a `HeuristicFraction` to `cache_heuristic` instead")]
+    pub fn set_cache_heuristic(&mut self, fraction: f32) {
+        self.cache_heuristic = HeuristicFraction::clamped(fraction);
+    }
+
+    /// Sets `immutable_min_time_to_live` from a number of seconds.
+    #[deprecated(note = "assign a `Duration` to `immutable_min_time_to_live` instead")]
+    pub fn set_immutable_min_time_to_live(&mut self, seconds: u32) {
+        self.immutable_min_time_to_live = Duration::from_secs(u64::from(seconds));
+    }
+
+    /// Sets `permanent_redirect_ttl` from a number of seconds.
+    #[deprecated(note = "assign a `Duration` to `permanent_redirect_ttl` instead")]
+    pub fn set_permanent_redirect_ttl(&mut self, seconds: Option<u32>) {
+        self.permanent_redirect_ttl = seconds.map(|s| Duration::from_secs(u64::from(s)));
+    }
+
+    /// Options for a browser's private cache: responses marked `private` or requested with
+    /// `Authorization` may be stored, request directives are obeyed, and permanent
+    /// redirects are kept for a year, as good as forever.
+    pub fn browser() -> Self {
+        CacheOptions {
+            shared: false,
+            generate_warnings: false,
+            permanent_redirect_ttl: Some(Duration::from_secs(365 * 24 * 3600)),
+            ..CacheOptions::default()
+        }
+    }
+
+    /// Options for a shared cache in front of origins its operator controls, such as a CDN.
+    /// Clients can't force a refetch with `no-cache`, legacy `pre-check`/`post-check`
+    /// headers are ignored, and permanent redirects are kept for a day.
+    pub fn shared_cdn() -> Self {
+        CacheOptions {
+            shared: true,
+            ignore_cargo_cult: true,
+            permanent_redirect_ttl: Some(Duration::from_secs(24 * 3600)),
+            honor_request_directives: RequestDirectiveMode::IgnoreNoCache,
+            ..CacheOptions::default()
+        }
+    }
+
+    /// Options for a crawler's cache, which issues its own requests and wants to fetch as
+    /// little as possible: request directives are ignored, heuristic freshness is generous,
+    /// permanent redirects are remembered for a month, and `Retry-After` is respected.
+    pub fn crawler() -> Self {
+        CacheOptions {
+            shared: false,
+            cache_heuristic: HeuristicFraction(0.2),
+            ignore_cargo_cult: true,
+            permanent_redirect_ttl: Some(Duration::from_secs(30 * 24 * 3600)),
+            cache_retry_after: true,
+            generate_warnings: false,
+            honor_request_directives: RequestDirectiveMode::IgnoreAll,
+            sort_query: true,
+            ..CacheOptions::default()
+        }
+    }
+}
+
+/// What a cache should do with a response body before it arrives. See
+/// `CachePolicy::storage_advice`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StorageAdvice {
+    /// Buffer the body and store it; it is known to fit.
+    Buffer,
+    /// Pass the body through to the client without storing it.
+    StreamThrough,
+    /// Store the body while streaming it, but abandon the stored copy if it grows past
+    /// `limit` bytes.
+    Opportunistic { limit: u64 },
+}
+
+/// Why `CachePolicy::storable` returned false.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnstorableReason {
+    /// RFC 7234 forbids storing any response to the request, because of its method,
+    /// `no-store` directive or `Authorization` header.
+    RequestForbids,
+    /// RFC 7234 forbids storing the response, because of its status, directives or lack of
+    /// freshness information.
+    ResponseForbids,
+    /// A storage hook or path rule vetoed storing it.
+    Vetoed,
+    /// Its `Content-Length` exceeds `CacheOptions::max_object_size`.
+    TooLarge { content_length: u64, limit: u64 },
+    /// It repeats a header with conflicting values under `DuplicateHeaderMode::Strict`.
+    ConflictingHeaders,
+    /// It has a `Vary` header and `CacheOptions::reject_vary` is set.
+    Vary,
+    /// Its `Vary` header nominates more fields than `CacheOptions::max_vary_fields`.
+    TooManyVaryFields { count: usize, limit: usize },
+    /// Its `Vary` header nominates a field in `CacheOptions::vary_deny_list`.
+    DeniedVaryField,
+    /// Its `Age`, `max-age` or `s-maxage` exceeds 2^31 seconds and
+    /// `CacheOptions::reject_absurd_values` is set.
+    AbsurdValue,
+    /// Its body ended before it was complete, or didn't match its `Content-Length`. See
+    /// `CachePolicy::finalize`.
+    Incomplete {
+        expected: Option<u64>,
+        received: u64,
+    },
+}
+
+/// A hook's verdict on whether to store a response. See `CacheOptions::storage_hooks`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StorageOverride {
+    /// Leave the decision to RFC 7234.
+    Default,
+    /// Never store the response, e.g. because it is too large or its URL is deny-listed.
+    NeverStore,
+    /// Store the response even if RFC 7234 forbids it, e.g. for trusted internal origins.
+    /// Its freshness lifetime is still computed normally.
+    AlwaysStore,
+}
+
+/// Extension point for storability decisions. The request and response carry the headers of
+/// the exchange but no body.
+pub type StorageHook = fn(&Request<()>, &Response<()>) -> StorageOverride;
+
+fn run_storage_hooks<Req, Res>(
+    hooks: &[StorageHook],
+    request: &Request<Req>,
+    response: &Response<Res>,
+) -> StorageOverride {
+    if hooks.is_empty() {
+        return StorageOverride::Default;
+    }
+    let mut head_request = Request::new(());
+    *head_request.method_mut() = request.method().clone();
+    *head_request.uri_mut() = request.uri().clone();
+    *head_request.version_mut() = request.version();
+    *head_request.headers_mut() = request.headers().clone();
+    let mut head_response = Response::new(());
+    *head_response.status_mut() = response.status();
+    *head_response.version_mut() = response.version();
+    *head_response.headers_mut() = response.headers().clone();
+    hooks
+        .iter()
+        .map(|hook| hook(&head_request, &head_response))
+        .find(|verdict| *verdict != StorageOverride::Default)
+        .unwrap_or(StorageOverride::Default)
+}
+
+/// What to do with responses to requests whose path matches a `PathRule`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PathAction {
+    /// Keep storable responses fresh for this long, whatever their headers say.
+    Ttl(Duration),
+    /// Never store the response.
+    NeverCache,
+}
+
+/// Applies `action` to requests whose path matches `pattern`.
+///
+/// A pattern without `*` matches paths that start with it. Otherwise it must match the
+/// whole path, where `*` stands for any run of characters other than `/`, and `**` for any
+/// run of characters at all.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PathRule {
+    pub pattern: String,
+    pub action: PathAction,
+}
+
+impl PathRule {
+    /// Returns true if the rule applies to requests for `path`.
+    pub fn matches(&self, path: &str) -> bool {
+        if self.pattern.contains('*') {
+            glob_matches(self.pattern.as_bytes(), path.as_bytes())
+        } else {
+            path.starts_with(&self.pattern)
+        }
+    }
+}
+
+fn glob_matches(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern.first() {
+        None => path.is_empty(),
+        Some(b'*') => {
+            let (crosses_slash, rest) = match pattern.strip_prefix(b"**") {
+                Some(rest) => (true, rest),
+                None => (false, &pattern[1..]),
+            };
+            // Try every possible length for the wildcard, shortest first.
+            for end in 0..=path.len() {
+                if glob_matches(rest, &path[end..]) {
+                    return true;
+                }
+                if end < path.len() && path[end] == b'/' && !crosses_slash {
+                    return false;
+                }
+            }
+            false
+        }
+        Some(&c) => path.first() == Some(&c) && glob_matches(&pattern[1..], &path[1..]),
+    }
+}
+
+fn path_action(rules: &[PathRule], uri: &Uri) -> Option<PathAction> {
+    rules
+        .iter()
+        .find(|rule| rule.matches(uri.path()))
+        .map(|rule| rule.action)
+}
+
+/// Serializes header names as their lowercase strings.
+#[cfg(feature = "serde")]
+mod header_names {
+    use http::header::HeaderName;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        names: &[HeaderName],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(names.iter().map(HeaderName::as_str))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<HeaderName>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|name| HeaderName::from_bytes(name.as_bytes()).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+/// Which request `Cache-Control` directives a cache obeys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RequestDirectiveMode {
+    /// Obey all request directives, as RFC 7234 requires.
+    Honor,
+    /// Ignore `no-cache` and `Pragma: no-cache`, but obey the other directives.
+    IgnoreNoCache,
+    /// Ignore request directives entirely.
+    IgnoreAll,
+}
+
+impl RequestDirectiveMode {
+    fn apply(self, mut directives: CacheDirectives) -> CacheDirectives {
+        match self {
+            RequestDirectiveMode::Honor => directives,
+            RequestDirectiveMode::IgnoreNoCache => {
+                directives.no_cache = false;
+                directives
+            }
+            RequestDirectiveMode::IgnoreAll => CacheDirectives::default(),
+        }
+    }
+}
+
+/// How to handle a response that repeats a header which should appear only once, with
+/// differing values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DuplicateHeaderMode {
+    /// Settle on one value: the `Age` picked by `CacheOptions::age_selection`, the earliest
+    /// `Date` and the latest `Last-Modified`, so that the response is never considered
+    /// fresher than any of the values would make it. Conflicting `Expires` headers mean the
+    /// response has expired.
+    Lenient,
+    /// Don't store the response at all, and make `try_policy_for` report the conflict.
+    Strict,
+}
+
+/// Which value to use when a response carries several `Age` values. See
+/// `CacheOptions::age_selection`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AgeSelection {
+    /// The largest, so that the response is never considered younger than any cache on the
+    /// way reported.
+    Max,
+    /// The first, in header order.
+    First,
+}
+
+/// A section of an RFC that a caching decision follows, e.g. RFC 9111 section 3.5 for
+/// responses to authenticated requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RfcCitation {
+    pub rfc: u16,
+    /// Section number, such as `"5.2.2.1"`.
+    pub section: &'static str,
+    pub title: &'static str,
+}
+
+impl RfcCitation {
+    const fn new(rfc: u16, section: &'static str, title: &'static str) -> Self {
+        RfcCitation {
+            rfc,
+            section,
+            title,
+        }
+    }
+
+    /// Link to the section on the RFC Editor's site.
+    pub fn url(&self) -> String {
+        format!(
+            "https://www.rfc-editor.org/rfc/rfc{}#section-{}",
+            self.rfc, self.section
+        )
+    }
+}
+
+impl fmt::Display for RfcCitation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RFC {} §{} {}", self.rfc, self.section, self.title)
+    }
+}
+
+const STORING_RESPONSES: RfcCitation = RfcCitation::new(9111, "3", "Storing Responses in Caches");
+
+const AUTHENTICATED_REQUESTS: RfcCitation =
+    RfcCitation::new(9111, "3.5", "Storing Responses to Authenticated Requests");
+
+pub(super) const VARY_KEYS: RfcCitation = RfcCitation::new(
+    9111,
+    "4.1",
+    "Calculating Cache Keys with the Vary Header Field",
+);
+
+pub(super) const HEURISTIC_FRESHNESS: RfcCitation =
+    RfcCitation::new(9111, "4.2.2", "Calculating Heuristic Freshness");
+
+const REQUEST_NO_STORE: RfcCitation = RfcCitation::new(9111, "5.2.1.5", "no-store");
+
+pub(super) const MAX_AGE: RfcCitation = RfcCitation::new(9111, "5.2.2.1", "max-age");
+
+pub(super) const NO_CACHE: RfcCitation = RfcCitation::new(9111, "5.2.2.4", "no-cache");
+
+const RESPONSE_NO_STORE: RfcCitation = RfcCitation::new(9111, "5.2.2.5", "no-store");
+
+const PRIVATE: RfcCitation = RfcCitation::new(9111, "5.2.2.7", "private");
+
+pub(super) const PROXY_REVALIDATE: RfcCitation =
+    RfcCitation::new(9111, "5.2.2.8", "proxy-revalidate");
+
+pub(super) const S_MAXAGE: RfcCitation = RfcCitation::new(9111, "5.2.2.10", "s-maxage");
+
+pub(super) const EXPIRES_FIELD: RfcCitation = RfcCitation::new(9111, "5.3", "Expires");
+
+pub(super) const RETRY_AFTER_FIELD: RfcCitation = RfcCitation::new(9110, "10.2.3", "Retry-After");
+
+pub(super) const IMMUTABLE: RfcCitation =
+    RfcCitation::new(8246, "2", "The Immutable Cache-Control Extension");
+
+/// How well-formed the caching headers of a stored response were. See
+/// `CachePolicy::parse_report`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParseReport {
+    /// `Date`, `Cache-Control`, `ETag` or `Last-Modified`, if the response lacked them.
+    pub missing: Vec<HeaderName>,
+    /// Headers present but unusable: dates that don't parse, a malformed `ETag`, an `Age`
+    /// that isn't a number, or values that aren't text.
+    pub unparseable: Vec<HeaderName>,
+    /// Dates that parsed, but had to be adjusted: see `DateQuality`.
+    pub imprecise: Vec<(HeaderName, DateQuality)>,
+    /// Headers that should appear once but arrived with differing values.
+    pub conflicting: Vec<HeaderName>,
+    /// Misconfigurations that `CacheOptions::quirks` worked around.
+    pub quirks: Vec<Quirk>,
+}
+
+impl ParseReport {
+    /// Returns true if nothing was missing, unparseable, imprecise or conflicting, and no
+    /// quirks were needed.
+    pub fn is_clean(&self) -> bool {
+        *self == ParseReport::default()
+    }
+}
+
+/// A parsed `ETag`, such as `W/"v1"`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EntityTag {
+    pub weak: bool,
+    /// The opaque tag, without its quotes.
+    pub tag: String,
+}
+
+impl EntityTag {
+    /// Parses an entity tag, returning `None` unless it's a quoted string with an optional
+    /// `W/` prefix.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let (weak, quoted) = match value.strip_prefix("W/") {
+            Some(quoted) => (true, quoted),
+            None => (false, value),
+        };
+        let tag = quoted.strip_prefix('"')?.strip_suffix('"')?;
+        if tag.contains('"') {
+            return None;
+        }
+        Some(EntityTag {
+            weak,
+            tag: tag.to_string(),
+        })
+    }
+
+    /// Strong comparison: both tags are strong and identical.
+    pub fn strong_eq(&self, other: &EntityTag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// Weak comparison: the tags are identical, whether or not either is weak.
+    pub fn weak_eq(&self, other: &EntityTag) -> bool {
+        self.tag == other.tag
+    }
+}
+
+impl fmt::Display for EntityTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.weak {
+            f.write_str("W/")?;
+        }
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+
+/// One-line description of a policy at a point in time. See `CachePolicy::summary`.
+#[derive(Debug, Clone, Copy)]
+pub struct Summary<'a> {
+    policy: &'a CachePolicy,
+    now: SystemTime,
+}
+
+impl Summary<'_> {
+    /// RFC sections behind the decisions the summary describes: why the response can't be
+    /// stored, and where its freshness lifetime came from.
+    pub fn citations(&self) -> Vec<RfcCitation> {
+        self.policy
+            .unstorable_citation()
+            .into_iter()
+            .chain(self.policy.freshness_lifetime().1.citation())
+            .collect()
+    }
+}
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let policy = self.policy;
+        let (max_age, source) = policy.freshness_lifetime();
+        let age = policy.age(self.now);
+        write!(
+            f,
+            "{} {} {} ttl={}s src={}",
+            if policy.storable() {
+                "storable"
+            } else {
+                "unstorable"
+            },
+            if policy.opts.shared {
+                "shared"
+            } else {
+                "private"
+            },
+            if max_age > age { "fresh" } else { "stale" },
+            max_age.saturating_sub(age),
+            source.as_str(),
+        )?;
+        if policy.res.contains_key(VARY) {
+            let fields: Vec<String> = vary_fields(&policy.res)
+                .map(|field| String::from_utf8_lossy(field).to_ascii_lowercase())
+                .collect();
+            write!(f, " vary={}", fields.join(","))?;
+        }
+        if let Some(etag) = policy.res.get(ETAG) {
+            write!(f, " etag={}", if is_weak(etag) { "weak" } else { "strong" })?;
+        }
+        Ok(())
+    }
+}
+
+/// Policies for the same exchange in a shared and a private cache. See `CachePolicy::dual`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DualPolicy {
+    shared: CachePolicy,
+    private: CachePolicy,
+}
+
+impl DualPolicy {
+    /// The policy a shared cache, such as a proxy or CDN, follows.
+    pub fn shared(&self) -> &CachePolicy {
+        &self.shared
+    }
+
+    /// The policy a private cache, such as a browser's, follows.
+    pub fn private(&self) -> &CachePolicy {
+        &self.private
+    }
+
+    pub fn storable_shared(&self) -> bool {
+        self.shared.storable()
+    }
+
+    pub fn storable_private(&self) -> bool {
+        self.private.storable()
+    }
+
+    /// Seconds from `now` until the response goes stale in a shared cache.
+    pub fn ttl_shared(&self, now: SystemTime) -> u64 {
+        self.shared.time_to_live(now)
+    }
+
+    /// Seconds from `now` until the response goes stale in a private cache.
+    pub fn ttl_private(&self, now: SystemTime) -> u64 {
+        self.private.time_to_live(now)
+    }
+}
+
+/// Partition of the cache that a request belongs to, such as the top-level site or a tenant.
+///
+/// Insert it into a request's extensions. Policies record the partition of the request they
+/// were created for, only match requests from the same partition, and include it in their
+/// cache key. Requests without one belong to a single default partition.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CachePartition(pub String);
+
+/// Plain-data snapshot of a `CachePolicy`, suitable for persisting alongside the
+/// cached response. See `CachePolicy::to_object` and `CachePolicy::from_object`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CachePolicyObject {
+    pub version: u32,
+    pub response_time: SystemTime,
+    pub options: CacheOptions,
+    pub status: u16,
+    pub http_version: String,
+    pub response_headers: Vec<(String, String)>,
+    pub response_cache_control: CacheControl,
+    pub method: String,
+    pub uri: String,
+    pub host: Option<String>,
+    pub no_authorization: bool,
+    pub request_headers: Vec<(String, String)>,
+    pub request_cache_control: CacheControl,
+    pub partition: Option<String>,
+    pub storage_override: StorageOverride,
+    pub path_ttl: Option<u64>,
+    pub pinned: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub conflicting_headers: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub incomplete: Option<u64>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub body_integrity: Option<BodyIntegrity>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub quirks: Vec<Quirk>,
+}
+
+/// Reasons `try_policy_for` can refuse to build a policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CachePolicyError {
+    /// A header the policy depends on contains bytes that aren't visible ASCII.
+    InvalidHeader(HeaderName),
+    /// The request method has no caching semantics, e.g. `CONNECT`.
+    UnsupportedMethod(Method),
+    /// A timestamp lies before the Unix epoch, which no real clock or server produces.
+    AbsurdTimestamp(SystemTime),
+    /// The raw request method isn't a valid token.
+    InvalidMethod,
+    /// The raw request target isn't a valid URI.
+    InvalidUri,
+    /// The raw status code is outside 100 to 999.
+    InvalidStatus(u16),
+    /// A header that should appear once is repeated with differing values, and
+    /// `DuplicateHeaderMode::Strict` is in effect.
+    ConflictingHeader(HeaderName),
+}
+
+impl fmt::Display for CachePolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CachePolicyError::InvalidHeader(name) => {
+                write!(f, "invalid bytes in {} header", name)
+            }
+            CachePolicyError::UnsupportedMethod(method) => {
+                write!(f, "{} requests can't be cached", method)
+            }
+            CachePolicyError::AbsurdTimestamp(time) => {
+                let before = UNIX_EPOCH.duration_since(*time).unwrap_or_default();
+                write!(
+                    f,
+                    "timestamp {} seconds before the Unix epoch",
+                    before.as_secs()
+                )
+            }
+            CachePolicyError::InvalidMethod => write!(f, "invalid request method"),
+            CachePolicyError::InvalidUri => write!(f, "invalid request target"),
+            CachePolicyError::InvalidStatus(status) => {
+                write!(f, "invalid status code {}", status)
+            }
+            CachePolicyError::ConflictingHeader(name) => {
+                write!(f, "conflicting {} headers", name)
+            }
+        }
+    }
+}
+
+impl Error for CachePolicyError {}
+
+/// Decides whether, and for how long, a response may be cached and reused,
+/// following RFC 7234.
+///
+/// A policy is an immutable value: every method takes `&self`, and updates such as
+/// `revalidated_policy` and `freshen` return a new policy instead of modifying this one.
+/// It is `Send + Sync` with no interior mutability, so it can be shared as
+/// `Arc<CachePolicy>` across threads and tasks without locking.
+///
+/// A policy keeps only what later decisions consult: the response headers, the request
+/// headers its `Vary` nominates, and the typed directives, with the request's only boxed
+/// if there are any. Standard header names are static, header values are
+/// reference-counted, and the options are shared with clones and revalidations of the
+/// policy instead of copied into each. To keep millions of policies in memory, create them
+/// with `policy_for_shared_options`, or load them through one `PolicyDecoder`, which also
+/// shares custom header names and common header values among the policies it reads.
+/// `benches/policy_memory.rs` measures what each policy costs.
+#[derive(Debug, Clone)]
+pub struct CachePolicy {
+    pub(super) req: HeaderMap,
+    pub(super) res: HeaderMap,
+    pub(super) uri: Uri,
+    pub(super) host: Option<HeaderValue>,
+    pub(super) method: Method,
+    pub(super) status: StatusCode,
+    pub(super) version: Version,
+    pub(super) no_authorization: bool,
+    /// The request's directives, or `None` if it had none, as most requests don't. Only
+    /// those `CacheDirectives` understands are kept. See `boxed_directives`.
+    pub(super) reqcc: Option<Box<CacheDirectives>>,
+    /// The response's directives as normalized by the options. Extensions aren't kept:
+    /// `CachePolicy::response_cache_control` parses them from `res` when needed.
+    pub(super) rescc: CacheDirectives,
+    /// Shared by every policy created from the same options, and by their revalidations.
+    pub(super) opts: Arc<CacheOptions>,
+    pub(super) response_time: SystemTime,
+    pub(super) partition: Option<CachePartition>,
+    /// Verdict of `CacheOptions::storage_hooks`, decided when the policy was created.
+    pub(super) storage_override: StorageOverride,
+    /// Freshness lifetime imposed by a `PathRule`.
+    pub(super) path_ttl: Option<u64>,
+    /// Set by `CachePolicy::pinned`.
+    pub(super) pinned: bool,
+    /// Singleton headers that arrived with differing values, for `CachePolicy::parse_report`.
+    pub(super) conflicting_headers: Vec<HeaderName>,
+    /// Quirks the response needed, for `CachePolicy::parse_report`.
+    pub(super) quirks: Vec<Quirk>,
+    /// Number of body bytes stored, if the body is incomplete. Set by
+    /// `CachePolicy::into_incomplete`.
+    pub(super) incomplete: Option<u64>,
+    /// Set by `CachePolicy::with_body_integrity`.
+    pub(super) body_integrity: Option<BodyIntegrity>,
+    /// Monotonic time corresponding to `response_time`, set by
+    /// `CachePolicy::with_response_instant`. Never persisted.
+    pub(super) response_instant: Option<Instant>,
+}
+
+/// A `CachePolicy` that borrows the request and response it was built from, for one-shot
+/// decisions such as whether to store a response and for how long. Building one copies no
+/// headers unless `CacheOptions::ignore_cargo_cult` or `DuplicateHeaderMode::Lenient` has
+/// to rewrite them. Call `to_owned` once the response is to be stored.
+#[derive(Debug, Clone)]
+pub struct CachePolicyRef<'a> {
+    request_headers: &'a HeaderMap,
+    uri: &'a Uri,
+    method: &'a Method,
+    partition: Option<&'a CachePartition>,
+    response_headers: &'a HeaderMap,
+    /// `response_headers` as normalized by the options.
+    res: Cow<'a, HeaderMap>,
+    status: StatusCode,
+    version: Version,
+    reqcc: CacheDirectives,
+    rescc: CacheDirectives,
+    /// Quirks worked around in `res` and `rescc`.
+    quirks: Vec<Quirk>,
+    opts: &'a CacheOptions,
+    response_time: SystemTime,
+    storage_override: StorageOverride,
+    path_ttl: Option<u64>,
+}
+
+/// The parts of a policy its storability and freshness lifetime are decided from, borrowed
+/// from a `CachePolicy` or a `CachePolicyRef`.
+pub(super) struct PolicyView<'a> {
+    pub(super) res: &'a HeaderMap,
+    method: &'a Method,
+    pub(super) status: StatusCode,
+    no_authorization: bool,
+    reqcc: &'a CacheDirectives,
+    pub(super) rescc: &'a CacheDirectives,
+    pub(super) opts: &'a CacheOptions,
+    pub(super) response_time: SystemTime,
+    storage_override: StorageOverride,
+    pub(super) path_ttl: Option<u64>,
+    pub(super) pinned: bool,
+}
+
+// Sharing policies between threads is part of the API; fail the build if a field breaks it.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<CachePolicy>();
+    assert_send_sync::<CacheOptions>();
+    assert_send_sync::<CachePolicyObject>();
+};
+
+// The response directives are derived from the stored response headers and options, so
+// equality and hashing skip them. The request directives are not: `req` keeps only the
+// headers `Vary` nominates, so they are compared like any other field.
+impl PartialEq for CachePolicy {
+    fn eq(&self, other: &Self) -> bool {
+        self.req == other.req
+            && self.reqcc == other.reqcc
+            && self.res == other.res
+            && self.uri == other.uri
+            && self.host == other.host
+            && self.method == other.method
+            && self.status == other.status
+            && self.version == other.version
+            && self.no_authorization == other.no_authorization
+            && self.opts == other.opts
+            && self.partition == other.partition
+            && self.storage_override == other.storage_override
+            && self.path_ttl == other.path_ttl
+            && self.pinned == other.pinned
+            && self.conflicting_headers == other.conflicting_headers
+            && self.quirks == other.quirks
+            && self.incomplete == other.incomplete
+            && self.body_integrity == other.body_integrity
+            && self.response_instant == other.response_instant
+            && self.response_time == other.response_time
+    }
+}
+
+impl Eq for CachePolicy {}
+
+impl Hash for CachePolicy {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_headers(&self.req, state);
+        self.reqcc.hash(state);
+        hash_headers(&self.res, state);
+        self.uri.hash(state);
+        self.host.hash(state);
+        self.method.hash(state);
+        self.status.hash(state);
+        self.version.hash(state);
+        self.no_authorization.hash(state);
+        self.opts.hash(state);
+        self.partition.hash(state);
+        self.storage_override.hash(state);
+        self.path_ttl.hash(state);
+        self.pinned.hash(state);
+        self.conflicting_headers.hash(state);
+        self.quirks.hash(state);
+        self.incomplete.hash(state);
+        self.body_integrity.hash(state);
+        self.response_instant.hash(state);
+        self.response_time.hash(state);
+    }
+}
+
+/// Summarizes the policy as of the time the response was received. Use
+/// `CachePolicy::summary` to describe it at another time.
+impl fmt::Display for CachePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.summary(self.response_time).fmt(f)
+    }
+}
+
+fn check_headers(headers: &HeaderMap) -> Result<(), CachePolicyError> {
+    for name in &INTERPRETED_HEADERS {
+        if headers
+            .get_all(name)
+            .iter()
+            .any(|value| value.to_str().is_err())
+        {
+            return Err(CachePolicyError::InvalidHeader(name.clone()));
+        }
+    }
+    Ok(())
+}
+
+fn check_timestamp(time: SystemTime) -> Result<(), CachePolicyError> {
+    if time < UNIX_EPOCH {
+        return Err(CachePolicyError::AbsurdTimestamp(time));
+    }
+    Ok(())
+}
+
+/// Like `policy_for`, but reports inputs that `policy_for` would have to paper over.
+pub fn try_policy_for<Req, Res>(
+    request: &Request<Req>,
+    response: &Response<Res>,
+    options: &CacheOptions,
+    response_time: SystemTime,
+) -> Result<CachePolicy, CachePolicyError> {
+    if request.method() == Method::CONNECT {
+        return Err(CachePolicyError::UnsupportedMethod(
+            request.method().clone(),
+        ));
+    }
+    check_headers(request.headers())?;
+    check_headers(response.headers())?;
+    if options.duplicate_headers == DuplicateHeaderMode::Strict {
+        if let Some(name) = conflicting_header(response.headers()) {
+            return Err(CachePolicyError::ConflictingHeader(name));
+        }
+    }
+    check_timestamp(response_time)?;
+    if let Some(date) = response.headers().get(DATE).and_then(parse_http_date) {
+        check_timestamp(date)?;
+    }
+    Ok(policy_for(request, response, options, response_time))
+}
+
+/// Turns a raw header field into a valid one following RFC 9110's parsing rules, or returns
+/// `None` if its name isn't a token.
+///
+/// Line folding (obs-fold) becomes a single space, as do CR, LF, NUL and other control
+/// characters, and surrounding whitespace is trimmed. Other bytes, including non-UTF-8
+/// obs-text, are kept.
+fn normalize_field(name: &[u8], value: &[u8]) -> Option<(HeaderName, HeaderValue)> {
+    let name = HeaderName::from_bytes(name).ok()?;
+    let mut normalized = Vec::with_capacity(value.len());
+    let mut i = 0;
+    while i < value.len() {
+        let b = value[i];
+        if b == b'\r' || b == b'\n' {
+            // Collapse a whole line break, along with the indentation that makes it a fold.
+            while i < value.len() && matches!(value[i], b'\r' | b'\n' | b' ' | b'\t') {
+                i += 1;
+            }
+            normalized.push(b' ');
+            continue;
+        }
+        normalized.push(if b != b'\t' && (b < 0x20 || b == 0x7f) {
+            b' '
+        } else {
+            b
+        });
+        i += 1;
+    }
+    let value = HeaderValue::from_bytes(normalized.trim_ascii()).ok()?;
+    Some((name, value))
+}
+
+fn headers_from_raw(fields: &[(&[u8], &[u8])]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in fields {
+        if let Some((name, value)) = normalize_field(name, value) {
+            headers.append(name, value);
+        }
+    }
+    headers
+}
+
+/// Builds a policy from an exchange exactly as it arrived off the wire.
+///
+/// This never panics, whatever the input, so proxies can feed it untrusted bytes. Header
+/// fields are normalized as HTTP parsers do: folded lines are unfolded, control characters
+/// become spaces, and fields with invalid names are dropped. Repeated fields are kept in
+/// order, as if combined into a list. Only a request line or status that can't be
+/// interpreted at all is an error.
+pub fn policy_for_raw(
+    method: &[u8],
+    uri: &[u8],
+    request_headers: &[(&[u8], &[u8])],
+    status: u16,
+    response_headers: &[(&[u8], &[u8])],
+    options: &CacheOptions,
+    response_time: SystemTime,
+) -> Result<CachePolicy, CachePolicyError> {
+    let method = Method::from_bytes(method).map_err(|_| CachePolicyError::InvalidMethod)?;
+    let uri = Uri::from_maybe_shared(Vec::from(uri)).map_err(|_| CachePolicyError::InvalidUri)?;
+    let status =
+        StatusCode::from_u16(status).map_err(|_| CachePolicyError::InvalidStatus(status))?;
+
+    let mut request = Request::new(());
+    *request.method_mut() = method;
+    *request.uri_mut() = uri;
+    *request.headers_mut() = headers_from_raw(request_headers);
+    let mut response = Response::new(());
+    *response.status_mut() = status;
+    *response.headers_mut() = headers_from_raw(response_headers);
+    Ok(policy_for(&request, &response, options, response_time))
+}
+
+/// Returns false if no response to `request` could be stored, so a cache can skip looking
+/// it up or preparing to store the response. Returns true if storing depends on the
+/// response.
+pub fn request_is_cache_eligible<B>(request: &Request<B>, options: &CacheOptions) -> bool {
+    let method = request.method();
+    if method != Method::GET && method != Method::HEAD && method != Method::POST {
+        return false;
+    }
+    // The "no-store" request directive indicates that a cache MUST NOT store any part
+    // of either this request or any response to it.
+    let directives = options
+        .honor_request_directives
+        .apply(parse_request_cache_control(request.headers()));
+    !directives.no_store
+}
+
+/// Cheap pre-screen that returns false for exchanges a policy would obviously not store, so a
+/// proxy can skip building one: methods other than `GET`, `HEAD` and `POST`, statuses the
+/// cache doesn't understand, `no-store` in the request or response, and `Vary: *`, which
+/// no later request could ever match. A true result still needs a `CachePolicy` or
+/// `CachePolicyRef` to decide. Always true when `CacheOptions::storage_hooks` are set, since
+/// they can force storing.
+pub fn quick_is_storable<Req, Res>(
+    request: &Request<Req>,
+    response: &Response<Res>,
+    options: &CacheOptions,
+) -> bool {
+    if !options.storage_hooks.is_empty() {
+        return true;
+    }
+    if !request_is_cache_eligible(request, options) {
+        return false;
+    }
+    let status = response.status();
+    let retry_after = options.cache_retry_after
+        && (status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE);
+    if !(is_understood_status(status) || retry_after) {
+        return false;
+    }
+    if vary_fields(response.headers()).any(|name| name == b"*") {
+        return false;
+    }
+    let rescc = parse_cache_control(response.headers().get_all(CACHE_CONTROL));
+    let cargo_cult = options.ignore_cargo_cult
+        && rescc.contains_key("pre-check")
+        && rescc.contains_key("post-check");
+    cargo_cult || !rescc.contains_key("no-store")
+}
+
+/// Returns false if RFC 7234 forbids storing any response to `request`, so that a proxy can
+/// skip preparing to store the response before contacting the origin. See
+/// `CachePolicy::request_allows_storing`.
+pub fn request_allows_storing<B>(request: &Request<B>, options: &CacheOptions) -> bool {
+    request_is_cache_eligible(request, options)
+}
+
+/// Returns false if RFC 7234 forbids storing `response` whatever request it answers. See
+/// `CachePolicy::response_allows_storing`. The response is taken to have just arrived, which
+/// matters only for `PastExpires::Unstorable` when it has no `Date` header.
+pub fn response_allows_storing<B>(response: &Response<B>, options: &CacheOptions) -> bool {
+    // None of the response-side conditions depend on the request. `Expires` is compared with
+    // the `Date`, so receiving the response at that `Date` keeps it from being discarded as
+    // clock drift.
+    let response_time = response
+        .headers()
+        .get(DATE)
+        .and_then(parse_http_date)
+        .unwrap_or_else(SystemTime::now);
+    policy_for(&Request::new(()), response, options, response_time).response_allows_storing()
+}
+
+/// Builds a policy for the given request and the response it received at `response_time`.
+///
+/// This never fails. Header values that aren't valid text are treated as absent, methods
+/// without caching semantics produce a policy that is never storable, and a `Date` that
+/// can't be trusted is replaced by `response_time`. Use `try_policy_for` to reject such
+/// inputs instead.
+pub fn policy_for<Req, Res>(
+    request: &Request<Req>,
+    response: &Response<Res>,
+    options: &CacheOptions,
+    response_time: SystemTime,
+) -> CachePolicy {
+    CachePolicyRef::new(request, response, options, response_time)
+        .into_policy(Arc::new(options.clone()))
+}
+
+/// Like `policy_for`, but the policy shares `options` instead of keeping its own copy of them.
+/// A cache that stores many policies created with the same options should create them this
+/// way, since the options, with their rules and hooks, are often larger than a policy.
+pub fn policy_for_shared_options<Req, Res>(
+    request: &Request<Req>,
+    response: &Response<Res>,
+    options: &Arc<CacheOptions>,
+    response_time: SystemTime,
+) -> CachePolicy {
+    CachePolicyRef::new(request, response, options, response_time).into_policy(Arc::clone(options))
+}
+
+/// Consults `storage_hooks` and `path_rules`, returning the storage override and the
+/// path rule's time to live, if any.
+fn storage_decision<Req, Res>(
+    options: &CacheOptions,
+    request: &Request<Req>,
+    response: &Response<Res>,
+) -> (StorageOverride, Option<u64>) {
+    let mut storage_override = run_storage_hooks(&options.storage_hooks, request, response);
+    let mut path_ttl = None;
+    match path_action(&options.path_rules, request.uri()) {
+        Some(PathAction::Ttl(ttl)) => path_ttl = Some(ttl.as_secs()),
+        Some(PathAction::NeverCache) if storage_override == StorageOverride::Default => {
+            storage_override = StorageOverride::NeverStore;
+        }
+        _ => {}
+    }
+    (storage_override, path_ttl)
+}
+
+/// Applies the option-dependent normalizations to response headers, returning the
+/// headers to keep along with their parsed `Cache-Control` directives.
+/// Parses the request's `Cache-Control`, skipping the values `parse_cache_control` would skip,
+/// so that every request-side check sees the same directives.
+pub(super) fn parse_request_cache_control(headers: &HeaderMap) -> CacheDirectives {
+    CacheDirectives::parse(
+        headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter(|value| value.to_str().is_ok()),
+    )
+}
+
+pub(super) fn parse_response_headers(
+    headers: &HeaderMap,
+    options: &CacheOptions,
+) -> (HeaderMap, CacheDirectives, Vec<Quirk>) {
+    let (res, rescc, quirks) = normalize_response_headers(headers, options);
+    (
+        res.into_owned(),
+        CacheDirectives::from_cache_control(&rescc),
+        quirks,
+    )
+}
+
+/// Like `parse_response_headers`, but only copies the headers if the options change them.
+fn normalize_response_headers<'a>(
+    headers: &'a HeaderMap,
+    options: &CacheOptions,
+) -> (Cow<'a, HeaderMap>, CacheControl, Vec<Quirk>) {
+    let mut res = Cow::Borrowed(headers);
+    let mut rescc = parse_cache_control(res.get_all(CACHE_CONTROL));
+
+    // Assume that if someone uses legacy, non-standard unnecessary options they don't
+    // understand caching, so there's no point strictly adhering to the blindly
+    // copy&pasted directives.
+    if options.ignore_cargo_cult
+        && rescc.contains_key("pre-check")
+        && rescc.contains_key("post-check")
+    {
+        for name in &[
+            "pre-check",
+            "post-check",
+            "no-cache",
+            "no-store",
+            "must-revalidate",
+        ] {
+            rescc.remove(*name);
+        }
+        let res = res.to_mut();
+        res.remove(CACHE_CONTROL);
+        if let Ok(value) = HeaderValue::from_str(&format_cache_control(&rescc)) {
+            if !value.is_empty() {
+                res.insert(CACHE_CONTROL, value);
+            }
+        }
+        res.remove(EXPIRES);
+        res.remove(PRAGMA);
+    }
+
+    if options.duplicate_headers == DuplicateHeaderMode::Lenient
+        && conflicting_header(&res).is_some()
+    {
+        resolve_conflicting_headers(res.to_mut(), options.age_selection);
+    }
+
+    let quirks = apply_quirks(&mut res, &mut rescc, &options.quirks);
+
+    // When the Cache-Control header field is not present in a request, caches MUST
+    // consider the no-cache request pragma-directive as having the same effect as if
+    // "Cache-Control: no-cache" were present (see Section 5.2.1).
+    if !headers.contains_key(CACHE_CONTROL)
+        && header_str(&res, PRAGMA).is_some_and(|pragma| pragma.contains("no-cache"))
+    {
+        rescc.insert("no-cache".to_string(), None);
+    }
+
+    (res, rescc, quirks)
+}
+
+impl<'a> CachePolicyRef<'a> {
+    /// Builds a policy borrowing `request` and `response`, which it answers exactly as
+    /// `policy_for` would.
+    pub fn new<Req, Res>(
+        request: &'a Request<Req>,
+        response: &'a Response<Res>,
+        options: &'a CacheOptions,
+        response_time: SystemTime,
+    ) -> CachePolicyRef<'a> {
+        let (res, rescc, quirks) = normalize_response_headers(response.headers(), options);
+        let (storage_override, path_ttl) = storage_decision(options, request, response);
+        CachePolicyRef {
+            request_headers: request.headers(),
+            uri: request.uri(),
+            method: request.method(),
+            partition: request.extensions().get::<CachePartition>(),
+            response_headers: response.headers(),
+            res,
+            status: response.status(),
+            version: response.version(),
+            reqcc: parse_request_cache_control(request.headers()),
+            rescc: CacheDirectives::from_cache_control(&rescc),
+            quirks,
+            opts: options,
+            response_time,
+            storage_override,
+            path_ttl,
+        }
+    }
+
+    fn view(&self) -> PolicyView<'_> {
+        PolicyView {
+            res: &self.res,
+            method: self.method,
+            status: self.status,
+            no_authorization: !self
+                .request_headers
+                .contains_key(http::header::AUTHORIZATION),
+            reqcc: &self.reqcc,
+            rescc: &self.rescc,
+            opts: self.opts,
+            response_time: self.response_time,
+            storage_override: self.storage_override,
+            path_ttl: self.path_ttl,
+            pinned: false,
+        }
+    }
+
+    /// Returns true if the response may be stored. See `CachePolicy::storable`.
+    pub fn storable(&self) -> bool {
+        self.view().storable()
+    }
+
+    /// Explains why the response can't be stored, or returns `None` if it can.
+    pub fn unstorable_reason(&self) -> Option<UnstorableReason> {
+        self.view().unstorable_reason()
+    }
+
+    /// Number of seconds the response is fresh for, counted from when it was generated.
+    pub fn max_age(&self) -> u64 {
+        self.view().freshness_lifetime().0
+    }
+
+    /// Number of seconds from `now` until the response becomes stale.
+    pub fn time_to_live(&self, now: SystemTime) -> u64 {
+        self.max_age().saturating_sub(self.view().age(now))
+    }
+
+    /// Copies what the policy borrows into a `CachePolicy` that can be stored.
+    pub fn to_owned(&self) -> CachePolicy {
+        self.clone().into_policy(Arc::new(self.opts.clone()))
+    }
+
+    /// Copies what the policy borrows into a `CachePolicy`, which keeps `opts`, the options
+    /// the policy was built with.
+    fn into_policy(self, opts: Arc<CacheOptions>) -> CachePolicy {
+        let res = self.res.into_owned();
+        CachePolicy {
+            // Only the request headers nominated by Vary are ever consulted again
+            req: vary_request_headers(&res, self.request_headers),
+            res,
+            uri: self.uri.clone(),
+            host: self.request_headers.get(HOST).cloned(),
+            method: self.method.clone(),
+            status: self.status,
+            version: self.version,
+            no_authorization: !self
+                .request_headers
+                .contains_key(http::header::AUTHORIZATION),
+            reqcc: boxed_directives(self.reqcc),
+            rescc: self.rescc,
+            opts,
+            partition: self.partition.cloned(),
+            storage_override: self.storage_override,
+            path_ttl: self.path_ttl,
+            pinned: false,
+            conflicting_headers: add_conflicts(&[], self.response_headers),
+            quirks: self.quirks,
+            incomplete: None,
+            body_integrity: None,
+            response_instant: None,
+            response_time: self.response_time,
+        }
+    }
+}
+
+impl CachePolicy {
+    pub(super) fn view(&self) -> PolicyView<'_> {
+        PolicyView {
+            res: &self.res,
+            method: &self.method,
+            status: self.status,
+            no_authorization: self.no_authorization,
+            reqcc: self.reqcc.as_deref().unwrap_or(&NO_DIRECTIVES),
+            rescc: &self.rescc,
+            opts: &self.opts,
+            response_time: self.response_time,
+            storage_override: self.storage_override,
+            path_ttl: self.path_ttl,
+            pinned: self.pinned,
+        }
+    }
+    /// Builds a policy for a response that is fresh forever and never revalidated, such as
+    /// an asset bundled for offline use. It is always storable, but still only answers
+    /// requests that match it, `Vary` included, and its headers are served as usual.
+    pub fn pinned<Req, Res>(
+        request: &Request<Req>,
+        response: &Response<Res>,
+        options: &CacheOptions,
+        response_time: SystemTime,
+    ) -> CachePolicy {
+        let mut policy = policy_for(request, response, options, response_time);
+        policy.storage_override = StorageOverride::AlwaysStore;
+        policy.pinned = true;
+        policy
+    }
+
+    /// Builds policies for both a shared and a private cache from one parse of the exchange,
+    /// for layered setups such as a browser cache behind a company proxy. `options.shared`
+    /// is ignored.
+    pub fn dual<Req, Res>(
+        request: &Request<Req>,
+        response: &Response<Res>,
+        options: &CacheOptions,
+        response_time: SystemTime,
+    ) -> DualPolicy {
+        let mut shared = policy_for(request, response, options, response_time);
+        Arc::make_mut(&mut shared.opts).shared = true;
+        let mut private = shared.clone();
+        Arc::make_mut(&mut private.opts).shared = false;
+        DualPolicy { shared, private }
+    }
+
+    /// Returns true if the policy was created by `CachePolicy::pinned`.
+    pub fn is_pinned(&self) -> bool {
+        self.pinned
+    }
+
+    /// Returns a one-line description of the policy at `now`, for logs and admin pages, e.g.
+    /// `storable shared fresh ttl=312s src=max-age vary=accept-encoding etag=strong`.
+    pub fn summary(&self, now: SystemTime) -> Summary<'_> {
+        Summary { policy: self, now }
+    }
+
+    /// Returns true if the response can be stored in a cache at all.
+    pub fn storable(&self) -> bool {
+        self.view().storable()
+    }
+
+    /// Returns true if the same response would be storable had it answered a `method`
+    /// request instead, with the rest of the stored request unchanged.
+    ///
+    /// This only answers storability. A stored `GET` response can answer `HEAD` requests,
+    /// but a stored `HEAD` response has no body to answer `GET` with.
+    pub fn storable_for_method(&self, method: Method) -> bool {
+        if method == self.method {
+            return self.storable();
+        }
+        let mut policy = self.clone();
+        policy.method = method;
+        policy.storable()
+    }
+
+    /// Explains why the response can't be stored, or returns `None` if it can.
+    pub fn unstorable_reason(&self) -> Option<UnstorableReason> {
+        self.view().unstorable_reason()
+    }
+
+    /// The RFC section that forbids storing the response, if `unstorable_reason` is
+    /// `RequestForbids` or `ResponseForbids`.
+    pub fn unstorable_citation(&self) -> Option<RfcCitation> {
+        match self.unstorable_reason()? {
+            UnstorableReason::RequestForbids => self.request_storage_violation(),
+            UnstorableReason::ResponseForbids => self.response_storage_violation(),
+            _ => None,
+        }
+    }
+
+    /// Length of the response body according to `Content-Length`, if the header is present
+    /// and valid.
+    pub fn content_length(&self) -> Option<u64> {
+        self.view().content_length()
+    }
+
+    /// Number of body bytes after which the cache should give up storing the response.
+    ///
+    /// Responses that announce a larger `Content-Length` aren't storable to begin with, but
+    /// the length of a chunked or close-delimited body is only known once it has arrived.
+    /// Callers streaming such a body into the cache should abort storing it once it grows
+    /// past this limit.
+    pub fn body_size_limit(&self) -> Option<u64> {
+        self.opts.max_object_size
+    }
+
+    /// Confirms a policy built from the response headers alone once the body has been
+    /// received, so that a proxy can start storing a response mid-stream and decide at the end
+    /// whether to keep it.
+    ///
+    /// `body_len` is the number of body bytes received, `trailers` any trailer fields, which are
+    /// merged as by `merge_trailers`, and `completed` whether the body ended normally. A body
+    /// that was cut short or doesn't match `Content-Length` must not be stored as a complete
+    /// response (RFC 9111 section 3.3), nor may one that turned out larger than
+    /// `CacheOptions::max_object_size`, or whose trailers forbid storing it.
+    pub fn finalize(
+        mut self,
+        body_len: u64,
+        trailers: &HeaderMap,
+        completed: bool,
+    ) -> Result<CachePolicy, UnstorableReason> {
+        // Responses to HEAD and bodiless statuses announce the length of a body they don't
+        // carry.
+        let has_body = self.method != Method::HEAD
+            && self.status != StatusCode::NO_CONTENT
+            && self.status != StatusCode::NOT_MODIFIED;
+        let expected = self.content_length().filter(|_| has_body);
+        if !completed || expected.is_some_and(|len| len != body_len) {
+            return Err(UnstorableReason::Incomplete {
+                expected,
+                received: body_len,
+            });
+        }
+        if let Some(limit) = self.opts.max_object_size.filter(|&limit| body_len > limit) {
+            return Err(UnstorableReason::TooLarge {
+                content_length: body_len,
+                limit,
+            });
+        }
+        if !trailers.is_empty() {
+            let mut headers = self.res.clone();
+            merge_trailers(&mut headers, trailers);
+            let (res, rescc, _) = parse_response_headers(&headers, &self.opts);
+            self.res = res;
+            self.rescc = rescc;
+        }
+        match self.unstorable_reason() {
+            Some(reason) => Err(reason),
+            None => Ok(self),
+        }
+    }
+
+    /// Marks the policy as covering only the first `stored_bytes` bytes of the body, e.g. after
+    /// `finalize` reported `UnstorableReason::Incomplete`. An incomplete response is never
+    /// served (RFC 9111 section 3.3), but can be stored to resume the download with a range
+    /// request, which `evaluate` returns as `CacheAction::Resume`.
+    pub fn into_incomplete(mut self, stored_bytes: u64) -> CachePolicy {
+        self.incomplete = Some(stored_bytes);
+        self
+    }
+
+    /// Returns false if the policy was marked with `into_incomplete` and hasn't been completed
+    /// with `complete_with` since.
+    pub fn is_complete(&self) -> bool {
+        self.incomplete.is_none()
+    }
+
+    /// Records a digest of the stored body, to be checked with `verify_body` before serving
+    /// it. The record survives `to_object` and revalidations that keep the body.
+    pub fn with_body_integrity(mut self, integrity: BodyIntegrity) -> CachePolicy {
+        self.body_integrity = Some(integrity);
+        self
+    }
+
+    /// The digest recorded with `with_body_integrity`, if any. Its `algorithm` tells which
+    /// digest to compute over the stored body for `verify_body`.
+    pub fn body_integrity(&self) -> Option<&BodyIntegrity> {
+        self.body_integrity.as_ref()
+    }
+
+    /// Returns true if a stored body of `length` bytes with `digest` still matches the policy.
+    ///
+    /// The length must equal the recorded one, or else the `Content-Length` of a complete
+    /// response. The digest is compared if both it and a recorded one are present, so pass
+    /// `None` to check the length alone.
+    pub fn verify_body(&self, length: u64, digest: Option<&[u8]>) -> bool {
+        let expected_length = match &self.body_integrity {
+            Some(integrity) => Some(integrity.length),
+            None if self.method == Method::HEAD => Some(0),
+            None => self.incomplete.or_else(|| self.content_length()),
+        };
+        if expected_length.is_some_and(|expected| expected != length) {
+            return false;
+        }
+        match (&self.body_integrity, digest) {
+            (Some(integrity), Some(digest)) => integrity.digest == digest,
+            _ => true,
+        }
+    }
+
+    /// Decides how to handle the response body from its headers alone, so that a proxy can
+    /// choose before the body arrives.
+    ///
+    /// `Transfer-Encoding` overrides `Content-Length` (RFC 7230 section 3.3.3), so a chunked
+    /// body of announced length is treated as being of unknown length.
+    pub fn storage_advice(&self) -> StorageAdvice {
+        if !self.storable() {
+            return StorageAdvice::StreamThrough;
+        }
+        let limit = match self.body_size_limit() {
+            Some(limit) => limit,
+            None => return StorageAdvice::Buffer,
+        };
+        if self.res.contains_key(TRANSFER_ENCODING) {
+            return StorageAdvice::Opportunistic { limit };
+        }
+        match self.content_length() {
+            // `storable` already rejected lengths over the limit.
+            Some(_) => StorageAdvice::Buffer,
+            None => StorageAdvice::Opportunistic { limit },
+        }
+    }
+
+    /// Returns true unless RFC 7234 forbids storing the response because of the request it
+    /// answers: its method, its `no-store` directive, or its `Authorization` header in a
+    /// shared cache.
+    pub fn request_allows_storing(&self) -> bool {
+        self.view().request_allows_storing()
+    }
+
+    pub(super) fn request_storage_violation(&self) -> Option<RfcCitation> {
+        self.view().request_storage_violation()
+    }
+
+    /// Returns true unless RFC 7234 forbids storing the response because of the response
+    /// itself: its status, its `no-store` or `private` directives, or its lack of freshness
+    /// information.
+    pub fn response_allows_storing(&self) -> bool {
+        self.view().response_allows_storing()
+    }
+
+    pub(super) fn response_storage_violation(&self) -> Option<RfcCitation> {
+        self.view().response_storage_violation()
+    }
+
+    pub(super) fn has_explicit_expiration(&self) -> bool {
+        self.view().has_explicit_expiration()
+    }
+
+    /// Returns true if the response's directives allow a shared cache to store it even though
+    /// the request carried `Authorization` (RFC 9111 section 3.5). Responses stored this way
+    /// are served under `must_revalidate_when_stale`.
+    pub fn allows_storing_authenticated(&self) -> bool {
+        self.view().allows_storing_authenticated()
+    }
+
+    /// How well the stored response's `name` header, such as `Expires` or `Last-Modified`,
+    /// parsed as a date, or `None` if the response doesn't have one.
+    pub fn date_quality(&self, name: HeaderName) -> Option<DateQuality> {
+        self.res
+            .get(name)
+            .map(|value| parse_http_date_with_quality(value).1)
+    }
+
+    /// Lists the stored response's caching headers that were missing, unparseable, imprecise
+    /// or conflicting, e.g. to score an origin's header hygiene.
+    pub fn parse_report(&self) -> ParseReport {
+        let mut report = ParseReport {
+            conflicting: self.conflicting_headers.clone(),
+            quirks: self.quirks.clone(),
+            ..ParseReport::default()
+        };
+        for name in &[DATE, CACHE_CONTROL, ETAG, LAST_MODIFIED] {
+            if !self.res.contains_key(name) {
+                report.missing.push(name.clone());
+            }
+        }
+        for name in &[DATE, EXPIRES, LAST_MODIFIED] {
+            // Conflicting values were resolved to one, which says nothing about the origin's.
+            if report.conflicting.contains(name) {
+                continue;
+            }
+            match self.date_quality(name.clone()) {
+                Some(DateQuality::Invalid) => report.unparseable.push(name.clone()),
+                Some(quality @ (DateQuality::PreEpoch | DateQuality::Clamped)) => {
+                    report.imprecise.push((name.clone(), quality))
+                }
+                Some(DateQuality::Exact) | None => {}
+            }
+        }
+        if self.res.contains_key(ETAG) && self.etag().is_none() {
+            report.unparseable.push(ETAG);
+        }
+        let valid_age = |value: &HeaderValue| {
+            value.to_str().is_ok_and(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .all(|age| !age.is_empty() && age.bytes().all(|b| b.is_ascii_digit()))
+            })
+        };
+        if !self.res.get_all(AGE).iter().all(valid_age) {
+            report.unparseable.push(AGE);
+        }
+        if self
+            .res
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .any(|value| value.to_str().is_err())
+        {
+            report.unparseable.push(CACHE_CONTROL);
+        }
+        report
+    }
+
+    /// The stored response's `ETag`, if it has a valid one.
+    pub fn etag(&self) -> Option<EntityTag> {
+        EntityTag::parse(header_str(&self.res, ETAG)?)
+    }
+
+    /// The stored response's `Last-Modified`, if it has a valid one.
+    pub fn last_modified(&self) -> Option<SystemTime> {
+        self.view().last_modified()
+    }
+
+    /// Returns true if the stored response has a valid `ETag` or `Last-Modified`, so that it
+    /// can be revalidated with a conditional request.
+    pub fn has_validators(&self) -> bool {
+        self.etag().is_some() || self.last_modified().is_some()
+    }
+
+    pub(super) fn is_permanent_redirect_status(&self) -> bool {
+        self.view().is_permanent_redirect_status()
+    }
+
+    /// Returns true if the stored response is a permanent redirect (301 or 308).
+    pub fn is_permanent_redirect(&self) -> bool {
+        self.is_permanent_redirect_status()
+    }
+
+    /// The stored response's `Content-Location`, resolved against the request URI: the URI of
+    /// the resource the content is a representation of (RFC 9110 section 8.7).
+    pub fn content_location(&self) -> Option<Uri> {
+        resolve_reference(&self.uri, header_str(&self.res, CONTENT_LOCATION)?)
+    }
+
+    /// Returns true if `Content-Location` identifies the request's own target, so that the
+    /// content is a representation of it whatever the request method was.
+    pub(super) fn content_location_is_target(&self) -> bool {
+        let location = match self.content_location() {
+            Some(location) => location,
+            None => return false,
+        };
+        same_origin(&self.uri, self.host.as_ref(), &location)
+            && comparable_uri(&location, &self.opts).path_and_query()
+                == comparable_uri(&self.uri, &self.opts).path_and_query()
+    }
+
+    /// If the stored response is a redirect (3xx), returns its `Location` resolved against
+    /// the URI of the stored request, or `None` if it has no usable `Location`.
+    pub fn redirect_location(&self) -> Option<Uri> {
+        if !self.status.is_redirection() {
+            return None;
+        }
+        resolve_reference(&self.uri, header_str(&self.res, LOCATION)?)
+    }
+
+    /// If the stored response is a permanent redirect (301 or 308), returns its `Location`.
+    pub fn permanent_redirect(&self) -> Option<&str> {
+        if !self.is_permanent_redirect_status() {
+            return None;
+        }
+        header_str(&self.res, LOCATION)
+    }
+
+    /// The response's directives as a map, including extensions the policy doesn't keep.
+    pub(super) fn response_cache_control(&self) -> CacheControl {
+        normalize_response_headers(&self.res, &self.opts).1
+    }
+
+    /// The response directives this policy actually honors, after the `ignore_cargo_cult`
+    /// option, merging of repeated headers, and the `Pragma: no-cache` fallback.
+    pub fn effective_response_directives(&self) -> CacheDirectives {
+        self.rescc.clone()
+    }
+
+    /// The directives of the original request that this policy honors, according to the
+    /// `honor_request_directives` option.
+    pub fn effective_request_directives(&self) -> CacheDirectives {
+        self.view().effective_request_directives()
+    }
+
+    /// The directives of a new request that this policy honors, with `Pragma: no-cache`
+    /// counting as `no-cache`.
+    pub(super) fn request_directives(&self, headers: &HeaderMap) -> CacheDirectives {
+        let mut directives = parse_request_cache_control(headers);
+        if header_str(headers, PRAGMA).is_some_and(|pragma| pragma.contains("no-cache")) {
+            directives.no_cache = true;
+        }
+        self.opts.honor_request_directives.apply(directives)
+    }
+
+    /// Parsed `Retry-After` header of the response, whether or not the response is storable.
+    pub fn retry_after(&self) -> Option<RetryAfter> {
+        self.view().retry_after()
+    }
+
+    /// Restores a policy saved with `to_object`. Returns `None` if the object is from an
+    /// incompatible version or is otherwise invalid.
+    pub fn from_object(obj: &CachePolicyObject) -> Option<CachePolicy> {
+        if obj.version != 1 {
+            return None;
+        }
+        Some(CachePolicy {
+            req: headers_from_pairs(&obj.request_headers)?,
+            res: headers_from_pairs(&obj.response_headers)?,
+            uri: obj.uri.parse().ok()?,
+            host: match &obj.host {
+                Some(host) => Some(HeaderValue::from_str(host).ok()?),
+                None => None,
+            },
+            method: Method::from_bytes(obj.method.as_bytes()).ok()?,
+            status: StatusCode::from_u16(obj.status).ok()?,
+            version: version_from_str(&obj.http_version)?,
+            no_authorization: obj.no_authorization,
+            reqcc: boxed_directives(CacheDirectives::from_cache_control(
+                &obj.request_cache_control,
+            )),
+            rescc: CacheDirectives::from_cache_control(&obj.response_cache_control),
+            opts: Arc::new(obj.options.clone()),
+            partition: obj.partition.clone().map(CachePartition),
+            storage_override: obj.storage_override,
+            path_ttl: obj.path_ttl,
+            pinned: obj.pinned,
+            conflicting_headers: obj
+                .conflicting_headers
+                .iter()
+                .map(|name| HeaderName::from_bytes(name.as_bytes()).ok())
+                .collect::<Option<_>>()?,
+            quirks: obj.quirks.clone(),
+            incomplete: obj.incomplete,
+            body_integrity: obj.body_integrity.clone(),
+            response_instant: None,
+            response_time: obj.response_time,
+        })
+    }
+
+    /// Re-classifies the stored exchange under `options`, e.g. when a shared cache imports
+    /// entries produced by a private one. Storability and freshness are recomputed from the
+    /// headers the policy retained.
+    ///
+    /// The original messages aren't available, so storage hooks see only the request
+    /// headers nominated by `Vary`, and normalizations the old options applied, such as
+    /// `ignore_cargo_cult`, stay applied. Pinned policies stay pinned.
+    pub fn with_options(self, options: CacheOptions) -> CachePolicy {
+        let (res, rescc, quirks) = parse_response_headers(&self.res, &options);
+        let mut request = Request::new(());
+        *request.method_mut() = self.method.clone();
+        *request.uri_mut() = self.uri.clone();
+        *request.headers_mut() = self.req.clone();
+        if let Some(host) = &self.host {
+            request.headers_mut().insert(HOST, host.clone());
+        }
+        let mut response = Response::new(());
+        *response.status_mut() = self.status;
+        *response.version_mut() = self.version;
+        *response.headers_mut() = res.clone();
+        let (storage_override, path_ttl) = if self.pinned {
+            (StorageOverride::AlwaysStore, None)
+        } else {
+            storage_decision(&options, &request, &response)
+        };
+        CachePolicy {
+            quirks: merge_quirks(&self.quirks, quirks),
+            res,
+            rescc,
+            opts: Arc::new(options),
+            storage_override,
+            path_ttl,
+            ..self
+        }
+    }
+
+    /// The key this policy's response is stored under. Equal to `cache_key_with` of the
+    /// request it was created for and the policy's options.
+    pub fn cache_key(&self) -> String {
+        format_cache_key(
+            self.partition.as_ref(),
+            &self.method,
+            &self.uri,
+            self.host.as_ref(),
+            &self.opts,
+        )
+    }
+
+    /// The partition the response was stored for, if any.
+    pub fn partition(&self) -> Option<&str> {
+        self.partition.as_ref().map(|p| p.0.as_str())
+    }
+
+    /// The method of the request the policy was created for.
+    pub fn original_method(&self) -> &Method {
+        &self.method
+    }
+
+    /// The URI of the request the policy was created for, as it was received.
+    pub fn original_uri(&self) -> &Uri {
+        &self.uri
+    }
+
+    /// The headers of the original request that the response's `Vary` nominates. These are
+    /// the only request headers the policy keeps.
+    pub fn original_vary_headers(&self) -> &HeaderMap {
+        &self.req
+    }
+
+    /// Saves the policy as plain data, so that it can be stored along with the response.
+    pub fn to_object(&self) -> CachePolicyObject {
+        CachePolicyObject {
+            version: 1,
+            response_time: self.response_time,
+            options: (*self.opts).clone(),
+            status: self.status.as_u16(),
+            http_version: version_to_str(self.version).to_string(),
+            response_headers: headers_to_pairs(&self.res),
+            response_cache_control: self.response_cache_control(),
+            method: self.method.as_str().to_string(),
+            uri: self.uri.to_string(),
+            host: self
+                .host
+                .as_ref()
+                .map(|host| String::from_utf8_lossy(host.as_bytes()).into_owned()),
+            no_authorization: self.no_authorization,
+            request_headers: headers_to_pairs(&self.req),
+            request_cache_control: directives_to_cache_control(
+                self.reqcc.as_deref().unwrap_or(&NO_DIRECTIVES),
+            ),
+            partition: self.partition.as_ref().map(|p| p.0.clone()),
+            storage_override: self.storage_override,
+            path_ttl: self.path_ttl,
+            pinned: self.pinned,
+            conflicting_headers: self
+                .conflicting_headers
+                .iter()
+                .map(|name| name.as_str().to_string())
+                .collect(),
+            incomplete: self.incomplete,
+            body_integrity: self.body_integrity.clone(),
+            quirks: self.quirks.clone(),
+        }
+    }
+}
+
+impl PolicyView<'_> {
+    pub(super) fn storable(&self) -> bool {
+        self.unstorable_reason().is_none()
+    }
+
+    pub(super) fn unstorable_reason(&self) -> Option<UnstorableReason> {
+        if self.storage_override == StorageOverride::NeverStore {
+            return Some(UnstorableReason::Vetoed);
+        }
+        if self.opts.duplicate_headers == DuplicateHeaderMode::Strict
+            && conflicting_header(self.res).is_some()
+        {
+            return Some(UnstorableReason::ConflictingHeaders);
+        }
+        if self.opts.reject_vary && self.res.contains_key(VARY) {
+            return Some(UnstorableReason::Vary);
+        }
+        let count = vary_fields(self.res).count();
+        if let Some(limit) = self.opts.max_vary_fields.filter(|&limit| count > limit) {
+            return Some(UnstorableReason::TooManyVaryFields { count, limit });
+        }
+        if vary_fields(self.res)
+            .filter_map(|name| HeaderName::from_bytes(name).ok())
+            .any(|name| self.opts.vary_deny_list.contains(&name))
+        {
+            return Some(UnstorableReason::DeniedVaryField);
+        }
+        if self.opts.reject_absurd_values && self.has_absurd_value() {
+            return Some(UnstorableReason::AbsurdValue);
+        }
+        if let (Some(content_length), Some(limit)) =
+            (self.content_length(), self.opts.max_object_size)
+        {
+            if content_length > limit {
+                return Some(UnstorableReason::TooLarge {
+                    content_length,
+                    limit,
+                });
+            }
+        }
+        if self.storage_override == StorageOverride::AlwaysStore {
+            None
+        } else if !self.request_allows_storing() {
+            Some(UnstorableReason::RequestForbids)
+        } else if !self.response_allows_storing() {
+            Some(UnstorableReason::ResponseForbids)
+        } else {
+            None
+        }
+    }
+
+    pub(super) fn has_absurd_value(&self) -> bool {
+        let absurd = |seconds: Option<u64>| seconds.is_some_and(|s| s > MAX_DELTA_SECONDS);
+        absurd(select_age(self.res, AgeSelection::Max))
+            || absurd(self.rescc.max_age)
+            || absurd(self.rescc.s_maxage)
+    }
+
+    pub(super) fn content_length(&self) -> Option<u64> {
+        header_str(self.res, CONTENT_LENGTH)?.trim().parse().ok()
+    }
+
+    pub(super) fn request_allows_storing(&self) -> bool {
+        self.request_storage_violation().is_none()
+    }
+
+    pub(super) fn request_storage_violation(&self) -> Option<RfcCitation> {
+        // The "no-store" request directive indicates that a cache MUST NOT store any part
+        // of either this request or any response to it.
+        if self.effective_request_directives().no_store {
+            return Some(REQUEST_NO_STORE);
+        }
+        // A cache MUST NOT store a response to any request, unless:
+        // The request method is understood by the cache and defined as being cacheable, and
+        if !(self.method == Method::GET
+            || self.method == Method::HEAD
+            || (self.method == Method::POST && self.has_explicit_expiration()))
+        {
+            return Some(STORING_RESPONSES);
+        }
+        // the Authorization header field does not appear in the request, if the cache is shared,
+        if self.opts.shared && !self.no_authorization && !self.allows_storing_authenticated() {
+            return Some(AUTHENTICATED_REQUESTS);
+        }
+        None
+    }
+
+    pub(super) fn response_allows_storing(&self) -> bool {
+        self.response_storage_violation().is_none()
+    }
+
+    pub(super) fn response_storage_violation(&self) -> Option<RfcCitation> {
+        // the response status code is understood by the cache, and
+        if !(is_understood_status(self.status) || self.is_cacheable_retry_after()) {
+            return Some(STORING_RESPONSES);
+        }
+        // the "no-store" cache directive does not appear in request or response header fields, and
+        if self.rescc.no_store {
+            return Some(RESPONSE_NO_STORE);
+        }
+        // the "private" response directive does not appear in the response, if the cache is
+        // shared, and
+        if self.opts.shared && self.rescc.private {
+            return Some(PRIVATE);
+        }
+        // the response either:
+        // contains an Expires header field, or
+        let has_freshness_information = self.res.contains_key(EXPIRES)
+            // contains a max-age response directive, or
+            // contains a s-maxage response directive and the cache is shared, or
+            // contains a public response directive.
+            || self.rescc.max_age.is_some()
+            || (self.opts.shared && self.rescc.s_maxage.is_some())
+            || self.rescc.public
+            // has a status code that is defined as cacheable by default
+            || is_cacheable_by_default(self.status)
+            // or tells clients when to come back.
+            || self.is_cacheable_retry_after();
+        if !has_freshness_information {
+            return Some(STORING_RESPONSES);
+        }
+        if self.requires_explicit_freshness() && !self.has_explicit_expiration() {
+            return Some(HEURISTIC_FRESHNESS);
+        }
+        if self.opts.past_expires == PastExpires::Unstorable && self.expires_predates_date() {
+            return Some(EXPIRES_FIELD);
+        }
+        None
+    }
+
+    pub(super) fn expires_predates_date(&self) -> bool {
+        let overridden =
+            self.rescc.max_age.is_some() || (self.opts.shared && self.rescc.s_maxage.is_some());
+        !overridden
+            && self
+                .res
+                .get(EXPIRES)
+                .and_then(parse_http_date)
+                .is_some_and(|expires| expires < self.date())
+    }
+
+    pub(super) fn requires_explicit_freshness(&self) -> bool {
+        self.opts.shared && self.opts.require_explicit_freshness
+    }
+
+    pub(super) fn has_explicit_expiration(&self) -> bool {
+        // 4.2.1 Calculating Freshness Lifetime
+        (self.opts.shared && self.rescc.s_maxage.is_some())
+            || self.rescc.max_age.is_some()
+            || self.res.contains_key(EXPIRES)
+    }
+
+    pub(super) fn allows_storing_authenticated(&self) -> bool {
+        // following Cache-Control response directives (Section 5.2.2) have such an effect:
+        // must-revalidate, public, and s-maxage.
+        self.rescc.must_revalidate || self.rescc.public || self.rescc.s_maxage.is_some()
+    }
+
+    pub(super) fn last_modified(&self) -> Option<SystemTime> {
+        self.res.get(LAST_MODIFIED).and_then(parse_http_date)
+    }
+
+    pub(super) fn is_permanent_redirect_status(&self) -> bool {
+        self.status == StatusCode::MOVED_PERMANENTLY
+            || self.status == StatusCode::PERMANENT_REDIRECT
+    }
+
+    pub(super) fn is_cacheable_retry_after(&self) -> bool {
+        self.opts.cache_retry_after
+            && (self.status == StatusCode::TOO_MANY_REQUESTS
+                || self.status == StatusCode::SERVICE_UNAVAILABLE)
+            && self.retry_after().is_some()
+    }
+
+    pub(super) fn effective_request_directives(&self) -> CacheDirectives {
+        self.opts.honor_request_directives.apply(self.reqcc.clone())
+    }
+
+    pub(super) fn retry_after(&self) -> Option<RetryAfter> {
+        let value = self.res.get(RETRY_AFTER)?;
+        let text = value.to_str().ok()?.trim();
+        if !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit()) {
+            return text.parse().ok().map(RetryAfter::Delay);
+        }
+        parse_http_date(value).map(RetryAfter::Date)
+    }
+}
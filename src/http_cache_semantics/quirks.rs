@@ -0,0 +1,197 @@
+//! Opt-in workarounds for widespread origin misconfigurations. See `CacheOptions::quirks`.
+
+use super::*;
+
+/// Workarounds for widespread origin misconfigurations, each off by default. See
+/// `CacheOptions::quirks`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Quirks {
+    /// Treat an `Expires` that is a number, such as `0` or `-1`, as `no-cache`, since
+    /// origins send it to mean "don't cache". Without this it only means the response has
+    /// already expired, so it can still be served stale. `max-age` and `s-maxage` override it.
+    pub expires_zero: bool,
+    /// Ignore `public` when `Cache-Control` also says `private`.
+    pub private_and_public: bool,
+    /// Read the directives after a `;` in a `max-age` or `s-maxage` value, as in
+    /// `max-age=3600;public`. The leading digits are used either way.
+    pub max_age_trailing_garbage: bool,
+    /// Treat `Vary: *` as `no-store`, as origins that send it intend. Such responses can
+    /// never be reused anyway.
+    pub vary_star_no_store: bool,
+    /// Accept `Date`, `Expires` and `Last-Modified` values whose weekday, month or `GMT`
+    /// is in the wrong case, such as `mon, 01 jan 2024 00:00:00 gmt`, and store them
+    /// recased.
+    pub lowercase_dates: bool,
+}
+
+impl Quirks {
+    /// Every quirk enabled.
+    pub fn all() -> Quirks {
+        Quirks {
+            expires_zero: true,
+            private_and_public: true,
+            max_age_trailing_garbage: true,
+            vary_star_no_store: true,
+            lowercase_dates: true,
+        }
+    }
+}
+
+/// A misconfiguration worked around under `CacheOptions::quirks`. See the `Quirks` field
+/// of the same name for each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Quirk {
+    /// `Quirks::expires_zero`.
+    ExpiresZero,
+    /// `Quirks::private_and_public`.
+    PrivateAndPublic,
+    /// `Quirks::max_age_trailing_garbage`.
+    MaxAgeTrailingGarbage,
+    /// `Quirks::vary_star_no_store`.
+    VaryStarNoStore,
+    /// `Quirks::lowercase_dates`.
+    LowercaseDates,
+}
+
+/// The quirks enabled in `quirks` that `headers`, whose parsed `Cache-Control` is `cc`,
+/// exhibit, in the order `Quirk` lists them.
+fn response_quirks(headers: &HeaderMap, cc: &CacheControl, quirks: &Quirks) -> Vec<Quirk> {
+    let mut found = Vec::new();
+    if *quirks == Quirks::default() {
+        return found;
+    }
+    if quirks.expires_zero
+        && !cc.contains_key("max-age")
+        && !cc.contains_key("s-maxage")
+        && header_str(headers, EXPIRES).is_some_and(is_number)
+    {
+        found.push(Quirk::ExpiresZero);
+    }
+    if quirks.private_and_public && cc.contains_key("private") && cc.contains_key("public") {
+        found.push(Quirk::PrivateAndPublic);
+    }
+    let has_garbage = |name: &str| {
+        cc.get(name)
+            .and_then(Option::as_ref)
+            .is_some_and(|value| !value.bytes().all(|b| b.is_ascii_digit()))
+    };
+    if quirks.max_age_trailing_garbage && (has_garbage("max-age") || has_garbage("s-maxage")) {
+        found.push(Quirk::MaxAgeTrailingGarbage);
+    }
+    if quirks.vary_star_no_store && vary_fields(headers).any(|name| name == b"*") {
+        found.push(Quirk::VaryStarNoStore);
+    }
+    if quirks.lowercase_dates
+        && [DATE, EXPIRES, LAST_MODIFIED]
+            .iter()
+            .any(|name| headers.get(name).and_then(recased_date).is_some())
+    {
+        found.push(Quirk::LowercaseDates);
+    }
+    found
+}
+
+/// Adds the quirks `headers` exhibit to those already `known`.
+pub(super) fn add_quirks(known: &[Quirk], headers: &HeaderMap, quirks: &Quirks) -> Vec<Quirk> {
+    if *quirks == Quirks::default() {
+        return known.to_vec();
+    }
+    let cc = parse_cache_control(headers.get_all(CACHE_CONTROL));
+    merge_quirks(known, response_quirks(headers, &cc, quirks))
+}
+
+/// Adds the quirks in `found` to those already `known`.
+pub(super) fn merge_quirks(known: &[Quirk], found: Vec<Quirk>) -> Vec<Quirk> {
+    let mut all = known.to_vec();
+    for quirk in found {
+        if !all.contains(&quirk) {
+            all.push(quirk);
+        }
+    }
+    all
+}
+
+/// Works around the quirks enabled in `quirks` that the response exhibits, adjusting its
+/// parsed directives and rewriting its headers if needed. Returns the quirks it worked around.
+pub(super) fn apply_quirks(
+    res: &mut Cow<'_, HeaderMap>,
+    rescc: &mut CacheControl,
+    quirks: &Quirks,
+) -> Vec<Quirk> {
+    let found = response_quirks(res, rescc, quirks);
+    for quirk in &found {
+        match quirk {
+            Quirk::ExpiresZero => {
+                rescc.insert("no-cache".to_string(), None);
+            }
+            Quirk::PrivateAndPublic => {
+                rescc.remove("public");
+            }
+            Quirk::MaxAgeTrailingGarbage => recover_directives(rescc),
+            Quirk::VaryStarNoStore => {
+                rescc.insert("no-store".to_string(), None);
+            }
+            Quirk::LowercaseDates => {
+                let res = res.to_mut();
+                for name in &[DATE, EXPIRES, LAST_MODIFIED] {
+                    if let Some(value) = res.get(name).and_then(recased_date) {
+                        res.insert(name.clone(), value);
+                    }
+                }
+            }
+        }
+    }
+    found
+}
+
+/// Returns true for values such as `0` or `-1`.
+fn is_number(value: &str) -> bool {
+    let digits = value.trim().trim_start_matches('-');
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Splits directives that follow a `;` out of `max-age` and `s-maxage` values, as in
+/// `max-age=3600;public`.
+fn recover_directives(rescc: &mut CacheControl) {
+    for name in &["max-age", "s-maxage"] {
+        let value = match rescc.get(*name) {
+            Some(Some(value)) if value.contains(';') => value.clone(),
+            _ => continue,
+        };
+        let (seconds, rest) = value.split_once(';').unwrap_or((&value, ""));
+        rescc.insert(name.to_string(), Some(seconds.trim().to_string()));
+        let rest = rest.replace(';', ",");
+        for (name, value) in CacheControlTokenizer::new(rest.as_bytes()) {
+            let name = String::from_utf8_lossy(name).to_ascii_lowercase();
+            let value = value.map(|v| String::from_utf8_lossy(v).into_owned());
+            rescc.insert(name, value);
+        }
+    }
+}
+
+/// A date that only parses once its weekday, month and `GMT` are in canonical case, recased.
+fn recased_date(value: &HeaderValue) -> Option<HeaderValue> {
+    if parse_http_date(value).is_some() {
+        return None;
+    }
+    let mut recased = String::new();
+    let mut word_start = true;
+    for c in value.to_str().ok()?.chars() {
+        if c.is_ascii_alphabetic() {
+            recased.push(if word_start {
+                c.to_ascii_uppercase()
+            } else {
+                c.to_ascii_lowercase()
+            });
+            word_start = false;
+        } else {
+            recased.push(c);
+            word_start = true;
+        }
+    }
+    let recased = HeaderValue::from_str(&recased.replace("Gmt", "GMT")).ok()?;
+    parse_http_date(&recased).map(|_| recased)
+}
@@ -0,0 +1,433 @@
+//! Conditional requests to the origin and updating the stored response from their results
+//! (RFC 9111 section 4.3).
+
+use super::*;
+
+/// Headers describing the stored body, which a 304 must not overwrite.
+pub(super) fn is_excluded_from_revalidation_update(name: &HeaderName) -> bool {
+    matches!(
+        name.as_str(),
+        "content-length" | "content-encoding" | "transfer-encoding" | "content-range"
+    )
+}
+
+pub(super) fn strip_weak(etag: &[u8]) -> &[u8] {
+    let etag = etag.trim_ascii_start();
+    etag.strip_prefix(b"W/").unwrap_or(etag)
+}
+
+pub(super) fn is_weak(etag: &HeaderValue) -> bool {
+    etag.as_bytes().trim_ascii_start().starts_with(b"W/")
+}
+
+/// Result of `CachePolicy::revalidated_policy`.
+#[derive(Debug)]
+pub struct RevalidatedPolicy {
+    /// Policy to store in place of the old one. The new response's directives take effect,
+    /// so if it isn't `storable()`, e.g. because a 304 carried `no-store`, evict the stored
+    /// response instead.
+    pub policy: CachePolicy,
+    /// If true, the response body has changed and the stored one can't be used.
+    pub modified: bool,
+    /// If true, the new response's validators matched the stored response.
+    pub matches: bool,
+}
+
+/// Parses a `Content-Range` of the form `bytes first-last/complete-length` (RFC 9110 section
+/// 14.4).
+fn parse_content_range(value: &str) -> Option<(u64, u64, u64)> {
+    let range = value.trim().strip_prefix("bytes ")?;
+    let (positions, complete_length) = range.split_once('/')?;
+    let (first, last) = positions.split_once('-')?;
+    let (first, last) = (first.trim().parse().ok()?, last.trim().parse().ok()?);
+    if first > last {
+        return None;
+    }
+    Some((first, last, complete_length.trim().parse().ok()?))
+}
+
+impl CachePolicy {
+    pub(super) fn upstream_request<B>(&self, request: &Request<B>) -> Request<()> {
+        let mut upstream_request = Request::new(());
+        *upstream_request.method_mut() = request.method().clone();
+        *upstream_request.uri_mut() = request.uri().clone();
+        *upstream_request.version_mut() = request.version();
+        *upstream_request.headers_mut() = self.revalidation_headers(request);
+        if self.opts.revalidate_with_head && request.method() == Method::GET {
+            *upstream_request.method_mut() = Method::HEAD;
+            let headers = upstream_request.headers_mut();
+            headers.remove(IF_NONE_MATCH);
+            headers.remove(IF_MODIFIED_SINCE);
+        }
+        upstream_request
+    }
+
+    /// Returns the headers to send to the origin server when revalidating the stored
+    /// response for `incoming_req`, including any applicable validators.
+    pub fn revalidation_headers<B>(&self, incoming_req: &Request<B>) -> HeaderMap {
+        let mut headers = Self::copy_without_hop_by_hop_headers(incoming_req.headers());
+
+        // This implementation does not understand range requests
+        headers.remove(IF_RANGE);
+
+        if !self.request_matches(incoming_req, true) || !self.storable() {
+            // revalidation allowed via HEAD
+            // not for the same resource, or wasn't allowed to be cached anyway
+            headers.remove(IF_NONE_MATCH);
+            headers.remove(IF_MODIFIED_SINCE);
+            return headers;
+        }
+
+        // MUST send that entity-tag in any cache validation request (using If-Match or
+        // If-None-Match) if an entity-tag has been provided by the origin server.
+        if let Some(etag) = self.res.get(ETAG) {
+            let value = match headers.get(IF_NONE_MATCH) {
+                Some(if_none_match) => HeaderValue::from_bytes(
+                    &[if_none_match.as_bytes(), b", ", etag.as_bytes()].concat(),
+                )
+                .unwrap_or_else(|_| etag.clone()),
+                None => etag.clone(),
+            };
+            headers.insert(IF_NONE_MATCH, value);
+        }
+
+        // Clients MAY issue simple (non-subrange) GET requests with either weak validators
+        // or strong validators. Clients MUST NOT use weak validators in other forms of request.
+        let forbids_weak_validators = headers.contains_key(ACCEPT_RANGES)
+            || headers.contains_key(IF_MATCH)
+            || headers.contains_key(IF_UNMODIFIED_SINCE)
+            || self.method != Method::GET;
+
+        // SHOULD send the Last-Modified value in non-subrange cache validation requests
+        // (using If-Modified-Since) if only a Last-Modified value has been provided by the
+        // origin server.
+        // Note: This implementation does not understand partial responses (206)
+        if forbids_weak_validators {
+            headers.remove(IF_MODIFIED_SINCE);
+
+            if let Some(if_none_match) = headers.remove(IF_NONE_MATCH) {
+                // Entity-tags are opaque, so filter them without assuming they are text.
+                let etags: Vec<&[u8]> = list_items(if_none_match.as_bytes())
+                    .filter(|etag| !etag.starts_with(b"W/"))
+                    .collect();
+                if !etags.is_empty() {
+                    if let Ok(value) = HeaderValue::from_bytes(&etags.join(&b", "[..])) {
+                        headers.insert(IF_NONE_MATCH, value);
+                    }
+                }
+            }
+        } else if let Some(last_modified) = self.res.get(LAST_MODIFIED) {
+            if !headers.contains_key(IF_MODIFIED_SINCE) {
+                headers.insert(IF_MODIFIED_SINCE, last_modified.clone());
+            }
+        }
+
+        headers
+    }
+
+    /// Returns a request that resumes a download of the stored response after the first
+    /// `stored_bytes` bytes, using `Range: bytes=N-` guarded by `If-Range`.
+    ///
+    /// `If-Range` requires a strong validator (RFC 9110 section 13.1.5): a strong `ETag` if
+    /// there is one, or else a `Last-Modified` date at least one second older than `Date`.
+    /// Returns `None` when no strong validator is available, or when there's nothing left to
+    /// fetch, since the origin would otherwise be free to splice together two different
+    /// representations. For a stored `206`, the length of the whole representation is taken
+    /// from `Content-Range`, as `Content-Length` only counts the stored part.
+    pub fn range_resume_request<B>(
+        &self,
+        request: &Request<B>,
+        stored_bytes: u64,
+    ) -> Option<Request<()>> {
+        let complete_length = if self.status == StatusCode::PARTIAL_CONTENT {
+            header_str(&self.res, CONTENT_RANGE)
+                .and_then(parse_content_range)
+                .map(|(_, _, complete_length)| complete_length)
+        } else {
+            self.content_length()
+        };
+        if stored_bytes == 0
+            || complete_length.is_some_and(|len| stored_bytes >= len)
+            || !(self.status == StatusCode::OK || self.status == StatusCode::PARTIAL_CONTENT)
+        {
+            return None;
+        }
+
+        let (_, validator) = self.strong_validator()?;
+
+        let mut headers = Self::copy_without_hop_by_hop_headers(request.headers());
+        for name in &[
+            IF_MATCH,
+            IF_NONE_MATCH,
+            IF_MODIFIED_SINCE,
+            IF_UNMODIFIED_SINCE,
+        ] {
+            headers.remove(name);
+        }
+        headers.insert(
+            RANGE,
+            HeaderValue::from_str(&format!("bytes={}-", stored_bytes)).ok()?,
+        );
+        headers.insert(IF_RANGE, validator.clone());
+
+        let mut resume = Request::new(());
+        *resume.method_mut() = Method::GET;
+        *resume.uri_mut() = request.uri().clone();
+        *resume.version_mut() = request.version();
+        *resume.headers_mut() = headers;
+        Some(resume)
+    }
+
+    /// The stored response's strong `ETag`, or else its `Last-Modified` if that is at least one
+    /// second older than `Date` and therefore strong (RFC 9110 section 8.8.2.2).
+    fn strong_validator(&self) -> Option<(HeaderName, &HeaderValue)> {
+        match self.etag() {
+            Some(etag) if !etag.weak => Some((ETAG, self.res.get(ETAG)?)),
+            _ => {
+                let last_modified = self.last_modified()?;
+                let date = self.server_date()?;
+                if seconds_between(last_modified, date) < 1 {
+                    return None;
+                }
+                Some((LAST_MODIFIED, self.res.get(LAST_MODIFIED)?))
+            }
+        }
+    }
+
+    /// Completes an incomplete stored response with a `206 Partial Content` answer to its
+    /// `CacheAction::Resume` request, received at `now` (RFC 9111 section 3.4).
+    ///
+    /// The parts can only be combined if both carry the same strong validator and the `206`
+    /// covers everything from the end of the stored bytes to the end of the representation.
+    /// Returns the policy for the complete response, with headers updated from the `206`, and
+    /// the number of leading bytes of the `206` body that duplicate stored ones and must be
+    /// skipped when appending it. Returns `None` if the parts can't be combined.
+    pub fn complete_with<B>(
+        &self,
+        response: &Response<B>,
+        now: SystemTime,
+    ) -> Option<(CachePolicy, u64)> {
+        let stored_bytes = self.incomplete?;
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return None;
+        }
+        let (name, validator) = self.strong_validator()?;
+        if response.headers().get(&name) != Some(validator) {
+            return None;
+        }
+        let (first, last, complete_length) =
+            parse_content_range(header_str(response.headers(), CONTENT_RANGE)?)?;
+        if first > stored_bytes
+            || last.checked_add(1) != Some(complete_length)
+            || self
+                .content_length()
+                .is_some_and(|len| len != complete_length)
+        {
+            return None;
+        }
+
+        let mut headers = self.merge_headers(response.headers());
+        headers.remove(CONTENT_RANGE);
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(complete_length));
+        let (res, rescc, _) = parse_response_headers(&headers, &self.opts);
+        let policy = CachePolicy {
+            res,
+            rescc,
+            conflicting_headers: add_conflicts(&self.conflicting_headers, response.headers()),
+            quirks: add_quirks(&self.quirks, response.headers(), &self.opts.quirks),
+            incomplete: None,
+            body_integrity: None,
+            response_instant: None,
+            response_time: now,
+            ..self.clone()
+        };
+        Some((policy, stored_bytes - first))
+    }
+
+    /// Creates an updated policy from the response to a revalidation request, which was
+    /// received at `response_time`.
+    ///
+    /// If the response is a `304 Not Modified` whose validators match the stored response,
+    /// the stored headers are updated from it and the stored body can be reused.
+    pub fn revalidated_policy<Req, Res>(
+        &self,
+        request: &Request<Req>,
+        response: &Response<Res>,
+        response_time: SystemTime,
+    ) -> RevalidatedPolicy {
+        let head_revalidation = request.method() == Method::HEAD && self.method != Method::HEAD;
+        let matches = if head_revalidation && response.status() == StatusCode::OK {
+            self.head_validators_match(response.headers())
+        } else {
+            self.validators_match(response.status(), response.headers())
+        };
+        if !matches {
+            return RevalidatedPolicy {
+                policy: policy_for_shared_options(request, response, &self.opts, response_time),
+                modified: response.status() != StatusCode::NOT_MODIFIED,
+                matches: false,
+            };
+        }
+
+        let mut new_response = Response::new(());
+        *new_response.status_mut() = self.status;
+        *new_response.version_mut() = self.version;
+        *new_response.headers_mut() = self.merge_headers(response.headers());
+        // A HEAD revalidation freshens the stored response to the original method.
+        let mut stored_request = Request::new(());
+        *stored_request.method_mut() = self.method.clone();
+        *stored_request.uri_mut() = request.uri().clone();
+        *stored_request.headers_mut() = request.headers().clone();
+        let mut policy =
+            policy_for_shared_options(&stored_request, &new_response, &self.opts, response_time);
+        // The updated response stays in the partition it was stored in.
+        policy.partition = self.partition.clone();
+        policy.pinned = self.pinned;
+        policy.conflicting_headers = add_conflicts(&self.conflicting_headers, response.headers());
+        policy.quirks = add_quirks(&self.quirks, response.headers(), &self.opts.quirks);
+        policy.incomplete = self.incomplete;
+        policy.body_integrity = self.body_integrity.clone();
+        RevalidatedPolicy {
+            policy,
+            modified: false,
+            matches: true,
+        }
+    }
+
+    /// Freshens the stored response from a `304 Not Modified` received at `now`, returning
+    /// the policy and the response headers to store in place of the old ones.
+    ///
+    /// The 304's headers replace the stored ones (except for those describing the body), its
+    /// `Cache-Control` and `Expires` are added if the stored response lacked them, its
+    /// directives take effect, and the response's age starts over. If the
+    /// resulting policy isn't `storable()`, e.g. because the 304 carried `no-store`, evict the
+    /// stored response. Returns `None` if
+    /// the 304 doesn't match the stored response, which must then be fetched again.
+    pub fn freshen<B>(
+        &self,
+        new_response: &Response<B>,
+        now: SystemTime,
+    ) -> Option<(CachePolicy, HeaderMap)> {
+        let matches = if self.opts.revalidate_with_head && new_response.status() == StatusCode::OK {
+            self.head_validators_match(new_response.headers())
+        } else {
+            self.validators_match(new_response.status(), new_response.headers())
+        };
+        if !matches {
+            return None;
+        }
+
+        let headers = self.merge_headers(new_response.headers());
+        let (res, rescc, _) = parse_response_headers(&headers, &self.opts);
+        let policy = CachePolicy {
+            req: self.req.clone(),
+            res,
+            uri: self.uri.clone(),
+            host: self.host.clone(),
+            method: self.method.clone(),
+            status: self.status,
+            version: self.version,
+            no_authorization: self.no_authorization,
+            reqcc: self.reqcc.clone(),
+            rescc,
+            opts: self.opts.clone(),
+            partition: self.partition.clone(),
+            storage_override: self.storage_override,
+            path_ttl: self.path_ttl,
+            pinned: self.pinned,
+            conflicting_headers: add_conflicts(&self.conflicting_headers, new_response.headers()),
+            quirks: add_quirks(&self.quirks, new_response.headers(), &self.opts.quirks),
+            incomplete: self.incomplete,
+            body_integrity: self.body_integrity.clone(),
+            response_instant: None,
+            response_time: now,
+        };
+        Some((policy, headers))
+    }
+
+    /// Returns true if a `200` response to a `HEAD` request describes the same representation
+    /// as the stored response (RFC 9111 section 4.3.5): the same strong `ETag`, the same
+    /// `Last-Modified`, and the same `Content-Length` if both have one.
+    pub(super) fn head_validators_match(&self, new_headers: &HeaderMap) -> bool {
+        let same_etag = match (self.res.get(ETAG), new_headers.get(ETAG)) {
+            (Some(etag), Some(new_etag)) => !is_weak(etag) && etag == new_etag,
+            (None, None) => true,
+            _ => false,
+        };
+        let same_length = match (
+            self.content_length(),
+            header_str(new_headers, CONTENT_LENGTH),
+        ) {
+            (Some(length), Some(new_length)) => new_length.trim().parse() == Ok(length),
+            _ => true,
+        };
+        (self.res.contains_key(ETAG) || self.res.contains_key(LAST_MODIFIED))
+            && same_etag
+            && self.res.get(LAST_MODIFIED) == new_headers.get(LAST_MODIFIED)
+            && same_length
+    }
+
+    pub(super) fn validators_match(&self, status: StatusCode, new_headers: &HeaderMap) -> bool {
+        // These aren't going to be supported exactly, since one CachePolicy object
+        // doesn't know about all the other cached objects.
+        if status != StatusCode::NOT_MODIFIED {
+            false
+        } else if let Some(new_etag) = new_headers.get(ETAG).filter(|etag| !is_weak(etag)) {
+            // "All of the stored responses with the same strong validator are selected.
+            // If none of the stored responses contain the same strong validator,
+            // then the cache MUST NOT use the new response to update any stored responses."
+            self.res
+                .get(ETAG)
+                .is_some_and(|etag| strip_weak(etag.as_bytes()) == new_etag.as_bytes())
+        } else if let (Some(etag), Some(new_etag)) = (self.res.get(ETAG), new_headers.get(ETAG)) {
+            // "If the new response contains a weak validator and that validator corresponds
+            // to one of the cache's stored responses,
+            // then the most recent of those matching stored responses is selected for update."
+            strip_weak(etag.as_bytes()) == strip_weak(new_etag.as_bytes())
+        } else if let Some(last_modified) = self.res.get(LAST_MODIFIED) {
+            new_headers.get(LAST_MODIFIED) == Some(last_modified)
+        } else {
+            // If the new response does not include any form of validator (such as in the case
+            // where a client generates an If-Modified-Since request from a source other than
+            // the Last-Modified response header field), and there is only one stored response,
+            // and that stored response also lacks a validator, then that stored response is
+            // selected for update.
+            !self.res.contains_key(ETAG)
+                && !self.res.contains_key(LAST_MODIFIED)
+                && !new_headers.contains_key(ETAG)
+                && !new_headers.contains_key(LAST_MODIFIED)
+        }
+    }
+
+    pub(super) fn merge_headers(&self, new_headers: &HeaderMap) -> HeaderMap {
+        // use other header fields provided in the 304 (Not Modified) response to replace all
+        // instances of the corresponding header fields in the stored response.
+        let mut headers = HeaderMap::new();
+        for name in self.res.keys() {
+            let source =
+                if new_headers.contains_key(name) && !is_excluded_from_revalidation_update(name) {
+                    new_headers
+                } else {
+                    &self.res
+                };
+            for value in source.get_all(name) {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+        // Other new fields are ignored, but freshness information the stored response lacked
+        // is added, so that directives first sent in the 304, such as no-store, take effect.
+        for name in &[CACHE_CONTROL, EXPIRES] {
+            if self.res.contains_key(name) {
+                continue;
+            }
+            for value in new_headers.get_all(name) {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+        // delete any Warning header fields in the stored response with warn-code 1xx
+        if !new_headers.contains_key(WARNING) {
+            strip_1xx_warnings(&mut headers);
+        }
+        headers
+    }
+}
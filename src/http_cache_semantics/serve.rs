@@ -0,0 +1,809 @@
+//! Deciding how to answer a request from the cache, and the headers to answer it with,
+//! including `Warning`, `Via` and `Alt-Svc`.
+
+use super::*;
+
+fn is_hop_by_hop_header(name: &HeaderName) -> bool {
+    matches!(
+        name.as_str(),
+        "date"
+            | "connection"
+            | "keep-alive"
+            | "proxy-authenticate"
+            | "proxy-authorization"
+            | "te"
+            | "trailer"
+            | "transfer-encoding"
+            | "upgrade"
+    )
+}
+
+/// Keeps the headers a `304 Not Modified` carries from the `200` it stands for (RFC 9110
+/// section 15.4.5), along with `Age`.
+fn not_modified_headers(headers: &HeaderMap) -> HeaderMap {
+    let mut kept = HeaderMap::new();
+    for name in &[
+        AGE,
+        CACHE_CONTROL,
+        CONTENT_LOCATION,
+        DATE,
+        ETAG,
+        EXPIRES,
+        VARY,
+    ] {
+        for value in headers.get_all(name) {
+            kept.append(name.clone(), value.clone());
+        }
+    }
+    kept
+}
+
+fn is_1xx_warning(warning: &str) -> bool {
+    let warning = warning.trim_start().as_bytes();
+    warning.len() >= 3
+        && warning[0] == b'1'
+        && warning[1].is_ascii_digit()
+        && warning[2].is_ascii_digit()
+}
+
+/// Removes warnings with a 1xx warn-code, which describe the freshness of a response and
+/// must not outlive it.
+pub(super) fn strip_1xx_warnings(headers: &mut HeaderMap) {
+    if !headers.contains_key(WARNING) {
+        return;
+    }
+    let warnings: Vec<String> = headers
+        .get_all(WARNING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter(|warning| !is_1xx_warning(warning))
+        .map(str::to_string)
+        .collect();
+    headers.remove(WARNING);
+    if let Ok(value) = HeaderValue::from_str(warnings.join(",").trim()) {
+        if !value.is_empty() {
+            headers.insert(WARNING, value);
+        }
+    }
+}
+
+fn add_warning(headers: &mut HeaderMap, warning: &str) {
+    let warning = match header_str(headers, WARNING) {
+        Some(existing) => format!("{}, {}", existing, warning),
+        None => warning.to_string(),
+    };
+    if let Ok(value) = HeaderValue::from_str(&warning) {
+        headers.insert(WARNING, value);
+    }
+}
+
+fn via_entry(version: Version, pseudonym: &str) -> String {
+    let protocol = match version {
+        Version::HTTP_09 => "0.9",
+        Version::HTTP_10 => "1.0",
+        Version::HTTP_2 => "2",
+        Version::HTTP_3 => "3",
+        _ => "1.1",
+    };
+    format!("{} {}", protocol, pseudonym)
+}
+
+fn via_received_by(headers: &HeaderMap) -> impl Iterator<Item = &str> {
+    headers
+        .get_all(VIA)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|entry| entry.split_whitespace().nth(1))
+}
+
+/// Splits `value` at each `separator` that isn't inside a quoted string.
+fn split_unquoted(value: &str, separator: u8) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (i, b) in value.bytes().enumerate() {
+        if escaped {
+            escaped = false;
+        } else if quoted && b == b'\\' {
+            escaped = true;
+        } else if b == b'"' {
+            quoted = !quoted;
+        } else if !quoted && b == separator {
+            parts.push(&value[start..i]);
+            start = i + 1;
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+/// The entries of all `Alt-Svc` headers in `headers`, unparsed.
+fn alt_svc_entries(headers: &HeaderMap) -> Vec<&str> {
+    headers
+        .get_all(ALT_SVC)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| split_unquoted(value, b','))
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .collect()
+}
+
+/// Appends a `Via` entry for a message received over `version` and forwarded by this
+/// cache under `pseudonym`.
+pub fn append_via(headers: &mut HeaderMap, version: Version, pseudonym: &str) {
+    if let Ok(value) = HeaderValue::from_str(&via_entry(version, pseudonym)) {
+        headers.append(VIA, value);
+    }
+}
+
+/// Returns true if `pseudonym` already appears in the `Via` chain, which means the message
+/// has looped back to this cache.
+pub fn via_contains(headers: &HeaderMap, pseudonym: &str) -> bool {
+    via_received_by(headers).any(|received_by| received_by.eq_ignore_ascii_case(pseudonym))
+}
+
+/// Merges fields that arrived in a response's trailers into the headers stored with it.
+///
+/// Each trailer field replaces all instances of that field in `stored_headers`, following the
+/// same rules as a 304 update: hop-by-hop fields and fields describing the stored body are
+/// ignored.
+pub fn merge_trailers(stored_headers: &mut HeaderMap, trailers: &HeaderMap) {
+    for name in trailers.keys() {
+        if is_hop_by_hop_header(name) || is_excluded_from_revalidation_update(name) {
+            continue;
+        }
+        stored_headers.remove(name);
+        for value in trailers.get_all(name) {
+            stored_headers.append(name.clone(), value.clone());
+        }
+    }
+}
+
+/// Headers for a response an origin server serves from its own application-level cache.
+///
+/// `generated_at` is when the content was produced and `lifetime` how long it stays fresh
+/// from then. The result carries `Date: generated_at`, `Age` for the time since, and both
+/// `Cache-Control: max-age` and `Expires` for the full lifetime, so that a downstream cache
+/// computes exactly `lifetime` minus the time already spent in the application cache as the
+/// remaining time to live, whether it trusts `Age` or its own clock.
+pub fn origin_cache_headers(
+    generated_at: SystemTime,
+    lifetime: Duration,
+    now: SystemTime,
+) -> HeaderMap {
+    let lifetime = Duration::from_secs(lifetime.as_secs());
+    let expires = generated_at.checked_add(lifetime).unwrap_or(generated_at);
+    let mut headers = HeaderMap::new();
+    for (name, value) in [
+        (DATE, format_http_date(generated_at)),
+        (AGE, seconds_between(generated_at, now).to_string()),
+        (CACHE_CONTROL, format!("max-age={}", lifetime.as_secs())),
+        (EXPIRES, format_http_date(expires)),
+    ] {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(name, value);
+        }
+    }
+    headers
+}
+
+/// How `CachePolicy::response_headers` writes `Date` and `Age`. See
+/// `CacheOptions::serve_date`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ServeDate {
+    /// Set `Date` to the time the response is served, and `Age` to its current age, as some
+    /// CDNs do.
+    ServingTime,
+    /// Keep the origin's `Date`, and set `Age` to the response's current age. Downstream
+    /// caches then see when the response was generated.
+    OriginWithAge,
+    /// Keep both the origin's `Date` and the `Age` it arrived with, so the response is
+    /// passed on as received. Downstream caches will think it younger than it is.
+    Origin,
+}
+
+/// Whether `CachePolicy::evaluate_with` serves stale responses while the origin is
+/// unreachable. See `CacheOptions::disconnected`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DisconnectedPolicy {
+    /// Always ask for revalidation, leaving the cache to report the failure.
+    Never,
+    /// Serve stale responses unless a directive prohibits it: `must-revalidate`, `no-cache`
+    /// or `always_revalidate`, `proxy-revalidate` or `s-maxage` in a shared cache, or the
+    /// request's own `no-cache`.
+    UnlessProhibited,
+    /// Serve stale responses regardless of directives, always marked with `110` and `112`
+    /// warnings even if `generate_warnings` is off.
+    AlwaysWithWarning,
+}
+
+/// How a shared cache writes the `Cache-Control` header of the responses it serves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CacheControlRewrite {
+    /// Serve the header exactly as it was stored.
+    Preserve,
+    /// Serve the directives in canonical form. See `format_canonical_cache_control`.
+    Canonical,
+    /// Like `Canonical`, but leave out directives this crate doesn't know about.
+    KnownOnly,
+}
+
+/// An alternative service advertised by a stored response's `Alt-Svc` header (RFC 7838),
+/// such as an HTTP/3 endpoint. See `CachePolicy::alt_services`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AltService {
+    /// The ALPN protocol ID, e.g. `h3`.
+    pub protocol: String,
+    /// The alternative's host, or `None` for the origin's own host.
+    pub host: Option<String>,
+    /// The alternative's port.
+    pub port: u16,
+    /// Seconds the advertisement remains valid for.
+    pub max_age: u64,
+    /// Whether `persist=1` asks clients to keep the alternative across network changes.
+    pub persist: bool,
+}
+
+impl AltService {
+    /// Parses one entry of an `Alt-Svc` header, e.g. `h3=":443"; ma=3600`.
+    fn parse(entry: &str) -> Option<AltService> {
+        let mut params = split_unquoted(entry, b';').into_iter();
+        let (protocol, authority) = params.next()?.split_once('=')?;
+        let authority = authority.trim().strip_prefix('"')?.strip_suffix('"')?;
+        let (host, port) = authority.rsplit_once(':')?;
+        let mut service = AltService {
+            protocol: protocol.trim().to_string(),
+            host: Some(host.to_string()).filter(|host| !host.is_empty()),
+            port: port.parse().ok()?,
+            // The default lifetime is 24 hours.
+            max_age: 86400,
+            persist: false,
+        };
+        for param in params {
+            let (name, value) = match param.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim().trim_matches('"')),
+                None => continue,
+            };
+            if name.eq_ignore_ascii_case("ma") {
+                service.max_age = seconds_from_bytes(value.as_bytes());
+            } else if name.eq_ignore_ascii_case("persist") {
+                service.persist = value == "1";
+            }
+        }
+        Some(service)
+    }
+}
+
+/// A digest of a stored body and its length, kept with the policy so that a disk cache can
+/// detect truncation or corruption before serving. The crate doesn't compute digests itself;
+/// `algorithm` names the one the caller used, e.g. `"sha-256"`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BodyIntegrity {
+    pub algorithm: String,
+    pub digest: Vec<u8>,
+    pub length: u64,
+}
+
+impl BodyIntegrity {
+    pub fn new(algorithm: impl Into<String>, digest: impl Into<Vec<u8>>, length: u64) -> Self {
+        BodyIntegrity {
+            algorithm: algorithm.into(),
+            digest: digest.into(),
+            length,
+        }
+    }
+}
+
+/// What to do with a request for which a response is stored. See `CachePolicy::evaluate`.
+#[derive(Debug)]
+pub enum CacheAction {
+    /// The stored response is usable as-is. Serve its body with these headers.
+    ///
+    /// If the request carried `If-None-Match` or `If-Modified-Since`, they didn't match the
+    /// stored response, so it's served in full rather than forwarded.
+    ServeStored { headers: HeaderMap },
+    /// The stored response is usable, and the request's `If-None-Match` or
+    /// `If-Modified-Since` matches it. Answer `304 Not Modified` with these headers, without
+    /// a body.
+    NotModified { headers: HeaderMap },
+    /// The stored response is stale, but within `CacheOptions::grace`. Serve its body with
+    /// these headers, and revalidate it in the background by sending this request to the
+    /// origin and passing the response to `CachePolicy::revalidated_policy`.
+    ServeStale {
+        headers: HeaderMap,
+        upstream_request: Request<()>,
+    },
+    /// The origin is unreachable and `CacheOptions::disconnected` allows serving the stale
+    /// response. Serve its body with these headers, which carry the appropriate warnings,
+    /// and append `cache_status` to the cache's own name in a `Cache-Status` entry (RFC
+    /// 9211), e.g. `hit; ttl=-30; detail=disconnected`.
+    ServeDisconnected {
+        headers: HeaderMap,
+        cache_status: String,
+    },
+    /// The stored response must be revalidated by sending this request to the origin,
+    /// and the response passed to `CachePolicy::revalidated_policy`.
+    Revalidate { upstream_request: Request<()> },
+    /// Only part of the stored response's body is stored. Send this range request to the
+    /// origin and pass a `206 Partial Content` answer to `CachePolicy::complete_with`; any
+    /// other answer replaces the stored response.
+    Resume { upstream_request: Request<()> },
+    /// The stored response is of no use for this request. Forward the request as-is.
+    ForwardUncached,
+    /// The request asked for `only-if-cached` and the stored response can't be used, so
+    /// answer with `504 Gateway Timeout` without contacting the origin.
+    SynthesizeGatewayTimeout,
+}
+
+impl CachePolicy {
+    /// Returns true if the stored response can be used to answer `req` at `now`
+    /// without contacting the origin server.
+    pub fn satisfies_without_revalidation<B>(&self, req: &Request<B>, now: SystemTime) -> bool {
+        self.satisfies_without_revalidation_with(req, now, EvaluationFlags::default())
+    }
+
+    /// Like `satisfies_without_revalidation`, for a lookup described by `flags`.
+    pub fn satisfies_without_revalidation_with<B>(
+        &self,
+        req: &Request<B>,
+        now: SystemTime,
+        flags: EvaluationFlags,
+    ) -> bool {
+        self.satisfies_at(req, &self.freshness_at(now, flags))
+    }
+
+    /// Like `satisfies_without_revalidation`, for each of `requests` in turn. The stored
+    /// response's age and freshness are only worked out once, which makes this cheaper for
+    /// picking among variants or sweeping many candidate requests.
+    pub fn satisfies_many<'a, B: 'a>(
+        &self,
+        requests: impl IntoIterator<Item = &'a Request<B>>,
+        now: SystemTime,
+    ) -> Vec<bool> {
+        let freshness = self.freshness_at(now, EvaluationFlags::default());
+        requests
+            .into_iter()
+            .map(|req| self.satisfies_at(req, &freshness))
+            .collect()
+    }
+
+    /// Like `satisfies_without_revalidation`, but explains why the stored response can't be
+    /// used, so that a store can tell a wrong variant from one that needs revalidating.
+    pub fn match_outcome<B>(&self, req: &Request<B>, now: SystemTime) -> MatchOutcome {
+        self.outcome_at(req, &self.freshness_at(now, EvaluationFlags::default()))
+    }
+
+    /// Number of seconds from `now` that the stored response can keep answering `req` without
+    /// revalidation, given the request's `max-age`, `min-fresh` and `max-stale`. Unlike
+    /// `time_to_live`, this counts the staleness the request accepts, so `max-stale=300` can
+    /// add up to 300 seconds. Zero if the response can't answer `req` at `now`, and `u64::MAX`
+    /// if nothing limits it, as for a bare `max-stale` or a pinned response.
+    pub fn ttl_with_request_directives<B>(&self, req: &Request<B>, now: SystemTime) -> u64 {
+        let freshness = self.freshness_at(now, EvaluationFlags::default());
+        if self.outcome_at(req, &freshness) != MatchOutcome::Fresh {
+            return 0;
+        }
+        if self.pinned {
+            return u64::MAX;
+        }
+        let FreshnessAt {
+            age,
+            max_age,
+            must_revalidate,
+            ..
+        } = freshness;
+        let requestcc = self.request_directives(req.headers());
+
+        // The earliest age at which the response can no longer be used, if any.
+        let mut limit = match requestcc.max_stale {
+            Some(_) if must_revalidate => Some(max_age),
+            Some(None) => None,
+            Some(Some(max_stale)) => Some(max_age.saturating_add(max_stale)),
+            None => Some(max_age),
+        };
+        let mut tighten = |bound: u64| {
+            limit = Some(limit.map_or(bound, |limit| cmp::min(limit, bound)));
+        };
+        if let Some(request_max_age) = requestcc.max_age {
+            tighten(request_max_age.saturating_add(1));
+        }
+        if let Some(min_fresh) = requestcc.min_fresh.filter(|&min_fresh| min_fresh > 0) {
+            tighten(max_age.saturating_add(1).saturating_sub(min_fresh));
+        }
+        limit.map_or(u64::MAX, |limit| limit.saturating_sub(age))
+    }
+
+    pub(super) fn satisfies_at<B>(&self, req: &Request<B>, freshness: &FreshnessAt) -> bool {
+        self.outcome_at(req, freshness) == MatchOutcome::Fresh
+    }
+
+    pub(super) fn outcome_at<B>(&self, req: &Request<B>, freshness: &FreshnessAt) -> MatchOutcome {
+        if !self.request_matches_resource(req, false) {
+            return MatchOutcome::MethodUrlMismatch;
+        }
+        if !self.vary_matches(req) {
+            return MatchOutcome::VariantMismatch;
+        }
+        if self.incomplete.is_some() {
+            return MatchOutcome::Incomplete;
+        }
+
+        let FreshnessAt {
+            age,
+            max_age,
+            must_revalidate,
+            is_prefetch,
+        } = *freshness;
+
+        // Pinned responses ignore the request's freshness requirements, too.
+        if self.pinned {
+            return MatchOutcome::Fresh;
+        }
+
+        // When presented with a request, a cache MUST NOT reuse a stored response, unless:
+        // the presented request does not contain the no-cache pragma (Section 5.4), nor the
+        // no-cache cache directive, unless the stored response is successfully validated
+        // (Section 4.3), and
+        let requestcc = self.request_directives(req.headers());
+        if requestcc.no_cache {
+            return MatchOutcome::RequestNoCache;
+        }
+
+        if let Some(max_age) = requestcc.max_age {
+            if age > max_age {
+                return MatchOutcome::RequestMaxAge;
+            }
+        }
+
+        if let Some(min_fresh) = requestcc.min_fresh {
+            if max_age.saturating_sub(age) < min_fresh {
+                return MatchOutcome::RequestMinFresh;
+            }
+        }
+
+        // the stored response is either:
+        // fresh, or allowed to be served stale
+        if max_age <= age {
+            let allows_stale = match requestcc.max_stale {
+                Some(_) if must_revalidate || is_prefetch => false,
+                Some(None) => true,
+                Some(Some(max_stale)) => max_stale > age - max_age,
+                None => false,
+            };
+            if !allows_stale {
+                return if must_revalidate || self.rescc.no_cache {
+                    MatchOutcome::MustRevalidate
+                } else {
+                    MatchOutcome::Stale
+                };
+            }
+        }
+
+        MatchOutcome::Fresh
+    }
+
+    pub(super) fn copy_without_hop_by_hop_headers(in_headers: &HeaderMap) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in in_headers {
+            if is_hop_by_hop_header(name) {
+                continue;
+            }
+            headers.append(name.clone(), value.clone());
+        }
+
+        // 9.1.  Connection
+        for connection in in_headers.get_all(CONNECTION) {
+            if let Ok(connection) = connection.to_str() {
+                for name in connection.split(',') {
+                    let name = name.trim().to_ascii_lowercase();
+                    if !name.is_empty() {
+                        headers.remove(name.as_str());
+                    }
+                }
+            }
+        }
+
+        strip_1xx_warnings(&mut headers);
+        headers
+    }
+
+    /// The response headers as the policy stores them: after merging repeated headers and any
+    /// rewriting required by the options, and updated by revalidations, but without the
+    /// adjustments `response_headers` makes at serving time. Together with `to_object`, this
+    /// lets a store rebuild the served headers without keeping the original response.
+    pub fn stored_response_headers(&self) -> &HeaderMap {
+        &self.res
+    }
+
+    /// Returns the headers to send along with the stored response when serving it at `now`.
+    /// Hop-by-hop headers and 1xx warnings are removed, `Age` and `Date` are written
+    /// according to `CacheOptions::serve_date`, and `110` and `113` warnings are added as
+    /// appropriate unless `generate_warnings` is off.
+    pub fn response_headers(&self, now: SystemTime) -> HeaderMap {
+        let mut headers = Self::copy_without_hop_by_hop_headers(&self.res);
+        let age = self.age(now);
+
+        if self.opts.generate_warnings {
+            // A cache SHOULD generate a 110 warning when it sends a stale response.
+            if self.stale(now) {
+                add_warning(&mut headers, "110 - \"Response is Stale\"");
+            }
+            // A cache SHOULD generate 113 warning if it heuristically chose a freshness
+            // lifetime greater than 24 hours and the response's age is greater than 24 hours.
+            if age > 3600 * 24 && !self.has_explicit_expiration() && self.max_age() > 3600 * 24 {
+                add_warning(&mut headers, "113 - \"rfc7234 5.5.4\"");
+            }
+        }
+        match self.opts.serve_date {
+            ServeDate::ServingTime => {
+                headers.insert(AGE, HeaderValue::from(age));
+                if let Ok(value) = HeaderValue::from_str(&format_http_date(now)) {
+                    headers.insert(DATE, value);
+                }
+            }
+            ServeDate::OriginWithAge | ServeDate::Origin => {
+                if self.opts.serve_date == ServeDate::OriginWithAge || self.in_chain() {
+                    headers.insert(AGE, HeaderValue::from(age));
+                }
+                // Date was dropped along with the hop-by-hop headers.
+                if let Some(date) = self.res.get(DATE) {
+                    headers.insert(DATE, date.clone());
+                }
+            }
+        }
+        if self.opts.shared && headers.contains_key(CACHE_CONTROL) {
+            let known_only = match self.opts.rewrite_cache_control {
+                CacheControlRewrite::Preserve => None,
+                CacheControlRewrite::Canonical => Some(false),
+                CacheControlRewrite::KnownOnly => Some(true),
+            };
+            if let Some(known_only) = known_only {
+                let canonical =
+                    format_canonical_cache_control(&self.response_cache_control(), known_only);
+                match HeaderValue::from_str(&canonical) {
+                    Ok(value) if !value.is_empty() => {
+                        headers.insert(CACHE_CONTROL, value);
+                    }
+                    _ => {
+                        headers.remove(CACHE_CONTROL);
+                    }
+                }
+            }
+        }
+        Self::strip_expired_alt_svc(&mut headers, age);
+        if let Some(pseudonym) = &self.opts.via_pseudonym {
+            // The stored response may already have been tagged when it was forwarded.
+            let already_tagged = via_received_by(&headers)
+                .last()
+                .is_some_and(|received_by| received_by.eq_ignore_ascii_case(pseudonym));
+            if !already_tagged {
+                append_via(&mut headers, self.version, pseudonym);
+            }
+        }
+        headers
+    }
+
+    /// The alternative services the stored response advertises that are still valid at `now`,
+    /// with `max_age` reduced to the seconds they remain valid for. Empty if there are none,
+    /// or if the response says `Alt-Svc: clear`.
+    pub fn alt_services(&self, now: SystemTime) -> Vec<AltService> {
+        let entries = alt_svc_entries(&self.res);
+        if entries.contains(&"clear") {
+            return Vec::new();
+        }
+        let age = self.age(now);
+        entries
+            .into_iter()
+            .filter_map(AltService::parse)
+            .filter(|service| service.max_age > age)
+            .map(|service| AltService {
+                max_age: service.max_age - age,
+                ..service
+            })
+            .collect()
+    }
+
+    /// Removes expired and malformed entries from `headers`' `Alt-Svc`, for a response served
+    /// at `age`.
+    pub(super) fn strip_expired_alt_svc(headers: &mut HeaderMap, age: u64) {
+        let entries = alt_svc_entries(headers);
+        if entries.is_empty() || entries.contains(&"clear") {
+            return;
+        }
+        let valid: Vec<&str> = entries
+            .iter()
+            .copied()
+            .filter(|entry| AltService::parse(entry).is_some_and(|s| s.max_age > age))
+            .collect();
+        if valid.len() == entries.len() {
+            return;
+        }
+        let value = HeaderValue::from_str(&valid.join(", "));
+        headers.remove(ALT_SVC);
+        if let Ok(value) = value {
+            if !value.is_empty() {
+                headers.insert(ALT_SVC, value);
+            }
+        }
+    }
+
+    /// Decides how to answer `request` at `now` given the stored response, combining
+    /// `satisfies_without_revalidation`, `response_headers` and `revalidation_headers`.
+    pub fn evaluate<B>(&self, request: &Request<B>, now: SystemTime) -> CacheAction {
+        self.evaluate_with(request, now, EvaluationFlags::default())
+    }
+
+    /// Like `evaluate`, for a lookup described by `flags`.
+    pub fn evaluate_with<B>(
+        &self,
+        request: &Request<B>,
+        now: SystemTime,
+        flags: EvaluationFlags,
+    ) -> CacheAction {
+        if self.storable() && self.satisfies_without_revalidation_with(request, now, flags) {
+            let headers = self.response_headers(now);
+            if self.client_conditionals_match(request) {
+                return CacheAction::NotModified {
+                    headers: not_modified_headers(&headers),
+                };
+            }
+            return CacheAction::ServeStored { headers };
+        }
+
+        // If a cache receives a request with only-if-cached and it cannot satisfy it from
+        // a stored response, it SHOULD respond with a 504 (Gateway Timeout) status code.
+        if self.request_directives(request.headers()).only_if_cached {
+            return CacheAction::SynthesizeGatewayTimeout;
+        }
+
+        if !self.storable() || !self.request_matches(request, true) {
+            return CacheAction::ForwardUncached;
+        }
+
+        // A cache MUST NOT use an incomplete response to answer requests unless the response
+        // has been made complete. Ranges wholly within the stored bytes could be answered
+        // too, but aren't: the rest of the response is fetched first.
+        if let Some(stored_bytes) = self.incomplete {
+            return match self.range_resume_request(request, stored_bytes) {
+                Some(upstream_request) => CacheAction::Resume { upstream_request },
+                None => CacheAction::ForwardUncached,
+            };
+        }
+
+        if self.opts.request_max_age_allows_stale_while_revalidate
+            && self.match_outcome(request, now) == MatchOutcome::RequestMaxAge
+            && self.within_stale_while_revalidate(request, now, flags)
+        {
+            return CacheAction::ServeStale {
+                headers: self.response_headers(now),
+                upstream_request: self.upstream_request(request),
+            };
+        }
+
+        if flags.origin_unreachable && !flags.is_prefetch && self.may_serve_disconnected(request) {
+            let ttl = i128::from(self.max_age()) - i128::from(self.age(now));
+            return CacheAction::ServeDisconnected {
+                headers: self.disconnected_headers(now),
+                cache_status: format!("hit; ttl={}; detail=disconnected", ttl),
+            };
+        }
+
+        if !flags.is_prefetch && self.within_grace(request, now) {
+            return CacheAction::ServeStale {
+                headers: self.response_headers(now),
+                upstream_request: self.upstream_request(request),
+            };
+        }
+        CacheAction::Revalidate {
+            upstream_request: self.upstream_request(request),
+        }
+    }
+
+    /// Returns true if the request's own `If-None-Match` or `If-Modified-Since` show that the
+    /// client already has the stored response (RFC 9111 section 4.3.2).
+    pub(super) fn client_conditionals_match<B>(&self, request: &Request<B>) -> bool {
+        if self.status != StatusCode::OK
+            || (request.method() != Method::GET && request.method() != Method::HEAD)
+        {
+            return false;
+        }
+        let headers = request.headers();
+        // If-None-Match takes precedence over If-Modified-Since.
+        if headers.contains_key(IF_NONE_MATCH) {
+            let etag = match self.res.get(ETAG) {
+                Some(etag) => strip_weak(etag.as_bytes()),
+                None => return false,
+            };
+            return headers.get_all(IF_NONE_MATCH).iter().any(|value| {
+                list_items(value.as_bytes()).any(|item| item == b"*" || strip_weak(item) == etag)
+            });
+        }
+        let since = match headers.get(IF_MODIFIED_SINCE).and_then(parse_http_date) {
+            Some(since) => since,
+            None => return false,
+        };
+        let last_modified = self
+            .res
+            .get(LAST_MODIFIED)
+            .and_then(parse_http_date)
+            .unwrap_or_else(|| self.date());
+        last_modified <= since
+    }
+
+    /// Returns true if the stored response is stale at `now`, but by less than
+    /// `CacheOptions::grace`, so that it may be served to `req` while it's revalidated in
+    /// the background.
+    pub fn within_grace<B>(&self, req: &Request<B>, now: SystemTime) -> bool {
+        if self.opts.grace.is_zero()
+            || !self.storable()
+            || self.must_revalidate_when_stale()
+            || self.incomplete.is_some()
+        {
+            return false;
+        }
+        let (age, max_age) = (self.age(now), self.max_age());
+        max_age <= age
+            && age - max_age < self.opts.grace.as_secs()
+            && !self.request_directives(req.headers()).no_cache
+            && self.request_matches(req, false)
+    }
+
+    /// Returns true if `CacheOptions::disconnected` allows serving the stored response for
+    /// `req` while the origin is unreachable.
+    pub(super) fn may_serve_disconnected<B>(&self, req: &Request<B>) -> bool {
+        match self.opts.disconnected {
+            DisconnectedPolicy::Never => false,
+            // A cache MUST NOT generate a stale response if it is prohibited by an explicit
+            // in-protocol directive.
+            DisconnectedPolicy::UnlessProhibited => {
+                let prohibited = self.must_revalidate_when_stale()
+                    || self.request_directives(req.headers()).no_cache;
+                !prohibited && self.request_matches(req, false)
+            }
+            DisconnectedPolicy::AlwaysWithWarning => self.request_matches(req, false),
+        }
+    }
+
+    pub(super) fn disconnected_headers(&self, now: SystemTime) -> HeaderMap {
+        let mut headers = self.response_headers(now);
+        let always_warn = self.opts.disconnected == DisconnectedPolicy::AlwaysWithWarning;
+        if always_warn && !self.opts.generate_warnings && self.stale(now) {
+            add_warning(&mut headers, "110 - \"Response is Stale\"");
+        }
+        if always_warn || self.opts.generate_warnings {
+            add_warning(&mut headers, "112 - \"Disconnected Operation\"");
+        }
+        headers
+    }
+}
+
+/// Typed access to headers through the `headers` crate, enabled by the `headers` feature.
+#[cfg(feature = "headers")]
+impl CachePolicy {
+    /// Decodes a header of the stored response, e.g. `headers::ETag` or `headers::Expires`.
+    /// Returns `None` if the header is missing or malformed.
+    pub fn typed_header<H: headers::Header>(&self) -> Option<H> {
+        headers::HeaderMapExt::typed_get(&self.res)
+    }
+
+    /// Decodes a header from `response_headers(now)`, i.e. as it will be sent to the client.
+    pub fn typed_response_header<H: headers::Header>(&self, now: SystemTime) -> Option<H> {
+        headers::HeaderMapExt::typed_get(&self.response_headers(now))
+    }
+
+    /// Decodes a header from `revalidation_headers(incoming_req)`, such as
+    /// `headers::IfNoneMatch`.
+    pub fn typed_revalidation_header<H: headers::Header, B>(
+        &self,
+        incoming_req: &Request<B>,
+    ) -> Option<H> {
+        headers::HeaderMapExt::typed_get(&self.revalidation_headers(incoming_req))
+    }
+}
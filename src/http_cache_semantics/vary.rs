@@ -0,0 +1,663 @@
+//! URI normalization and cache keys, and matching requests against a stored response,
+//! including `Vary` (RFC 9111 section 4.1).
+
+use super::*;
+
+/// Splits a comma-separated header value into its non-empty, trimmed items, without
+/// requiring it to be valid text.
+pub(super) fn list_items(value: &[u8]) -> impl Iterator<Item = &[u8]> {
+    value
+        .split(|&b| b == b',')
+        .map(<[u8]>::trim_ascii)
+        .filter(|item| !item.is_empty())
+}
+
+/// How request URIs are compared. See `CacheOptions::url_matching`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UrlMatching {
+    /// Compare URIs after `normalize_uri`, so that equivalent spellings match.
+    Normalized,
+    /// Compare URIs byte for byte.
+    Exact,
+}
+
+impl UrlMatching {
+    fn apply(self, uri: &Uri) -> Uri {
+        match self {
+            UrlMatching::Normalized => normalize_uri(uri),
+            UrlMatching::Exact => uri.clone(),
+        }
+    }
+}
+
+/// How request headers nominated by `Vary` are compared. See `CacheOptions::vary_matching`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum VaryMatching {
+    /// Values must be byte for byte identical, as RFC 7234 requires.
+    Exact,
+    /// `Accept` and `Accept-Language` match if the stored response's `Content-Type` or
+    /// `Content-Language` is acceptable to the new request, taking q-values into account.
+    /// Other fields, and responses without those headers, are compared exactly.
+    Negotiated,
+}
+
+/// Why a stored response can or can't answer a request. See `CachePolicy::match_outcome`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MatchOutcome {
+    /// The stored response can be used without contacting the origin.
+    Fresh,
+    /// The request is for another URI, method or partition, so this entry is irrelevant.
+    MethodUrlMismatch,
+    /// The request is for the same resource, but the fields nominated by `Vary` differ.
+    /// Look for another variant, or store the response to this request alongside.
+    VariantMismatch,
+    /// The right variant, but the request's `no-cache` requires revalidating it. Stale
+    /// responses are never served for such requests.
+    RequestNoCache,
+    /// The right variant, but older than the request's `max-age` allows. Revalidate it, or
+    /// see `CacheOptions::request_max_age_allows_stale_while_revalidate`.
+    RequestMaxAge,
+    /// The right variant, but fresh for less than the request's `min-fresh`. Revalidate it.
+    RequestMinFresh,
+    /// The right variant, but past its freshness lifetime. Revalidate it.
+    Stale,
+    /// The right variant, but stale, and its `no-cache` or `must-revalidate` or the
+    /// `always_revalidate` option require revalidating it.
+    MustRevalidate,
+    /// The right variant, but only part of its body is stored. It can only be used to resume
+    /// the download. See `CachePolicy::into_incomplete`.
+    Incomplete,
+}
+
+/// Decodes percent-encoded unreserved characters and uppercases the hex digits of the
+/// remaining escapes, as RFC 3986 section 6.2.2.2 describes.
+fn normalize_percent_encoding(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut normalized = Vec::with_capacity(value.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(b) if b.is_ascii_alphanumeric() || b"-._~".contains(&b) => {
+                normalized.push(b);
+            }
+            Some(b) => normalized.extend_from_slice(format!("%{:02X}", b).as_bytes()),
+            None => {
+                normalized.push(bytes[i]);
+                i += 1;
+                continue;
+            }
+        }
+        i += 3;
+    }
+    // Only ASCII escapes were rewritten, so multi-byte characters are copied whole.
+    String::from_utf8(normalized).unwrap_or_else(|_| value.to_string())
+}
+
+/// Resolves `.` and `..` segments in an absolute path (RFC 3986 section 5.2.4).
+fn remove_dot_segments(path: &str) -> String {
+    if !path.starts_with('/') {
+        return path.to_string();
+    }
+    let mut output = Vec::new();
+    let mut segments = path.split('/').skip(1).peekable();
+    while let Some(segment) = segments.next() {
+        if segment == "." || segment == ".." {
+            if segment == ".." {
+                output.pop();
+            }
+            // A path ending in a dot segment still refers to a directory.
+            if segments.peek().is_none() {
+                output.push("");
+            }
+        } else {
+            output.push(segment);
+        }
+    }
+    format!("/{}", output.join("/"))
+}
+
+/// Resolves the URI reference `reference` against `base` (RFC 3986 section 5.2). If `base`
+/// has no scheme and authority, relative references resolve to a URI in origin form.
+pub(super) fn resolve_reference(base: &Uri, reference: &str) -> Option<Uri> {
+    let reference = reference.trim();
+    let reference = reference.split('#').next().unwrap_or("");
+    if let Ok(uri) = reference.parse::<Uri>() {
+        if uri.scheme().is_some() {
+            return Some(uri);
+        }
+    }
+    if let Some(network_path) = reference.strip_prefix("//") {
+        return format!("{}://{}", base.scheme_str()?, network_path)
+            .parse()
+            .ok();
+    }
+    let (path, query) = match reference.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (reference, None),
+    };
+    let (path, query) = if path.is_empty() {
+        (base.path().to_string(), query.or_else(|| base.query()))
+    } else if path.starts_with('/') {
+        (remove_dot_segments(path), query)
+    } else {
+        let base_path = base.path();
+        let directory = &base_path[..base_path.rfind('/').map_or(0, |slash| slash + 1)];
+        let directory = if directory.is_empty() { "/" } else { directory };
+        (
+            remove_dot_segments(&format!("{}{}", directory, path)),
+            query,
+        )
+    };
+    let mut resolved = match (base.scheme_str(), base.authority()) {
+        (Some(scheme), Some(authority)) => format!("{}://{}{}", scheme, authority, path),
+        _ => path,
+    };
+    if let Some(query) = query {
+        resolved.push('?');
+        resolved.push_str(query);
+    }
+    resolved.parse().ok()
+}
+
+fn default_port(scheme: Option<&str>) -> Option<u16> {
+    match scheme {
+        Some(scheme) if scheme.eq_ignore_ascii_case("http") => Some(80),
+        Some(scheme) if scheme.eq_ignore_ascii_case("https") => Some(443),
+        _ => None,
+    }
+}
+
+/// The host of `authority`, lowercased, and its port unless it is `default_port`.
+fn normalize_host(authority: &Authority, default_port: Option<u16>) -> String {
+    let host = authority.host().to_ascii_lowercase();
+    match authority
+        .port_u16()
+        .filter(|&port| Some(port) != default_port)
+    {
+        Some(port) => format!("{}:{}", host, port),
+        None => host,
+    }
+}
+
+/// The scheme a request is for, lowercased. Requests in origin form don't say, and are
+/// assumed to use `http`; give their URI a scheme to mark them as `https`.
+fn effective_scheme(uri: &Uri) -> String {
+    uri.scheme_str().unwrap_or("http").to_ascii_lowercase()
+}
+
+/// The authority a request is for, normalized by `normalize_host`: that of its URI if it is
+/// in absolute form, and otherwise its `Host` header (RFC 7230 section 5.4). Requests
+/// without a scheme are assumed to use `http`.
+fn effective_authority(uri: &Uri, host: Option<&HeaderValue>) -> Option<String> {
+    let default_port = default_port(Some(uri.scheme_str().unwrap_or("http")));
+    match uri.authority() {
+        Some(authority) => Some(normalize_host(authority, default_port)),
+        None => {
+            let authority = host?.to_str().ok()?.trim().parse().ok()?;
+            Some(normalize_host(&authority, default_port))
+        }
+    }
+}
+
+/// Normalizes `uri` so that spellings of the same resource compare equal: the scheme and
+/// host are lowercased, default ports are removed, percent-encoding is normalized and dot
+/// segments are resolved. `http::Uri` already drops fragments.
+pub fn normalize_uri(uri: &Uri) -> Uri {
+    let mut parts = uri.clone().into_parts();
+    if let Some(authority) = uri.authority() {
+        let mut normalized = match authority.as_str().rsplit_once('@') {
+            Some((userinfo, _)) => format!("{}@", userinfo),
+            None => String::new(),
+        };
+        normalized.push_str(&normalize_host(authority, default_port(uri.scheme_str())));
+        parts.authority = normalized.parse().ok().or(parts.authority);
+    }
+    if let Some(path_and_query) = uri.path_and_query() {
+        let path = remove_dot_segments(&normalize_percent_encoding(path_and_query.path()));
+        let normalized = match path_and_query.query() {
+            Some(query) => format!("{}?{}", path, normalize_percent_encoding(query)),
+            None => path,
+        };
+        parts.path_and_query = normalized.parse().ok().or(parts.path_and_query);
+    }
+    Uri::from_parts(parts).unwrap_or_else(|_| uri.clone())
+}
+
+fn is_ignored_query_param(param: &str, ignored: &[String]) -> bool {
+    let name = param.split('=').next().unwrap_or("");
+    ignored
+        .iter()
+        .any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == pattern,
+        })
+}
+
+/// The form of `uri` that matching and cache keys use, following the URI handling in
+/// `options`.
+pub(super) fn comparable_uri(uri: &Uri, options: &CacheOptions) -> Uri {
+    let uri = options.url_matching.apply(uri);
+    let query = match uri.query() {
+        Some(query) if options.sort_query || !options.ignored_query_params.is_empty() => query,
+        _ => return uri,
+    };
+    let mut params: Vec<&str> = query
+        .split('&')
+        .filter(|param| !is_ignored_query_param(param, &options.ignored_query_params))
+        .collect();
+    if options.sort_query {
+        params.sort_unstable();
+    }
+    let path_and_query = if params.is_empty() {
+        uri.path().to_string()
+    } else {
+        format!("{}?{}", uri.path(), params.join("&"))
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = path_and_query.parse().ok();
+    Uri::from_parts(parts).unwrap_or(uri)
+}
+
+/// The method under which `CacheOptions::vary_on_method` keys a request. A stored `GET`
+/// response can answer `HEAD`, so both share a key.
+fn key_method(method: &Method) -> &str {
+    if method == Method::HEAD {
+        Method::GET.as_str()
+    } else {
+        method.as_str()
+    }
+}
+
+pub(super) fn format_cache_key(
+    partition: Option<&CachePartition>,
+    method: &Method,
+    uri: &Uri,
+    host: Option<&HeaderValue>,
+    options: &CacheOptions,
+) -> String {
+    let uri = &comparable_uri(uri, options);
+    let target = match (uri.authority(), host.and_then(|host| host.to_str().ok())) {
+        (None, Some(_)) if options.url_matching == UrlMatching::Normalized => {
+            let host = effective_authority(uri, host).unwrap_or_default();
+            format!("{}://{}{}", effective_scheme(uri), host, uri)
+        }
+        (None, Some(host)) => format!("{}://{}{}", effective_scheme(uri), host, uri),
+        _ => uri.to_string(),
+    };
+    let target = if options.vary_on_method {
+        format!("{} {}", key_method(method), target)
+    } else {
+        target
+    };
+    match partition {
+        // The length prefix keeps partitions from being confused with part of the URI.
+        Some(CachePartition(partition)) => {
+            format!("{}:{} {}", partition.len(), partition, target)
+        }
+        None => target,
+    }
+}
+
+/// Key under which to look up stored responses for `request`: its target URI, including
+/// the scheme and authority, qualified by its `CachePartition` if it has one.
+/// Responses stored under the same key may still differ in `Vary`ing headers or method,
+/// which `CachePolicy` checks. With `CacheOptions::vary_on_method` (see `cache_key_with`)
+/// the key also names the method.
+pub fn cache_key<B>(request: &Request<B>) -> String {
+    cache_key_with(request, &CacheOptions::default())
+}
+
+/// Like `cache_key`, but follows the URI handling configured in `options`.
+pub fn cache_key_with<B>(request: &Request<B>, options: &CacheOptions) -> String {
+    format_cache_key(
+        request.extensions().get::<CachePartition>(),
+        request.method(),
+        request.uri(),
+        request.headers().get(HOST),
+        options,
+    )
+}
+
+/// Returns true if `uri` resolved against `target` shares its origin: scheme, host and port.
+pub(super) fn same_origin(target: &Uri, host: Option<&HeaderValue>, uri: &Uri) -> bool {
+    effective_scheme(target) == effective_scheme(uri)
+        && effective_authority(target, host) == effective_authority(uri, host)
+}
+
+/// URIs whose stored responses a cache should invalidate after forwarding `request` and
+/// receiving `response` (RFC 9111 section 4.4), e.g. so that a `GET` after a successful
+/// `PUT` doesn't return the old representation.
+///
+/// Empty unless the request method is unsafe and the status isn't an error. Otherwise the
+/// request's target URI comes first, followed by the URIs in `Location` and
+/// `Content-Location`, resolved against it, if they share its origin. Look up stored
+/// responses for each with `cache_key_with` of a request for that URI.
+pub fn invalidation_hints<Req, Res>(request: &Request<Req>, response: &Response<Res>) -> Vec<Uri> {
+    let safe = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    );
+    let status = response.status();
+    if safe || !(status.is_success() || status.is_redirection()) {
+        return Vec::new();
+    }
+    let target = request.uri();
+    let host = request.headers().get(HOST);
+    let mut hints = vec![target.clone()];
+    for name in &[LOCATION, CONTENT_LOCATION] {
+        let uri = match header_str(response.headers(), name.clone())
+            .and_then(|reference| resolve_reference(target, reference))
+        {
+            Some(uri) => uri,
+            None => continue,
+        };
+        // A cache MUST NOT invalidate a URI from a Location or Content-Location response
+        // header field if the host part of that URI differs from the host part in the
+        // target URI.
+        if same_origin(target, host, &uri) && !hints.contains(&uri) {
+            hints.push(uri);
+        }
+    }
+    hints
+}
+
+/// The field names nominated by every `Vary` line in `headers`, in order.
+pub(super) fn vary_fields(headers: &HeaderMap) -> impl Iterator<Item = &[u8]> {
+    headers
+        .get_all(VARY)
+        .into_iter()
+        .flat_map(|value| list_items(value.as_bytes()))
+}
+
+/// Canonical bytes identifying the values `headers` has for the fields nominated by
+/// `response`'s `Vary`: one `name: values` line per field, sorted by name, with repeated
+/// fields joined by commas. Header values can't contain newlines, so this is unambiguous.
+/// A `method` comes first, as a `:method:` line no field name can be confused with.
+fn variant_key_material(
+    method: Option<&Method>,
+    response: &HeaderMap,
+    headers: &HeaderMap,
+) -> Vec<u8> {
+    let mut names: Vec<HeaderName> = vary_fields(response)
+        .filter_map(|name| HeaderName::from_bytes(name).ok())
+        .collect();
+    names.sort_unstable_by(|a, b| a.as_str().cmp(b.as_str()));
+    names.dedup();
+    let mut material = Vec::new();
+    if let Some(method) = method {
+        material.extend_from_slice(b":method:");
+        material.extend_from_slice(key_method(method).as_bytes());
+        material.push(b'\n');
+    }
+    for name in names {
+        material.extend_from_slice(name.as_str().as_bytes());
+        material.push(b':');
+        for (i, value) in headers.get_all(&name).iter().enumerate() {
+            if i > 0 {
+                material.push(b',');
+            }
+            material.extend_from_slice(value.as_bytes().trim_ascii());
+        }
+        material.push(b'\n');
+    }
+    material
+}
+
+/// Splits an item of a list such as `Accept` into its value and its q-value, which
+/// defaults to 1.
+fn weighted_item(item: &str) -> (&str, f32) {
+    let mut parts = item.split(';');
+    let value = parts.next().unwrap_or("").trim();
+    let q = parts
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+        .and_then(|(_, q)| q.trim().parse().ok())
+        .unwrap_or(1.0);
+    (value, q)
+}
+
+/// How specifically the media range `range` matches `content_type`, or `None` if it
+/// doesn't match at all.
+fn media_range_specificity(range: &str, content_type: &str) -> Option<usize> {
+    let essence = content_type.split(';').next().unwrap_or("").trim();
+    let (kind, _) = essence.split_once('/')?;
+    if range.eq_ignore_ascii_case(essence) {
+        Some(2)
+    } else if range
+        .strip_suffix("/*")
+        .is_some_and(|r| r.eq_ignore_ascii_case(kind))
+    {
+        Some(1)
+    } else if range == "*/*" {
+        Some(0)
+    } else {
+        None
+    }
+}
+
+/// How specifically the language range `range` matches `tag` under RFC 4647 basic
+/// filtering, or `None` if it doesn't match at all.
+fn language_range_specificity(range: &str, tag: &str) -> Option<usize> {
+    if range == "*" {
+        return Some(0);
+    }
+    let prefix = tag.get(..range.len())?;
+    let rest = &tag[range.len()..];
+    if prefix.eq_ignore_ascii_case(range) && (rest.is_empty() || rest.starts_with('-')) {
+        Some(range.len())
+    } else {
+        None
+    }
+}
+
+/// Picks which of several stored variants of one URL should answer `request` at `now`,
+/// returning its position among `candidates`, or `None` if none matches the request's URI,
+/// method and `Vary`-nominated fields.
+///
+/// A fresh variant beats a stale one, and a complete one an incomplete one, then the one with
+/// the longest time to live wins, then the youngest. Earlier candidates win ties. A stale
+/// winner still needs revalidating: see `CachePolicy::evaluate`.
+pub fn select_variant<'a, B>(
+    request: &Request<B>,
+    candidates: impl IntoIterator<Item = &'a CachePolicy>,
+    now: SystemTime,
+) -> Option<usize> {
+    let mut best = None;
+    let mut best_rank = (false, false, 0, cmp::Reverse(u64::MAX));
+    for (index, policy) in candidates.into_iter().enumerate() {
+        let fresh = match policy.match_outcome(request, now) {
+            MatchOutcome::MethodUrlMismatch | MatchOutcome::VariantMismatch => continue,
+            MatchOutcome::Fresh => true,
+            MatchOutcome::RequestNoCache
+            | MatchOutcome::RequestMaxAge
+            | MatchOutcome::RequestMinFresh
+            | MatchOutcome::Stale
+            | MatchOutcome::MustRevalidate
+            | MatchOutcome::Incomplete => false,
+        };
+        let rank = (
+            fresh,
+            policy.is_complete(),
+            policy.time_to_live(now),
+            cmp::Reverse(policy.age(now)),
+        );
+        if best.is_none() || rank > best_rank {
+            best = Some(index);
+            best_rank = rank;
+        }
+    }
+    best
+}
+
+/// Copies the request headers that `vary` nominates, which is all a policy needs to match
+/// later requests against the one that produced the response.
+pub(super) fn vary_request_headers(response: &HeaderMap, headers: &HeaderMap) -> HeaderMap {
+    let mut fields = Vec::new();
+    for name in vary_fields(response).filter_map(|name| HeaderName::from_bytes(name).ok()) {
+        for value in headers.get_all(&name) {
+            fields.push((name.clone(), value.clone()));
+        }
+    }
+    // Every policy keeps this map, and one grown by inserting reserves room for six fields
+    // when Vary usually nominates one or two.
+    let mut selected = HeaderMap::with_capacity(fields.len());
+    for (name, value) in fields {
+        selected.append(name, value);
+    }
+    selected
+}
+
+impl CachePolicy {
+    /// Canonical bytes identifying the variant this policy stores: the values the original
+    /// request had for the fields its response's `Vary` nominates. Feed them to any digest,
+    /// such as SHA-256 for a persistent store, to build a secondary cache key. Empty if the
+    /// response has no `Vary` and `CacheOptions::vary_on_method` is off.
+    ///
+    /// Two requests with the same material are the same variant. Under
+    /// `VaryMatching::Negotiated` the converse doesn't hold, so look up all variants of a URL
+    /// with `select_variant` rather than by secondary key.
+    pub fn variant_key_material(&self) -> Vec<u8> {
+        variant_key_material(self.keyed_method(&self.method), &self.res, &self.req)
+    }
+
+    /// Like `variant_key_material`, for the values `request` has for the same fields, so that
+    /// a lookup can compute the secondary key it expects to find.
+    pub fn request_variant_key_material<B>(&self, request: &Request<B>) -> Vec<u8> {
+        variant_key_material(
+            self.keyed_method(request.method()),
+            &self.res,
+            request.headers(),
+        )
+    }
+
+    fn keyed_method<'a>(&self, method: &'a Method) -> Option<&'a Method> {
+        Some(method).filter(|_| self.opts.vary_on_method)
+    }
+
+    /// Hashes `variant_key_material` with `hasher`, which can be keyed per process (e.g. from
+    /// `std::collections::hash_map::RandomState`) where untrusted requests choose the values,
+    /// or a fixed-seed hash such as xxHash where keys must be stable across runs.
+    pub fn variant_key<H: Hasher>(&self, mut hasher: H) -> u64 {
+        hasher.write(&self.variant_key_material());
+        hasher.finish()
+    }
+
+    /// Hashes `request_variant_key_material` with `hasher`. Equal to `variant_key` with an
+    /// identically keyed hasher if `request` asks for the stored variant.
+    pub fn request_variant_key<B, H: Hasher>(&self, request: &Request<B>, mut hasher: H) -> u64 {
+        hasher.write(&self.request_variant_key_material(request));
+        hasher.finish()
+    }
+
+    pub(super) fn request_matches<B>(&self, req: &Request<B>, allow_head_method: bool) -> bool {
+        self.request_matches_resource(req, allow_head_method)
+            // selecting header fields nominated by the stored response (if any) match those
+            // presented, and
+            && self.vary_matches(req)
+    }
+
+    pub(super) fn request_matches_resource<B>(
+        &self,
+        req: &Request<B>,
+        allow_head_method: bool,
+    ) -> bool {
+        // Entries are never shared between partitions, and
+        self.partition.as_ref() == req.extensions().get::<CachePartition>()
+            // The presented effective request URI and that of the stored response match, and
+            && self.target_matches(req)
+            // the request method associated with the stored response allows it to be used
+            // for the presented request, and
+            && (self.method == req.method()
+                || (allow_head_method && req.method() == Method::HEAD)
+                // A cached POST response can be reused to satisfy a later GET or HEAD
+                // request, if its Content-Location is the POST's target URI.
+                || (self.method == Method::POST
+                    && !self.opts.vary_on_method
+                    && (req.method() == Method::GET || req.method() == Method::HEAD)
+                    && self.content_location_is_target()))
+    }
+
+    pub(super) fn target_matches<B>(&self, req: &Request<B>) -> bool {
+        let stored = comparable_uri(&self.uri, &self.opts);
+        let presented = comparable_uri(req.uri(), &self.opts);
+        // Hosts are compared case-insensitively and regardless of default ports, so that
+        // spelling a host differently neither misses nor reaches another host's entries.
+        // Entries for http and https are never interchangeable.
+        effective_scheme(&stored) == effective_scheme(&presented)
+            && stored.path_and_query() == presented.path_and_query()
+            && effective_authority(&self.uri, self.host.as_ref())
+                == effective_authority(req.uri(), req.headers().get(HOST))
+    }
+
+    pub(super) fn vary_matches<B>(&self, req: &Request<B>) -> bool {
+        for name in vary_fields(&self.res) {
+            // A Vary header field-value of "*" always fails to match
+            if name == b"*" {
+                return false;
+            }
+            // Neither can a field name we can't look up.
+            let name = match HeaderName::from_bytes(name) {
+                Ok(name) => name,
+                Err(_) => return false,
+            };
+            if self.opts.vary_matching == VaryMatching::Negotiated {
+                if let Some(acceptable) = self.negotiated_match(&name, req.headers()) {
+                    if !acceptable {
+                        return false;
+                    }
+                    continue;
+                }
+            }
+            // Values are compared byte for byte, whether or not they are valid text.
+            if !req
+                .headers()
+                .get_all(&name)
+                .iter()
+                .eq(self.req.get_all(&name).iter())
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Whether the stored response is acceptable according to the `Accept` or
+    /// `Accept-Language` header in `headers`, or `None` if `name` isn't negotiated or there is
+    /// nothing to negotiate with.
+    pub(super) fn negotiated_match(&self, name: &HeaderName, headers: &HeaderMap) -> Option<bool> {
+        let (content, acceptable) = if name == ACCEPT {
+            (
+                CONTENT_TYPE,
+                media_range_specificity as fn(&str, &str) -> Option<usize>,
+            )
+        } else if name == ACCEPT_LANGUAGE {
+            (CONTENT_LANGUAGE, language_range_specificity as _)
+        } else {
+            return None;
+        };
+        let content = header_str(&self.res, content)?;
+        let mut ranges = Vec::new();
+        for value in headers.get_all(name) {
+            ranges.extend(value.to_str().ok()?.split(',').map(weighted_item));
+        }
+        if ranges.iter().all(|(range, _)| range.is_empty()) {
+            // No preference means anything is acceptable.
+            return Some(true);
+        }
+        // Content-Language may list several languages; any acceptable one will do.
+        Some(content.split(',').any(|content| {
+            ranges
+                .iter()
+                .filter_map(|(range, q)| Some((acceptable(range, content.trim())?, *q)))
+                .max_by_key(|&(specificity, _)| specificity)
+                .is_some_and(|(_, q)| q > 0.0)
+        }))
+    }
+}
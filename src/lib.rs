@@ -1,729 +1,6371 @@
-#[macro_use(lazy_static)]
-extern crate lazy_static;
+pub use crate::directives::{
+    format_canonical_cache_control, CacheControl, CacheControlTokenizer, CacheDirectives,
+    RequestCacheControl,
+};
+pub use crate::http_cache_semantics::{
+    append_via, cache_key, cache_key_with, compute_freshness, invalidation_hints, merge_trailers,
+    normalize_uri, origin_cache_headers, policy_for, policy_for_raw, policy_for_shared_options,
+    quick_is_storable, request_allows_storing, request_is_cache_eligible, response_allows_storing,
+    select_variant, try_policy_for, via_contains, AgeSelection, AltService, BodyIntegrity,
+    CacheAction, CacheControlRewrite, CacheEventKind, CacheOptions, CachePartition, CachePolicy,
+    CachePolicyError, CachePolicyObject, CachePolicyRef, ContentTypeRule, DateQuality,
+    DisconnectedPolicy, DualPolicy, DuplicateHeaderMode, EntityTag, EvaluationFlags,
+    FreshnessInputs, FreshnessResult, FreshnessSource, HeuristicFraction, MatchOutcome,
+    ParseReport, PastExpires, PathAction, PathRule, PolicyDecoder, PolicyEncoder, Quirk, Quirks,
+    RequestDirectiveMode, RetryAfter, RevalidatedPolicy, RfcCitation, ServeDate, StorageAdvice,
+    StorageHook, StorageOverride, Summary, UnstorableReason, UrlMatching, VaryMatching,
+};
 
-#[allow(dead_code)]
-mod http_cache_semantics {
-    use std::collections::HashSet;
+#[cfg(feature = "proptest")]
+pub mod arbitrary;
+pub mod directives;
+#[cfg(feature = "test_support")]
+pub mod test_support;
 
-    lazy_static! {
-        static ref STATUS_CODE_CACHEABLE_BY_DEFAULT: HashSet<i32> = {
-            let mut set = HashSet::new();
-            return set;
-        };
-    }
+mod http_cache_semantics;
 
-    lazy_static! {
-        static ref UNDERSTOOD_STATUSES: HashSet<i32> = {
-            let mut set = HashSet::new();
-            return set;
-        };
-    }
+#[cfg(test)]
+mod tests {
+    use super::directives::parse_cache_control;
+    use super::http_cache_semantics::format_http_date;
+    use super::*;
+    use http::{Method, Request, Response};
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-    lazy_static! {
-        static ref HOP_BY_HOP_HEADERS: HashSet<&'static str> = {
-            let mut set = HashSet::new();
-            return set;
-        };
+    fn req() -> Request<()> {
+        Request::builder().uri("/").body(()).unwrap()
     }
 
-    lazy_static! {
-        static ref EXCLUDED_FROM_REVALIDATION_UPDATE: HashSet<&'static str> = {
-            let mut set = HashSet::new();                    
-            return set;
-        };
+    fn request_with(headers: &[(&str, &str)]) -> Request<()> {
+        let mut builder = Request::builder().uri("/");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(()).unwrap()
     }
 
-    fn parse_cache_control() -> () {
-        unimplemented!();
+    fn response_with(status: u16, headers: &[(&str, &str)]) -> Response<()> {
+        let mut builder = Response::builder().status(status);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(()).unwrap()
     }
 
-    fn format_cache_control() -> () {
-        unimplemented!();
+    fn shared() -> CacheOptions {
+        CacheOptions::default()
     }
 
-    struct CachePolicy;
-    impl CachePolicy {
-        pub fn now() -> String {
-            unimplemented!();
-        }
-
-        pub fn storable() {
-            unimplemented!();
-        }
-
-        fn has_explicit_expiration() {
-            unimplemented!();
-        }
-
-        fn assert_request_has_headers() {
-            unimplemented!();
-        }
-
-        pub fn satisfies_without_revalidation() {
-            unimplemented!();
-        }
-
-        fn request_matches() {
-            unimplemented!();
-        }
-
-        fn allows_storing_authenticated() {
-            unimplemented!();
-        }
-
-        fn vary_matches() {
-            unimplemented!();
-        }
-
-        fn copy_without_hop_by_hop_headers() {
-            unimplemented!();
-        }
-
-        pub fn response_headers() {
-            unimplemented!();
-        }
-
-        pub fn date() {
-            unimplemented!();
-        }
-
-        fn server_date() {
-            unimplemented!();
-        }
-
-        pub fn age() {
-            unimplemented!();
-        }
-
-        fn age_value() {
-            unimplemented!();
-        }
-        
-        pub fn max_age() {
-            unimplemented!();
-        }
-
-        pub fn time_to_live() {
-            unimplemented!();
-        }
-
-        pub fn stale() {
-            unimplemented!();
-        }
-
-        pub fn from_object() {
-            unimplemented!();
-        }
-
-        pub fn to_object() {
-            unimplemented!();
+    fn private() -> CacheOptions {
+        CacheOptions {
+            shared: false,
+            ..CacheOptions::default()
         }
+    }
 
-        pub fn revalidation_headers() {
-            unimplemented!();
+    fn format_date(now: SystemTime, delta: i64, unit: i64) -> String {
+        let offset = Duration::from_secs((delta * unit).unsigned_abs());
+        if delta < 0 {
+            format_http_date(now - offset)
+        } else {
+            format_http_date(now + offset)
         }
+    }
 
-        pub fn revalidated_policy() {
-            unimplemented!();
+    fn assert_cached(should_put: bool, response_code: u16) {
+        let now = SystemTime::now();
+        let last_modified = format_date(now, -1, 3600);
+        let expires = format_date(now, 1, 3600);
+        let mut headers = vec![
+            ("last-modified", last_modified.as_str()),
+            ("expires", expires.as_str()),
+            ("www-authenticate", "challenge"),
+        ];
+        if response_code == 407 {
+            headers.push(("proxy-authenticate", "Basic realm=\"protected area\""));
+        } else if response_code == 401 {
+            headers.push(("www-authenticate", "Basic realm=\"protected area\""));
         }
+        let response = response_with(response_code, &headers);
+        let policy = policy_for(&req(), &response, &private(), now);
+        assert_eq!(should_put, policy.storable(), "status {}", response_code);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    
-    fn assert_cached() {
-        assert!(false);
-    }
-    
     #[test]
     fn test_ok_http_response_caching_by_response_code() {
-        assert!(false);
+        assert_cached(false, 100);
+        assert_cached(false, 101);
+        assert_cached(false, 102);
+        assert_cached(true, 200);
+        assert_cached(false, 201);
+        assert_cached(false, 202);
+        assert_cached(true, 203);
+        assert_cached(true, 204);
+        assert_cached(false, 205);
+        // Electing to not cache partial responses
+        assert_cached(false, 206);
+        assert_cached(false, 207);
+        assert_cached(true, 300);
+        assert_cached(true, 301);
+        assert_cached(true, 302);
+        assert_cached(false, 304);
+        assert_cached(false, 305);
+        assert_cached(false, 306);
+        assert_cached(true, 307);
+        assert_cached(true, 308);
+        assert_cached(false, 400);
+        assert_cached(false, 401);
+        assert_cached(false, 402);
+        assert_cached(false, 403);
+        assert_cached(true, 404);
+        assert_cached(true, 405);
+        assert_cached(false, 406);
+        assert_cached(false, 408);
+        assert_cached(false, 409);
+        // the HTTP spec permits caching 410s, but the RI doesn't.
+        assert_cached(true, 410);
+        assert_cached(false, 411);
+        assert_cached(false, 412);
+        assert_cached(false, 413);
+        assert_cached(true, 414);
+        assert_cached(false, 415);
+        assert_cached(false, 416);
+        assert_cached(false, 417);
+        assert_cached(false, 418);
+        assert_cached(false, 429);
+        assert_cached(false, 500);
+        assert_cached(true, 501);
+        assert_cached(false, 502);
+        assert_cached(false, 503);
+        assert_cached(false, 504);
+        assert_cached(false, 505);
+        assert_cached(false, 506);
     }
-    
+
     #[test]
     fn test_default_expiration_date_fully_cached_for_less_than_24_hours() {
-        assert!(false);
+        //      last modified: 105 seconds ago
+        //             served:   5 seconds ago
+        //   default lifetime: (105 - 5) / 10 = 10 seconds
+        //            expires:  10 seconds from served date = 5 seconds from now
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("last-modified", &format_date(now, -105, 1)),
+                    ("date", &format_date(now, -5, 1)),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(policy.time_to_live(now) > 4);
     }
-    
+
     #[test]
     fn test_default_expiration_date_fully_cached_for_more_than_24_hours() {
-        assert!(false);
+        //      last modified: 105 days ago
+        //             served:   5 days ago
+        //   default lifetime: (105 - 5) / 10 = 10 days
+        //            expires:  10 days from served date = 5 days from now
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("last-modified", &format_date(now, -105, 3600 * 24)),
+                    ("date", &format_date(now, -5, 3600 * 24)),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(policy.max_age() >= 10 * 3600 * 24);
+        assert!(policy.time_to_live(now) + 1 >= 5 * 3600 * 24);
     }
-    
+
     #[test]
     fn test_max_age_in_the_past_with_date_header_but_no_last_modified_header() {
-        assert!(false);
+        // Chrome interprets max-age relative to the local clock. Both our cache
+        // and Firefox both use the earlier of the local and server's clock.
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("date", &format_date(now, -120, 1)),
+                    ("cache-control", "max-age=60"),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(policy.stale(now));
     }
-    
+
     #[test]
     fn test_max_age_preferred_over_lower_shared_max_age() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("date", &format_date(now, -2, 60)),
+                    ("cache-control", "s-maxage=60, max-age=180"),
+                ],
+            ),
+            &private(),
+            now,
+        );
+        assert_eq!(policy.max_age(), 180);
     }
-    
+
     #[test]
     fn test_max_age_preferred_over_higher_max_age() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("age", "360"),
+                    ("cache-control", "s-maxage=60, max-age=180"),
+                ],
+            ),
+            &private(),
+            now,
+        );
+        assert!(policy.stale(now));
     }
-    
-    fn request_method_not_cached() {
-        assert!(false);
+
+    fn request_method_not_cached(method: Method) {
+        // 1. seed the cache (potentially)
+        // 2. expect a cache hit or miss
+        let now = SystemTime::now();
+        let request = Request::builder().method(method).uri("/").body(()).unwrap();
+        let policy = policy_for(
+            &request,
+            &response_with(200, &[("expires", &format_date(now, 1, 3600))]),
+            &shared(),
+            now,
+        );
+        assert!(policy.stale(now));
     }
-    
+
     #[test]
     fn test_request_method_options_is_not_cached() {
-        assert!(false);
+        request_method_not_cached(Method::OPTIONS);
     }
-    
+
     #[test]
     fn test_request_method_put_is_not_cached() {
-        assert!(false);
+        request_method_not_cached(Method::PUT);
     }
-    
+
     #[test]
     fn test_request_method_delete_is_not_cached() {
-        assert!(false);
+        request_method_not_cached(Method::DELETE);
     }
-    
+
     #[test]
     fn test_request_method_trace_is_not_cached() {
-        assert!(false);
+        request_method_not_cached(Method::TRACE);
     }
-    
+
     #[test]
     fn test_etag_and_expiration_date_in_the_future() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("etag", "v1"),
+                    ("last-modified", &format_date(now, -2, 3600)),
+                    ("expires", &format_date(now, 1, 3600)),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(policy.time_to_live(now) > 0);
     }
-    
+
     #[test]
     fn test_client_side_no_store() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &request_with(&[("cache-control", "no-store")]),
+            &response_with(200, &[("cache-control", "max-age=60")]),
+            &shared(),
+            now,
+        );
+        assert!(!policy.storable());
     }
-    
+
     #[test]
     fn test_request_max_age() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("last-modified", &format_date(now, -2, 3600)),
+                    ("age", "60"),
+                    ("expires", &format_date(now, 1, 3600)),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(!policy.stale(now));
+        assert!(policy.age(now) >= 60);
+        assert!(policy.satisfies_without_revalidation(
+            &request_with(&[("cache-control", "max-age=90")]),
+            now
+        ));
+        assert!(!policy.satisfies_without_revalidation(
+            &request_with(&[("cache-control", "max-age=30")]),
+            now
+        ));
     }
-    
+
     #[test]
     fn test_request_min_fresh() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "max-age=60")]),
+            &shared(),
+            now,
+        );
+        assert!(!policy.stale(now));
+        assert!(!policy.satisfies_without_revalidation(
+            &request_with(&[("cache-control", "min-fresh=120")]),
+            now
+        ));
+        assert!(policy.satisfies_without_revalidation(
+            &request_with(&[("cache-control", "min-fresh=10")]),
+            now
+        ));
     }
-    
+
     #[test]
     fn test_request_max_stale() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "max-age=120"), ("age", "240")]),
+            &shared(),
+            now,
+        );
+        assert!(policy.stale(now));
+        assert!(policy.satisfies_without_revalidation(
+            &request_with(&[("cache-control", "max-stale=180")]),
+            now
+        ));
+        assert!(policy
+            .satisfies_without_revalidation(&request_with(&[("cache-control", "max-stale")]), now));
+        assert!(!policy.satisfies_without_revalidation(
+            &request_with(&[("cache-control", "max-stale=10")]),
+            now
+        ));
     }
-    
+
     #[test]
     fn test_request_max_stale_not_honored_with_must_revalidate() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("cache-control", "max-age=120, must-revalidate"),
+                    ("age", "360"),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(policy.stale(now));
+        assert!(!policy.satisfies_without_revalidation(
+            &request_with(&[("cache-control", "max-stale=180")]),
+            now
+        ));
+        assert!(!policy
+            .satisfies_without_revalidation(&request_with(&[("cache-control", "max-stale")]), now));
     }
-    
+
     #[test]
     fn test_get_headers_deletes_cached_100_level_warnings() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("warning", "199 test danger, 200 ok ok")]),
+            &shared(),
+            now,
+        );
+        // The response has no freshness information, so it is also marked stale.
+        assert_eq!(
+            "200 ok ok, 110 - \"Response is Stale\"",
+            policy.response_headers(now)["warning"]
+        );
     }
-    
+
     #[test]
     fn test_do_not_cache_partial_response() {
-        assert!(false);
-    }
-    
-    fn format_date() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                206,
+                &[
+                    ("content-range", "bytes 100-100/200"),
+                    ("cache-control", "max-age=60"),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(!policy.storable());
     }
-    
+
     #[test]
     fn test_no_store_kills_cache() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &request_with(&[("cache-control", "no-store")]),
+            &response_with(200, &[("cache-control", "public, max-age=222")]),
+            &shared(),
+            now,
+        );
+        assert!(policy.stale(now));
+        assert!(!policy.storable());
     }
-    
+
     #[test]
     fn test_post_not_cacheable_by_default() {
-        assert!(false);
+        let now = SystemTime::now();
+        let request = Request::post("/").body(()).unwrap();
+        let policy = policy_for(
+            &request,
+            &response_with(200, &[("cache-control", "public")]),
+            &shared(),
+            now,
+        );
+        assert!(policy.stale(now));
+        assert!(!policy.storable());
     }
-    
+
     #[test]
     fn test_post_cacheable_explicitly() {
-        assert!(false);
+        let now = SystemTime::now();
+        let request = Request::post("/").body(()).unwrap();
+        let policy = policy_for(
+            &request,
+            &response_with(200, &[("cache-control", "public, max-age=222")]),
+            &shared(),
+            now,
+        );
+        assert!(!policy.stale(now));
+        assert!(policy.storable());
     }
-    
+
     #[test]
     fn test_public_cacheable_auth_is_ok() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &request_with(&[("authorization", "test")]),
+            &response_with(200, &[("cache-control", "public, max-age=222")]),
+            &shared(),
+            now,
+        );
+        assert!(!policy.stale(now));
+        assert!(policy.storable());
     }
-    
+
     #[test]
     fn test_proxy_cacheable_auth_is_ok() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &request_with(&[("authorization", "test")]),
+            &response_with(200, &[("cache-control", "max-age=0,s-maxage=12")]),
+            &shared(),
+            now,
+        );
+        assert!(!policy.stale(now));
+        assert!(policy.storable());
+
+        let policy2 = CachePolicy::from_object(&policy.to_object()).unwrap();
+        assert!(!policy2.stale(now));
+        assert!(policy2.storable());
     }
-    
+
     #[test]
     fn test_private_auth_is_ok() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &request_with(&[("authorization", "test")]),
+            &response_with(200, &[("cache-control", "max-age=111")]),
+            &private(),
+            now,
+        );
+        assert!(!policy.stale(now));
+        assert!(policy.storable());
     }
-    
+
     #[test]
     fn test_revalidate_auth_is_ok() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &request_with(&[("authorization", "test")]),
+            &response_with(200, &[("cache-control", "max-age=88,must-revalidate")]),
+            &shared(),
+            now,
+        );
+        assert!(policy.storable());
     }
-    
+
     #[test]
     fn test_auth_prevents_caching_by_default() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &request_with(&[("authorization", "test")]),
+            &response_with(200, &[("cache-control", "max-age=111")]),
+            &shared(),
+            now,
+        );
+        assert!(policy.stale(now));
+        assert!(!policy.storable());
     }
-    
+
     #[test]
     fn test_simple_miss() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(&req(), &response_with(200, &[]), &shared(), now);
+        assert!(policy.stale(now));
     }
-    
+
     #[test]
     fn test_simple_hit() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "public, max-age=999999")]),
+            &shared(),
+            now,
+        );
+        assert!(!policy.stale(now));
+        assert_eq!(policy.max_age(), 999999);
     }
-    
+
     #[test]
     fn test_weird_syntax() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", ",,,,max-age =  456      ,")]),
+            &shared(),
+            now,
+        );
+        assert!(!policy.stale(now));
+        assert_eq!(policy.max_age(), 456);
+
+        let policy2 = CachePolicy::from_object(&policy.to_object()).unwrap();
+        assert!(!policy2.stale(now));
+        assert_eq!(policy2.max_age(), 456);
     }
-    
+
     #[test]
     fn test_quoted_syntax() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "  max-age = \"678\"      ")]),
+            &shared(),
+            now,
+        );
+        assert!(!policy.stale(now));
+        assert_eq!(policy.max_age(), 678);
     }
-    
+
     #[test]
     fn test_iis() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "private, public, max-age=259200")]),
+            &private(),
+            now,
+        );
+        assert!(!policy.stale(now));
+        assert_eq!(policy.max_age(), 259200);
     }
-    
+
     #[test]
     fn test_pre_check_tolerated() {
-        assert!(false);
+        let now = SystemTime::now();
+        let cc = "pre-check=0, post-check=0, no-store, no-cache, max-age=100";
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", cc)]),
+            &shared(),
+            now,
+        );
+        assert!(policy.stale(now));
+        assert!(!policy.storable());
+        assert_eq!(policy.max_age(), 0);
+        assert_eq!(policy.response_headers(now)["cache-control"], cc);
     }
-    
+
     #[test]
     fn test_pre_check_poison() {
-        assert!(false);
+        let now = SystemTime::now();
+        let orig_cc = "pre-check=0, post-check=0, no-cache, no-store, max-age=100, custom, foo=bar";
+        let response = response_with(200, &[("cache-control", orig_cc), ("pragma", "no-cache")]);
+        let options = CacheOptions {
+            ignore_cargo_cult: true,
+            ..CacheOptions::default()
+        };
+        let policy = policy_for(&req(), &response, &options, now);
+        assert!(!policy.stale(now));
+        assert!(policy.storable());
+        assert_eq!(policy.max_age(), 100);
+
+        let headers = policy.response_headers(now);
+        let cc = parse_cache_control(headers.get_all("cache-control"));
+        assert!(!cc.contains_key("pre-check"));
+        assert!(!cc.contains_key("post-check"));
+        assert!(!cc.contains_key("no-cache"));
+        assert!(!cc.contains_key("no-store"));
+        assert_eq!(cc["max-age"], Some("100".to_string()));
+        assert_eq!(cc["custom"], None);
+        assert_eq!(cc["foo"], Some("bar".to_string()));
+
+        assert_eq!(response.headers()["cache-control"], orig_cc);
+        assert!(response.headers().contains_key("pragma"));
+        assert!(!headers.contains_key("pragma"));
     }
-    
+
     #[test]
     fn test_pre_check_poison_undefined_header() {
-        assert!(false);
+        let now = SystemTime::now();
+        let orig_cc = "pre-check=0, post-check=0, no-cache, no-store";
+        let response = response_with(
+            200,
+            &[("cache-control", orig_cc), ("expires", "yesterday!")],
+        );
+        let options = CacheOptions {
+            ignore_cargo_cult: true,
+            ..CacheOptions::default()
+        };
+        let policy = policy_for(&req(), &response, &options, now);
+        assert!(policy.stale(now));
+        assert!(policy.storable());
+        assert_eq!(policy.max_age(), 0);
+
+        let headers = policy.response_headers(now);
+        assert!(!headers.contains_key("cache-control"));
+        assert!(response.headers().contains_key("expires"));
+        assert!(!headers.contains_key("expires"));
     }
-    
+
     #[test]
     fn test_cache_with_expires() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("date", &format_date(now, 0, 1)),
+                    ("expires", &format_date(now, 2, 1)),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(!policy.stale(now));
+        assert_eq!(2, policy.max_age());
     }
-    
+
     #[test]
     fn test_cache_with_expires_always_relative_to_date() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("date", &format_date(now, -3, 1)),
+                    ("expires", &format_date(now, 0, 1)),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        assert_eq!(3, policy.max_age());
     }
-    
+
     #[test]
     fn test_cache_expires_no_date() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("cache-control", "public"),
+                    ("expires", &format_date(now, 3600, 1)),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(!policy.stale(now));
+        assert!(policy.max_age() > 3595);
+        assert!(policy.max_age() < 3605);
     }
-    
+
     #[test]
     fn test_ages() {
-        assert!(false);
+        let mut now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "max-age=100"), ("age", "50")]),
+            &shared(),
+            now,
+        );
+        assert!(policy.storable());
+        assert_eq!(50, policy.time_to_live(now));
+        assert!(!policy.stale(now));
+
+        now += Duration::from_secs(48);
+        assert_eq!(2, policy.time_to_live(now));
+        assert!(!policy.stale(now));
+
+        now += Duration::from_secs(5);
+        assert!(policy.stale(now));
+        assert_eq!(0, policy.time_to_live(now));
     }
-    
+
     #[test]
     fn test_age_can_make_stale() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "max-age=100"), ("age", "101")]),
+            &shared(),
+            now,
+        );
+        assert!(policy.stale(now));
+        assert!(policy.storable());
     }
-    
+
     #[test]
     fn test_age_not_always_stale() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "max-age=20"), ("age", "15")]),
+            &shared(),
+            now,
+        );
+        assert!(!policy.stale(now));
+        assert!(policy.storable());
     }
-    
+
     #[test]
     fn test_bogus_age_ignored() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "max-age=20"), ("age", "golden")]),
+            &shared(),
+            now,
+        );
+        assert!(!policy.stale(now));
+        assert!(policy.storable());
     }
-    
+
     #[test]
     fn test_cache_old_files() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("date", &format_date(now, 0, 1)),
+                    ("last-modified", "Mon, 07 Mar 2016 11:52:56 GMT"),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(!policy.stale(now));
+        assert!(policy.max_age() > 100);
     }
-    
+
     #[test]
     fn test_immutable_simple_hit() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "immutable, max-age=999999")]),
+            &shared(),
+            now,
+        );
+        assert!(!policy.stale(now));
+        assert_eq!(policy.max_age(), 999999);
     }
-    
+
     #[test]
     fn test_immutable_can_expire() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "immutable, max-age=0")]),
+            &shared(),
+            now,
+        );
+        assert!(policy.stale(now));
+        assert_eq!(policy.max_age(), 0);
     }
-    
+
     #[test]
     fn test_cache_immutable_files() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("date", &format_date(now, 0, 1)),
+                    ("cache-control", "immutable"),
+                    ("last-modified", &format_date(now, 0, 1)),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(!policy.stale(now));
+        assert!(policy.max_age() > 100);
     }
-    
+
     #[test]
     fn test_immutable_can_be_off() {
-        assert!(false);
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            immutable_min_time_to_live: Duration::ZERO,
+            ..CacheOptions::default()
+        };
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("date", &format_date(now, 0, 1)),
+                    ("cache-control", "immutable"),
+                    ("last-modified", &format_date(now, 0, 1)),
+                ],
+            ),
+            &options,
+            now,
+        );
+        assert!(policy.stale(now));
+        assert_eq!(policy.max_age(), 0);
     }
-    
+
     #[test]
     fn test_pragma_no_cache() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("pragma", "no-cache"),
+                    ("last-modified", "Mon, 07 Mar 2016 11:52:56 GMT"),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(policy.stale(now));
     }
-    
+
     #[test]
     fn test_blank_cache_control_and_pragma_no_cache() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("cache-control", ""),
+                    ("pragma", "no-cache"),
+                    ("last-modified", &format_date(now, -10, 1)),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(!policy.stale(now));
     }
-    
+
     #[test]
     fn test_no_store() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "no-store, public, max-age=1")]),
+            &shared(),
+            now,
+        );
+        assert!(policy.stale(now));
+        assert_eq!(0, policy.max_age());
     }
-    
+
     #[test]
     fn test_observe_private_cache() {
-        assert!(false);
+        let now = SystemTime::now();
+        let response = response_with(200, &[("cache-control", "private, max-age=1234")]);
+        let proxy_policy = policy_for(&req(), &response, &shared(), now);
+        assert!(proxy_policy.stale(now));
+        assert_eq!(0, proxy_policy.max_age());
+
+        let ua_policy = policy_for(&req(), &response, &private(), now);
+        assert!(!ua_policy.stale(now));
+        assert_eq!(1234, ua_policy.max_age());
     }
-    
+
     #[test]
     fn test_do_not_share_cookies() {
-        assert!(false);
+        let now = SystemTime::now();
+        let response = response_with(
+            200,
+            &[("set-cookie", "foo=bar"), ("cache-control", "max-age=99")],
+        );
+        let proxy_policy = policy_for(&req(), &response, &shared(), now);
+        assert!(proxy_policy.stale(now));
+        assert_eq!(0, proxy_policy.max_age());
+
+        let ua_policy = policy_for(&req(), &response, &private(), now);
+        assert!(!ua_policy.stale(now));
+        assert_eq!(99, ua_policy.max_age());
     }
-    
+
     #[test]
     fn test_do_share_cookies_if_immutable() {
-        assert!(false);
+        let now = SystemTime::now();
+        let response = response_with(
+            200,
+            &[
+                ("set-cookie", "foo=bar"),
+                ("cache-control", "immutable, max-age=99"),
+            ],
+        );
+        let proxy_policy = policy_for(&req(), &response, &shared(), now);
+        assert!(!proxy_policy.stale(now));
+        assert_eq!(99, proxy_policy.max_age());
     }
-    
+
     #[test]
     fn test_cache_explicitly_public_cookie() {
-        assert!(false);
+        let now = SystemTime::now();
+        let response = response_with(
+            200,
+            &[
+                ("set-cookie", "foo=bar"),
+                ("cache-control", "max-age=5, public"),
+            ],
+        );
+        let proxy_policy = policy_for(&req(), &response, &shared(), now);
+        assert!(!proxy_policy.stale(now));
+        assert_eq!(5, proxy_policy.max_age());
     }
-    
+
     #[test]
     fn test_miss_max_age_equals_zero() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "public, max-age=0")]),
+            &shared(),
+            now,
+        );
+        assert!(policy.stale(now));
+        assert_eq!(0, policy.max_age());
     }
-    
+
     #[test]
     fn test_uncacheable_503() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(503, &[("cache-control", "public, max-age=1000")]),
+            &shared(),
+            now,
+        );
+        assert!(policy.stale(now));
+        assert_eq!(0, policy.max_age());
     }
-    
+
     #[test]
     fn test_cacheable_301() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(301, &[("last-modified", "Mon, 07 Mar 2016 11:52:56 GMT")]),
+            &shared(),
+            now,
+        );
+        assert!(!policy.stale(now));
     }
-    
+
     #[test]
     fn test_uncacheable_303() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(303, &[("last-modified", "Mon, 07 Mar 2016 11:52:56 GMT")]),
+            &shared(),
+            now,
+        );
+        assert!(policy.stale(now));
+        assert_eq!(0, policy.max_age());
     }
-    
+
     #[test]
     fn test_cacheable_303() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(303, &[("cache-control", "max-age=1000")]),
+            &shared(),
+            now,
+        );
+        assert!(!policy.stale(now));
     }
-    
+
     #[test]
     fn test_uncacheable_412() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(412, &[("cache-control", "public, max-age=1000")]),
+            &shared(),
+            now,
+        );
+        assert!(policy.stale(now));
+        assert_eq!(0, policy.max_age());
     }
-    
+
     #[test]
     fn test_expired_expires_cache_with_max_age() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("cache-control", "public, max-age=9999"),
+                    ("expires", "Sat, 07 May 2016 15:35:18 GMT"),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(!policy.stale(now));
+        assert_eq!(9999, policy.max_age());
     }
-    
+
     #[test]
     fn test_expired_expires_cached_with_s_maxage() {
-        assert!(false);
+        let now = SystemTime::now();
+        let response = response_with(
+            200,
+            &[
+                ("cache-control", "public, s-maxage=9999"),
+                ("expires", "Sat, 07 May 2016 15:35:18 GMT"),
+            ],
+        );
+        let proxy_policy = policy_for(&req(), &response, &shared(), now);
+        assert!(!proxy_policy.stale(now));
+        assert_eq!(9999, proxy_policy.max_age());
+
+        let ua_policy = policy_for(&req(), &response, &private(), now);
+        assert!(ua_policy.stale(now));
+        assert_eq!(0, ua_policy.max_age());
     }
-    
+
     #[test]
     fn test_max_age_wins_over_future_expires() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("cache-control", "public, max-age=333"),
+                    ("expires", &format_date(now, 1, 3600)),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(!policy.stale(now));
+        assert_eq!(333, policy.max_age());
     }
-    
+
     #[test]
     fn test_remove_hop_headers() {
-        assert!(false);
-    }    
-    
-    fn assert_headers_passed() {
-        assert!(false);
+        let mut now = SystemTime::now();
+        let response = response_with(
+            200,
+            &[
+                ("te", "deflate"),
+                ("date", "now"),
+                ("custom", "header"),
+                ("oompa", "lumpa"),
+                ("connection", "close, oompa, header"),
+                ("age", "10"),
+            ],
+        );
+        let policy = policy_for(&req(), &response, &shared(), now);
+
+        now += Duration::from_millis(1005);
+        let headers = policy.response_headers(now);
+        assert!(!headers.contains_key("connection"));
+        assert!(!headers.contains_key("te"));
+        assert!(!headers.contains_key("oompa"));
+        assert_eq!(headers["custom"], "header");
+        assert_eq!(headers["age"], "11");
+        assert_eq!(headers["date"], format_http_date(now).as_str());
+        assert_eq!(response.headers()["age"], "10");
+
+        let policy2 = CachePolicy::from_object(&policy.to_object()).unwrap();
+        assert_eq!(headers, policy2.response_headers(now));
+    }
+
+    const SIMPLE_URI: &str = "/Protocols/rfc2616/rfc2616-sec14.html";
+
+    fn simple_request_builder() -> http::request::Builder {
+        Request::builder()
+            .uri(SIMPLE_URI)
+            .header("host", "www.w3c.org")
+            .header("connection", "close")
+            .header("x-custom", "yes")
+    }
+
+    fn simple_request() -> Request<()> {
+        simple_request_builder().body(()).unwrap()
+    }
+
+    const CACHEABLE_HEADERS: &[(&str, &str)] = &[("cache-control", "max-age=111")];
+    const ETAGGED_HEADERS: &[(&str, &str)] =
+        &[("etag", "\"123456789\""), ("cache-control", "max-age=111")];
+    const WEAK_TAGGED_HEADERS: &[(&str, &str)] = &[
+        ("etag", "W/\"123456789\""),
+        ("cache-control", "max-age=111"),
+    ];
+    const LAST_MODIFIED_HEADERS: &[(&str, &str)] = &[
+        ("last-modified", "Tue, 15 Nov 1994 12:45:26 GMT"),
+        ("cache-control", "max-age=111"),
+    ];
+    const MULTI_VALIDATOR_HEADERS: &[(&str, &str)] = &[
+        ("etag", "\"123456789\""),
+        ("last-modified", "Tue, 15 Nov 1994 12:45:26 GMT"),
+        ("cache-control", "max-age=111"),
+    ];
+    const ALWAYS_VARIABLE_HEADERS: &[(&str, &str)] =
+        &[("vary", "*"), ("cache-control", "max-age=111")];
+
+    fn assert_headers_passed(headers: &http::HeaderMap) {
+        assert!(!headers.contains_key("connection"));
+        assert_eq!(headers["x-custom"], "yes");
     }
-    
-    fn assert_no_validators() {
-        assert!(false);
+
+    fn assert_no_validators(headers: &http::HeaderMap) {
+        assert!(!headers.contains_key("if-none-match"));
+        assert!(!headers.contains_key("if-modified-since"));
     }
-    
+
     #[test]
     fn test_ok_if_method_changes_to_head() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(200, ETAGGED_HEADERS),
+            &shared(),
+            now,
+        );
+        let incoming = simple_request_builder()
+            .method(Method::HEAD)
+            .body(())
+            .unwrap();
+        let headers = policy.revalidation_headers(&incoming);
+        assert_headers_passed(&headers);
+        assert_eq!(headers["if-none-match"], "\"123456789\"");
     }
-    
+
     #[test]
     fn test_not_if_method_mismatch_other_than_head() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(200, ETAGGED_HEADERS),
+            &shared(),
+            now,
+        );
+        let incoming = simple_request_builder()
+            .method(Method::POST)
+            .body(())
+            .unwrap();
+        let headers = policy.revalidation_headers(&incoming);
+        assert_headers_passed(&headers);
+        assert_no_validators(&headers);
     }
-    
+
     #[test]
     fn test_not_if_url_mismatch() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(200, ETAGGED_HEADERS),
+            &shared(),
+            now,
+        );
+        let incoming = simple_request_builder().uri("/yomomma").body(()).unwrap();
+        let headers = policy.revalidation_headers(&incoming);
+        assert_headers_passed(&headers);
+        assert_no_validators(&headers);
     }
-    
+
     #[test]
     fn test_not_if_host_mismatch() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(200, ETAGGED_HEADERS),
+            &shared(),
+            now,
+        );
+        let mut incoming = simple_request();
+        incoming
+            .headers_mut()
+            .insert("host", "www.w4c.org".parse().unwrap());
+        let headers = policy.revalidation_headers(&incoming);
+        assert_no_validators(&headers);
+        assert_eq!(headers["x-custom"], "yes");
     }
-    
+
     #[test]
     fn test_not_if_vary_fields_prevent() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(200, ALWAYS_VARIABLE_HEADERS),
+            &shared(),
+            now,
+        );
+        let headers = policy.revalidation_headers(&simple_request());
+        assert_headers_passed(&headers);
+        assert_no_validators(&headers);
     }
-    
+
     #[test]
     fn test_when_entity_tag_validator_is_present() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(200, ETAGGED_HEADERS),
+            &shared(),
+            now,
+        );
+        let headers = policy.revalidation_headers(&simple_request());
+        assert_headers_passed(&headers);
+        assert_eq!(headers["if-none-match"], "\"123456789\"");
     }
-    
+
     #[test]
     fn test_skips_weak_validators_on_post_2() {
-        assert!(false);
+        let now = SystemTime::now();
+        let post_request = simple_request_builder()
+            .method(Method::POST)
+            .header("if-none-match", "W/\"weak\"")
+            .body(())
+            .unwrap();
+        let policy = policy_for(
+            &post_request,
+            &response_with(200, LAST_MODIFIED_HEADERS),
+            &shared(),
+            now,
+        );
+        let headers = policy.revalidation_headers(&post_request);
+        assert!(!headers.contains_key("if-none-match"));
+        assert!(!headers.contains_key("if-modified-since"));
     }
-    
+
     #[test]
     fn test_merges_validators() {
-        assert!(false);
+        let now = SystemTime::now();
+        let request = simple_request_builder()
+            .header("if-none-match", "W/\"weak\", \"strong\", W/\"weak2\"")
+            .body(())
+            .unwrap();
+        let policy = policy_for(
+            &request,
+            &response_with(200, MULTI_VALIDATOR_HEADERS),
+            &shared(),
+            now,
+        );
+        let headers = policy.revalidation_headers(&request);
+        assert_eq!(
+            headers["if-none-match"],
+            "W/\"weak\", \"strong\", W/\"weak2\", \"123456789\""
+        );
+        assert_eq!(
+            headers["if-modified-since"],
+            "Tue, 15 Nov 1994 12:45:26 GMT"
+        );
     }
-    
+
     #[test]
     fn test_when_last_modified_validator_is_present() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(200, LAST_MODIFIED_HEADERS),
+            &shared(),
+            now,
+        );
+        let headers = policy.revalidation_headers(&simple_request());
+        assert_headers_passed(&headers);
+        assert_eq!(
+            headers["if-modified-since"],
+            "Tue, 15 Nov 1994 12:45:26 GMT"
+        );
+        assert!(!headers.contains_key("if-none-match"));
     }
-    
+
     #[test]
     fn test_not_without_validators() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(200, CACHEABLE_HEADERS),
+            &shared(),
+            now,
+        );
+        let headers = policy.revalidation_headers(&simple_request());
+        assert_headers_passed(&headers);
+        assert_no_validators(&headers);
     }
-    
+
     #[test]
     fn test_113_added() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(
+                200,
+                &[
+                    ("age", "259200"),
+                    ("last-modified", "Tue, 15 Nov 1994 12:45:26 GMT"),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        assert_eq!(
+            policy.response_headers(now)["warning"],
+            "113 - \"rfc7234 5.5.4\""
+        );
     }
-    
+
     #[test]
     fn test_removes_warnings() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("warning", "199 test danger, 200 ok ok")]),
+            &shared(),
+            now,
+        );
+        assert_eq!(
+            policy.response_headers(now)["warning"],
+            "200 ok ok, 110 - \"Response is Stale\""
+        );
     }
-    
+
     #[test]
     fn test_must_contain_any_etag() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(200, MULTI_VALIDATOR_HEADERS),
+            &shared(),
+            now,
+        );
+        let headers = policy.revalidation_headers(&simple_request());
+        assert_eq!(headers["if-none-match"], "\"123456789\"");
     }
-    
+
     #[test]
     fn test_merges_etags() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(200, ETAGGED_HEADERS),
+            &shared(),
+            now,
+        );
+        let incoming = simple_request_builder()
+            .header("if-none-match", "\"foo\", \"bar\"")
+            .body(())
+            .unwrap();
+        let headers = policy.revalidation_headers(&incoming);
+        assert_eq!(headers["if-none-match"], "\"foo\", \"bar\", \"123456789\"");
     }
-    
+
     #[test]
     fn test_should_send_the_last_modified_value() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(200, MULTI_VALIDATOR_HEADERS),
+            &shared(),
+            now,
+        );
+        let headers = policy.revalidation_headers(&simple_request());
+        assert_eq!(
+            headers["if-modified-since"],
+            "Tue, 15 Nov 1994 12:45:26 GMT"
+        );
     }
-    
+
     #[test]
     fn test_should_not_send_the_last_modified_value_for_post() {
-        assert!(false);
+        let now = SystemTime::now();
+        let post_request = Request::post(SIMPLE_URI)
+            .header("if-modified-since", "yesterday")
+            .body(())
+            .unwrap();
+        let policy = policy_for(
+            &post_request,
+            &response_with(200, LAST_MODIFIED_HEADERS),
+            &shared(),
+            now,
+        );
+        let headers = policy.revalidation_headers(&post_request);
+        assert!(!headers.contains_key("if-modified-since"));
     }
-    
+
     #[test]
     fn test_should_not_send_the_last_modified_value_for_range_request() {
-        assert!(false);
+        let now = SystemTime::now();
+        let range_request = Request::get(SIMPLE_URI)
+            .header("accept-ranges", "1-3")
+            .header("if-modified-since", "yesterday")
+            .body(())
+            .unwrap();
+        let policy = policy_for(
+            &range_request,
+            &response_with(200, LAST_MODIFIED_HEADERS),
+            &shared(),
+            now,
+        );
+        let headers = policy.revalidation_headers(&range_request);
+        assert!(!headers.contains_key("if-modified-since"));
     }
-    
+
     #[test]
     fn test_when_urls_match() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "max-age=2")]),
+            &shared(),
+            now,
+        );
+        assert!(policy.satisfies_without_revalidation(&req(), now));
     }
-    
+
     #[test]
     fn test_when_expires_is_present() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("expires", &format_date(now, 2, 1))]),
+            &shared(),
+            now,
+        );
+        assert!(policy.satisfies_without_revalidation(&req(), now));
     }
-    
+
     #[test]
     fn test_not_when_urls_mismatch() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &Request::get("/foo").body(()).unwrap(),
+            &response_with(200, &[("cache-control", "max-age=2")]),
+            &shared(),
+            now,
+        );
+        assert!(!policy
+            .satisfies_without_revalidation(&Request::get("/foo?bar").body(()).unwrap(), now));
     }
-    
+
     #[test]
     fn test_when_methods_match() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &Request::get("/").body(()).unwrap(),
+            &response_with(200, &[("cache-control", "max-age=2")]),
+            &shared(),
+            now,
+        );
+        assert!(policy.satisfies_without_revalidation(&Request::get("/").body(()).unwrap(), now));
     }
-    
+
     #[test]
     fn test_not_when_hosts_mismatch() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &request_with(&[("host", "foo")]),
+            &response_with(200, &[("cache-control", "max-age=2")]),
+            &shared(),
+            now,
+        );
+        assert!(policy.satisfies_without_revalidation(&request_with(&[("host", "foo")]), now));
+        assert!(!policy.satisfies_without_revalidation(&request_with(&[("host", "foofoo")]), now));
     }
-    
+
     #[test]
     fn test_when_methods_match_head() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &Request::head("/").body(()).unwrap(),
+            &response_with(200, &[("cache-control", "max-age=2")]),
+            &shared(),
+            now,
+        );
+        assert!(policy.satisfies_without_revalidation(&Request::head("/").body(()).unwrap(), now));
     }
-    
+
     #[test]
     fn test_not_when_methods_mismatch() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &Request::post("/").body(()).unwrap(),
+            &response_with(200, &[("cache-control", "max-age=2")]),
+            &shared(),
+            now,
+        );
+        assert!(!policy.satisfies_without_revalidation(&Request::get("/").body(()).unwrap(), now));
     }
-    
+
     #[test]
     fn test_not_when_methods_mismatch_head() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &Request::get("/").body(()).unwrap(),
+            &response_with(200, &[("cache-control", "max-age=2")]),
+            &shared(),
+            now,
+        );
+        assert!(!policy.satisfies_without_revalidation(&Request::head("/").body(()).unwrap(), now));
     }
-    
+
     #[test]
     fn test_not_when_proxy_revalidating() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "max-age=2, proxy-revalidate ")]),
+            &shared(),
+            now,
+        );
+        assert!(!policy.satisfies_without_revalidation(&req(), now));
     }
-    
+
     #[test]
     fn test_when_not_a_proxy_revalidating() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "max-age=2, proxy-revalidate ")]),
+            &private(),
+            now,
+        );
+        assert!(policy.satisfies_without_revalidation(&req(), now));
     }
-    
+
     #[test]
     fn test_not_when_no_cache_requesting() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "max-age=2")]),
+            &shared(),
+            now,
+        );
+        assert!(
+            policy.satisfies_without_revalidation(&request_with(&[("cache-control", "fine")]), now)
+        );
+        assert!(!policy
+            .satisfies_without_revalidation(&request_with(&[("cache-control", "no-cache")]), now));
+        assert!(
+            !policy.satisfies_without_revalidation(&request_with(&[("pragma", "no-cache")]), now)
+        );
+    }
+
+    fn not_modified_response_headers(
+        first_request: &Request<()>,
+        first_response: &Response<()>,
+        second_request: &Request<()>,
+        second_response: &Response<()>,
+    ) -> Option<http::HeaderMap> {
+        let now = SystemTime::now();
+        let policy = policy_for(first_request, first_response, &shared(), now);
+        let headers = policy.revalidation_headers(second_request);
+        let mut revalidation_request = Request::get(second_request.uri().clone()).body(()).unwrap();
+        *revalidation_request.headers_mut() = headers;
+        let revalidated = policy.revalidated_policy(&revalidation_request, second_response, now);
+        if revalidated.modified || !revalidated.matches {
+            return None;
+        }
+        Some(revalidated.policy.response_headers(now))
     }
-    
-    fn not_modified_response_headers() {
-        assert!(false);
+
+    fn with_headers(status: u16, base: &[(&str, &str)], extra: &[(&str, &str)]) -> Response<()> {
+        let mut response = response_with(status, base);
+        for (name, value) in extra {
+            response.headers_mut().insert(
+                http::header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+        response
     }
-    
-    fn assert_updates() {
-        assert!(false);
+
+    fn assert_updates(
+        first_request: &Request<()>,
+        first_response: &[(&str, &str)],
+        second_request: &Request<()>,
+        second_response: &[(&str, &str)],
+    ) {
+        let first = with_headers(
+            200,
+            first_response,
+            &[("foo", "original"), ("x-other", "original")],
+        );
+        let second = with_headers(
+            304,
+            second_response,
+            &[("foo", "updated"), ("x-ignore-new", "ignoreme")],
+        );
+        let headers =
+            not_modified_response_headers(first_request, &first, second_request, &second).unwrap();
+        assert_eq!(headers["foo"], "updated");
+        assert_eq!(headers["x-other"], "original");
+        assert!(!headers.contains_key("x-ignore-new"));
+        assert_eq!(headers.get("etag"), second.headers().get("etag"));
     }
-    
+
     #[test]
     fn test_matching_etags_are_updated() {
-        assert!(false);
+        assert_updates(
+            &simple_request(),
+            ETAGGED_HEADERS,
+            &simple_request(),
+            ETAGGED_HEADERS,
+        );
     }
-    
+
     #[test]
     fn test_matching_weak_etags_are_updated() {
-        assert!(false);
+        assert_updates(
+            &simple_request(),
+            WEAK_TAGGED_HEADERS,
+            &simple_request(),
+            WEAK_TAGGED_HEADERS,
+        );
     }
-    
+
     #[test]
     fn test_matching_last_mod_are_updated() {
-        assert!(false);
+        assert_updates(
+            &simple_request(),
+            LAST_MODIFIED_HEADERS,
+            &simple_request(),
+            LAST_MODIFIED_HEADERS,
+        );
     }
-    
+
     #[test]
     fn test_both_matching_are_updated() {
-        assert!(false);
+        assert_updates(
+            &simple_request(),
+            MULTI_VALIDATOR_HEADERS,
+            &simple_request(),
+            MULTI_VALIDATOR_HEADERS,
+        );
     }
-    
+
     #[test]
     fn test_check_status() {
-        assert!(false);
+        assert_updates(
+            &simple_request(),
+            ETAGGED_HEADERS,
+            &simple_request(),
+            ETAGGED_HEADERS,
+        );
+        assert!(not_modified_response_headers(
+            &simple_request(),
+            &response_with(200, ETAGGED_HEADERS),
+            &simple_request(),
+            &response_with(200, ETAGGED_HEADERS),
+        )
+        .is_none());
     }
-    
+
     #[test]
     fn test_last_mod_ignored_if_etag_is_wrong() {
-        assert!(false);
+        assert!(not_modified_response_headers(
+            &simple_request(),
+            &response_with(200, MULTI_VALIDATOR_HEADERS),
+            &simple_request(),
+            &with_headers(304, MULTI_VALIDATOR_HEADERS, &[("etag", "bad")]),
+        )
+        .is_none());
+        assert!(not_modified_response_headers(
+            &simple_request(),
+            &response_with(200, MULTI_VALIDATOR_HEADERS),
+            &simple_request(),
+            &with_headers(304, MULTI_VALIDATOR_HEADERS, &[("etag", "W/bad")]),
+        )
+        .is_none());
     }
-    
+
     #[test]
     fn test_ignored_if_validator_is_missing() {
-        assert!(false);
+        for first in &[ETAGGED_HEADERS, WEAK_TAGGED_HEADERS, LAST_MODIFIED_HEADERS] {
+            assert!(not_modified_response_headers(
+                &simple_request(),
+                &response_with(200, first),
+                &simple_request(),
+                &response_with(304, CACHEABLE_HEADERS),
+            )
+            .is_none());
+        }
     }
-    
+
     #[test]
     fn test_skips_update_of_content_length() {
-        assert!(false);
+        let headers = not_modified_response_headers(
+            &simple_request(),
+            &with_headers(200, ETAGGED_HEADERS, &[("content-length", "1")]),
+            &simple_request(),
+            &with_headers(304, ETAGGED_HEADERS, &[("content-length", "2")]),
+        )
+        .unwrap();
+        assert_eq!(headers["content-length"], "1");
     }
-    
+
     #[test]
     fn test_ignored_if_validator_is_different() {
-        assert!(false);
+        assert!(not_modified_response_headers(
+            &simple_request(),
+            &response_with(200, LAST_MODIFIED_HEADERS),
+            &simple_request(),
+            &response_with(304, ETAGGED_HEADERS),
+        )
+        .is_none());
+        assert!(not_modified_response_headers(
+            &simple_request(),
+            &response_with(200, LAST_MODIFIED_HEADERS),
+            &simple_request(),
+            &response_with(304, WEAK_TAGGED_HEADERS),
+        )
+        .is_none());
+        assert!(not_modified_response_headers(
+            &simple_request(),
+            &response_with(200, ETAGGED_HEADERS),
+            &simple_request(),
+            &response_with(304, LAST_MODIFIED_HEADERS),
+        )
+        .is_none());
     }
-    
+
     #[test]
     fn test_ignored_if_validator_does_not_match() {
-        assert!(false);
+        assert!(not_modified_response_headers(
+            &simple_request(),
+            &response_with(200, ETAGGED_HEADERS),
+            &simple_request(),
+            &with_headers(304, ETAGGED_HEADERS, &[("etag", "\"other\"")]),
+        )
+        .is_none());
+        assert!(not_modified_response_headers(
+            &simple_request(),
+            &response_with(200, LAST_MODIFIED_HEADERS),
+            &simple_request(),
+            &with_headers(304, LAST_MODIFIED_HEADERS, &[("last-modified", "dunno")]),
+        )
+        .is_none());
     }
-    
+
     #[test]
     fn test_vary_basic() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &request_with(&[("weather", "nice")]),
+            &response_with(200, &[("cache-control", "max-age=5"), ("vary", "weather")]),
+            &shared(),
+            now,
+        );
+        assert!(policy.satisfies_without_revalidation(&request_with(&[("weather", "nice")]), now));
+        assert!(!policy.satisfies_without_revalidation(&request_with(&[("weather", "bad")]), now));
     }
-    
+
     #[test]
     fn test_asterisks_does_not_match() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &request_with(&[("weather", "ok")]),
+            &response_with(200, &[("cache-control", "max-age=5"), ("vary", "*")]),
+            &shared(),
+            now,
+        );
+        assert!(!policy.satisfies_without_revalidation(&request_with(&[("weather", "ok")]), now));
     }
-    
+
     #[test]
     fn test_asterisks_is_stale() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy1 = policy_for(
+            &request_with(&[("weather", "ok")]),
+            &response_with(
+                200,
+                &[("cache-control", "public,max-age=99"), ("vary", "*")],
+            ),
+            &shared(),
+            now,
+        );
+        let policy2 = policy_for(
+            &request_with(&[("weather", "ok")]),
+            &response_with(
+                200,
+                &[("cache-control", "public,max-age=99"), ("vary", "weather")],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(policy1.stale(now));
+        assert!(!policy2.stale(now));
     }
-    
+
     #[test]
     fn test_values_are_case_sensitive() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &request_with(&[("weather", "BAD")]),
+            &response_with(200, &[("cache-control", "max-age=5"), ("vary", "Weather")]),
+            &shared(),
+            now,
+        );
+        assert!(policy.satisfies_without_revalidation(&request_with(&[("weather", "BAD")]), now));
+        assert!(!policy.satisfies_without_revalidation(&request_with(&[("weather", "bad")]), now));
     }
-    
+
     #[test]
     fn test_irrelevant_headers_ignored() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &request_with(&[("weather", "nice")]),
+            &response_with(
+                200,
+                &[("cache-control", "max-age=5"), ("vary", "moon-phase")],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(policy.satisfies_without_revalidation(&request_with(&[("weather", "bad")]), now));
+        assert!(
+            policy.satisfies_without_revalidation(&request_with(&[("weather", "shining")]), now)
+        );
+        assert!(
+            !policy.satisfies_without_revalidation(&request_with(&[("moon-phase", "full")]), now)
+        );
     }
-    
+
     #[test]
     fn test_absence_is_meaningful() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &request_with(&[("weather", "nice")]),
+            &response_with(
+                200,
+                &[
+                    ("cache-control", "max-age=5"),
+                    ("vary", "moon-phase, weather"),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(policy.satisfies_without_revalidation(&request_with(&[("weather", "nice")]), now));
+        assert!(!policy.satisfies_without_revalidation(
+            &request_with(&[("weather", "nice"), ("moon-phase", "")]),
+            now
+        ));
+        assert!(!policy.satisfies_without_revalidation(&req(), now));
     }
-    
+
     #[test]
     fn test_all_values_must_match() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &request_with(&[("sun", "shining"), ("weather", "nice")]),
+            &response_with(
+                200,
+                &[("cache-control", "max-age=5"), ("vary", "weather, sun")],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(policy.satisfies_without_revalidation(
+            &request_with(&[("sun", "shining"), ("weather", "nice")]),
+            now
+        ));
+        assert!(!policy.satisfies_without_revalidation(
+            &request_with(&[("sun", "shining"), ("weather", "bad")]),
+            now
+        ));
     }
-    
+
     #[test]
     fn test_whitespace_is_okay() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &request_with(&[("sun", "shining"), ("weather", "nice")]),
+            &response_with(
+                200,
+                &[
+                    ("cache-control", "max-age=5"),
+                    ("vary", "    weather       ,     sun     "),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(policy.satisfies_without_revalidation(
+            &request_with(&[("sun", "shining"), ("weather", "nice")]),
+            now
+        ));
+        assert!(!policy.satisfies_without_revalidation(&request_with(&[("weather", "nice")]), now));
+        assert!(!policy.satisfies_without_revalidation(&request_with(&[("sun", "shining")]), now));
     }
-    
+
     #[test]
     fn test_order_is_irrelevant() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy1 = policy_for(
+            &request_with(&[("sun", "shining"), ("weather", "nice")]),
+            &response_with(
+                200,
+                &[("cache-control", "max-age=5"), ("vary", "weather, sun")],
+            ),
+            &shared(),
+            now,
+        );
+        let policy2 = policy_for(
+            &request_with(&[("sun", "shining"), ("weather", "nice")]),
+            &response_with(
+                200,
+                &[("cache-control", "max-age=5"), ("vary", "sun, weather")],
+            ),
+            &shared(),
+            now,
+        );
+        let request = request_with(&[("weather", "nice"), ("sun", "shining")]);
+        assert!(policy1.satisfies_without_revalidation(&request, now));
+        assert!(policy2.satisfies_without_revalidation(&request, now));
     }
-    
+
     #[test]
     fn test_thaw_wrong_object() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(&req(), &response_with(200, &[]), &shared(), now);
+        let mut obj = policy.to_object();
+        obj.version = 2;
+        assert!(CachePolicy::from_object(&obj).is_none());
     }
-    
+
     #[test]
     fn test_missing_headers() {
-        assert!(false);
+        let now = SystemTime::now();
+        let policy = policy_for(&Request::new(()), &Response::new(()), &shared(), now);
+        assert!(policy.storable());
+        assert!(policy.stale(now));
+        assert!(policy.revalidation_headers(&Request::new(())).is_empty());
     }
-    
+
     #[test]
     fn test_github_response_with_small_clock_skew() {
-        assert!(false);
+        // https://github.com/kornelski/http-cache-semantics/issues/5
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("date", &format_date(now, 3, 1)),
+                    ("cache-control", "private, max-age=60, s-maxage=60"),
+                    ("vary", "Accept, Authorization, Cookie, X-GitHub-OTP"),
+                    ("expires", &format_date(now, 63, 1)),
+                    ("etag", "W/\"b8e9e8c4cbf8e9a5c1b1d9b1d3a1cbbe\""),
+                ],
+            ),
+            &private(),
+            now,
+        );
+        assert!(!policy.stale(now));
+    }
+
+    #[test]
+    fn test_permanent_redirect_uses_heuristic_by_default() {
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(301, &[("location", "/elsewhere")]),
+            &shared(),
+            now,
+        );
+        assert!(policy.storable());
+        assert_eq!(policy.max_age(), 0);
+        assert_eq!(policy.permanent_redirect(), Some("/elsewhere"));
+    }
+
+    #[test]
+    fn test_permanent_redirect_long_cache() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            permanent_redirect_ttl: Some(Duration::from_secs(365 * 24 * 3600)),
+            ..CacheOptions::default()
+        };
+        for status in &[301, 308] {
+            let policy = policy_for(
+                &req(),
+                &response_with(
+                    *status,
+                    &[
+                        ("location", "/elsewhere"),
+                        ("last-modified", &format_date(now, -1, 3600)),
+                    ],
+                ),
+                &options,
+                now,
+            );
+            assert!(!policy.stale(now));
+            assert_eq!(policy.max_age(), 365 * 24 * 3600);
+            assert_eq!(policy.permanent_redirect(), Some("/elsewhere"));
+        }
+    }
+
+    #[test]
+    fn test_permanent_redirect_explicit_freshness_wins() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            permanent_redirect_ttl: Some(Duration::from_secs(365 * 24 * 3600)),
+            ..CacheOptions::default()
+        };
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                301,
+                &[("location", "/elsewhere"), ("cache-control", "max-age=60")],
+            ),
+            &options,
+            now,
+        );
+        assert_eq!(policy.max_age(), 60);
+
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                301,
+                &[("location", "/elsewhere"), ("cache-control", "no-cache")],
+            ),
+            &options,
+            now,
+        );
+        assert_eq!(policy.max_age(), 0);
+    }
+
+    #[test]
+    fn test_retry_after_not_cached_by_default() {
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(429, &[("retry-after", "120")]),
+            &shared(),
+            now,
+        );
+        assert!(!policy.storable());
+        assert_eq!(policy.retry_after(), Some(RetryAfter::Delay(120)));
+    }
+
+    #[test]
+    fn test_retry_after_delay_caching() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            cache_retry_after: true,
+            ..CacheOptions::default()
+        };
+        let policy = policy_for(
+            &req(),
+            &response_with(429, &[("retry-after", "120")]),
+            &options,
+            now,
+        );
+        assert!(policy.storable());
+        assert_eq!(policy.max_age(), 120);
+        assert!(!policy.stale(now));
+        assert!(policy.stale(now + Duration::from_secs(120)));
+    }
+
+    #[test]
+    fn test_retry_after_date_caching() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            cache_retry_after: true,
+            ..CacheOptions::default()
+        };
+        let retry_at = format_date(now, 90, 1);
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                503,
+                &[
+                    ("date", &format_date(now, 0, 1)),
+                    ("retry-after", &retry_at),
+                ],
+            ),
+            &options,
+            now,
+        );
+        assert!(policy.storable());
+        assert!(policy.max_age() >= 89 && policy.max_age() <= 90);
+        match policy.retry_after() {
+            Some(RetryAfter::Date(date)) => assert_eq!(format_http_date(date), retry_at),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_retry_after_respects_explicit_directives() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            cache_retry_after: true,
+            ..CacheOptions::default()
+        };
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                503,
+                &[("retry-after", "120"), ("cache-control", "max-age=10")],
+            ),
+            &options,
+            now,
+        );
+        assert_eq!(policy.max_age(), 10);
+
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                503,
+                &[("retry-after", "120"), ("cache-control", "no-store")],
+            ),
+            &options,
+            now,
+        );
+        assert!(!policy.storable());
+
+        let policy = policy_for(
+            &req(),
+            &response_with(500, &[("retry-after", "120")]),
+            &options,
+            now,
+        );
+        assert!(!policy.storable());
+        assert_eq!(policy.retry_after(), Some(RetryAfter::Delay(120)));
+    }
+
+    #[test]
+    fn test_append_via() {
+        let mut headers = http::HeaderMap::new();
+        append_via(&mut headers, http::Version::HTTP_11, "cache.example");
+        assert_eq!(headers["via"], "1.1 cache.example");
+
+        headers.insert("via", "1.0 fred, 1.1 p.example.net".parse().unwrap());
+        append_via(&mut headers, http::Version::HTTP_2, "cache.example");
+        let via: Vec<_> = headers.get_all("via").iter().collect();
+        assert_eq!(via, ["1.0 fred, 1.1 p.example.net", "2 cache.example"]);
+    }
+
+    #[test]
+    fn test_via_loop_detection() {
+        let request = request_with(&[("via", "1.0 fred, 1.1 Cache.Example (squid)")]);
+        assert!(via_contains(request.headers(), "cache.example"));
+        assert!(!via_contains(request.headers(), "example"));
+        assert!(!via_contains(req().headers(), "cache.example"));
+    }
+
+    #[test]
+    fn test_response_headers_via() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            via_pseudonym: Some("cache.example".to_string()),
+            ..CacheOptions::default()
+        };
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "max-age=60"), ("via", "1.0 fred")]),
+            &options,
+            now,
+        );
+        let headers = policy.response_headers(now);
+        let via: Vec<_> = headers.get_all("via").iter().collect();
+        assert_eq!(via, ["1.0 fred", "1.1 cache.example"]);
+
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("cache-control", "max-age=60"),
+                    ("via", "1.0 fred, 1.1 cache.example"),
+                ],
+            ),
+            &options,
+            now,
+        );
+        let headers = policy.response_headers(now);
+        assert_eq!(headers.get_all("via").iter().count(), 1);
+
+        let policy2 = CachePolicy::from_object(&policy.to_object()).unwrap();
+        assert_eq!(headers, policy2.response_headers(now));
+    }
+
+    #[test]
+    fn test_temporary_redirect_is_not_long_cached() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            permanent_redirect_ttl: Some(Duration::from_secs(365 * 24 * 3600)),
+            ..CacheOptions::default()
+        };
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                302,
+                &[("location", "/elsewhere"), ("cache-control", "public")],
+            ),
+            &options,
+            now,
+        );
+        assert_eq!(policy.max_age(), 0);
+        assert_eq!(policy.permanent_redirect(), None);
+    }
+
+    #[test]
+    fn test_evaluate_serves_fresh() {
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "max-age=60"), ("etag", "\"x\"")]),
+            &shared(),
+            now,
+        );
+        match policy.evaluate(&req(), now + Duration::from_secs(10)) {
+            CacheAction::ServeStored { headers } => {
+                assert_eq!(headers["age"], "10");
+                assert_eq!(headers["etag"], "\"x\"");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_evaluate_revalidates_stale() {
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(200, ETAGGED_HEADERS),
+            &shared(),
+            now,
+        );
+        let later = now + Duration::from_secs(200);
+        match policy.evaluate(&simple_request(), later) {
+            CacheAction::Revalidate { upstream_request } => {
+                assert_eq!(upstream_request.uri(), SIMPLE_URI);
+                assert_eq!(upstream_request.method(), Method::GET);
+                assert_headers_passed(upstream_request.headers());
+                assert_eq!(upstream_request.headers()["if-none-match"], "\"123456789\"");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let request = simple_request_builder()
+            .header("cache-control", "no-cache")
+            .body(())
+            .unwrap();
+        assert!(matches!(
+            policy.evaluate(&request, now),
+            CacheAction::Revalidate { .. }
+        ));
     }
-    
-}    
+
+    #[test]
+    fn test_evaluate_only_if_cached() {
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "max-age=60")]),
+            &shared(),
+            now,
+        );
+        let request = request_with(&[("cache-control", "only-if-cached")]);
+        assert!(matches!(
+            policy.evaluate(&request, now),
+            CacheAction::ServeStored { .. }
+        ));
+        assert!(matches!(
+            policy.evaluate(&request, now + Duration::from_secs(120)),
+            CacheAction::SynthesizeGatewayTimeout
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_forwards_unusable() {
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "max-age=60")]),
+            &shared(),
+            now,
+        );
+        let other_url = Request::get("/other").body(()).unwrap();
+        assert!(matches!(
+            policy.evaluate(&other_url, now),
+            CacheAction::ForwardUncached
+        ));
+
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "no-store")]),
+            &shared(),
+            now,
+        );
+        assert!(matches!(
+            policy.evaluate(&req(), now),
+            CacheAction::ForwardUncached
+        ));
+    }
+
+    #[test]
+    fn test_freshen_updates_policy_and_headers() {
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &simple_request(),
+            &with_headers(
+                200,
+                ETAGGED_HEADERS,
+                &[("content-length", "5"), ("x-other", "original")],
+            ),
+            &shared(),
+            now,
+        );
+        let later = now + Duration::from_secs(500);
+        assert!(policy.stale(later));
+
+        let not_modified = with_headers(
+            304,
+            &[("etag", "\"123456789\""), ("cache-control", "max-age=300")],
+            &[("content-length", "0"), ("x-other", "updated")],
+        );
+        let (freshened, headers) = policy.freshen(&not_modified, later).unwrap();
+        assert_eq!(headers["cache-control"], "max-age=300");
+        assert_eq!(headers["content-length"], "5");
+        assert_eq!(headers["x-other"], "updated");
+        assert_eq!(freshened.max_age(), 300);
+        assert!(!freshened.stale(later));
+        assert_eq!(freshened.age(later), 0);
+        assert_eq!(freshened.response_headers(later)["x-other"], "updated");
+        assert!(freshened.satisfies_without_revalidation(&simple_request(), later));
+    }
+
+    #[test]
+    fn test_freshen_rejects_mismatched_validators() {
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(200, ETAGGED_HEADERS),
+            &shared(),
+            now,
+        );
+        assert!(policy
+            .freshen(&response_with(304, &[("etag", "\"other\"")]), now)
+            .is_none());
+        assert!(policy
+            .freshen(&response_with(200, ETAGGED_HEADERS), now)
+            .is_none());
+    }
+
+    #[test]
+    fn test_110_added_when_stale() {
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(200, &[("cache-control", "max-age=10")]),
+            &shared(),
+            now,
+        );
+        assert!(!policy.response_headers(now).contains_key("warning"));
+        let later = now + Duration::from_secs(20);
+        assert_eq!(
+            policy.response_headers(later)["warning"],
+            "110 - \"Response is Stale\""
+        );
+    }
+
+    #[test]
+    fn test_warnings_can_be_disabled() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            generate_warnings: false,
+            ..CacheOptions::default()
+        };
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(
+                200,
+                &[
+                    ("age", "259200"),
+                    ("last-modified", "Tue, 15 Nov 1994 12:45:26 GMT"),
+                    ("warning", "199 test danger, 214 - transformed"),
+                ],
+            ),
+            &options,
+            now,
+        );
+        let later = now + Duration::from_secs(3650 * 86400);
+        assert!(policy.stale(later));
+        assert_eq!(
+            policy.response_headers(later)["warning"],
+            "214 - transformed"
+        );
+    }
+
+    #[test]
+    fn test_revalidation_drops_1xx_warnings() {
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &simple_request(),
+            &with_headers(
+                200,
+                ETAGGED_HEADERS,
+                &[("warning", "110 - \"Response is Stale\", 299 - persistent")],
+            ),
+            &shared(),
+            now,
+        );
+        let (_, headers) = policy
+            .freshen(&response_with(304, ETAGGED_HEADERS), now)
+            .unwrap();
+        assert_eq!(headers["warning"], "299 - persistent");
+
+        let (_, headers) = policy
+            .freshen(
+                &with_headers(304, ETAGGED_HEADERS, &[("warning", "199 - fresh")]),
+                now,
+            )
+            .unwrap();
+        assert_eq!(headers["warning"], "199 - fresh");
+    }
+
+    #[test]
+    fn test_try_policy_for_accepts_valid_input() {
+        let now = SystemTime::now();
+        let policy = try_policy_for(
+            &simple_request(),
+            &response_with(200, CACHEABLE_HEADERS),
+            &shared(),
+            now,
+        )
+        .unwrap();
+        assert!(policy.storable());
+    }
+
+    #[test]
+    fn test_try_policy_for_rejects_invalid_header() {
+        let now = SystemTime::now();
+        let mut response = response_with(200, &[]);
+        response.headers_mut().insert(
+            "cache-control",
+            http::HeaderValue::from_bytes(b"max-age=\xff").unwrap(),
+        );
+        let err = try_policy_for(&simple_request(), &response, &shared(), now).unwrap_err();
+        assert_eq!(
+            err,
+            CachePolicyError::InvalidHeader(http::header::CACHE_CONTROL)
+        );
+
+        // The lenient constructor ignores the header instead.
+        let policy = policy_for(&simple_request(), &response, &shared(), now);
+        assert_eq!(policy.max_age(), 0);
+    }
+
+    #[test]
+    fn test_try_policy_for_rejects_connect() {
+        let now = SystemTime::now();
+        let request = Request::builder()
+            .method(Method::CONNECT)
+            .uri("example.com:443")
+            .body(())
+            .unwrap();
+        let err = try_policy_for(
+            &request,
+            &response_with(200, CACHEABLE_HEADERS),
+            &shared(),
+            now,
+        )
+        .unwrap_err();
+        assert_eq!(err, CachePolicyError::UnsupportedMethod(Method::CONNECT));
+        assert!(!policy_for(
+            &request,
+            &response_with(200, CACHEABLE_HEADERS),
+            &shared(),
+            now
+        )
+        .storable());
+    }
+
+    #[test]
+    fn test_try_policy_for_rejects_absurd_timestamps() {
+        let before_epoch = UNIX_EPOCH - Duration::from_secs(1);
+        let err = try_policy_for(
+            &simple_request(),
+            &response_with(200, CACHEABLE_HEADERS),
+            &shared(),
+            before_epoch,
+        )
+        .unwrap_err();
+        assert_eq!(err, CachePolicyError::AbsurdTimestamp(before_epoch));
+
+        let err = try_policy_for(
+            &simple_request(),
+            &response_with(200, &[("date", "Wed, 31 Dec 1969 23:59:00 GMT")]),
+            &shared(),
+            SystemTime::now(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, CachePolicyError::AbsurdTimestamp(_)));
+        assert_eq!(
+            err.to_string(),
+            "timestamp 60 seconds before the Unix epoch"
+        );
+    }
+
+    fn hash_of<T: std::hash::Hash>(value: &T) -> u64 {
+        use std::hash::Hasher;
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_clone_and_equality() {
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(200, CACHEABLE_HEADERS),
+            &shared(),
+            now,
+        );
+        let copy = policy.clone();
+        assert_eq!(policy, copy);
+        assert_eq!(hash_of(&policy), hash_of(&copy));
+
+        let thawed = CachePolicy::from_object(&policy.to_object()).unwrap();
+        assert_eq!(policy, thawed);
+        assert_eq!(hash_of(&policy), hash_of(&thawed));
+
+        let later = policy_for(
+            &simple_request(),
+            &response_with(200, CACHEABLE_HEADERS),
+            &shared(),
+            now + Duration::from_secs(1),
+        );
+        assert_ne!(policy, later);
+
+        let private = policy_for(
+            &simple_request(),
+            &response_with(200, CACHEABLE_HEADERS),
+            &private(),
+            now,
+        );
+        assert_ne!(policy, private);
+    }
+
+    #[test]
+    fn test_equality_ignores_header_order() {
+        let now = SystemTime::now();
+        let a = policy_for(
+            &simple_request(),
+            &response_with(200, &[("etag", "\"x\""), ("cache-control", "max-age=5")]),
+            &shared(),
+            now,
+        );
+        let b = policy_for(
+            &simple_request(),
+            &response_with(200, &[("cache-control", "max-age=5"), ("etag", "\"x\"")]),
+            &shared(),
+            now,
+        );
+        assert_eq!(a, b);
+        assert_eq!(hash_of(&a), hash_of(&b));
+    }
+
+    #[test]
+    fn test_display_summary() {
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(
+                200,
+                &[
+                    ("cache-control", "max-age=312"),
+                    ("vary", "Accept-Encoding"),
+                    ("etag", "\"v1\""),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        assert_eq!(
+            policy.to_string(),
+            "storable shared fresh ttl=312s src=max-age vary=accept-encoding etag=strong"
+        );
+        assert_eq!(
+            policy.summary(now + Duration::from_secs(400)).to_string(),
+            "storable shared stale ttl=0s src=max-age vary=accept-encoding etag=strong"
+        );
+    }
+
+    #[test]
+    fn test_display_summary_sources() {
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(200, &[("cache-control", "no-store"), ("etag", "W/\"v1\"")]),
+            &private(),
+            now,
+        );
+        assert_eq!(
+            policy.to_string(),
+            "unstorable private stale ttl=0s src=not-storable etag=weak"
+        );
+
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(
+                200,
+                &[
+                    ("date", &format_date(now, 0, 1)),
+                    ("last-modified", &format_date(now, -1000, 1)),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        assert_eq!(
+            policy.to_string(),
+            "storable shared fresh ttl=100s src=heuristic"
+        );
+    }
+
+    fn tokens(value: &str) -> Vec<(&str, Option<&str>)> {
+        CacheControlTokenizer::new(value.as_bytes())
+            .map(|(name, value)| {
+                (
+                    std::str::from_utf8(name).unwrap(),
+                    value.map(|v| std::str::from_utf8(v).unwrap()),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenizer() {
+        assert_eq!(
+            tokens(" Max-Age = 10 ,public"),
+            vec![("Max-Age", Some("10")), ("public", None)]
+        );
+        assert_eq!(
+            tokens(",, no-cache ,, ,private,"),
+            vec![("no-cache", None), ("private", None)]
+        );
+        assert_eq!(
+            tokens("private=\"set-cookie, x-a\", max-age=\"5\" junk, s-maxage="),
+            vec![
+                ("private", Some("set-cookie, x-a")),
+                ("max-age", Some("5")),
+                ("s-maxage", Some("")),
+            ]
+        );
+        assert_eq!(
+            tokens("a=\"x\\\"y, z\", b=\"unterminated, c"),
+            vec![("a", Some("x\\\"y, z")), ("b", Some("unterminated, c"))]
+        );
+        assert_eq!(tokens("=5, =, b"), vec![("b", None)]);
+        assert!(tokens("").is_empty());
+    }
+
+    #[test]
+    fn test_typed_directives() {
+        let directives = CacheDirectives::from_bytes(
+            b"Public, max-age=60, S-MAXAGE=\"120\", max-stale, immutable, max-age=30, unknown=1",
+        );
+        assert_eq!(
+            directives,
+            CacheDirectives {
+                max_age: Some(30),
+                s_maxage: Some(120),
+                max_stale: Some(None),
+                public: true,
+                immutable: true,
+                ..CacheDirectives::default()
+            }
+        );
+
+        let directives =
+            CacheDirectives::from_bytes(b"max-stale=99999999999999999999999, min-fresh=x");
+        assert_eq!(directives.max_stale, Some(Some(u64::MAX)));
+        assert_eq!(directives.min_fresh, Some(0));
+    }
+
+    #[test]
+    fn test_parse_cache_control_quoted_commas() {
+        let cc = parse_cache_control(&[http::HeaderValue::from_static(
+            "no-cache=\"set-cookie, x-a\", MAX-AGE=5",
+        )]);
+        assert_eq!(cc.len(), 2);
+        assert_eq!(cc["no-cache"].as_deref(), Some("set-cookie, x-a"));
+        assert_eq!(cc["max-age"].as_deref(), Some("5"));
+    }
+
+    #[test]
+    fn test_satisfies_many() {
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &request_with(&[("accept-language", "en")]),
+            &response_with(
+                200,
+                &[
+                    ("cache-control", "max-age=100"),
+                    ("vary", "accept-language"),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        let requests = [
+            request_with(&[("accept-language", "en")]),
+            request_with(&[("accept-language", "fr")]),
+            request_with(&[("accept-language", "en"), ("cache-control", "max-age=10")]),
+            request_with(&[("accept-language", "en"), ("cache-control", "min-fresh=50")]),
+        ];
+        let later = now + Duration::from_secs(60);
+        assert_eq!(
+            policy.satisfies_many(&requests, later),
+            vec![true, false, false, false]
+        );
+        for request in &requests {
+            assert_eq!(
+                policy.satisfies_many(std::iter::once(request), later),
+                vec![policy.satisfies_without_revalidation(request, later)]
+            );
+        }
+    }
+
+    #[test]
+    fn test_policy_shared_across_threads() {
+        use std::sync::Arc;
+
+        let now = SystemTime::now();
+        let policy = Arc::new(policy_for(
+            &simple_request(),
+            &response_with(200, &[("cache-control", "max-age=100"), ("etag", "\"v1\"")]),
+            &shared(),
+            now,
+        ));
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let policy = Arc::clone(&policy);
+                std::thread::spawn(move || {
+                    let later = now + Duration::from_secs(i * 50);
+                    policy.satisfies_without_revalidation(&simple_request(), later)
+                })
+            })
+            .collect();
+        let results: Vec<bool> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(results, vec![true, true, false, false]);
+
+        // Revalidation produces a new policy and leaves the shared one untouched.
+        let later = now + Duration::from_secs(150);
+        let revalidated = policy.revalidated_policy(
+            &simple_request(),
+            &response_with(304, &[("cache-control", "max-age=100"), ("etag", "\"v1\"")]),
+            later,
+        );
+        assert!(revalidated.matches);
+        assert!(!revalidated.policy.stale(later));
+        assert!(policy.stale(later));
+    }
+
+    #[cfg(feature = "test_support")]
+    #[test]
+    fn test_support_scenarios() {
+        use crate::test_support;
+
+        for scenario in test_support::scenarios() {
+            assert_eq!(scenario.check(), Ok(()));
+        }
+
+        let mut scenario = test_support::stale_while_revalidate();
+        scenario.requested_at += Duration::from_secs(600);
+        assert_eq!(
+            scenario.check(),
+            Err("stale-while-revalidate: expected within_stale_while_revalidate to be true".into())
+        );
+    }
+
+    #[test]
+    fn test_within_stale_while_revalidate() {
+        let now = SystemTime::now();
+        let flags = EvaluationFlags::default();
+        let later = now + Duration::from_secs(120);
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[("cache-control", "max-age=60, stale-while-revalidate=600")],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(policy.within_stale_while_revalidate(&req(), later, flags));
+        let no_cache = request_with(&[("cache-control", "no-cache")]);
+        assert!(!policy.within_stale_while_revalidate(&no_cache, later, flags));
+        let prefetch = EvaluationFlags {
+            is_prefetch: true,
+            ..flags
+        };
+        assert!(!policy.within_stale_while_revalidate(&req(), later, prefetch));
+        assert!(!policy.within_stale_while_revalidate(
+            &req(),
+            now + Duration::from_secs(700),
+            flags
+        ));
+
+        let must_revalidate = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[(
+                    "cache-control",
+                    "max-age=60, stale-while-revalidate=600, must-revalidate",
+                )],
+            ),
+            &shared(),
+            now,
+        );
+        assert!(!must_revalidate.within_stale_while_revalidate(&req(), later, flags));
+
+        let grace = CacheOptions {
+            grace: Duration::from_secs(100),
+            ..shared()
+        };
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "max-age=60")]),
+            &grace,
+            now,
+        );
+        assert!(policy.within_stale_while_revalidate(&req(), later, flags));
+    }
+
+    #[cfg(feature = "proptest")]
+    mod properties {
+        use crate::arbitrary::{
+            base_time, build_request, build_response, check_fresh_implies_storable,
+            check_ttl_monotonic, http_date_any_year,
+        };
+        use crate::CachePolicy;
+        use proptest::prelude::*;
+        use std::time::Duration;
+
+        proptest! {
+            #[test]
+            fn fresh_implies_storable(policy in any::<CachePolicy>(), offset in 0u64..1_000_000) {
+                let now = base_time() + Duration::from_secs(offset);
+                prop_assert_eq!(check_fresh_implies_storable(&policy, now), Ok(()));
+            }
+
+            #[test]
+            fn ttl_monotonic_in_now(
+                policy in any::<CachePolicy>(),
+                a in 0u64..1_000_000,
+                b in 0u64..1_000_000,
+            ) {
+                let earlier = base_time() + Duration::from_secs(a);
+                let later = base_time() + Duration::from_secs(b);
+                prop_assert_eq!(check_ttl_monotonic(&policy, earlier, later), Ok(()));
+            }
+
+            #[test]
+            fn raw_ingestion_never_panics(
+                method in proptest::collection::vec(any::<u8>(), 0..8),
+                uri in proptest::collection::vec(any::<u8>(), 0..32),
+                status in any::<u16>(),
+                request_headers in proptest::collection::vec(
+                    (proptest::collection::vec(any::<u8>(), 0..12),
+                     proptest::collection::vec(any::<u8>(), 0..40)),
+                    0..6,
+                ),
+                response_headers in proptest::collection::vec(
+                    (proptest::sample::select(vec![
+                        b"cache-control".to_vec(), b"date".to_vec(), b"expires".to_vec(),
+                        b"vary".to_vec(), b"age".to_vec(), b"etag".to_vec(), b"x\xff".to_vec(),
+                    ]),
+                     proptest::collection::vec(any::<u8>(), 0..40)),
+                    0..8,
+                ),
+            ) {
+                let request_headers: Vec<(&[u8], &[u8])> = request_headers
+                    .iter()
+                    .map(|(name, value)| (&name[..], &value[..]))
+                    .collect();
+                let response_headers: Vec<(&[u8], &[u8])> = response_headers
+                    .iter()
+                    .map(|(name, value)| (&name[..], &value[..]))
+                    .collect();
+                let now = base_time();
+                if let Ok(policy) = crate::policy_for_raw(
+                    &method,
+                    &uri,
+                    &request_headers,
+                    status,
+                    &response_headers,
+                    &crate::CacheOptions::default(),
+                    now,
+                ) {
+                    let later = now + Duration::from_secs(1000);
+                    policy.response_headers(later);
+                    policy.time_to_live(later);
+                    prop_assert_eq!(check_ttl_monotonic(&policy, now, later), Ok(()));
+                }
+            }
+
+            #[test]
+            fn extreme_dates_are_deterministic(
+                expires in http_date_any_year(),
+                last_modified in http_date_any_year(),
+                offset in 0u64..1_000_000,
+            ) {
+                let res = build_response(200, &[
+                    ("expires".to_string(), expires),
+                    ("last-modified".to_string(), last_modified),
+                ]);
+                let req = build_request(http::Method::GET, &[]);
+                let options = crate::CacheOptions::default();
+                let policy = crate::policy_for(&req, &res, &options, base_time());
+                let again = crate::policy_for(&req, &res, &options, base_time());
+                let now = base_time() + Duration::from_secs(offset);
+                prop_assert_eq!(policy.time_to_live(now), again.time_to_live(now));
+                prop_assert_eq!(check_ttl_monotonic(&policy, base_time(), now), Ok(()));
+                policy.response_headers(now);
+            }
+
+            #[test]
+            fn survives_serialization(policy in any::<CachePolicy>()) {
+                let thawed = CachePolicy::from_object(&policy.to_object());
+                prop_assert_eq!(thawed.as_ref(), Some(&policy));
+            }
+        }
+    }
+
+    #[test]
+    fn test_policy_for_raw_normalizes_fields() {
+        let now = SystemTime::now();
+        let policy = policy_for_raw(
+            b"GET",
+            b"http://example.com/",
+            &[(b"Host", b"example.com"), (b"bad name", b"x")],
+            200,
+            &[
+                (b"Cache-Control", b"public,\r\n\t max-age=100"),
+                (b"cache-control", b" immutable "),
+                (b"X-Bytes", b"caf\xe9\0\x01"),
+                (b"", b"empty name"),
+                (b"Vary", b"\r\n"),
+            ],
+            &shared(),
+            now,
+        )
+        .unwrap();
+        assert_eq!(policy.max_age(), 100);
+        let headers = policy.response_headers(now);
+        let cache_control: Vec<_> = headers.get_all("cache-control").iter().collect();
+        assert_eq!(cache_control, ["public, max-age=100", "immutable"]);
+        assert_eq!(headers["x-bytes"].as_bytes(), b"caf\xe9");
+        assert_eq!(headers["vary"], "");
+        let request = Request::builder()
+            .uri("http://example.com/")
+            .header("host", "example.com")
+            .body(())
+            .unwrap();
+        assert!(policy.satisfies_without_revalidation(&request, now));
+    }
+
+    #[test]
+    fn test_policy_for_raw_rejects_request_line() {
+        let now = SystemTime::now();
+        let raw = |method: &[u8], uri: &[u8], status| {
+            policy_for_raw(method, uri, &[], status, &[], &shared(), now).unwrap_err()
+        };
+        assert_eq!(raw(b"G T", b"/", 200), CachePolicyError::InvalidMethod);
+        assert_eq!(raw(b"GET", b"\xff", 200), CachePolicyError::InvalidUri);
+        assert_eq!(raw(b"GET", b"/", 42), CachePolicyError::InvalidStatus(42));
+    }
+
+    #[test]
+    fn test_canonical_cache_control() {
+        let cc = parse_cache_control(&[
+            http::HeaderValue::from_static("Public, max-age=60, x-custom=\"a b\""),
+            http::HeaderValue::from_static("MAX-AGE=30, private=\"set-cookie\", no-cache"),
+        ]);
+        assert_eq!(
+            format_canonical_cache_control(&cc, false),
+            "max-age=30, no-cache, private=set-cookie, public, x-custom=\"a b\""
+        );
+        assert_eq!(
+            format_canonical_cache_control(&cc, true),
+            "max-age=30, no-cache, private=set-cookie, public"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_cache_control_when_serving() {
+        let now = SystemTime::now();
+        let response = response_with(
+            200,
+            &[("cache-control", "public, x-ext, max-age=60, max-age=60")],
+        );
+        let serve = |rewrite, shared| {
+            let options = CacheOptions {
+                rewrite_cache_control: rewrite,
+                shared,
+                ..CacheOptions::default()
+            };
+            let policy = policy_for(&simple_request(), &response, &options, now);
+            policy.response_headers(now)["cache-control"].clone()
+        };
+        assert_eq!(
+            serve(CacheControlRewrite::Preserve, true),
+            "public, x-ext, max-age=60, max-age=60"
+        );
+        assert_eq!(
+            serve(CacheControlRewrite::Canonical, true),
+            "max-age=60, public, x-ext"
+        );
+        assert_eq!(
+            serve(CacheControlRewrite::KnownOnly, true),
+            "max-age=60, public"
+        );
+        // Private caches pass the header through untouched.
+        assert_eq!(
+            serve(CacheControlRewrite::KnownOnly, false),
+            "public, x-ext, max-age=60, max-age=60"
+        );
+    }
+
+    #[test]
+    fn test_effective_directives() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            ignore_cargo_cult: true,
+            ..CacheOptions::default()
+        };
+        let policy = policy_for(
+            &request_with(&[("cache-control", "max-stale=10")]),
+            &response_with(
+                200,
+                &[
+                    ("cache-control", "pre-check=0, post-check=0, no-cache"),
+                    ("cache-control", "max-age=30, public"),
+                ],
+            ),
+            &options,
+            now,
+        );
+        assert_eq!(
+            policy.effective_response_directives(),
+            CacheDirectives {
+                max_age: Some(30),
+                public: true,
+                ..CacheDirectives::default()
+            }
+        );
+        assert_eq!(
+            policy.effective_request_directives().max_stale,
+            Some(Some(10))
+        );
+
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("pragma", "no-cache")]),
+            &shared(),
+            now,
+        );
+        assert!(policy.effective_response_directives().no_cache);
+    }
+
+    #[test]
+    fn test_request_directive_modes() {
+        let now = SystemTime::now();
+        let policy_with = |mode, request: &Request<()>| {
+            let options = CacheOptions {
+                honor_request_directives: mode,
+                ..CacheOptions::default()
+            };
+            policy_for(
+                request,
+                &response_with(200, &[("cache-control", "max-age=100")]),
+                &options,
+                now,
+            )
+        };
+        let no_cache = request_with(&[("cache-control", "no-cache")]);
+        let pragma = request_with(&[("pragma", "no-cache")]);
+        let max_age = request_with(&[("cache-control", "max-age=0")]);
+        let no_store = request_with(&[("cache-control", "no-store")]);
+        let later = now + Duration::from_secs(10);
+
+        let policy = policy_with(RequestDirectiveMode::Honor, &req());
+        assert!(!policy.satisfies_without_revalidation(&no_cache, later));
+        assert!(!policy.satisfies_without_revalidation(&pragma, later));
+        assert!(!policy.satisfies_without_revalidation(&max_age, later));
+        assert!(!policy_with(RequestDirectiveMode::Honor, &no_store).storable());
+
+        let policy = policy_with(RequestDirectiveMode::IgnoreNoCache, &req());
+        assert!(policy.satisfies_without_revalidation(&no_cache, later));
+        assert!(policy.satisfies_without_revalidation(&pragma, later));
+        assert!(!policy.satisfies_without_revalidation(&max_age, later));
+        assert!(!policy_with(RequestDirectiveMode::IgnoreNoCache, &no_store).storable());
+
+        let policy = policy_with(RequestDirectiveMode::IgnoreAll, &req());
+        assert!(policy.satisfies_without_revalidation(&no_cache, later));
+        assert!(policy.satisfies_without_revalidation(&max_age, later));
+        let policy = policy_with(RequestDirectiveMode::IgnoreAll, &no_store);
+        assert!(policy.storable());
+        assert!(!policy.effective_request_directives().no_store);
+    }
+
+    #[test]
+    fn test_prefetch_never_served_stale() {
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(200, &[("cache-control", "max-age=10"), ("etag", "\"v\"")]),
+            &shared(),
+            now,
+        );
+        let prefetch = EvaluationFlags {
+            is_prefetch: true,
+            ..EvaluationFlags::default()
+        };
+        let request = simple_request_builder()
+            .header("cache-control", "max-stale=100")
+            .body(())
+            .unwrap();
+
+        assert!(policy.satisfies_without_revalidation_with(&request, now, prefetch));
+        let later = now + Duration::from_secs(20);
+        assert!(policy.satisfies_without_revalidation(&request, later));
+        assert!(!policy.satisfies_without_revalidation_with(&request, later, prefetch));
+        match policy.evaluate_with(&request, later, prefetch) {
+            CacheAction::Revalidate { .. } => {}
+            action => panic!("unexpected {:?}", action),
+        }
+    }
+
+    #[test]
+    fn test_per_content_type_rules() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            per_content_type: vec![
+                ContentTypeRule {
+                    pattern: "image/*".to_string(),
+                    cache_heuristic: HeuristicFraction::new(0.5),
+                    immutable_min_time_to_live: Some(Duration::from_secs(365 * 24 * 3600)),
+                },
+                ContentTypeRule {
+                    pattern: "text/html".to_string(),
+                    cache_heuristic: HeuristicFraction::new(0.0),
+                    immutable_min_time_to_live: None,
+                },
+            ],
+            ..CacheOptions::default()
+        };
+        let heuristic = |content_type: &str| {
+            policy_for(
+                &req(),
+                &response_with(
+                    200,
+                    &[
+                        ("content-type", content_type),
+                        ("date", &format_date(now, 0, 1)),
+                        ("last-modified", &format_date(now, -1000, 1)),
+                    ],
+                ),
+                &options,
+                now,
+            )
+            .max_age()
+        };
+        assert_eq!(heuristic("Image/PNG"), 500);
+        assert_eq!(heuristic("text/html; charset=utf-8"), 0);
+        assert_eq!(heuristic("application/json"), 100);
+
+        let immutable = |content_type: &str| {
+            policy_for(
+                &req(),
+                &response_with(
+                    200,
+                    &[
+                        ("content-type", content_type),
+                        ("cache-control", "immutable"),
+                    ],
+                ),
+                &options,
+                now,
+            )
+            .max_age()
+        };
+        assert_eq!(immutable("image/webp"), 365 * 24 * 3600);
+        assert_eq!(immutable("text/html"), 24 * 3600);
+    }
+
+    fn partitioned(partition: &str) -> Request<()> {
+        let mut request = simple_request();
+        request
+            .extensions_mut()
+            .insert(CachePartition(partition.to_string()));
+        request
+    }
+
+    #[test]
+    fn test_partitioned_matching() {
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &partitioned("a.example"),
+            &response_with(200, CACHEABLE_HEADERS),
+            &shared(),
+            now,
+        );
+        assert_eq!(policy.partition(), Some("a.example"));
+        assert!(policy.satisfies_without_revalidation(&partitioned("a.example"), now));
+        assert!(!policy.satisfies_without_revalidation(&partitioned("b.example"), now));
+        assert!(!policy.satisfies_without_revalidation(&simple_request(), now));
+
+        let unpartitioned = policy_for(
+            &simple_request(),
+            &response_with(200, CACHEABLE_HEADERS),
+            &shared(),
+            now,
+        );
+        assert!(!unpartitioned.satisfies_without_revalidation(&partitioned("a.example"), now));
+
+        let thawed = CachePolicy::from_object(&policy.to_object()).unwrap();
+        assert_eq!(thawed, policy);
+        assert_ne!(policy, unpartitioned);
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let now = SystemTime::now();
+        assert_eq!(
+            cache_key(&simple_request()),
+            "http://www.w3c.org/Protocols/rfc2616/rfc2616-sec14.html"
+        );
+        let request = partitioned("a b");
+        assert_eq!(
+            cache_key(&request),
+            "3:a b http://www.w3c.org/Protocols/rfc2616/rfc2616-sec14.html"
+        );
+        let policy = policy_for(
+            &request,
+            &response_with(200, CACHEABLE_HEADERS),
+            &shared(),
+            now,
+        );
+        assert_eq!(policy.cache_key(), cache_key(&request));
+
+        let absolute = Request::builder()
+            .uri("https://example.com/x?y")
+            .body(())
+            .unwrap();
+        assert_eq!(cache_key(&absolute), "https://example.com/x?y");
+    }
+
+    #[test]
+    fn test_request_is_cache_eligible() {
+        let options = CacheOptions::default();
+        assert!(request_is_cache_eligible(&simple_request(), &options));
+        assert!(request_is_cache_eligible(
+            &request_with(&[("authorization", "Bearer x")]),
+            &options
+        ));
+        let post = Request::builder().method(Method::POST).body(()).unwrap();
+        assert!(request_is_cache_eligible(&post, &options));
+        let put = Request::builder().method(Method::PUT).body(()).unwrap();
+        assert!(!request_is_cache_eligible(&put, &options));
+
+        let no_store = request_with(&[("cache-control", "no-store")]);
+        assert!(!request_is_cache_eligible(&no_store, &options));
+        let ignoring = CacheOptions {
+            honor_request_directives: RequestDirectiveMode::IgnoreAll,
+            ..CacheOptions::default()
+        };
+        assert!(request_is_cache_eligible(&no_store, &ignoring));
+
+        // Eligibility never rules out a request whose exchange turns out storable.
+        for request in &[simple_request(), no_store] {
+            let policy = policy_for(
+                request,
+                &response_with(200, CACHEABLE_HEADERS),
+                &options,
+                SystemTime::now(),
+            );
+            assert!(!policy.storable() || request_is_cache_eligible(request, &options));
+        }
+    }
+
+    #[test]
+    fn test_storage_hooks() {
+        fn deny_admin(request: &Request<()>, _: &Response<()>) -> StorageOverride {
+            if request.uri().path().starts_with("/admin") {
+                StorageOverride::NeverStore
+            } else {
+                StorageOverride::Default
+            }
+        }
+        fn trust_internal(_: &Request<()>, response: &Response<()>) -> StorageOverride {
+            if response.headers().contains_key("x-internal") {
+                StorageOverride::AlwaysStore
+            } else {
+                StorageOverride::Default
+            }
+        }
+
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            storage_hooks: vec![deny_admin, trust_internal],
+            ..CacheOptions::default()
+        };
+        let admin = Request::builder().uri("/admin/x").body(()).unwrap();
+        let policy = policy_for(
+            &admin,
+            &response_with(200, CACHEABLE_HEADERS),
+            &options,
+            now,
+        );
+        assert!(!policy.storable());
+        assert_eq!(policy.max_age(), 0);
+
+        // The first decisive hook wins.
+        let policy = policy_for(
+            &admin,
+            &with_headers(200, CACHEABLE_HEADERS, &[("x-internal", "1")]),
+            &options,
+            now,
+        );
+        assert!(!policy.storable());
+
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(
+                200,
+                &[
+                    ("cache-control", "no-store, max-age=60"),
+                    ("x-internal", "1"),
+                ],
+            ),
+            &options,
+            now,
+        );
+        assert!(policy.storable());
+        assert_eq!(policy.max_age(), 60);
+        let thawed = CachePolicy::from_object(&policy.to_object()).unwrap();
+        assert!(thawed.storable());
+
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(200, CACHEABLE_HEADERS),
+            &options,
+            now,
+        );
+        assert!(policy.storable());
+    }
+
+    #[test]
+    fn test_glob_patterns() {
+        let rule = |pattern: &str| PathRule {
+            pattern: pattern.to_string(),
+            action: PathAction::NeverCache,
+        };
+        assert!(rule("/api/").matches("/api/users"));
+        assert!(!rule("/api/").matches("/apix"));
+        assert!(rule("/static/**").matches("/static/css/site.css"));
+        assert!(!rule("/static/**").matches("/other/static/x"));
+        assert!(rule("/static/*.css").matches("/static/site.css"));
+        assert!(!rule("/static/*.css").matches("/static/css/site.css"));
+        assert!(rule("/**/*.png").matches("/a/b/c.png"));
+        assert!(!rule("/**/*.png").matches("/a/b/c.jpg"));
+    }
+
+    #[test]
+    fn test_path_rules() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            path_rules: vec![
+                PathRule {
+                    pattern: "/static/**".to_string(),
+                    action: PathAction::Ttl(Duration::from_secs(30 * 24 * 3600)),
+                },
+                PathRule {
+                    pattern: "/api/".to_string(),
+                    action: PathAction::NeverCache,
+                },
+            ],
+            ..CacheOptions::default()
+        };
+        let request = |path: &str| Request::builder().uri(path).body(()).unwrap();
+
+        let policy = policy_for(
+            &request("/static/app.js"),
+            &response_with(200, &[("cache-control", "max-age=60")]),
+            &options,
+            now,
+        );
+        assert_eq!(policy.max_age(), 30 * 24 * 3600);
+        assert!(policy.to_string().contains("src=path-rule"));
+        assert_eq!(
+            CachePolicy::from_object(&policy.to_object()).unwrap(),
+            policy
+        );
+
+        // A TTL rule doesn't make unstorable responses storable.
+        let policy = policy_for(
+            &request("/static/app.js"),
+            &response_with(200, &[("cache-control", "no-store")]),
+            &options,
+            now,
+        );
+        assert_eq!(policy.max_age(), 0);
+
+        let policy = policy_for(
+            &request("/api/users"),
+            &response_with(200, &[("cache-control", "public, max-age=60")]),
+            &options,
+            now,
+        );
+        assert!(!policy.storable());
+
+        let policy = policy_for(
+            &request("/index.html"),
+            &response_with(200, &[("cache-control", "max-age=60")]),
+            &options,
+            now,
+        );
+        assert_eq!(policy.max_age(), 60);
+    }
+
+    #[test]
+    fn test_max_object_size() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            max_object_size: Some(1000),
+            ..CacheOptions::default()
+        };
+        let policy = policy_for(
+            &simple_request(),
+            &with_headers(200, CACHEABLE_HEADERS, &[("content-length", "1001")]),
+            &options,
+            now,
+        );
+        assert!(!policy.storable());
+        assert_eq!(
+            policy.unstorable_reason(),
+            Some(UnstorableReason::TooLarge {
+                content_length: 1001,
+                limit: 1000
+            })
+        );
+
+        let policy = policy_for(
+            &simple_request(),
+            &with_headers(200, CACHEABLE_HEADERS, &[("content-length", "1000")]),
+            &options,
+            now,
+        );
+        assert_eq!(policy.unstorable_reason(), None);
+
+        // Without a length, storing is up to the caller as the body streams in.
+        let policy = policy_for(
+            &simple_request(),
+            &with_headers(200, CACHEABLE_HEADERS, &[("transfer-encoding", "chunked")]),
+            &options,
+            now,
+        );
+        assert!(policy.storable());
+        assert_eq!(policy.content_length(), None);
+        assert_eq!(policy.body_size_limit(), Some(1000));
+
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(200, &[("cache-control", "no-store")]),
+            &options,
+            now,
+        );
+        assert_eq!(
+            policy.unstorable_reason(),
+            Some(UnstorableReason::ResponseForbids)
+        );
+    }
+
+    #[test]
+    fn test_storage_advice() {
+        let now = SystemTime::now();
+        let limited = CacheOptions {
+            max_object_size: Some(1000),
+            ..CacheOptions::default()
+        };
+        let advice = |headers: &[(&str, &str)], options: &CacheOptions| {
+            policy_for(
+                &simple_request(),
+                &with_headers(200, CACHEABLE_HEADERS, headers),
+                options,
+                now,
+            )
+            .storage_advice()
+        };
+
+        assert_eq!(
+            advice(&[("content-length", "10")], &limited),
+            StorageAdvice::Buffer
+        );
+        assert_eq!(
+            advice(&[("content-length", "10000")], &limited),
+            StorageAdvice::StreamThrough
+        );
+        assert_eq!(
+            advice(&[("transfer-encoding", "chunked")], &limited),
+            StorageAdvice::Opportunistic { limit: 1000 }
+        );
+        assert_eq!(
+            advice(
+                &[("transfer-encoding", "chunked"), ("content-length", "10")],
+                &limited
+            ),
+            StorageAdvice::Opportunistic { limit: 1000 }
+        );
+        assert_eq!(
+            advice(
+                &[("transfer-encoding", "chunked")],
+                &CacheOptions::default()
+            ),
+            StorageAdvice::Buffer
+        );
+
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(200, &[("cache-control", "no-store")]),
+            &limited,
+            now,
+        );
+        assert_eq!(policy.storage_advice(), StorageAdvice::StreamThrough);
+    }
+
+    #[test]
+    fn test_merge_trailers() {
+        let mut stored = response_with(
+            200,
+            &[
+                ("content-length", "100"),
+                ("server-timing", "db;dur=1"),
+                ("etag", "\"a\""),
+            ],
+        )
+        .headers()
+        .clone();
+        let trailers = response_with(
+            200,
+            &[
+                ("server-timing", "db;dur=53"),
+                ("server-timing", "app;dur=47"),
+                ("etag", "\"b\""),
+                ("content-length", "5"),
+                ("connection", "close"),
+            ],
+        )
+        .headers()
+        .clone();
+        merge_trailers(&mut stored, &trailers);
+
+        let timings: Vec<_> = stored.get_all("server-timing").iter().collect();
+        assert_eq!(timings, ["db;dur=53", "app;dur=47"]);
+        assert_eq!(stored["etag"], "\"b\"");
+        assert_eq!(stored["content-length"], "100");
+        assert!(!stored.contains_key("connection"));
+    }
+
+    #[test]
+    fn test_lenient_duplicate_headers() {
+        let now = SystemTime::now();
+        let res = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=1000"),
+                ("date", &format_date(now, -10, 1)),
+                ("date", &format_date(now, -100, 1)),
+                ("age", "5"),
+                ("age", "500"),
+                ("last-modified", &format_date(now, -3600, 1)),
+                ("last-modified", &format_date(now, -7200, 1)),
+            ],
+        );
+        let policy = policy_for(&req(), &res, &CacheOptions::default(), now);
+        let headers = policy.response_headers(now);
+        assert_eq!(headers.get_all("date").iter().count(), 1);
+        let unix_seconds = |time: SystemTime| time.duration_since(UNIX_EPOCH).unwrap().as_secs();
+        assert_eq!(unix_seconds(policy.date()), unix_seconds(now) - 100);
+        assert_eq!(policy.age(now), 500);
+        assert_eq!(
+            headers["last-modified"],
+            format_date(now, -3600, 1).as_str()
+        );
+
+        // Identical duplicates aren't a conflict.
+        let res = response_with(
+            200,
+            &[
+                ("expires", &format_date(now, 100, 1)),
+                ("expires", &format_date(now, 100, 1)),
+            ],
+        );
+        let policy = policy_for(&req(), &res, &CacheOptions::default(), now);
+        assert!(!policy.stale(now));
+
+        let res = response_with(
+            200,
+            &[
+                ("expires", &format_date(now, 100, 1)),
+                ("expires", &format_date(now, 200, 1)),
+            ],
+        );
+        let policy = policy_for(&req(), &res, &CacheOptions::default(), now);
+        assert!(policy.stale(now));
+    }
+
+    #[test]
+    fn test_strict_duplicate_headers() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            duplicate_headers: DuplicateHeaderMode::Strict,
+            ..CacheOptions::default()
+        };
+        let res = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=1000"),
+                ("age", "5"),
+                ("age", "50"),
+            ],
+        );
+        let policy = policy_for(&req(), &res, &options, now);
+        assert_eq!(
+            policy.unstorable_reason(),
+            Some(UnstorableReason::ConflictingHeaders)
+        );
+        assert_eq!(
+            try_policy_for(&req(), &res, &options, now).unwrap_err(),
+            CachePolicyError::ConflictingHeader(http::header::AGE)
+        );
+
+        let res = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=1000"),
+                ("age", "5"),
+                ("age", "5"),
+            ],
+        );
+        assert!(try_policy_for(&req(), &res, &options, now)
+            .unwrap()
+            .storable());
+    }
+
+    #[test]
+    fn test_vary_matches_opaque_values_bytewise() {
+        let now = SystemTime::now();
+        let opaque = |bytes: &[u8]| {
+            let mut req = request_with(&[]);
+            req.headers_mut()
+                .insert("x-token", http::HeaderValue::from_bytes(bytes).unwrap());
+            req
+        };
+        let res = response_with(200, &[("cache-control", "max-age=60"), ("vary", "X-Token")]);
+        let policy = policy_for(&opaque(b"caf\xe9"), &res, &CacheOptions::default(), now);
+        assert!(policy.satisfies_without_revalidation(&opaque(b"caf\xe9"), now));
+        assert!(!policy.satisfies_without_revalidation(&opaque(b"caf\xe8"), now));
+    }
+
+    #[test]
+    fn test_opaque_validators() {
+        let now = SystemTime::now();
+        let mut res = response_with(200, &[("cache-control", "max-age=60")]);
+        res.headers_mut()
+            .insert("etag", http::HeaderValue::from_bytes(b"\"\xff\"").unwrap());
+        let policy = policy_for(&req(), &res, &CacheOptions::default(), now);
+
+        let mut not_modified = response_with(304, &[]);
+        not_modified
+            .headers_mut()
+            .insert("etag", res.headers()["etag"].clone());
+        assert!(
+            policy
+                .revalidated_policy(&req(), &not_modified, now)
+                .matches
+        );
+
+        // If-Match forbids weak validators.
+        let mut conditional = request_with(&[("if-match", "*")]);
+        conditional.headers_mut().insert(
+            "if-none-match",
+            http::HeaderValue::from_bytes(b"W/\"\xfe\", \"\xfd\"").unwrap(),
+        );
+        let headers = policy.revalidation_headers(&conditional);
+        assert_eq!(
+            headers["if-none-match"].as_bytes(),
+            &b"\"\xfd\", \"\xff\""[..]
+        );
+    }
+
+    #[test]
+    fn test_records_only_vary_request_headers() {
+        let now = SystemTime::now();
+        let mut request = request_with(&[
+            ("accept-language", "en"),
+            ("accept-language", "fr"),
+            ("user-agent", "test"),
+        ]);
+        request.headers_mut().insert(
+            "cookie",
+            http::HeaderValue::from_bytes(b"session=\xff").unwrap(),
+        );
+        let res = response_with(
+            200,
+            &[("cache-control", "max-age=60"), ("vary", "Accept-Language")],
+        );
+        let policy = policy_for(&request, &res, &CacheOptions::default(), now);
+        let object = policy.to_object();
+        assert_eq!(
+            object.request_headers,
+            [
+                ("accept-language".to_string(), "en".to_string()),
+                ("accept-language".to_string(), "fr".to_string()),
+            ]
+        );
+
+        // The unrecorded, non-text cookie doesn't stop the policy from being restored.
+        let restored = CachePolicy::from_object(&object).unwrap();
+        assert!(restored.satisfies_without_revalidation(&request, now));
+        let other = request_with(&[("accept-language", "en")]);
+        assert!(!restored.satisfies_without_revalidation(&other, now));
+    }
+
+    #[test]
+    fn test_reject_vary() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            reject_vary: true,
+            ..CacheOptions::default()
+        };
+        let res = response_with(
+            200,
+            &[("cache-control", "max-age=60"), ("vary", "Accept-Encoding")],
+        );
+        let policy = policy_for(&req(), &res, &options, now);
+        assert!(!policy.storable());
+        assert_eq!(policy.unstorable_reason(), Some(UnstorableReason::Vary));
+        assert!(policy_for(&req(), &res, &CacheOptions::default(), now).storable());
+
+        let res = response_with(200, &[("cache-control", "max-age=60")]);
+        assert!(policy_for(&req(), &res, &options, now).storable());
+    }
+
+    #[test]
+    fn test_vary_limits() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            max_vary_fields: Some(2),
+            vary_deny_list: vec![http::header::COOKIE],
+            ..CacheOptions::default()
+        };
+        let reason = |vary: &str| {
+            let res = response_with(200, &[("cache-control", "max-age=60"), ("vary", vary)]);
+            policy_for(&req(), &res, &options, now).unstorable_reason()
+        };
+        assert_eq!(reason("Accept-Encoding, Accept-Language"), None);
+        assert_eq!(
+            reason("Accept-Encoding, Accept-Language, User-Agent"),
+            Some(UnstorableReason::TooManyVaryFields { count: 3, limit: 2 })
+        );
+        assert_eq!(
+            reason("Accept-Encoding, cookie"),
+            Some(UnstorableReason::DeniedVaryField)
+        );
+    }
+
+    #[test]
+    fn test_negotiated_vary_matching() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            vary_matching: VaryMatching::Negotiated,
+            ..CacheOptions::default()
+        };
+        let res = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=60"),
+                ("vary", "Accept, Accept-Language"),
+                ("content-type", "application/json; charset=utf-8"),
+                ("content-language", "en-GB"),
+            ],
+        );
+        let stored = request_with(&[("accept", "application/json"), ("accept-language", "en")]);
+        let policy = policy_for(&stored, &res, &options, now);
+        let satisfies = |headers: &[(&str, &str)]| {
+            policy.satisfies_without_revalidation(&request_with(headers), now)
+        };
+
+        assert!(satisfies(&[
+            ("accept", "text/html;q=0.9, application/*"),
+            ("accept-language", "fr, en-gb;q=0.5"),
+        ]));
+        assert!(satisfies(&[]));
+        assert!(!satisfies(&[("accept", "text/html")]));
+        assert!(!satisfies(&[("accept", "*/*, application/json;q=0")]));
+        assert!(!satisfies(&[("accept-language", "en-US")]));
+        assert!(!satisfies(&[("accept-language", "*, en;q=0")]));
+
+        // Exact matching is the default.
+        let policy = policy_for(&stored, &res, &CacheOptions::default(), now);
+        assert!(!policy.satisfies_without_revalidation(
+            &request_with(&[("accept", "application/*"), ("accept-language", "en")]),
+            now
+        ));
+    }
+
+    #[test]
+    fn test_normalize_uri() {
+        let normalize = |uri: &str| normalize_uri(&uri.parse().unwrap()).to_string();
+        assert_eq!(
+            normalize("HTTP://Example.COM:80/a/./b/../c/%7euser?q=%2f%41"),
+            "http://example.com/a/c/~user?q=%2FA"
+        );
+        assert_eq!(
+            normalize("https://example.com:8443/a/.."),
+            "https://example.com:8443/"
+        );
+        assert_eq!(normalize("https://example.com:443"), "https://example.com/");
+        assert_eq!(normalize("/a/../b#frag"), "/b");
+        assert_eq!(normalize("/%2e%2E/x"), "/x");
+        assert_eq!(normalize("http://a/café?q=ü%7e"), "http://a/café?q=ü~");
+    }
+
+    #[test]
+    fn test_url_matching() {
+        let now = SystemTime::now();
+        let get = |uri: &str| Request::builder().uri(uri).body(()).unwrap();
+        let res = response_with(200, &[("cache-control", "max-age=60")]);
+        let normalized = CacheOptions {
+            url_matching: UrlMatching::Normalized,
+            ..CacheOptions::default()
+        };
+        let policy = policy_for(&get("http://example.com/a/../b"), &res, &normalized, now);
+        assert!(policy.satisfies_without_revalidation(&get("http://EXAMPLE.com:80/b"), now));
+        assert_eq!(
+            policy.cache_key(),
+            cache_key_with(&get("http://example.com/b"), &normalized)
+        );
+
+        let policy = policy_for(
+            &get("http://example.com/a/../b"),
+            &res,
+            &CacheOptions::default(),
+            now,
+        );
+        assert!(!policy.satisfies_without_revalidation(&get("http://example.com/b"), now));
+        assert_eq!(
+            policy.cache_key(),
+            cache_key(&get("http://example.com/a/../b"))
+        );
+    }
+
+    #[test]
+    fn test_query_rules() {
+        let now = SystemTime::now();
+        let get = |uri: &str| Request::builder().uri(uri).body(()).unwrap();
+        let options = CacheOptions {
+            sort_query: true,
+            ignored_query_params: vec!["utm_*".to_string(), "fbclid".to_string()],
+            ..CacheOptions::default()
+        };
+        assert_eq!(
+            cache_key_with(
+                &get("https://example.com/p?b=2&utm_source=x&a=1&fbclid=y"),
+                &options
+            ),
+            "https://example.com/p?a=1&b=2"
+        );
+        assert_eq!(
+            cache_key_with(&get("https://example.com/p?utm_medium=email"), &options),
+            "https://example.com/p"
+        );
+
+        let res = response_with(200, &[("cache-control", "max-age=60")]);
+        let policy = policy_for(&get("https://example.com/p?a=1&b=2"), &res, &options, now);
+        assert!(policy
+            .satisfies_without_revalidation(&get("https://example.com/p?b=2&a=1&utm_id=3"), now));
+        assert!(!policy.satisfies_without_revalidation(&get("https://example.com/p?a=1"), now));
+
+        // Without the rules, order and tracking parameters matter.
+        let policy = policy_for(
+            &get("https://example.com/p?a=1&b=2"),
+            &res,
+            &CacheOptions::default(),
+            now,
+        );
+        assert!(!policy.satisfies_without_revalidation(&get("https://example.com/p?b=2&a=1"), now));
+    }
+
+    #[test]
+    fn test_host_matching() {
+        let now = SystemTime::now();
+        let get = |uri: &str, host: &str| {
+            Request::builder()
+                .uri(uri)
+                .header("host", host)
+                .body(())
+                .unwrap()
+        };
+        let res = response_with(200, &[("cache-control", "max-age=60")]);
+        let policy = policy_for(&get("/x", "Example.com"), &res, &shared(), now);
+        assert!(policy.satisfies_without_revalidation(&get("/x", "example.COM:80"), now));
+        assert!(!policy.satisfies_without_revalidation(&get("/x", "example.com:8080"), now));
+        assert!(!policy.satisfies_without_revalidation(&get("/x", "example.org"), now));
+        assert!(!policy
+            .satisfies_without_revalidation(&Request::builder().uri("/x").body(()).unwrap(), now));
+        // Keys only normalize the host under `UrlMatching::Normalized`.
+        assert_eq!(policy.cache_key(), "http://Example.com/x");
+
+        // An absolute URI's authority takes precedence over the Host header.
+        let policy = policy_for(
+            &get("http://example.com/x", "example.com"),
+            &res,
+            &shared(),
+            now,
+        );
+        assert!(policy
+            .satisfies_without_revalidation(&get("http://EXAMPLE.com/x", "evil.example"), now));
+        assert!(!policy
+            .satisfies_without_revalidation(&get("http://evil.example/x", "example.com"), now));
+    }
+
+    #[test]
+    fn test_scheme_matching() {
+        let now = SystemTime::now();
+        let get = |uri: &str| {
+            Request::builder()
+                .uri(uri)
+                .header("host", "example.com")
+                .body(())
+                .unwrap()
+        };
+        let res = response_with(200, &[("cache-control", "max-age=60")]);
+        let policy = policy_for(&get("http://example.com/x"), &res, &shared(), now);
+        assert!(!policy.satisfies_without_revalidation(&get("https://example.com/x"), now));
+        // Origin-form requests are assumed to be http.
+        assert!(policy.satisfies_without_revalidation(&get("/x"), now));
+        assert_eq!(policy.cache_key(), cache_key(&get("/x")));
+        assert_ne!(policy.cache_key(), cache_key(&get("https://example.com/x")));
+
+        let policy = policy_for(&get("https://example.com/x"), &res, &shared(), now);
+        assert!(!policy.satisfies_without_revalidation(&get("http://example.com/x"), now));
+        assert!(!policy.satisfies_without_revalidation(&get("/x"), now));
+        assert!(policy.satisfies_without_revalidation(&get("HTTPS://example.com:443/x"), now));
+    }
+
+    #[test]
+    fn test_storability_phases() {
+        let now = SystemTime::now();
+        let no_store = request_with(&[("cache-control", "no-store")]);
+        assert!(!request_allows_storing(&no_store, &shared()));
+        assert!(request_allows_storing(&req(), &shared()));
+
+        let private = response_with(200, &[("cache-control", "private, max-age=60")]);
+        assert!(!response_allows_storing(&private, &shared()));
+        assert!(response_allows_storing(&private, &self::private()));
+
+        let res = response_with(200, &[("cache-control", "max-age=60")]);
+        let policy = policy_for(&no_store, &res, &shared(), now);
+        assert!(!policy.request_allows_storing());
+        assert!(policy.response_allows_storing());
+        assert_eq!(
+            policy.unstorable_reason(),
+            Some(UnstorableReason::RequestForbids)
+        );
+
+        let authorized = request_with(&[("authorization", "Bearer x")]);
+        let policy = policy_for(&authorized, &res, &shared(), now);
+        assert_eq!(
+            policy.unstorable_reason(),
+            Some(UnstorableReason::RequestForbids)
+        );
+
+        let policy = policy_for(&req(), &private, &shared(), now);
+        assert!(policy.request_allows_storing());
+        assert_eq!(
+            policy.unstorable_reason(),
+            Some(UnstorableReason::ResponseForbids)
+        );
+    }
+
+    #[test]
+    fn test_option_presets() {
+        let now = SystemTime::now();
+        let private = response_with(200, &[("cache-control", "private, max-age=60")]);
+        assert!(policy_for(&req(), &private, &CacheOptions::browser(), now).storable());
+        assert!(!policy_for(&req(), &private, &CacheOptions::shared_cdn(), now).storable());
+
+        let res = response_with(200, &[("cache-control", "max-age=60")]);
+        let no_cache = request_with(&[("cache-control", "no-cache")]);
+        let satisfies = |options: &CacheOptions| {
+            policy_for(&req(), &res, options, now).satisfies_without_revalidation(&no_cache, now)
+        };
+        assert!(!satisfies(&CacheOptions::browser()));
+        assert!(satisfies(&CacheOptions::shared_cdn()));
+        assert!(satisfies(&CacheOptions::crawler()));
+
+        let moved = response_with(301, &[("location", "/elsewhere")]);
+        let policy = policy_for(&req(), &moved, &CacheOptions::crawler(), now);
+        assert_eq!(policy.time_to_live(now), 30 * 24 * 3600);
+        let policy = policy_for(&req(), &moved, &CacheOptions::browser(), now);
+        assert_eq!(policy.time_to_live(now), 365 * 24 * 3600);
+    }
+
+    #[test]
+    fn test_always_revalidate() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            always_revalidate: true,
+            ..CacheOptions::default()
+        };
+        let policy = policy_for(
+            &simple_request(),
+            &response_with(200, &[("cache-control", "max-age=3600"), ("etag", "\"x\"")]),
+            &options,
+            now,
+        );
+        assert!(policy.storable());
+        assert!(policy.stale(now));
+        assert_eq!(policy.time_to_live(now), 0);
+        let max_stale = simple_request_builder()
+            .header("cache-control", "max-stale")
+            .body(())
+            .unwrap();
+        assert!(!policy.satisfies_without_revalidation(&max_stale, now));
+        match policy.evaluate(&simple_request(), now) {
+            CacheAction::Revalidate { upstream_request } => {
+                assert_eq!(upstream_request.headers()["if-none-match"], "\"x\"");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let not_modified = response_with(304, &[("etag", "\"x\"")]);
+        let (freshened, _) = policy.freshen(&not_modified, now).unwrap();
+        assert!(freshened.stale(now));
+    }
+
+    #[test]
+    fn test_pinned() {
+        let now = SystemTime::now();
+        let res = response_with(
+            200,
+            &[("cache-control", "no-cache"), ("vary", "accept-language")],
+        );
+        let request = request_with(&[("accept-language", "en")]);
+        let policy = CachePolicy::pinned(&request, &res, &CacheOptions::default(), now);
+        assert!(policy.is_pinned());
+        assert!(policy.storable());
+        let much_later = now + Duration::from_secs(10 * 365 * 86400);
+        assert!(!policy.stale(much_later));
+        assert!(policy.satisfies_without_revalidation(&request, much_later));
+        let no_cache = request_with(&[("accept-language", "en"), ("cache-control", "no-cache")]);
+        assert!(policy.satisfies_without_revalidation(&no_cache, much_later));
+        let french = request_with(&[("accept-language", "fr")]);
+        assert!(!policy.satisfies_without_revalidation(&french, now));
+
+        let restored = CachePolicy::from_object(&policy.to_object()).unwrap();
+        assert!(restored.is_pinned());
+        assert!(!policy_for(&request, &res, &CacheOptions::default(), now).is_pinned());
+    }
+
+    #[test]
+    fn test_heuristic_fraction() {
+        assert_eq!(
+            HeuristicFraction::new(0.5).map(HeuristicFraction::get),
+            Some(0.5)
+        );
+        assert_eq!(HeuristicFraction::new(1.5), None);
+        assert_eq!(HeuristicFraction::new(f32::NAN), None);
+        assert_eq!(HeuristicFraction::new(-0.0), HeuristicFraction::new(0.0));
+        assert_eq!(HeuristicFraction::clamped(2.0).get(), 1.0);
+        assert_eq!(HeuristicFraction::clamped(f32::NAN).get(), 0.0);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_deprecated_option_setters() {
+        let mut options = CacheOptions::default();
+        options.set_cache_heuristic(0.25);
+        options.set_immutable_min_time_to_live(60);
+        options.set_permanent_redirect_ttl(Some(3600));
+        assert_eq!(
+            options.cache_heuristic,
+            HeuristicFraction::new(0.25).unwrap()
+        );
+        assert_eq!(options.immutable_min_time_to_live, Duration::from_secs(60));
+        assert_eq!(
+            options.permanent_redirect_ttl,
+            Some(Duration::from_secs(3600))
+        );
+    }
+
+    #[test]
+    fn test_http_dates_round_trip_and_clamp() {
+        let date = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!(format_http_date(date), "Sun, 06 Nov 1994 08:49:37 GMT");
+        let value = http::HeaderValue::from_static(" Sun, 06 Nov 1994 08:49:37 GMT ");
+        assert_eq!(
+            super::http_cache_semantics::parse_http_date(&value),
+            Some(date)
+        );
+
+        let value = http::HeaderValue::from_static("Thu, 01 Jan 1570 00:00:00 GMT");
+        let parsed = super::http_cache_semantics::parse_http_date(&value).unwrap();
+        assert_eq!(
+            UNIX_EPOCH.duration_since(parsed).unwrap(),
+            Duration::from_secs(146_097 * 86400)
+        );
+
+        assert_eq!(
+            format_http_date(UNIX_EPOCH - Duration::from_secs(1)),
+            "Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_policy_object_serde_round_trip() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            vary_deny_list: vec![http::header::COOKIE],
+            ..CacheOptions::browser()
+        };
+        let policy = policy_for(
+            &request_with(&[("accept-language", "en")]),
+            &response_with(
+                200,
+                &[("cache-control", "max-age=60"), ("vary", "accept-language")],
+            ),
+            &options,
+            now,
+        );
+        let json = serde_json::to_string(&policy.to_object()).unwrap();
+        let object: CachePolicyObject = serde_json::from_str(&json).unwrap();
+        assert_eq!(CachePolicy::from_object(&object), Some(policy));
+
+        let options: CacheOptions = serde_json::from_str(r#"{"shared": false}"#).unwrap();
+        assert!(!options.shared);
+        assert_eq!(
+            options.cache_heuristic,
+            CacheOptions::default().cache_heuristic
+        );
+        assert!(serde_json::from_str::<CacheOptions>(r#"{"cache_heuristic": 2.0}"#).is_err());
+    }
+
+    #[cfg(feature = "headers")]
+    #[test]
+    fn test_typed_headers() {
+        use headers::HeaderMapExt;
+
+        let now = SystemTime::now();
+        let mut res = response_with(200, &[("etag", "\"v1\"")]);
+        res.headers_mut().typed_insert(
+            headers::CacheControl::new()
+                .with_public()
+                .with_max_age(Duration::from_secs(60)),
+        );
+        let policy = policy_for(&req(), &res, &shared(), now);
+        assert_eq!(policy.max_age(), 60);
+        assert_eq!(
+            policy.typed_header::<headers::ETag>(),
+            Some("\"v1\"".parse().unwrap())
+        );
+        assert_eq!(policy.typed_header::<headers::Expires>(), None);
+        let age: headers::Age = policy
+            .typed_response_header(now + Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(age.as_secs(), 10);
+        assert!(policy
+            .typed_revalidation_header::<headers::IfNoneMatch, _>(&req())
+            .is_some());
+
+        let cc = headers::CacheControl::new()
+            .with_no_store()
+            .with_max_age(Duration::from_secs(5));
+        let directives = CacheDirectives::from(&cc);
+        assert!(directives.no_store);
+        assert_eq!(directives.max_age, Some(5));
+    }
+
+    #[test]
+    fn test_rfc_citations() {
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &request_with(&[("authorization", "Bearer x")]),
+            &response_with(200, &[("cache-control", "max-age=60")]),
+            &shared(),
+            now,
+        );
+        assert_eq!(
+            policy.unstorable_reason(),
+            Some(UnstorableReason::RequestForbids)
+        );
+        let citation = policy.unstorable_citation().unwrap();
+        assert_eq!((citation.rfc, citation.section), (9111, "3.5"));
+        assert_eq!(
+            citation.url(),
+            "https://www.rfc-editor.org/rfc/rfc9111#section-3.5"
+        );
+        assert_eq!(
+            citation.to_string(),
+            "RFC 9111 §3.5 Storing Responses to Authenticated Requests"
+        );
+        assert_eq!(policy.freshness_citation(), Some(citation));
+
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "private, max-age=60")]),
+            &shared(),
+            now,
+        );
+        assert_eq!(policy.unstorable_citation().unwrap().section, "5.2.2.7");
+
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "max-age=60, s-maxage=30")]),
+            &shared(),
+            now,
+        );
+        assert_eq!(policy.unstorable_citation(), None);
+        let citations = policy.summary(now).citations();
+        assert_eq!(citations.len(), 1);
+        assert_eq!(citations[0].title, "s-maxage");
+
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "max-age=60")]),
+            &CacheOptions {
+                always_revalidate: true,
+                ..shared()
+            },
+            now,
+        );
+        assert_eq!(policy.freshness_citation(), None);
+    }
+
+    #[test]
+    fn test_with_options_reclassifies() {
+        let now = SystemTime::now();
+        let policy = policy_for(
+            &req(),
+            &response_with(200, &[("cache-control", "private, max-age=60")]),
+            &private(),
+            now,
+        );
+        assert!(policy.storable());
+        let imported = policy.clone().with_options(shared());
+        assert_eq!(
+            imported.unstorable_reason(),
+            Some(UnstorableReason::ResponseForbids)
+        );
+        assert_eq!(imported.clone().with_options(private()), policy);
+
+        let policy = policy_for(
+            &request_with(&[]),
+            &response_with(200, &[("cache-control", "max-age=60")]),
+            &shared(),
+            now,
+        );
+        let ruled = policy.with_options(CacheOptions {
+            path_rules: vec![PathRule {
+                pattern: "/".to_string(),
+                action: PathAction::Ttl(Duration::from_secs(600)),
+            }],
+            ..shared()
+        });
+        assert_eq!(ruled.max_age(), 600);
+    }
+
+    #[test]
+    fn test_age_selection() {
+        let now = SystemTime::now();
+        let date = format_date(now, 0, 1);
+        let res = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=1000"),
+                ("date", &date),
+                ("age", "30, 500"),
+                ("age", "200"),
+            ],
+        );
+        let policy = policy_for(&req(), &res, &shared(), now);
+        assert_eq!(policy.age_header_value(), Some(500));
+        assert_eq!(policy.age(now), 500);
+
+        let first = CacheOptions {
+            age_selection: AgeSelection::First,
+            ..shared()
+        };
+        let policy = policy_for(&req(), &res, &first, now);
+        assert_eq!(policy.age_header_value(), Some(30));
+        assert_eq!(policy.age(now), 30);
+
+        let single = response_with(200, &[("cache-control", "max-age=1000"), ("age", "7, 3")]);
+        let policy = policy_for(&req(), &single, &shared(), now);
+        assert_eq!(policy.age_header_value(), Some(7));
+        let policy = policy_for(&req(), &response_with(200, &[]), &shared(), now);
+        assert_eq!(policy.age_header_value(), None);
+    }
+
+    #[test]
+    fn test_grace_serves_stale_while_revalidating() {
+        let now = SystemTime::now();
+        let graceful = CacheOptions {
+            grace: Duration::from_secs(300),
+            ..shared()
+        };
+        let res = response_with(200, &[("cache-control", "max-age=60"), ("etag", "\"v1\"")]);
+        let policy = policy_for(&req(), &res, &graceful, now);
+
+        assert!(matches!(
+            policy.evaluate(&req(), now + Duration::from_secs(30)),
+            CacheAction::ServeStored { .. }
+        ));
+        let later = now + Duration::from_secs(200);
+        assert!(policy.within_grace(&req(), later));
+        match policy.evaluate(&req(), later) {
+            CacheAction::ServeStale {
+                headers,
+                upstream_request,
+            } => {
+                assert_eq!(headers["age"], "200");
+                assert_eq!(upstream_request.headers()["if-none-match"], "\"v1\"");
+            }
+            other => panic!("expected ServeStale, got {:?}", other),
+        }
+        let prefetch = EvaluationFlags {
+            is_prefetch: true,
+            ..EvaluationFlags::default()
+        };
+        assert!(matches!(
+            policy.evaluate_with(&req(), later, prefetch),
+            CacheAction::Revalidate { .. }
+        ));
+        assert!(matches!(
+            policy.evaluate(&req(), now + Duration::from_secs(400)),
+            CacheAction::Revalidate { .. }
+        ));
+        assert!(!policy.within_grace(&request_with(&[("cache-control", "no-cache")]), later));
+
+        let strict = response_with(200, &[("cache-control", "max-age=60, must-revalidate")]);
+        let policy = policy_for(&req(), &strict, &graceful, now);
+        assert!(!policy.within_grace(&req(), later));
+        let policy = policy_for(&req(), &res, &shared(), now);
+        assert!(!policy.within_grace(&req(), later));
+    }
+
+    #[test]
+    fn test_grace_and_keep_windows() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            grace: Duration::from_secs(100),
+            keep: Duration::from_secs(1000),
+            ..shared()
+        };
+        let res = response_with(200, &[("cache-control", "max-age=60"), ("etag", "\"v1\"")]);
+        let policy = policy_for(&req(), &res, &options, now);
+        let later = now + Duration::from_secs(90);
+        assert_eq!(policy.time_to_live(later), 0);
+        assert_eq!(policy.time_to_grace_end(later), 70);
+        assert_eq!(policy.time_to_evict(later), 1070);
+
+        let res = response_with(200, &[("cache-control", "max-age=60")]);
+        let policy = policy_for(&req(), &res, &options, now);
+        assert_eq!(policy.time_to_evict(later), 70);
+
+        let res = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=60, must-revalidate"),
+                ("etag", "\"v1\""),
+            ],
+        );
+        let policy = policy_for(&req(), &res, &options, now);
+        assert_eq!(policy.time_to_grace_end(later), 0);
+        assert_eq!(policy.time_to_evict(later), 970);
+
+        let res = response_with(200, &[("cache-control", "no-store"), ("etag", "\"v1\"")]);
+        let policy = policy_for(&req(), &res, &options, now);
+        assert_eq!(policy.time_to_evict(now), 0);
+    }
+
+    #[test]
+    fn test_freshness_inputs() {
+        let now = SystemTime::now();
+        let expires = format_date(now, 1, 3600);
+        let res = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=60, s-maxage=600"),
+                ("date", &format_date(now, 0, 1)),
+                ("expires", &expires),
+            ],
+        );
+        let cdn = policy_for(&req(), &res, &shared(), now).freshness_inputs(now);
+        assert!(cdn.shared);
+        assert_eq!(cdn.s_maxage, Some(600));
+        assert_eq!(cdn.max_age, Some(60));
+        assert_eq!(
+            cdn.expires.flatten().map(format_http_date),
+            Some(expires.clone())
+        );
+        let result = compute_freshness(cdn);
+        assert_eq!(result.lifetime, 600);
+        assert_eq!(result.source, FreshnessSource::SMaxAge);
+
+        let browser = policy_for(&req(), &res, &private(), now).freshness_inputs(now);
+        assert!(!browser.shared);
+        assert_eq!(browser.s_maxage, Some(600));
+        let result = compute_freshness(browser);
+        assert_eq!(result.lifetime, 60);
+        assert_eq!(result.source, FreshnessSource::MaxAge);
+        assert_eq!(result.source.as_str(), "max-age");
+    }
+
+    #[test]
+    fn test_eviction_score() {
+        let now = SystemTime::now();
+        let score = |headers: &[(&str, &str)], hits| {
+            policy_for(&req(), &response_with(200, headers), &shared(), now)
+                .eviction_score(now, hits)
+        };
+        let short = score(&[("cache-control", "max-age=60")], None);
+        let long = score(&[("cache-control", "max-age=86400")], None);
+        assert!(short > 0.0 && long > short);
+        assert_eq!(
+            score(&[("cache-control", "max-age=60")], Some(3)),
+            short * 4.0
+        );
+        assert_eq!(
+            score(&[("cache-control", "max-age=60, immutable")], None),
+            short * 2.0
+        );
+        let last_modified = format_date(now, -600, 1);
+        assert_eq!(
+            score(
+                &[
+                    ("date", &format_date(now, 0, 1)),
+                    ("last-modified", &last_modified)
+                ],
+                None
+            ),
+            short / 2.0
+        );
+        assert_eq!(score(&[("cache-control", "no-store")], Some(100)), 0.0);
+
+        let pinned = CachePolicy::pinned(&req(), &response_with(200, &[]), &shared(), now);
+        assert_eq!(pinned.eviction_score(now, None), f64::INFINITY);
+    }
+
+    #[test]
+    fn test_serve_date_modes() {
+        let now = SystemTime::now();
+        let origin_date = format_date(now, -100, 1);
+        let res = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=1000"),
+                ("date", &origin_date),
+                ("age", "10"),
+            ],
+        );
+        let later = now + Duration::from_secs(50);
+        let headers = |serve_date| {
+            let options = CacheOptions {
+                serve_date,
+                ..shared()
+            };
+            policy_for(&req(), &res, &options, now).response_headers(later)
+        };
+
+        let served = headers(ServeDate::ServingTime);
+        assert_eq!(served["date"], format_http_date(later).as_str());
+        assert_eq!(served["age"], "150");
+        let served = headers(ServeDate::OriginWithAge);
+        assert_eq!(served["date"], origin_date.as_str());
+        assert_eq!(served["age"], "150");
+        let served = headers(ServeDate::Origin);
+        assert_eq!(served["date"], origin_date.as_str());
+        assert_eq!(served["age"], "10");
+    }
+
+    #[test]
+    fn test_client_conditionals_answered_locally() {
+        let now = SystemTime::now();
+        let res = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=600"),
+                ("etag", "W/\"v1\""),
+                ("last-modified", &format_date(now, -3600, 1)),
+                ("content-type", "text/plain"),
+            ],
+        );
+        let policy = policy_for(&req(), &res, &shared(), now);
+
+        match policy.evaluate(&request_with(&[("if-none-match", "\"v0\", \"v1\"")]), now) {
+            CacheAction::NotModified { headers } => {
+                assert_eq!(headers["etag"], "W/\"v1\"");
+                assert!(headers.contains_key("date"));
+                assert!(!headers.contains_key("content-type"));
+            }
+            other => panic!("expected NotModified, got {:?}", other),
+        }
+        assert!(matches!(
+            policy.evaluate(&request_with(&[("if-none-match", "\"v2\"")]), now),
+            CacheAction::ServeStored { .. }
+        ));
+        // If-None-Match wins over a matching If-Modified-Since.
+        assert!(matches!(
+            policy.evaluate(
+                &request_with(&[
+                    ("if-none-match", "\"v2\""),
+                    ("if-modified-since", &format_date(now, 0, 1)),
+                ]),
+                now
+            ),
+            CacheAction::ServeStored { .. }
+        ));
+        assert!(matches!(
+            policy.evaluate(
+                &request_with(&[("if-modified-since", &format_date(now, -60, 1))]),
+                now
+            ),
+            CacheAction::NotModified { .. }
+        ));
+        assert!(matches!(
+            policy.evaluate(
+                &request_with(&[("if-modified-since", &format_date(now, -7200, 1))]),
+                now
+            ),
+            CacheAction::ServeStored { .. }
+        ));
+    }
+
+    #[test]
+    fn test_head_revalidation() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            revalidate_with_head: true,
+            ..shared()
+        };
+        let res = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=60"),
+                ("etag", "\"v1\""),
+                ("content-length", "5"),
+            ],
+        );
+        let policy = policy_for(&req(), &res, &options, now);
+        let later = now + Duration::from_secs(120);
+        let upstream_request = match policy.evaluate(&req(), later) {
+            CacheAction::Revalidate { upstream_request } => upstream_request,
+            other => panic!("expected Revalidate, got {:?}", other),
+        };
+        assert_eq!(upstream_request.method(), Method::HEAD);
+        assert!(!upstream_request.headers().contains_key("if-none-match"));
+
+        let head_response = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=60"),
+                ("etag", "\"v1\""),
+                ("content-length", "5"),
+            ],
+        );
+        let revalidated = policy.revalidated_policy(&upstream_request, &head_response, later);
+        assert!(revalidated.matches && !revalidated.modified);
+        assert!(revalidated
+            .policy
+            .satisfies_without_revalidation(&req(), later));
+        assert!(policy.freshen(&head_response, later).is_some());
+
+        let changed = response_with(200, &[("etag", "\"v2\""), ("content-length", "5")]);
+        let revalidated = policy.revalidated_policy(&upstream_request, &changed, later);
+        assert!(!revalidated.matches && revalidated.modified);
+        assert!(policy.freshen(&changed, later).is_none());
+    }
+
+    #[test]
+    fn test_validator_accessors() {
+        let now = SystemTime::now();
+        let last_modified = format_date(now, -3600, 1);
+        let res = response_with(
+            200,
+            &[("etag", " W/\"v1\""), ("last-modified", &last_modified)],
+        );
+        let policy = policy_for(&req(), &res, &shared(), now);
+        let etag = policy.etag().unwrap();
+        assert!(etag.weak);
+        assert_eq!(etag.tag, "v1");
+        assert_eq!(etag.to_string(), "W/\"v1\"");
+        let strong = EntityTag::parse("\"v1\"").unwrap();
+        assert!(etag.weak_eq(&strong) && !etag.strong_eq(&strong));
+        assert!(strong.strong_eq(&strong));
+        assert_eq!(
+            policy.last_modified().map(format_http_date),
+            Some(last_modified)
+        );
+        assert!(policy.has_validators());
+
+        let res = response_with(200, &[("etag", "unquoted"), ("last-modified", "yesterday")]);
+        let policy = policy_for(&req(), &res, &shared(), now);
+        assert_eq!(policy.etag(), None);
+        assert_eq!(policy.last_modified(), None);
+        assert!(!policy.has_validators());
+    }
+
+    #[test]
+    fn test_extreme_dates() {
+        let now = SystemTime::now();
+        let res = response_with(
+            200,
+            &[
+                ("date", &format_date(now, 0, 1)),
+                ("expires", "Fri, 01 Jan 12345 00:00:00 GMT"),
+                ("last-modified", "Sat, 01 Jan 1870 00:00:00 GMT"),
+            ],
+        );
+        let policy = policy_for(&req(), &res, &shared(), now);
+        assert_eq!(
+            policy.date_quality(http::header::EXPIRES),
+            Some(DateQuality::Clamped)
+        );
+        assert_eq!(
+            policy.date_quality(http::header::LAST_MODIFIED),
+            Some(DateQuality::PreEpoch)
+        );
+        assert_eq!(
+            policy.date_quality(http::header::DATE),
+            Some(DateQuality::Exact)
+        );
+        assert_eq!(policy.date_quality(http::header::RETRY_AFTER), None);
+        assert_eq!(
+            format_http_date(policy.freshness_inputs(now).expires.flatten().unwrap()),
+            "Fri, 31 Dec 9999 23:59:59 GMT"
+        );
+        assert!(policy.time_to_live(now) > 200 * 365 * 86400);
+
+        let res = response_with(200, &[("expires", "Fri, 01 Jan 12345 00:00:00 UTC+1")]);
+        let policy = policy_for(&req(), &res, &shared(), now);
+        assert_eq!(
+            policy.date_quality(http::header::EXPIRES),
+            Some(DateQuality::Invalid)
+        );
+        assert_eq!(policy.time_to_live(now), 0);
+    }
+
+    #[test]
+    fn test_dual_policy() {
+        let now = SystemTime::now();
+        let res = response_with(200, &[("cache-control", "max-age=60, s-maxage=600")]);
+        let dual = CachePolicy::dual(&req(), &res, &private(), now);
+        assert_eq!(dual.ttl_shared(now), 600);
+        assert_eq!(dual.ttl_private(now), 60);
+        assert_eq!(dual.shared(), &policy_for(&req(), &res, &shared(), now));
+        assert_eq!(dual.private(), &policy_for(&req(), &res, &private(), now));
+
+        let res = response_with(200, &[("cache-control", "private, max-age=60")]);
+        let dual = CachePolicy::dual(&req(), &res, &shared(), now);
+        assert!(!dual.storable_shared());
+        assert!(dual.storable_private());
+        assert_eq!(dual.ttl_shared(now), 0);
+    }
+
+    #[test]
+    fn test_origin_cache_headers_round_trip() {
+        let now = SystemTime::now();
+        let generated_at = now - Duration::from_secs(100);
+        let headers = origin_cache_headers(generated_at, Duration::from_secs(300), now);
+        assert_eq!(headers["age"], "100");
+        assert_eq!(headers["cache-control"], "max-age=300");
+        assert_eq!(
+            headers["expires"],
+            format_http_date(generated_at + Duration::from_secs(300)).as_str()
+        );
+
+        let mut response = Response::builder().status(200);
+        for (name, value) in &headers {
+            response = response.header(name, value);
+        }
+        let policy = policy_for(&req(), &response.body(()).unwrap(), &shared(), now);
+        assert!(policy.storable());
+        assert_eq!(policy.time_to_live(now), 200);
+    }
+
+    #[test]
+    fn test_require_explicit_freshness() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            require_explicit_freshness: true,
+            ..shared()
+        };
+        let heuristic = response_with(
+            200,
+            &[
+                ("date", &format_date(now, 0, 1)),
+                ("last-modified", &format_date(now, -10000, 1)),
+            ],
+        );
+        assert!(policy_for(&req(), &heuristic, &shared(), now).storable());
+        let policy = policy_for(&req(), &heuristic, &options, now);
+        assert!(!policy.storable());
+        assert_eq!(policy.time_to_live(now), 0);
+        let private_options = CacheOptions {
+            shared: false,
+            ..options.clone()
+        };
+        assert!(policy_for(&req(), &heuristic, &private_options, now).storable());
+
+        let explicit = response_with(200, &[("cache-control", "s-maxage=60")]);
+        assert!(policy_for(&req(), &explicit, &options, now).storable());
+    }
+
+    #[test]
+    fn test_storable_for_method() {
+        let now = SystemTime::now();
+        let res = response_with(200, &[("cache-control", "max-age=60")]);
+        let policy = policy_for(&req(), &res, &shared(), now);
+        assert!(policy.storable_for_method(Method::GET));
+        assert!(policy.storable_for_method(Method::HEAD));
+        assert!(policy.storable_for_method(Method::POST));
+        assert!(!policy.storable_for_method(Method::PUT));
+
+        let heuristic = response_with(200, &[("last-modified", &format_date(now, -1, 3600))]);
+        let policy = policy_for(&req(), &heuristic, &shared(), now);
+        assert!(policy.storable_for_method(Method::HEAD));
+        assert!(!policy.storable_for_method(Method::POST));
+    }
+
+    #[test]
+    fn test_vary_across_header_lines() {
+        let now = SystemTime::now();
+        let stored_request = request_with(&[("accept-encoding", "gzip"), ("origin", "https://a")]);
+        let res = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=60"),
+                ("vary", "accept-encoding"),
+                ("vary", "origin"),
+            ],
+        );
+        let policy = policy_for(&stored_request, &res, &shared(), now);
+        assert!(policy.satisfies_without_revalidation(&stored_request, now));
+        let other_origin = request_with(&[("accept-encoding", "gzip"), ("origin", "https://b")]);
+        assert!(!policy.satisfies_without_revalidation(&other_origin, now));
+        assert!(policy
+            .summary(now)
+            .to_string()
+            .contains(" vary=accept-encoding,origin"));
+
+        let limited = CacheOptions {
+            max_vary_fields: Some(1),
+            ..shared()
+        };
+        assert_eq!(
+            policy_for(&stored_request, &res, &limited, now).unstorable_reason(),
+            Some(UnstorableReason::TooManyVaryFields { count: 2, limit: 1 })
+        );
+
+        let star = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=60"),
+                ("vary", "accept-encoding"),
+                ("vary", "*"),
+            ],
+        );
+        let policy = policy_for(&stored_request, &star, &shared(), now);
+        assert!(!policy.satisfies_without_revalidation(&stored_request, now));
+        assert_eq!(policy.time_to_live(now), 0);
+    }
+
+    #[test]
+    fn test_disconnected_policy() {
+        let now = SystemTime::now();
+        let later = now + Duration::from_secs(120);
+        let unreachable = EvaluationFlags {
+            origin_unreachable: true,
+            ..EvaluationFlags::default()
+        };
+        let res = response_with(200, &[("cache-control", "max-age=60")]);
+        let revalidate = response_with(200, &[("cache-control", "max-age=60, must-revalidate")]);
+        let with_policy = |disconnected| CacheOptions {
+            disconnected,
+            ..shared()
+        };
+
+        let policy = policy_for(&req(), &res, &shared(), now);
+        assert!(matches!(
+            policy.evaluate_with(&req(), later, unreachable),
+            CacheAction::Revalidate { .. }
+        ));
+
+        let options = with_policy(DisconnectedPolicy::UnlessProhibited);
+        let policy = policy_for(&req(), &res, &options, now);
+        assert!(matches!(
+            policy.evaluate(&req(), later),
+            CacheAction::Revalidate { .. }
+        ));
+        match policy.evaluate_with(&req(), later, unreachable) {
+            CacheAction::ServeDisconnected {
+                headers,
+                cache_status,
+            } => {
+                assert_eq!(
+                    headers["warning"],
+                    "110 - \"Response is Stale\", 112 - \"Disconnected Operation\""
+                );
+                assert_eq!(cache_status, "hit; ttl=-60; detail=disconnected");
+            }
+            action => panic!("unexpected {:?}", action),
+        }
+        let policy = policy_for(&req(), &revalidate, &options, now);
+        assert!(matches!(
+            policy.evaluate_with(&req(), later, unreachable),
+            CacheAction::Revalidate { .. }
+        ));
+
+        let options = CacheOptions {
+            generate_warnings: false,
+            ..with_policy(DisconnectedPolicy::AlwaysWithWarning)
+        };
+        let policy = policy_for(&req(), &revalidate, &options, now);
+        match policy.evaluate_with(&req(), later, unreachable) {
+            CacheAction::ServeDisconnected { headers, .. } => assert_eq!(
+                headers["warning"],
+                "110 - \"Response is Stale\", 112 - \"Disconnected Operation\""
+            ),
+            action => panic!("unexpected {:?}", action),
+        }
+    }
+
+    #[test]
+    fn test_redirect_location() {
+        let now = SystemTime::now();
+        let request = Request::builder()
+            .uri("https://example.com/a/b?x=1")
+            .body(())
+            .unwrap();
+        let location = |status, location| {
+            let res = response_with(status, &[("location", location)]);
+            policy_for(&request, &res, &shared(), now)
+                .redirect_location()
+                .map(|uri| uri.to_string())
+        };
+        assert_eq!(
+            location(302, "c/../d?y=2#frag").as_deref(),
+            Some("https://example.com/a/d?y=2")
+        );
+        assert_eq!(
+            location(301, "/elsewhere").as_deref(),
+            Some("https://example.com/elsewhere")
+        );
+        assert_eq!(
+            location(307, "//cdn.example.com/z").as_deref(),
+            Some("https://cdn.example.com/z")
+        );
+        assert_eq!(
+            location(308, "http://other.example/").as_deref(),
+            Some("http://other.example/")
+        );
+        assert_eq!(
+            location(303, "").as_deref(),
+            Some("https://example.com/a/b?x=1")
+        );
+        assert_eq!(location(200, "/elsewhere"), None);
+
+        let res = response_with(308, &[("location", "/elsewhere")]);
+        let policy = policy_for(&req(), &res, &shared(), now);
+        assert!(policy.is_permanent_redirect());
+        assert_eq!(
+            policy.redirect_location(),
+            Some(http::Uri::from_static("/elsewhere"))
+        );
+        let res = response_with(302, &[("location", "/elsewhere")]);
+        assert!(!policy_for(&req(), &res, &shared(), now).is_permanent_redirect());
+    }
+
+    #[test]
+    fn test_304_directives_downgrade_cacheability() {
+        let now = SystemTime::now();
+        let later = now + Duration::from_secs(120);
+        let stored = response_with(
+            200,
+            &[
+                ("date", &format_date(now, 0, 1)),
+                ("etag", "\"v1\""),
+                ("expires", &format_date(now, 60, 1)),
+            ],
+        );
+        let policy = policy_for(&req(), &stored, &shared(), now);
+        assert!(policy.storable());
+
+        let no_store = response_with(304, &[("etag", "\"v1\""), ("cache-control", "no-store")]);
+        let revalidated = policy.revalidated_policy(&req(), &no_store, later);
+        assert!(revalidated.matches);
+        assert!(!revalidated.policy.storable());
+        let (freshened, headers) = policy.freshen(&no_store, later).unwrap();
+        assert!(!freshened.storable());
+        assert_eq!(headers["cache-control"], "no-store");
+
+        let shorter = response_with(
+            304,
+            &[
+                ("date", &format_date(now, 120, 1)),
+                ("etag", "\"v1\""),
+                ("cache-control", "max-age=10"),
+            ],
+        );
+        let (freshened, _) = policy.freshen(&shorter, later).unwrap();
+        assert!(freshened.storable());
+        assert_eq!(freshened.max_age(), 10);
+        let revalidated = policy.revalidated_policy(&req(), &shorter, later);
+        assert_eq!(revalidated.policy.time_to_live(later), 10);
+
+        let hop_by_hop = response_with(304, &[("etag", "\"v1\""), ("connection", "close")]);
+        let (_, headers) = policy.freshen(&hop_by_hop, later).unwrap();
+        assert!(!headers.contains_key("connection"));
+    }
+
+    #[test]
+    fn test_past_expires() {
+        let now = SystemTime::now();
+        let res = response_with(
+            200,
+            &[
+                ("date", &format_date(now, 0, 1)),
+                ("expires", &format_date(now, -10, 1)),
+            ],
+        );
+        let with = |past_expires| CacheOptions {
+            past_expires,
+            ..shared()
+        };
+
+        let policy = policy_for(&req(), &res, &shared(), now);
+        assert!(policy.storable());
+        assert_eq!(policy.max_age(), 0);
+        assert!(policy.stale(now));
+
+        let policy = policy_for(
+            &req(),
+            &res,
+            &with(PastExpires::Clamp(Duration::from_secs(5))),
+            now,
+        );
+        assert!(policy.storable());
+        assert_eq!(policy.max_age(), 5);
+        assert_eq!(policy.time_to_live(now), 5);
+        assert!(!policy.stale(now));
+
+        let policy = policy_for(&req(), &res, &with(PastExpires::Unstorable), now);
+        assert!(!policy.storable());
+        assert_eq!(policy.time_to_live(now), 0);
+
+        let overridden = response_with(
+            200,
+            &[
+                ("date", &format_date(now, 0, 1)),
+                ("expires", &format_date(now, -10, 1)),
+                ("cache-control", "max-age=60"),
+            ],
+        );
+        assert!(policy_for(&req(), &overridden, &with(PastExpires::Unstorable), now).storable());
+    }
+
+    #[test]
+    fn test_chain_hop() {
+        let now = SystemTime::now();
+        let chain = CacheOptions {
+            chain_hop: true,
+            ..shared()
+        };
+        let res = response_with(
+            200,
+            &[
+                ("date", &format_date(now, -10, 1)),
+                ("age", "30"),
+                ("cache-control", "max-age=100, proxy-revalidate"),
+            ],
+        );
+        let policy = policy_for(&req(), &res, &shared(), now);
+        assert_eq!(policy.time_to_live(now), 0);
+        let policy = policy_for(&req(), &res, &chain, now);
+        assert_eq!(policy.age(now), 30);
+        assert_eq!(policy.time_to_live(now), 70);
+        let later = now + Duration::from_secs(80);
+        assert!(!policy.satisfies_without_revalidation(
+            &request_with(&[("cache-control", "max-stale=60")]),
+            later
+        ));
+
+        let origin_dates = CacheOptions {
+            serve_date: ServeDate::Origin,
+            ..chain.clone()
+        };
+        let policy = policy_for(&req(), &res, &origin_dates, now);
+        let headers = policy.response_headers(now + Duration::from_secs(5));
+        assert_eq!(headers["age"], "35");
+
+        let immutable = response_with(200, &[("cache-control", "max-age=10, immutable")]);
+        let rules = CacheOptions {
+            path_rules: vec![PathRule {
+                pattern: "/*".to_string(),
+                action: PathAction::Ttl(Duration::from_secs(3600)),
+            }],
+            ..chain.clone()
+        };
+        assert_eq!(policy_for(&req(), &immutable, &rules, now).max_age(), 10);
+        let rules = CacheOptions {
+            chain_hop: false,
+            ..rules
+        };
+        assert_eq!(policy_for(&req(), &immutable, &rules, now).max_age(), 3600);
+    }
+
+    #[test]
+    fn test_match_outcome() {
+        let now = SystemTime::now();
+        let later = now + Duration::from_secs(120);
+        let stored_request = request_with(&[("accept-language", "en")]);
+        let res = response_with(
+            200,
+            &[("cache-control", "max-age=60"), ("vary", "accept-language")],
+        );
+        let policy = policy_for(&stored_request, &res, &shared(), now);
+        assert_eq!(
+            policy.match_outcome(&stored_request, now),
+            MatchOutcome::Fresh
+        );
+        assert_eq!(
+            policy.match_outcome(&request_with(&[("accept-language", "fr")]), now),
+            MatchOutcome::VariantMismatch
+        );
+        let other_url = Request::builder()
+            .uri("/other")
+            .header("accept-language", "en")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            policy.match_outcome(&other_url, now),
+            MatchOutcome::MethodUrlMismatch
+        );
+        assert_eq!(
+            policy.match_outcome(&stored_request, later),
+            MatchOutcome::Stale
+        );
+        let no_cache = request_with(&[("accept-language", "en"), ("cache-control", "no-cache")]);
+        assert_eq!(
+            policy.match_outcome(&no_cache, now),
+            MatchOutcome::RequestNoCache
+        );
+
+        let res = response_with(200, &[("cache-control", "max-age=60, must-revalidate")]);
+        let policy = policy_for(&req(), &res, &shared(), now);
+        assert_eq!(
+            policy.match_outcome(&req(), later),
+            MatchOutcome::MustRevalidate
+        );
+    }
+
+    #[test]
+    fn test_select_variant() {
+        let now = SystemTime::now();
+        let stored = |language, max_age: &str, stored_at| {
+            policy_for(
+                &request_with(&[("accept-language", language)]),
+                &response_with(
+                    200,
+                    &[("cache-control", max_age), ("vary", "accept-language")],
+                ),
+                &shared(),
+                stored_at,
+            )
+        };
+        let candidates = vec![
+            stored("fr", "max-age=600", now),
+            stored("en", "max-age=60", now - Duration::from_secs(120)),
+            stored("en", "max-age=60", now),
+            stored("en", "max-age=300", now),
+        ];
+        let english = request_with(&[("accept-language", "en")]);
+        assert_eq!(select_variant(&english, &candidates, now), Some(3));
+        assert_eq!(select_variant(&english, &candidates[..3], now), Some(2));
+        assert_eq!(select_variant(&english, &candidates[..2], now), Some(1));
+        let german = request_with(&[("accept-language", "de")]);
+        assert_eq!(select_variant(&german, &candidates, now), None);
+    }
+
+    #[test]
+    fn test_absurd_values_saturate() {
+        let now = SystemTime::now();
+        let later = now + Duration::from_secs(3600);
+        let huge = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=99999999999999999999999"),
+                ("age", "99999999999999999999999"),
+            ],
+        );
+        let policy = policy_for(&req(), &huge, &shared(), now);
+        assert!(policy.storable());
+        assert_eq!(policy.max_age(), u64::MAX);
+        assert_eq!(policy.age(later), u64::MAX);
+        assert_eq!(policy.time_to_live(later), 0);
+        assert!(policy.stale(later));
+        assert_eq!(policy.time_to_evict(later), 0);
+        policy.response_headers(later);
+
+        let long_lived = response_with(200, &[("cache-control", "max-age=99999999999")]);
+        let policy = policy_for(&req(), &long_lived, &shared(), now);
+        assert_eq!(policy.max_age(), 99_999_999_999);
+        assert_eq!(policy.time_to_live(later), 99_999_996_399);
+
+        let strict = CacheOptions {
+            reject_absurd_values: true,
+            ..shared()
+        };
+        for res in &[huge, long_lived] {
+            assert_eq!(
+                policy_for(&req(), res, &strict, now).unstorable_reason(),
+                Some(UnstorableReason::AbsurdValue)
+            );
+        }
+        let limit = response_with(200, &[("cache-control", "max-age=2147483648")]);
+        assert!(policy_for(&req(), &limit, &strict, now).storable());
+    }
+
+    #[test]
+    fn test_parse_report() {
+        use http::header::{AGE, CACHE_CONTROL, DATE, ETAG, EXPIRES, LAST_MODIFIED};
+        let now = SystemTime::now();
+        let clean = response_with(
+            200,
+            &[
+                ("date", &format_date(now, 0, 1)),
+                ("cache-control", "max-age=60"),
+                ("etag", "\"v1\""),
+                ("last-modified", &format_date(now, -1, 3600)),
+            ],
+        );
+        assert!(policy_for(&req(), &clean, &shared(), now)
+            .parse_report()
+            .is_clean());
+
+        let messy = response_with(
+            200,
+            &[
+                ("date", "yesterday"),
+                ("expires", "Thu, 01 Jan 1970 00:00:00 GMT"),
+                ("expires", "Fri, 31 Dec 99999 23:59:59 GMT"),
+                ("etag", "v1"),
+                ("age", "ten"),
+            ],
+        );
+        let policy = policy_for(&req(), &messy, &shared(), now);
+        let report = policy.parse_report();
+        assert_eq!(report.missing, vec![CACHE_CONTROL, LAST_MODIFIED]);
+        assert_eq!(report.unparseable, vec![DATE, ETAG, AGE]);
+        assert_eq!(report.conflicting, vec![EXPIRES]);
+        let restored = CachePolicy::from_object(&policy.to_object()).unwrap();
+        assert_eq!(restored.parse_report(), report);
+
+        let clamped = response_with(
+            200,
+            &[
+                ("date", &format_date(now, 0, 1)),
+                ("expires", "Fri, 31 Dec 99999 23:59:59 GMT"),
+            ],
+        );
+        let report = policy_for(&req(), &clamped, &shared(), now).parse_report();
+        assert_eq!(report.imprecise, vec![(EXPIRES, DateQuality::Clamped)]);
+    }
+
+    #[test]
+    fn test_request_max_age_vs_no_cache() {
+        let now = SystemTime::now();
+        let later = now + Duration::from_secs(90);
+        let res = response_with(
+            200,
+            &[("cache-control", "max-age=60, stale-while-revalidate=60")],
+        );
+        let reload = request_with(&[("cache-control", "max-age=0")]);
+        let no_cache = request_with(&[("cache-control", "no-cache")]);
+        let min_fresh = request_with(&[("cache-control", "min-fresh=50")]);
+
+        let policy = policy_for(&req(), &res, &shared(), now);
+        assert_eq!(
+            policy.match_outcome(&reload, later),
+            MatchOutcome::RequestMaxAge
+        );
+        assert_eq!(
+            policy.match_outcome(&no_cache, now),
+            MatchOutcome::RequestNoCache
+        );
+        assert_eq!(
+            policy.match_outcome(&min_fresh, now + Duration::from_secs(20)),
+            MatchOutcome::RequestMinFresh
+        );
+        assert!(matches!(
+            policy.evaluate(&reload, later),
+            CacheAction::Revalidate { .. }
+        ));
+
+        let options = CacheOptions {
+            request_max_age_allows_stale_while_revalidate: true,
+            ..shared()
+        };
+        let policy = policy_for(&req(), &res, &options, now);
+        assert!(matches!(
+            policy.evaluate(&reload, later),
+            CacheAction::ServeStale { .. }
+        ));
+        assert!(matches!(
+            policy.evaluate(&no_cache, later),
+            CacheAction::Revalidate { .. }
+        ));
+        assert!(matches!(
+            policy.evaluate(&reload, now + Duration::from_secs(150)),
+            CacheAction::Revalidate { .. }
+        ));
+    }
+
+    #[test]
+    fn test_alt_services() {
+        let now = SystemTime::now();
+        let res = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=3600"),
+                ("age", "100"),
+                (
+                    "alt-svc",
+                    "h3=\":443\"; ma=3600, h3-29=\"alt.example.com:8443\"; ma=60",
+                ),
+                ("alt-svc", "h2=\":443\"; persist=1"),
+            ],
+        );
+        let policy = policy_for(&req(), &res, &shared(), now);
+        assert_eq!(
+            policy.alt_services(now),
+            vec![
+                AltService {
+                    protocol: "h3".to_string(),
+                    host: None,
+                    port: 443,
+                    max_age: 3500,
+                    persist: false,
+                },
+                AltService {
+                    protocol: "h2".to_string(),
+                    host: None,
+                    port: 443,
+                    max_age: 86300,
+                    persist: true,
+                },
+            ]
+        );
+        let headers = policy.response_headers(now);
+        assert_eq!(
+            headers["alt-svc"],
+            "h3=\":443\"; ma=3600, h2=\":443\"; persist=1"
+        );
+        let much_later = now + Duration::from_secs(86400);
+        assert!(policy.alt_services(much_later).is_empty());
+        assert!(!policy.response_headers(much_later).contains_key("alt-svc"));
+
+        let res = response_with(
+            200,
+            &[("cache-control", "max-age=60"), ("alt-svc", "clear")],
+        );
+        let policy = policy_for(&req(), &res, &shared(), now);
+        assert!(policy.alt_services(now).is_empty());
+        assert_eq!(policy.response_headers(now)["alt-svc"], "clear");
+    }
+
+    #[test]
+    fn test_range_resume_request() {
+        let now = SystemTime::now();
+        let date = format_date(now, 0, 1);
+        let last_modified = format_date(now, -3600, 1);
+        let policy = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("date", &date),
+                    ("etag", "\"abc\""),
+                    ("content-length", "1000"),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        let resume = policy
+            .range_resume_request(&request_with(&[("if-none-match", "\"abc\"")]), 400)
+            .unwrap();
+        assert_eq!(resume.headers()["range"], "bytes=400-");
+        assert_eq!(resume.headers()["if-range"], "\"abc\"");
+        assert!(!resume.headers().contains_key("if-none-match"));
+        assert!(policy.range_resume_request(&req(), 1000).is_none());
+
+        let weak = policy_for(
+            &req(),
+            &response_with(
+                200,
+                &[
+                    ("date", &date),
+                    ("etag", "W/\"abc\""),
+                    ("last-modified", &last_modified),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        let resume = weak.range_resume_request(&req(), 400).unwrap();
+        assert_eq!(resume.headers()["if-range"], last_modified.as_str());
+
+        let weak_only = policy_for(
+            &req(),
+            &response_with(200, &[("date", &date), ("etag", "W/\"abc\"")]),
+            &shared(),
+            now,
+        );
+        assert!(weak_only.range_resume_request(&req(), 400).is_none());
+
+        let partial = policy_for(
+            &req(),
+            &response_with(
+                206,
+                &[
+                    ("date", &date),
+                    ("etag", "\"abc\""),
+                    ("content-range", "bytes 0-499/1000"),
+                    ("content-length", "500"),
+                ],
+            ),
+            &shared(),
+            now,
+        );
+        let resume = partial.range_resume_request(&req(), 500).unwrap();
+        assert_eq!(resume.headers()["range"], "bytes=500-");
+        assert!(partial.range_resume_request(&req(), 1000).is_none());
+    }
+
+    #[test]
+    fn test_compute_freshness_on_parsed_inputs() {
+        let now = SystemTime::now();
+        let res = response_with(
+            200,
+            &[
+                ("date", &format_date(now, -100, 1)),
+                ("last-modified", &format_date(now, -10100, 1)),
+            ],
+        );
+        let policy = policy_for(&req(), &res, &shared(), now);
+        let inputs = policy.freshness_inputs(now);
+        let result = compute_freshness(inputs);
+        assert_eq!(result.source, FreshnessSource::Heuristic);
+        assert_eq!(result.lifetime, policy.max_age());
+        assert_eq!(result.time_to_live, policy.time_to_live(now));
+        assert!(!result.stale);
+
+        let result = compute_freshness(FreshnessInputs {
+            max_age: Some(50),
+            ..inputs
+        });
+        assert_eq!(result.source, FreshnessSource::MaxAge);
+        assert_eq!(result.time_to_live, 0);
+        assert!(result.stale);
+    }
+
+    #[test]
+    fn test_original_request_accessors_survive_thawing() {
+        let now = SystemTime::now();
+        let request = Request::builder()
+            .method(Method::HEAD)
+            .uri("/page?x=1")
+            .header("accept-language", "en")
+            .header("user-agent", "test")
+            .body(())
+            .unwrap();
+        let res = response_with(
+            200,
+            &[("cache-control", "max-age=60"), ("vary", "Accept-Language")],
+        );
+        let frozen = policy_for(&request, &res, &shared(), now).to_object();
+        let policy = CachePolicy::from_object(&frozen).unwrap();
+        assert_eq!(policy.original_method(), Method::HEAD);
+        assert_eq!(policy.original_uri(), "/page?x=1");
+        let vary = policy.original_vary_headers();
+        assert_eq!(vary.len(), 1);
+        assert_eq!(vary["accept-language"], "en");
+    }
+
+    #[test]
+    fn test_stored_response_headers_snapshot() {
+        let now = SystemTime::now();
+        let res = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=60"),
+                ("connection", "close"),
+                ("etag", "\"v1\""),
+            ],
+        );
+        let policy = policy_for(&req(), &res, &shared(), now);
+        let stored = policy.stored_response_headers();
+        assert_eq!(stored["etag"], "\"v1\"");
+        assert!(!stored.contains_key("age"));
+
+        let thawed = CachePolicy::from_object(&policy.to_object()).unwrap();
+        assert_eq!(thawed.stored_response_headers(), stored);
+        let later = now + Duration::from_secs(10);
+        assert_eq!(
+            thawed.response_headers(later),
+            policy.response_headers(later)
+        );
+        assert!(!thawed.response_headers(later).contains_key("connection"));
+    }
+
+    #[test]
+    fn test_shared_max_age_authorizes_authenticated_storage_with_revalidation() {
+        let now = SystemTime::now();
+        let request = request_with(&[("authorization", "Bearer token")]);
+        let res = response_with(
+            200,
+            &[
+                ("cache-control", "public, s-maxage=0, max-age=600"),
+                ("date", &format_date(now, 0, 1)),
+            ],
+        );
+        let options = CacheOptions {
+            grace: Duration::from_secs(60),
+            ..shared()
+        };
+        let policy = policy_for(&request, &res, &options, now);
+        assert!(policy.storable());
+        assert!(policy.allows_storing_authenticated());
+        assert!(policy.must_revalidate_when_stale());
+        assert_eq!(policy.time_to_grace_end(now), 0);
+        let later = now + Duration::from_secs(5);
+        assert!(!policy.within_grace(&request, later));
+        let lenient = request_with(&[
+            ("authorization", "Bearer token"),
+            ("cache-control", "max-stale"),
+        ]);
+        assert_eq!(
+            policy.match_outcome(&lenient, later),
+            MatchOutcome::MustRevalidate
+        );
+
+        let browser = policy_for(&request, &res, &private(), now);
+        assert!(!browser.must_revalidate_when_stale());
+        assert!(browser.satisfies_without_revalidation(&request, later));
+    }
+
+    #[test]
+    fn test_heuristic_statuses_allow_list() {
+        let now = SystemTime::now();
+        let headers = [
+            ("date", format_date(now, 0, 1)),
+            ("last-modified", format_date(now, -10, 3600)),
+        ];
+        let headers: Vec<(&str, &str)> = headers.iter().map(|(n, v)| (*n, v.as_str())).collect();
+        let options = CacheOptions {
+            heuristic_statuses: Some(vec![200, 203, 301, 410]),
+            ..shared()
+        };
+        let ok = policy_for(&req(), &response_with(200, &headers), &options, now);
+        assert_eq!(
+            compute_freshness(ok.freshness_inputs(now)).source,
+            FreshnessSource::Heuristic
+        );
+        assert!(ok.time_to_live(now) > 0);
+
+        let not_found = policy_for(&req(), &response_with(404, &headers), &options, now);
+        assert!(not_found.storable());
+        assert_eq!(not_found.time_to_live(now), 0);
+
+        let default = policy_for(&req(), &response_with(404, &headers), &shared(), now);
+        assert!(default.time_to_live(now) > 0);
+    }
+
+    #[test]
+    fn test_private_set_cookie_ttl() {
+        let now = SystemTime::now();
+        let res = response_with(
+            200,
+            &[("cache-control", "max-age=3600"), ("set-cookie", "a=b")],
+        );
+        let options = CacheOptions {
+            private_set_cookie_ttl: Some(Duration::from_secs(60)),
+            ..private()
+        };
+        let policy = policy_for(&req(), &res, &options, now);
+        assert_eq!(policy.max_age(), 60);
+        assert_eq!(
+            compute_freshness(policy.freshness_inputs(now)).source,
+            FreshnessSource::SetCookie
+        );
+
+        let res = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=3600, public"),
+                ("set-cookie", "a=b"),
+            ],
+        );
+        assert_eq!(policy_for(&req(), &res, &options, now).max_age(), 3600);
+        assert_eq!(policy_for(&req(), &res, &private(), now).max_age(), 3600);
+    }
+
+    #[test]
+    fn test_is_heuristic() {
+        let now = SystemTime::now();
+        let last_modified = format_date(now, -10, 3600);
+        let heuristic = response_with(200, &[("last-modified", &last_modified)]);
+        assert!(policy_for(&req(), &heuristic, &shared(), now).is_heuristic());
+
+        let explicit = response_with(
+            200,
+            &[
+                ("last-modified", &last_modified),
+                ("cache-control", "max-age=60"),
+            ],
+        );
+        assert!(!policy_for(&req(), &explicit, &shared(), now).is_heuristic());
+    }
+
+    #[test]
+    fn test_min_ttl_floor() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            min_ttl: Some(Duration::from_secs(30)),
+            ..shared()
+        };
+        let tiny = response_with(200, &[("cache-control", "max-age=1")]);
+        assert_eq!(policy_for(&req(), &tiny, &options, now).max_age(), 30);
+        assert_eq!(policy_for(&req(), &tiny, &shared(), now).max_age(), 1);
+
+        let strict = response_with(200, &[("cache-control", "max-age=1, must-revalidate")]);
+        assert_eq!(policy_for(&req(), &strict, &options, now).max_age(), 1);
+
+        let no_cache = response_with(200, &[("cache-control", "no-cache")]);
+        assert_eq!(policy_for(&req(), &no_cache, &options, now).max_age(), 0);
+    }
+
+    #[test]
+    fn test_finalize_streamed_response() {
+        use http::header::{HeaderMap, HeaderValue};
+
+        let now = SystemTime::now();
+        let res = response_with(
+            200,
+            &[("cache-control", "max-age=60"), ("content-length", "10")],
+        );
+        let provisional = policy_for(&req(), &res, &shared(), now);
+        assert!(provisional.storable());
+        assert!(provisional
+            .clone()
+            .finalize(10, &HeaderMap::new(), true)
+            .is_ok());
+        assert_eq!(
+            provisional.clone().finalize(4, &HeaderMap::new(), false),
+            Err(UnstorableReason::Incomplete {
+                expected: Some(10),
+                received: 4
+            })
+        );
+        assert!(provisional
+            .clone()
+            .finalize(12, &HeaderMap::new(), true)
+            .is_err());
+
+        let chunked = response_with(200, &[("cache-control", "max-age=60")]);
+        let options = CacheOptions {
+            max_object_size: Some(100),
+            ..shared()
+        };
+        let provisional = policy_for(&req(), &chunked, &options, now);
+        assert_eq!(
+            provisional.clone().finalize(200, &HeaderMap::new(), true),
+            Err(UnstorableReason::TooLarge {
+                content_length: 200,
+                limit: 100
+            })
+        );
+        let mut trailers = HeaderMap::new();
+        trailers.insert("cache-control", HeaderValue::from_static("no-store"));
+        assert_eq!(
+            provisional.finalize(50, &trailers, true),
+            Err(UnstorableReason::ResponseForbids)
+        );
+    }
+
+    #[test]
+    fn test_incomplete_response_resumed_with_206() {
+        let now = SystemTime::now();
+        let res = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=600"),
+                ("date", &format_date(now, 0, 1)),
+                ("etag", "\"v1\""),
+                ("content-length", "1000"),
+            ],
+        );
+        let policy = policy_for(&req(), &res, &shared(), now).into_incomplete(400);
+        assert!(!policy.is_complete());
+        assert_eq!(policy.match_outcome(&req(), now), MatchOutcome::Incomplete);
+        assert!(!policy.satisfies_without_revalidation(&req(), now));
+        let thawed = CachePolicy::from_object(&policy.to_object()).unwrap();
+        assert_eq!(thawed, policy);
+
+        let upstream_request = match policy.evaluate(&req(), now) {
+            CacheAction::Resume { upstream_request } => upstream_request,
+            action => panic!("unexpected {:?}", action),
+        };
+        assert_eq!(upstream_request.headers()["range"], "bytes=400-");
+        assert_eq!(upstream_request.headers()["if-range"], "\"v1\"");
+
+        let later = now + Duration::from_secs(5);
+        let partial = response_with(
+            206,
+            &[
+                ("etag", "\"v1\""),
+                ("content-range", "bytes 300-999/1000"),
+                ("content-length", "700"),
+                ("cache-control", "max-age=900"),
+            ],
+        );
+        let (complete, skip) = policy.complete_with(&partial, later).unwrap();
+        assert_eq!(skip, 100);
+        assert!(complete.is_complete());
+        assert_eq!(complete.content_length(), Some(1000));
+        assert!(!complete
+            .stored_response_headers()
+            .contains_key("content-range"));
+        assert_eq!(complete.max_age(), 900);
+        assert!(complete.satisfies_without_revalidation(&req(), later));
+
+        let changed = response_with(
+            206,
+            &[("etag", "\"v2\""), ("content-range", "bytes 400-999/1000")],
+        );
+        assert!(policy.complete_with(&changed, later).is_none());
+        let gap = response_with(
+            206,
+            &[("etag", "\"v1\""), ("content-range", "bytes 500-999/1000")],
+        );
+        assert!(policy.complete_with(&gap, later).is_none());
+    }
+
+    #[test]
+    fn test_variant_key_with_supplied_hasher() {
+        use std::collections::hash_map::{DefaultHasher, RandomState};
+        use std::hash::BuildHasher;
+
+        let now = SystemTime::now();
+        let stored_request = request_with(&[("accept-language", "en"), ("accept", "text/html")]);
+        let res = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=60"),
+                ("vary", "Accept-Language, accept"),
+            ],
+        );
+        let policy = policy_for(&stored_request, &res, &shared(), now);
+        assert_eq!(
+            policy.variant_key_material(),
+            b"accept:text/html\naccept-language:en\n".to_vec()
+        );
+
+        let same = request_with(&[("accept", "text/html"), ("accept-language", "en")]);
+        let other = request_with(&[("accept", "text/html"), ("accept-language", "de")]);
+        assert_eq!(
+            policy.variant_key(DefaultHasher::new()),
+            policy.request_variant_key(&same, DefaultHasher::new())
+        );
+        assert_ne!(
+            policy.variant_key(DefaultHasher::new()),
+            policy.request_variant_key(&other, DefaultHasher::new())
+        );
+
+        let keyed = RandomState::new();
+        assert_eq!(
+            policy.variant_key(keyed.build_hasher()),
+            policy.request_variant_key(&same, keyed.build_hasher())
+        );
+    }
+
+    #[test]
+    fn test_body_integrity_round_trip_and_verify() {
+        let now = SystemTime::now();
+        let res = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=60"),
+                ("content-length", "5"),
+                ("etag", "\"v1\""),
+            ],
+        );
+        let policy = policy_for(&req(), &res, &shared(), now);
+        assert!(policy.verify_body(5, None));
+        assert!(!policy.verify_body(4, None));
+
+        let policy = policy.with_body_integrity(BodyIntegrity::new("sha-256", vec![1, 2, 3], 5));
+        let thawed = CachePolicy::from_object(&policy.to_object()).unwrap();
+        assert_eq!(thawed.body_integrity().unwrap().algorithm, "sha-256");
+        assert!(thawed.verify_body(5, Some(&[1, 2, 3])));
+        assert!(!thawed.verify_body(5, Some(&[1, 2, 4])));
+        assert!(!thawed.verify_body(3, Some(&[1, 2, 3])));
+
+        let not_modified =
+            response_with(304, &[("etag", "\"v1\""), ("cache-control", "max-age=90")]);
+        let revalidated = thawed.revalidated_policy(&req(), &not_modified, now);
+        assert!(revalidated.matches);
+        assert_eq!(revalidated.policy.body_integrity(), thawed.body_integrity());
+    }
+
+    #[test]
+    fn test_content_location_and_invalidation_hints() {
+        let now = SystemTime::now();
+        let post = Request::builder()
+            .method(Method::POST)
+            .uri("/items/1")
+            .body(())
+            .unwrap();
+        let res = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=60"),
+                ("content-location", "/items/1"),
+            ],
+        );
+        let policy = policy_for(&post, &res, &shared(), now);
+        assert_eq!(policy.content_location().unwrap(), "/items/1");
+        let get = Request::builder().uri("/items/1").body(()).unwrap();
+        assert!(policy.satisfies_without_revalidation(&get, now));
+
+        let elsewhere = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=60"),
+                ("content-location", "/items/2"),
+            ],
+        );
+        let policy = policy_for(&post, &elsewhere, &shared(), now);
+        assert!(!policy.satisfies_without_revalidation(&get, now));
+
+        let put = Request::builder()
+            .method(Method::PUT)
+            .uri("/items/1")
+            .header("host", "example.com")
+            .body(())
+            .unwrap();
+        let created = response_with(
+            201,
+            &[
+                ("location", "/items/1/"),
+                ("content-location", "https://other.example/items/1"),
+            ],
+        );
+        let hints = invalidation_hints(&put, &created);
+        assert_eq!(hints, vec!["/items/1", "/items/1/"]);
+        assert!(invalidation_hints(&put, &response_with(500, &[])).is_empty());
+        assert!(invalidation_hints(&get, &created).is_empty());
+    }
+
+    #[test]
+    fn test_vary_on_method() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            vary_on_method: true,
+            ..shared()
+        };
+        let get = request_with(&[("host", "example.com")]);
+        let mut query = request_with(&[("host", "example.com")]);
+        *query.method_mut() = Method::from_bytes(b"QUERY").unwrap();
+        let mut head = request_with(&[("host", "example.com")]);
+        *head.method_mut() = Method::HEAD;
+
+        assert_eq!(
+            cache_key_with(&get, &shared()),
+            cache_key_with(&query, &shared())
+        );
+        assert_ne!(
+            cache_key_with(&get, &options),
+            cache_key_with(&query, &options)
+        );
+        assert_eq!(
+            cache_key_with(&get, &options),
+            cache_key_with(&head, &options)
+        );
+
+        let res = response_with(200, &[("cache-control", "max-age=100")]);
+        let policy = policy_for(&get, &res, &options, now);
+        assert_eq!(policy.cache_key(), cache_key_with(&get, &options));
+        assert_eq!(
+            policy.variant_key_material(),
+            policy.request_variant_key_material(&head)
+        );
+        assert_ne!(
+            policy.variant_key_material(),
+            policy.request_variant_key_material(&query)
+        );
+        assert!(policy_for(&get, &res, &shared(), now)
+            .variant_key_material()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_policy_ref_agrees_with_policy() {
+        let now = SystemTime::now();
+        let request = req();
+        let options = CacheOptions {
+            ignore_cargo_cult: true,
+            ..shared()
+        };
+        for response in &[
+            response_with(200, &[("cache-control", "max-age=100"), ("age", "10")]),
+            response_with(200, &[("cache-control", "no-store")]),
+            response_with(200, &[("cache-control", "s-maxage=5, max-age=50")]),
+            response_with(
+                200,
+                &[
+                    ("cache-control", "max-age=100, pre-check=0, post-check=0"),
+                    ("age", "1"),
+                    ("age", "2"),
+                ],
+            ),
+        ] {
+            let borrowed = CachePolicyRef::new(&request, response, &options, now);
+            let owned = policy_for(&request, response, &options, now);
+            assert_eq!(borrowed.storable(), owned.storable());
+            assert_eq!(borrowed.unstorable_reason(), owned.unstorable_reason());
+            assert_eq!(borrowed.max_age(), owned.max_age());
+            assert_eq!(borrowed.time_to_live(now), owned.time_to_live(now));
+            assert_eq!(borrowed.to_owned(), owned);
+        }
+    }
+
+    #[test]
+    fn test_quick_is_storable() {
+        let options = shared();
+        let ok = response_with(200, &[("cache-control", "max-age=100")]);
+        assert!(quick_is_storable(&req(), &ok, &options));
+
+        let mut put = req();
+        *put.method_mut() = Method::PUT;
+        assert!(!quick_is_storable(&put, &ok, &options));
+        let no_store = request_with(&[("cache-control", "no-store")]);
+        assert!(!quick_is_storable(&no_store, &ok, &options));
+        for response in &[
+            response_with(500, &[("cache-control", "max-age=100")]),
+            response_with(200, &[("cache-control", "no-store")]),
+            response_with(200, &[("cache-control", "max-age=100"), ("vary", "*")]),
+        ] {
+            assert!(!quick_is_storable(&req(), response, &options));
+            assert!(!policy_for(&req(), response, &options, SystemTime::now())
+                .satisfies_without_revalidation(&req(), SystemTime::now()));
+        }
+    }
+
+    #[test]
+    fn test_response_instant_ignores_clock_steps() {
+        let response_time = SystemTime::now();
+        let received = Instant::now();
+        let res = response_with(200, &[("cache-control", "max-age=100")]);
+        let policy = policy_for(&req(), &res, &shared(), response_time);
+        assert!(policy.response_instant().is_none());
+
+        let policy = policy.with_response_instant(received);
+        assert_eq!(policy.response_instant(), Some(received));
+        let later = received + Duration::from_secs(30);
+        assert_eq!(
+            policy.now_at(later),
+            response_time + Duration::from_secs(30)
+        );
+        assert_eq!(policy.time_to_live(policy.now_at(later)), 70);
+        assert_eq!(policy.now_at(received), response_time);
+
+        // Instants can't be persisted.
+        let thawed = CachePolicy::from_object(&policy.to_object()).unwrap();
+        assert!(thawed.response_instant().is_none());
+    }
+
+    #[test]
+    fn test_ttl_with_request_directives() {
+        let now = SystemTime::now();
+        let res = response_with(200, &[("cache-control", "max-age=100")]);
+        let policy = policy_for(&req(), &res, &shared(), now);
+        let later = now + Duration::from_secs(150);
+
+        assert_eq!(policy.ttl_with_request_directives(&req(), now), 100);
+        assert_eq!(policy.ttl_with_request_directives(&req(), later), 0);
+        let max_stale = request_with(&[("cache-control", "max-stale=300")]);
+        assert_eq!(policy.ttl_with_request_directives(&max_stale, later), 250);
+        let any_stale = request_with(&[("cache-control", "max-stale")]);
+        assert_eq!(
+            policy.ttl_with_request_directives(&any_stale, later),
+            u64::MAX
+        );
+        let min_fresh = request_with(&[("cache-control", "min-fresh=10")]);
+        assert_eq!(policy.ttl_with_request_directives(&min_fresh, now), 91);
+        let max_age = request_with(&[("cache-control", "max-age=30, max-stale=300")]);
+        assert_eq!(policy.ttl_with_request_directives(&max_age, now), 31);
+
+        let strict = response_with(200, &[("cache-control", "max-age=100, must-revalidate")]);
+        let policy = policy_for(&req(), &strict, &shared(), now);
+        assert_eq!(policy.ttl_with_request_directives(&max_stale, now), 100);
+    }
+
+    #[test]
+    fn test_request_cache_control_builder() {
+        let now = SystemTime::now();
+        let built = RequestCacheControl::new()
+            .max_age(Duration::from_secs(600))
+            .min_fresh(Duration::from_secs(5))
+            .any_stale()
+            .no_cache()
+            .only_if_cached();
+        assert_eq!(
+            built.to_string(),
+            "max-age=600, max-stale, min-fresh=5, no-cache, only-if-cached"
+        );
+        assert_eq!(
+            CacheDirectives::from_bytes(built.to_string().as_bytes()),
+            built.directives()
+        );
+        assert_eq!(RequestCacheControl::new().to_header_value(), None);
+
+        let res = response_with(200, &[("cache-control", "max-age=100")]);
+        let policy = policy_for(&req(), &res, &shared(), now);
+        let mut request = request_with(&[("cache-control", "no-cache")]);
+        RequestCacheControl::new()
+            .max_stale(Duration::from_secs(300))
+            .apply(&mut request);
+        let later = now + Duration::from_secs(150);
+        assert_eq!(policy.ttl_with_request_directives(&request, later), 250);
+        RequestCacheControl::new().apply(&mut request);
+        assert!(!request.headers().contains_key("cache-control"));
+    }
+
+    #[test]
+    fn test_quirks() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            quirks: Quirks::all(),
+            ..shared()
+        };
+        let quirks_of = |headers: &[(&str, &str)]| {
+            let res = response_with(200, headers);
+            let strict = policy_for(&req(), &res, &shared(), now);
+            let lenient = policy_for(&req(), &res, &options, now);
+            assert!(strict.parse_report().quirks.is_empty());
+            (strict, lenient)
+        };
+
+        let (strict, lenient) = quirks_of(&[
+            ("expires", "0"),
+            ("last-modified", "Mon, 01 Jan 2024 00:00:00 GMT"),
+        ]);
+        assert!(!strict.effective_response_directives().no_cache);
+        assert!(lenient.effective_response_directives().no_cache);
+        assert_eq!(lenient.parse_report().quirks, vec![Quirk::ExpiresZero]);
+
+        let (strict, lenient) = quirks_of(&[("cache-control", "private, public, max-age=60")]);
+        assert!(strict.effective_response_directives().public);
+        assert!(!lenient.effective_response_directives().public);
+
+        let (strict, lenient) = quirks_of(&[("cache-control", "max-age=3600;must-revalidate")]);
+        assert_eq!(strict.max_age(), 3600);
+        assert_eq!(lenient.max_age(), 3600);
+        assert!(!strict.effective_response_directives().must_revalidate);
+        assert!(lenient.effective_response_directives().must_revalidate);
+
+        let (strict, lenient) = quirks_of(&[("cache-control", "max-age=60"), ("vary", "*")]);
+        assert!(strict.storable());
+        assert!(!lenient.storable());
+
+        let date = format_date(now, 0, 1).to_lowercase();
+        let (strict, lenient) = quirks_of(&[("cache-control", "max-age=60"), ("date", &date)]);
+        assert_eq!(strict.parse_report().unparseable, vec![http::header::DATE]);
+        let report = lenient.parse_report();
+        assert!(report.unparseable.is_empty());
+        assert_eq!(report.quirks, vec![Quirk::LowercaseDates]);
+        assert_eq!(
+            lenient.stored_response_headers()["date"],
+            format_date(now, 0, 1).as_str()
+        );
+    }
+
+    #[test]
+    fn test_next_event() {
+        let now = SystemTime::now();
+        let at = |seconds| now + Duration::from_secs(seconds);
+        let options = CacheOptions {
+            grace: Duration::from_secs(10),
+            keep: Duration::from_secs(1000),
+            ..shared()
+        };
+        let res = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=100, stale-while-revalidate=50"),
+                ("etag", "\"v1\""),
+            ],
+        );
+        let policy = policy_for(&req(), &res, &options, now);
+        assert_eq!(
+            policy.next_event(now),
+            Some((CacheEventKind::Stale, at(100)))
+        );
+        assert_eq!(
+            policy.next_event(at(100)),
+            Some((CacheEventKind::GraceEnds, at(110)))
+        );
+        assert_eq!(
+            policy.next_event(at(110)),
+            Some((CacheEventKind::StaleWhileRevalidateEnds, at(160)))
+        );
+        assert_eq!(
+            policy.next_event(at(160)),
+            Some((CacheEventKind::Evict, at(1160)))
+        );
+        assert_eq!(policy.next_event(at(1160)), None);
+
+        // Coinciding transitions are reported as the last of them.
+        let plain = response_with(200, &[("cache-control", "max-age=100")]);
+        let policy = policy_for(&req(), &plain, &shared(), now);
+        assert_eq!(
+            policy.next_event(now),
+            Some((CacheEventKind::Evict, at(100)))
+        );
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            quirks: Quirks::all(),
+            ..shared()
+        };
+        let mut request =
+            request_with(&[("accept-language", "en"), ("cache-control", "max-age=5")]);
+        request
+            .extensions_mut()
+            .insert(CachePartition("tenant".to_string()));
+        let policies = vec![
+            policy_for(
+                &request,
+                &response_with(
+                    200,
+                    &[
+                        ("cache-control", "max-age=100, ext=\"a, b\""),
+                        ("vary", "accept-language"),
+                        ("expires", "0"),
+                        ("etag", "\"v1\""),
+                    ],
+                ),
+                &options,
+                now,
+            )
+            .with_body_integrity(BodyIntegrity::new("sha-256", vec![0, 1, 255], 3))
+            .into_incomplete(1),
+            policy_for(
+                &req(),
+                &response_with(404, &[("age", "1"), ("age", "2")]),
+                &options,
+                now,
+            ),
+        ];
+
+        let mut encoder = PolicyEncoder::new(Vec::new());
+        for policy in &policies {
+            encoder.write(policy).unwrap();
+        }
+        let segment = encoder.into_inner();
+        let mut decoder = PolicyDecoder::new(&segment[..], options.clone());
+        for policy in &policies {
+            assert_eq!(decoder.read().unwrap().as_ref(), Some(policy));
+        }
+        assert!(decoder.read().unwrap().is_none());
+
+        // Names are only written once per stream.
+        let mut single = Vec::new();
+        policies[0].serialize_into(&mut single).unwrap();
+        policies[0].serialize_into(&mut single).unwrap();
+        assert!(segment.len() < single.len());
+        let mut reader = &single[..];
+        for _ in 0..2 {
+            let policy = CachePolicy::deserialize_from(&mut reader, &options).unwrap();
+            assert_eq!(policy, policies[0]);
+        }
+        assert!(CachePolicy::deserialize_from(&mut &single[..10], &options).is_err());
+    }
+
+    #[test]
+    fn test_binary_shared_values_round_trip() {
+        let now = SystemTime::now();
+        let policies: Vec<_> = ["gzip", "br", "gzip"]
+            .iter()
+            .map(|encoding| {
+                policy_for(
+                    &request_with(&[("accept-encoding", encoding), ("x-tenant", "a")]),
+                    &response_with(
+                        200,
+                        &[
+                            ("cache-control", "max-age=60"),
+                            ("content-encoding", encoding),
+                            ("vary", "accept-encoding, x-tenant"),
+                            ("x-served-by", encoding),
+                        ],
+                    ),
+                    &shared(),
+                    now,
+                )
+            })
+            .collect();
+
+        let mut encoder = PolicyEncoder::new(Vec::new());
+        for policy in &policies {
+            encoder.write(policy).unwrap();
+        }
+        let segment = encoder.into_inner();
+        let mut decoder = PolicyDecoder::new(&segment[..], shared());
+        for policy in &policies {
+            let decoded = decoder.read().unwrap().unwrap();
+            assert_eq!(&decoded, policy);
+            assert_eq!(decoded.cache_key(), policy.cache_key());
+            assert_eq!(
+                decoded.variant_key_material(),
+                policy.variant_key_material()
+            );
+        }
+        assert!(decoder.read().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_policy_for_shared_options() {
+        use std::sync::Arc;
+
+        let now = SystemTime::now();
+        let options = Arc::new(shared());
+        let res = response_with(200, &[("cache-control", "max-age=60"), ("etag", "\"v1\"")]);
+        let policy = policy_for_shared_options(&req(), &res, &options, now);
+        assert_eq!(policy, policy_for(&req(), &res, &shared(), now));
+        assert_eq!(Arc::strong_count(&options), 2);
+
+        let revalidated = policy.revalidated_policy(
+            &req(),
+            &response_with(304, &[("etag", "\"v1\"")]),
+            now + Duration::from_secs(120),
+        );
+        assert!(revalidated.matches);
+        assert_eq!(Arc::strong_count(&options), 3);
+    }
+
+    #[test]
+    fn test_request_cache_control_distinguishes_policies() {
+        let now = SystemTime::now();
+        let res = response_with(200, &[("cache-control", "max-age=60")]);
+        let plain = policy_for(&req(), &res, &shared(), now);
+        let no_store = policy_for(
+            &request_with(&[("cache-control", "no-store")]),
+            &res,
+            &shared(),
+            now,
+        );
+        assert!(plain.storable());
+        assert!(!no_store.storable());
+        assert_ne!(plain, no_store);
+        assert_ne!(hash_of(&plain), hash_of(&no_store));
+    }
+
+    #[test]
+    fn test_stale_while_revalidate_delays_eviction() {
+        let now = SystemTime::now();
+        let at = |seconds| now + Duration::from_secs(seconds);
+        let res = response_with(
+            200,
+            &[("cache-control", "max-age=60, stale-while-revalidate=600")],
+        );
+        let policy = policy_for(&req(), &res, &shared(), now);
+        assert!(policy.within_stale_while_revalidate(&req(), at(120), EvaluationFlags::default()));
+        assert_eq!(policy.time_to_evict(at(120)), 540);
+        assert_eq!(
+            policy.next_event(now),
+            Some((CacheEventKind::GraceEnds, at(60)))
+        );
+        assert_eq!(
+            policy.next_event(at(60)),
+            Some((CacheEventKind::Evict, at(660)))
+        );
+
+        // With validators to keep, the window closes before the response is evicted.
+        let options = CacheOptions {
+            keep: Duration::from_secs(100),
+            ..shared()
+        };
+        let res = response_with(
+            200,
+            &[
+                ("cache-control", "max-age=60, stale-while-revalidate=600"),
+                ("etag", "\"v1\""),
+            ],
+        );
+        let policy = policy_for(&req(), &res, &options, now);
+        assert_eq!(
+            policy.next_event(at(60)),
+            Some((CacheEventKind::StaleWhileRevalidateEnds, at(660)))
+        );
+        assert_eq!(
+            policy.next_event(at(660)),
+            Some((CacheEventKind::Evict, at(760)))
+        );
+
+        // No stale use is allowed with must-revalidate, so the window doesn't count.
+        let res = response_with(
+            200,
+            &[(
+                "cache-control",
+                "max-age=60, stale-while-revalidate=600, must-revalidate",
+            )],
+        );
+        let policy = policy_for(&req(), &res, &shared(), now);
+        assert_eq!(policy.time_to_evict(at(30)), 30);
+    }
+
+    #[test]
+    fn test_response_allows_storing_agrees_with_policy() {
+        let now = SystemTime::now();
+        let options = CacheOptions {
+            past_expires: PastExpires::Unstorable,
+            ..shared()
+        };
+        for (date, expires) in &[(-3600, 0), (0, -3600), (0, 3600), (-7200, -3600)] {
+            let res = response_with(
+                200,
+                &[
+                    ("date", &format_date(now, *date, 1)),
+                    ("expires", &format_date(now, *expires, 1)),
+                ],
+            );
+            let policy = policy_for(&req(), &res, &options, now);
+            assert_eq!(policy.response_allows_storing(), expires >= date);
+            assert_eq!(
+                response_allows_storing(&res, &options),
+                policy.response_allows_storing(),
+                "date {}, expires {}",
+                date,
+                expires
+            );
+        }
+    }
+
+    #[test]
+    fn test_request_checks_skip_invalid_cache_control() {
+        let now = SystemTime::now();
+        let mut request = req();
+        request.headers_mut().insert(
+            "cache-control",
+            http::HeaderValue::from_bytes(b"no-store, \xff").unwrap(),
+        );
+        let res = response_with(200, &[("cache-control", "max-age=60")]);
+        let policy = policy_for(&request, &res, &shared(), now);
+        assert!(policy.storable());
+        assert!(request_allows_storing(&request, &shared()));
+        assert!(quick_is_storable(&request, &res, &shared()));
+    }
+}
@@ -0,0 +1,257 @@
+//! Helpers for testing caches built on this crate.
+//!
+//! Enabled by the `test_support` feature. The builders panic on malformed literals, which is
+//! what a test wants.
+
+use crate::http_cache_semantics::format_http_date;
+use crate::{policy_for, CacheOptions, CachePolicy, EvaluationFlags};
+use http::{Method, Request, Response};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Builds a request with an empty body.
+///
+/// Panics if `method`, `uri` or any header isn't valid.
+pub fn request(method: &str, uri: &str, headers: &[(&str, &str)]) -> Request<()> {
+    let mut builder = Request::builder()
+        .method(Method::from_bytes(method.as_bytes()).expect("invalid method"))
+        .uri(uri);
+    for (name, value) in headers {
+        builder = builder.header(*name, *value);
+    }
+    builder.body(()).expect("invalid request")
+}
+
+/// Builds a `GET` request with an empty body.
+pub fn get(uri: &str, headers: &[(&str, &str)]) -> Request<()> {
+    request("GET", uri, headers)
+}
+
+/// Builds a response with an empty body.
+///
+/// Panics if `status` or any header isn't valid.
+pub fn response(status: u16, headers: &[(&str, &str)]) -> Response<()> {
+    let mut builder = Response::builder().status(status);
+    for (name, value) in headers {
+        builder = builder.header(*name, *value);
+    }
+    builder.body(()).expect("invalid response")
+}
+
+/// A clock that only moves when told to, so that tests don't depend on how fast they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Clock {
+    now: SystemTime,
+}
+
+impl Default for Clock {
+    /// Starts at midnight on 1 January 2020, UTC.
+    fn default() -> Self {
+        Clock::at(UNIX_EPOCH + Duration::from_secs(1_577_836_800))
+    }
+}
+
+impl Clock {
+    pub fn at(now: SystemTime) -> Self {
+        Clock { now }
+    }
+
+    pub fn now(&self) -> SystemTime {
+        self.now
+    }
+
+    /// Moves the clock forward by `seconds` and returns the new time.
+    pub fn advance(&mut self, seconds: u64) -> SystemTime {
+        self.now += Duration::from_secs(seconds);
+        self.now
+    }
+
+    /// Formats the time `offset` seconds from now as an HTTP date, for `Date`, `Expires` and
+    /// `Last-Modified` headers.
+    pub fn http_date(&self, offset: i64) -> String {
+        let distance = Duration::from_secs(offset.unsigned_abs());
+        if offset < 0 {
+            format_http_date(self.now - distance)
+        } else {
+            format_http_date(self.now + distance)
+        }
+    }
+}
+
+/// A stored exchange, a later request for it, and what a conforming cache does with them.
+#[derive(Debug)]
+pub struct Scenario {
+    pub name: &'static str,
+    pub options: CacheOptions,
+    pub stored_request: Request<()>,
+    pub stored_response: Response<()>,
+    pub stored_at: SystemTime,
+    pub request: Request<()>,
+    pub requested_at: SystemTime,
+    /// Whether the stored response can answer `request` without contacting the origin.
+    pub satisfies: bool,
+    /// Whether the stored response may be served stale in answer to `request` while the cache
+    /// revalidates it in the background.
+    pub serve_while_revalidating: bool,
+    /// The origin's answer when the cache revalidates, if the scenario gets that far.
+    pub revalidation_response: Option<Response<()>>,
+    /// Whether the policy resulting from revalidation can answer `request` without contacting
+    /// the origin again.
+    pub fresh_after_revalidation: bool,
+}
+
+impl Scenario {
+    /// Policy for the stored exchange.
+    pub fn policy(&self) -> CachePolicy {
+        policy_for(
+            &self.stored_request,
+            &self.stored_response,
+            &self.options,
+            self.stored_at,
+        )
+    }
+
+    /// Runs the scenario against this crate, describing the first expectation that fails.
+    pub fn check(&self) -> Result<(), String> {
+        let policy = self.policy();
+        let satisfies = policy.satisfies_without_revalidation(&self.request, self.requested_at);
+        if satisfies != self.satisfies {
+            return Err(format!(
+                "{}: expected satisfies_without_revalidation to be {}",
+                self.name, self.satisfies
+            ));
+        }
+        let serve_while_revalidating = policy.within_stale_while_revalidate(
+            &self.request,
+            self.requested_at,
+            EvaluationFlags::default(),
+        );
+        if serve_while_revalidating != self.serve_while_revalidating {
+            return Err(format!(
+                "{}: expected within_stale_while_revalidate to be {}",
+                self.name, self.serve_while_revalidating
+            ));
+        }
+        if let Some(revalidation_response) = &self.revalidation_response {
+            let revalidated =
+                policy.revalidated_policy(&self.request, revalidation_response, self.requested_at);
+            let fresh = revalidated
+                .policy
+                .satisfies_without_revalidation(&self.request, self.requested_at);
+            if fresh != self.fresh_after_revalidation {
+                return Err(format!(
+                    "{}: expected the revalidated policy to be {}",
+                    self.name,
+                    if self.fresh_after_revalidation {
+                        "fresh"
+                    } else {
+                        "stale"
+                    }
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A response that varies on `Accept-Language`, requested again in another language.
+pub fn vary_mismatch() -> Scenario {
+    let clock = Clock::default();
+    Scenario {
+        name: "vary mismatch",
+        options: CacheOptions::default(),
+        stored_request: get("http://example.com/", &[("accept-language", "en")]),
+        stored_response: response(
+            200,
+            &[
+                ("date", &clock.http_date(0)),
+                ("cache-control", "max-age=3600"),
+                ("vary", "Accept-Language"),
+            ],
+        ),
+        stored_at: clock.now(),
+        request: get("http://example.com/", &[("accept-language", "fr")]),
+        requested_at: clock.now() + Duration::from_secs(10),
+        satisfies: false,
+        serve_while_revalidating: false,
+        revalidation_response: None,
+        fresh_after_revalidation: false,
+    }
+}
+
+/// A stale response with an `ETag`, freshened by a matching `304 Not Modified`.
+pub fn freshen_304() -> Scenario {
+    let mut clock = Clock::default();
+    let stored_at = clock.now();
+    let stored_response = response(
+        200,
+        &[
+            ("date", &clock.http_date(0)),
+            ("cache-control", "max-age=60"),
+            ("etag", "\"v1\""),
+        ],
+    );
+    let requested_at = clock.advance(120);
+    Scenario {
+        name: "304 freshen",
+        options: CacheOptions::default(),
+        stored_request: get("http://example.com/", &[]),
+        stored_response,
+        stored_at,
+        request: get("http://example.com/", &[]),
+        requested_at,
+        satisfies: false,
+        serve_while_revalidating: false,
+        revalidation_response: Some(response(
+            304,
+            &[
+                ("date", &clock.http_date(0)),
+                ("cache-control", "max-age=60"),
+                ("etag", "\"v1\""),
+            ],
+        )),
+        fresh_after_revalidation: true,
+    }
+}
+
+/// A response requested within its `stale-while-revalidate` window.
+///
+/// The policy reports it stale, but a cache may serve it while revalidating in the
+/// background: see `CachePolicy::within_stale_while_revalidate`.
+pub fn stale_while_revalidate() -> Scenario {
+    let mut clock = Clock::default();
+    let stored_at = clock.now();
+    let stored_response = response(
+        200,
+        &[
+            ("date", &clock.http_date(0)),
+            ("cache-control", "max-age=60, stale-while-revalidate=600"),
+            ("etag", "\"v1\""),
+        ],
+    );
+    let requested_at = clock.advance(120);
+    Scenario {
+        name: "stale-while-revalidate",
+        options: CacheOptions::default(),
+        stored_request: get("http://example.com/", &[]),
+        stored_response,
+        stored_at,
+        request: get("http://example.com/", &[]),
+        requested_at,
+        satisfies: false,
+        serve_while_revalidating: true,
+        revalidation_response: Some(response(
+            304,
+            &[
+                ("date", &clock.http_date(0)),
+                ("cache-control", "max-age=60, stale-while-revalidate=600"),
+                ("etag", "\"v1\""),
+            ],
+        )),
+        fresh_after_revalidation: true,
+    }
+}
+
+/// All of the canonical scenarios.
+pub fn scenarios() -> Vec<Scenario> {
+    vec![vary_mismatch(), freshen_304(), stale_while_revalidate()]
+}